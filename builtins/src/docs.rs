@@ -0,0 +1,436 @@
+use crate::add_builtin;
+use crate::print::pretty_value;
+use crate::SloshVm;
+use compile_state::state::SloshVmTrait;
+use slvm::{Interned, VMError, VMResult, Value};
+use std::collections::BTreeMap;
+
+/// Section used for documented globals that do not include a Section: line.
+const NO_SECTION: &str = "uncategorized";
+
+/// Parsed form of a doc string, the format is:
+/// Usage: (form)
+///
+/// Description.
+///
+/// Section: name
+///
+/// Example:
+/// (example code)
+#[derive(Clone, Debug, Default)]
+pub struct DocEntry {
+    pub name: String,
+    pub usage: Option<String>,
+    pub description: String,
+    pub section: String,
+    pub example: Option<String>,
+}
+
+impl DocEntry {
+    pub fn parse(name: &str, doc: &str) -> Self {
+        let mut usage: Option<String> = None;
+        let mut description = String::new();
+        let mut section = None;
+        let mut example = None;
+        let mut in_usage = false;
+        let mut lines = doc.lines();
+        while let Some(line) = lines.next() {
+            let trimmed = line.trim();
+            if let Some(u) = trimmed.strip_prefix("Usage:") {
+                usage = Some(u.trim().to_string());
+                in_usage = true;
+            } else if let Some(s) = trimmed.strip_prefix("Section:") {
+                section = Some(s.trim().to_string());
+                in_usage = false;
+            } else if trimmed == "Example:" {
+                let ex: Vec<&str> = lines.by_ref().collect();
+                let ex = ex.join("\n");
+                if !ex.trim().is_empty() {
+                    example = Some(ex.trim_end().to_string());
+                }
+            } else if trimmed.is_empty() {
+                in_usage = false;
+                if !description.is_empty() {
+                    description.push('\n');
+                }
+            } else if in_usage {
+                // Usage that spans lines.
+                if let Some(usage) = usage.as_mut() {
+                    usage.push('\n');
+                    usage.push_str(line);
+                }
+            } else {
+                description.push_str(line);
+                description.push('\n');
+            }
+        }
+        Self {
+            name: name.to_string(),
+            usage,
+            description: description.trim().to_string(),
+            section: section
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| NO_SECTION.to_string()),
+            example,
+        }
+    }
+
+    /// Format for display in the REPL.
+    pub fn format_help(&self) -> String {
+        let mut help = format!("{}\n", self.name);
+        if let Some(usage) = &self.usage {
+            help.push_str(&format!("Usage: {usage}\n"));
+        }
+        if !self.description.is_empty() {
+            help.push('\n');
+            help.push_str(&self.description);
+            help.push('\n');
+        }
+        help.push_str(&format!("\nSection: {}\n", self.section));
+        if let Some(example) = &self.example {
+            help.push_str(&format!("\nExample:\n{example}\n"));
+        }
+        help
+    }
+
+    /// One line summary, the usage if available or the start of the description.
+    pub fn summary(&self) -> String {
+        if let Some(usage) = &self.usage {
+            usage.lines().next().unwrap_or_default().to_string()
+        } else {
+            self.description
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        }
+    }
+}
+
+/// The doc-string property of the global sym (None if it is not documented).
+pub fn doc_string_for(vm: &mut SloshVm, sym: Interned) -> Option<String> {
+    let key = vm.intern("doc-string");
+    let slot = vm.global_intern_slot(sym)?;
+    match vm.get_global_property(slot, key)? {
+        Value::String(h) => Some(vm.get_string(h).to_string()),
+        Value::StringConst(i) => Some(vm.get_interned(i).to_string()),
+        _ => None,
+    }
+}
+
+/// Return the doc entry for every global that is documented, a builtin or a special form, sorted
+/// by name.
+pub fn doc_entries(vm: &mut SloshVm) -> Vec<DocEntry> {
+    let key = vm.intern("doc-string");
+    let mut entries = Vec::new();
+    for (sym, slot) in vm.globals() {
+        let doc = match vm.get_global_property(*slot as u32, key) {
+            Some(Value::String(h)) => vm.get_string(h),
+            Some(Value::StringConst(i)) => vm.get_interned(i),
+            _ => match vm.get_global(*slot as u32) {
+                Value::Builtin(_) | Value::Special(_) => "",
+                _ => continue,
+            },
+        };
+        entries.push(DocEntry::parse(vm.get_interned(*sym), doc));
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+/// Group doc entries by section (sections in alphabetical order with uncategorized last).
+pub fn doc_sections(entries: Vec<DocEntry>) -> Vec<(String, Vec<DocEntry>)> {
+    let mut sections: BTreeMap<String, Vec<DocEntry>> = BTreeMap::new();
+    for entry in entries {
+        sections
+            .entry(entry.section.clone())
+            .or_default()
+            .push(entry);
+    }
+    let uncategorized = sections.remove(NO_SECTION);
+    let mut sections: Vec<(String, Vec<DocEntry>)> = sections.into_iter().collect();
+    if let Some(uncategorized) = uncategorized {
+        sections.push((NO_SECTION.to_string(), uncategorized));
+    }
+    sections
+}
+
+fn anchor(prefix: &str, name: &str) -> String {
+    let mut anchor = prefix.to_string();
+    for ch in name.chars() {
+        if ch.is_alphanumeric() {
+            anchor.push(ch);
+        } else {
+            anchor.push_str(&format!("-{:x}", ch as u32));
+        }
+    }
+    anchor
+}
+
+/// Generate a Markdown API reference for the documented globals in vm.
+pub fn docs_markdown(vm: &mut SloshVm) -> String {
+    let sections = doc_sections(doc_entries(vm));
+    let mut out = String::from("# Slosh API Reference\n\n## Sections\n");
+    for (section, entries) in &sections {
+        out.push_str(&format!(
+            "- [{section}](#{}) ({})\n",
+            anchor("section-", section),
+            entries.len()
+        ));
+    }
+    for (section, entries) in &sections {
+        out.push_str(&format!(
+            "\n<a id=\"{}\"></a>\n## {section}\n",
+            anchor("section-", section)
+        ));
+        for entry in entries {
+            out.push_str(&format!(
+                "\n<a id=\"{}\"></a>\n### {}\n",
+                anchor("sym-", &entry.name),
+                entry.name.replace('*', "\\*").replace('_', "\\_")
+            ));
+            if let Some(usage) = &entry.usage {
+                out.push_str(&format!("\nUsage: `{}`\n", usage.replace('\n', " ")));
+            }
+            if !entry.description.is_empty() {
+                out.push_str(&format!("\n{}\n", entry.description));
+            }
+            if let Some(example) = &entry.example {
+                out.push_str(&format!("\nExample:\n```\n{example}\n```\n"));
+            }
+        }
+    }
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Generate an HTML API reference for the documented globals in vm.
+pub fn docs_html(vm: &mut SloshVm) -> String {
+    let sections = doc_sections(doc_entries(vm));
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Slosh API Reference</title>\n</head>\n<body>\n<h1>Slosh API Reference</h1>\n<h2>Sections</h2>\n<ul>\n",
+    );
+    for (section, entries) in &sections {
+        out.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a> ({})</li>\n",
+            anchor("section-", section),
+            html_escape(section),
+            entries.len()
+        ));
+    }
+    out.push_str("</ul>\n");
+    for (section, entries) in &sections {
+        out.push_str(&format!(
+            "<h2 id=\"{}\">{}</h2>\n",
+            anchor("section-", section),
+            html_escape(section)
+        ));
+        for entry in entries {
+            out.push_str(&format!(
+                "<h3 id=\"{}\">{}</h3>\n",
+                anchor("sym-", &entry.name),
+                html_escape(&entry.name)
+            ));
+            if let Some(usage) = &entry.usage {
+                out.push_str(&format!(
+                    "<p>Usage: <code>{}</code></p>\n",
+                    html_escape(usage)
+                ));
+            }
+            if !entry.description.is_empty() {
+                for para in entry.description.split("\n\n") {
+                    out.push_str(&format!("<p>{}</p>\n", html_escape(para)));
+                }
+            }
+            if let Some(example) = &entry.example {
+                out.push_str(&format!(
+                    "<p>Example:</p>\n<pre><code>{}</code></pre>\n",
+                    html_escape(example)
+                ));
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Usage for the global sym from its parameters if it is a lambda, for instance
+/// "(name a % b &key sep)".
+pub fn lambda_usage(vm: &SloshVm, sym: Interned) -> Option<String> {
+    let chunk = match vm.get_global(vm.global_intern_slot(sym)?) {
+        Value::Lambda(h) => vm.get_lambda(h),
+        Value::Closure(h) => vm.get_closure(h).0,
+        _ => return None,
+    };
+    let dbg_args = chunk.dbg_args.as_ref()?;
+    let num_args = (chunk.args + chunk.opt_args) as usize;
+    let mut usage = format!("({}", vm.get_interned(sym));
+    for (i, arg) in dbg_args.iter().take(num_args).enumerate() {
+        let name = if *arg == vm.specials().scratch {
+            "[..]"
+        } else {
+            vm.get_interned(*arg)
+        };
+        if chunk.rest && i == num_args - 1 {
+            if chunk.keys.is_empty() {
+                usage.push_str(&format!(" & {name}"));
+            } else {
+                usage.push_str(" &key");
+                for key in &chunk.keys {
+                    usage.push_str(&format!(" {}", vm.get_interned(*key)));
+                }
+            }
+        } else {
+            if chunk.opt_args > 0 && i == chunk.args as usize {
+                usage.push_str(" %");
+            }
+            usage.push_str(&format!(" {name}"));
+        }
+    }
+    usage.push(')');
+    Some(usage)
+}
+
+fn doc(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [Value::Symbol(sym)] = registers {
+        if vm.global_intern_slot(*sym).is_none() {
+            return Err(VMError::new_vm(format!(
+                "doc: {} is not defined",
+                vm.get_interned(*sym)
+            )));
+        }
+        let name = vm.get_interned(*sym);
+        let usage = lambda_usage(vm, *sym);
+        match doc_string_for(vm, *sym) {
+            Some(doc) if !doc.trim().is_empty() => {
+                let mut entry = DocEntry::parse(name, &doc);
+                if entry.usage.is_none() {
+                    entry.usage = usage;
+                }
+                print!("{}", entry.format_help())
+            }
+            _ => match usage {
+                Some(usage) => println!("{name}\nUsage: {usage}\n\nNo documentation."),
+                None => println!("{name}\n\nNo documentation."),
+            },
+        }
+        Ok(Value::Nil)
+    } else {
+        Err(VMError::new_vm("doc: takes one symbol"))
+    }
+}
+
+fn apropos(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [search] = registers {
+        let search = match search {
+            Value::StringConst(_) | Value::String(_) | Value::Symbol(_) => {
+                pretty_value(vm, *search).to_lowercase()
+            }
+            _ => return Err(VMError::new_vm("apropos: takes a string")),
+        };
+        for entry in doc_entries(vm) {
+            if entry.name.to_lowercase().contains(&search)
+                || entry.description.to_lowercase().contains(&search)
+                || entry
+                    .usage
+                    .as_ref()
+                    .map(|u| u.to_lowercase().contains(&search))
+                    .unwrap_or(false)
+            {
+                println!("{}: {}", entry.name, entry.summary());
+            }
+        }
+        Ok(Value::Nil)
+    } else {
+        Err(VMError::new_vm("apropos: takes one string"))
+    }
+}
+
+pub fn add_doc_builtins(env: &mut SloshVm) {
+    add_builtin(
+        env,
+        "doc",
+        doc,
+        "Usage: (doc symbol)
+
+Print the documentation for the global symbol (the doc-string property set by a
+doc comment or for a builtin).
+
+Section: doc
+
+Example:
+(doc 'doc)
+",
+    );
+    add_builtin(
+        env,
+        "apropos",
+        apropos,
+        "Usage: (apropos string)
+
+Print the name and usage of each documented global whose name, usage or
+description contains string (case insensitive).
+
+Section: doc
+
+Example:
+(apropos \"vec\")
+",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_doc() {
+        let entry = DocEntry::parse(
+            "vec-slice",
+            "Usage: (vec-slice vector start end?)
+
+Returns a slice of a vector (0 based indexes, end is exclusive).
+
+Section: vector
+
+Example:
+(test::assert-equal '(5 6) (vec-slice '#(1 2 3 4 5 6) 4 6))
+",
+        );
+        assert_eq!(
+            entry.usage.as_deref(),
+            Some("(vec-slice vector start end?)")
+        );
+        assert_eq!(
+            entry.description,
+            "Returns a slice of a vector (0 based indexes, end is exclusive)."
+        );
+        assert_eq!(entry.section, "vector");
+        assert_eq!(
+            entry.example.as_deref(),
+            Some("(test::assert-equal '(5 6) (vec-slice '#(1 2 3 4 5 6) 4 6))")
+        );
+
+        let entry = DocEntry::parse("defn", "Define a named function.\n\nSection: core\n");
+        assert_eq!(entry.usage, None);
+        assert_eq!(entry.description, "Define a named function.");
+        assert_eq!(entry.section, "core");
+        assert_eq!(entry.example, None);
+
+        let entry = DocEntry::parse("car", "");
+        assert_eq!(entry.section, NO_SECTION);
+    }
+}
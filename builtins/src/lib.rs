@@ -2,12 +2,16 @@ extern crate core;
 
 use compile_state::state::{CompileEnvironment, SloshVm, SloshVmTrait};
 use slvm::{CallFuncSig, VMError, VMResult, Value};
+use std::collections::HashMap;
 
 pub mod collections;
 pub mod conversions;
+pub mod docs;
 pub mod io;
+pub mod macroexpand;
 pub mod print;
 pub mod string;
+pub mod test;
 
 fn get_prop(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     if registers.len() != 2 {
@@ -81,12 +85,21 @@ fn sizeof_value(_vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
 }
 
 fn gensym(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
-    if !registers.is_empty() {
-        return Err(VMError::new_vm("gensym: takes no arguments".to_string()));
-    }
     let line = vm.env().line();
     let sym_idx = vm.env_mut().next_gensym();
-    let sym = vm.intern(&format!("#<SYM:{line}:{sym_idx}>"));
+    let sym = match registers {
+        [] => vm.intern(&format!("#<SYM:{line}:{sym_idx}>")),
+        // Keep the name a gensym replaces so expansions stay readable.
+        [Value::Symbol(name)] => {
+            let name = vm.get_interned(*name);
+            vm.intern(&format!("#<{name}:{line}:{sym_idx}>"))
+        }
+        _ => {
+            return Err(VMError::new_vm(
+                "gensym: takes an optional symbol".to_string(),
+            ))
+        }
+    };
     Ok(Value::Symbol(sym))
 }
 
@@ -138,6 +151,41 @@ fn expand_macro(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     }
 }
 
+fn arity(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let chunk = match registers {
+        [Value::Lambda(h)] => vm.get_lambda(*h),
+        [Value::Closure(h)] => vm.get_closure(*h).0,
+        _ => return Err(VMError::new_vm("arity: takes one lambda")),
+    };
+    // The rest (or &key) arg is counted in args or opt_args (if it follows optional args).
+    let rest_opt = chunk.rest && chunk.opt_args > 0;
+    let required = chunk.args - u16::from(chunk.rest && !rest_opt);
+    let optional = chunk.opt_args - u16::from(rest_opt);
+    let keys = chunk.keys.iter().map(|k| Value::Keyword(*k)).collect();
+    // Keep keys from being collected when the map is allocated.
+    vm.pause_gc();
+    let keys = vm.alloc_vector(keys);
+    let mut map = HashMap::new();
+    map.insert(
+        Value::Keyword(vm.intern("required")),
+        Value::UInt32(required.into()),
+    );
+    map.insert(
+        Value::Keyword(vm.intern("optional")),
+        Value::UInt32(optional.into()),
+    );
+    let rest = if chunk.rest && chunk.keys.is_empty() {
+        Value::True
+    } else {
+        Value::False
+    };
+    map.insert(Value::Keyword(vm.intern("rest")), rest);
+    map.insert(Value::Keyword(vm.intern("keys")), keys);
+    let map = vm.alloc_map(map);
+    vm.unpause_gc();
+    Ok(map)
+}
+
 fn remainder(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     let mut i = registers.iter();
     if let (Some(val1), Some(val2), None) = (i.next(), i.next(), i.next()) {
@@ -161,6 +209,62 @@ pub fn add_builtin(
     env.set_global_property(si, key, s);
 }
 
+fn is_constant(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [Value::Symbol(sym)] = registers {
+        match vm.global_intern_slot(*sym) {
+            Some(slot) if vm.is_global_constant(slot) => Ok(Value::True),
+            _ => Ok(Value::False),
+        }
+    } else {
+        Err(VMError::new_vm("constant?: takes a symbol"))
+    }
+}
+
+/// Dynamic globals (*name* or ns::*name*) are expected to change so freezing everything leaves
+/// them alone.
+fn is_earmuffed(name: &str) -> bool {
+    let name = name.rsplit("::").next().unwrap_or(name);
+    name.len() > 2 && name.starts_with('*') && name.ends_with('*')
+}
+
+/// Make every defined global (except dynamic ones named like *name*) a constant.  slosh calls
+/// this once the builtins and core definitions are loaded so they can not be clobbered.
+pub fn freeze_all_globals(vm: &mut SloshVm) {
+    let slots: Vec<u32> = vm
+        .globals()
+        .iter()
+        .filter(|(sym, _)| !is_earmuffed(vm.get_interned(**sym)))
+        .map(|(_, slot)| *slot as u32)
+        .filter(|slot| !matches!(vm.get_global(*slot), Value::Undefined))
+        .collect();
+    for slot in slots {
+        vm.set_global_constant(slot);
+    }
+}
+
+fn freeze_globals(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if registers.is_empty() {
+        return Err(VMError::new_vm("freeze-globals: takes one or more symbols"));
+    }
+    for sym in registers {
+        match sym {
+            Value::Symbol(i) => match vm.global_intern_slot(*i) {
+                Some(slot) if !matches!(vm.get_global(slot), Value::Undefined) => {
+                    vm.set_global_constant(slot)
+                }
+                _ => {
+                    return Err(VMError::new_vm(format!(
+                        "freeze-globals: {} is not defined",
+                        vm.get_interned(*i)
+                    )))
+                }
+            },
+            _ => return Err(VMError::new_vm("freeze-globals: takes symbols")),
+        }
+    }
+    Ok(Value::Nil)
+}
+
 pub fn add_misc_builtins(env: &mut SloshVm) {
     env.set_global_builtin("get-prop", get_prop);
     env.set_global_builtin("set-prop", set_prop);
@@ -168,6 +272,69 @@ pub fn add_misc_builtins(env: &mut SloshVm) {
     env.set_global_builtin("sizeof-value", sizeof_value);
     env.set_global_builtin("gensym", gensym);
     env.set_global_builtin("expand-macro", expand_macro);
+    add_builtin(
+        env,
+        "constant?",
+        is_constant,
+        "Usage: (constant? symbol)
+
+True if the global symbol is a constant (from defconst or freeze-globals).
+
+Section: core
+
+Example:
+(defconst constant?-test 1)
+(def constant?-test2 1)
+(test::assert-true (constant? 'constant?-test))
+(test::assert-false (constant? 'constant?-test2))
+(test::assert-false (constant? 'constant?-not-defined))
+",
+    );
+    add_builtin(
+        env,
+        "freeze-globals",
+        freeze_globals,
+        "Usage: (freeze-globals symbol+)
+
+Make the given globals constants, they keep their current values and can no
+longer be changed with def or set!.  The shell freezes the builtins and core
+definitions (except dynamic ones named like *name*) before it loads init.slosh
+or a script.
+
+Section: core
+
+Example:
+(def freeze-test 1)
+(freeze-globals 'freeze-test)
+(test::assert-true (constant? 'freeze-test))
+(test::assert-error (eval '(def freeze-test 2)))
+(test::assert-equal 1 freeze-test)
+(test::assert-error (freeze-globals 'freeze-test-not-defined))
+(test::assert-error (freeze-globals))
+",
+    );
+    add_builtin(
+        env,
+        "arity",
+        arity,
+        "Usage: (arity lambda)
+
+Describe the parameters of lambda as a map: :required and :optional are the
+number of required and optional (%) args, :rest is true if it takes rest (&)
+args and :keys is a vector of its keyword (&key) params.
+
+Section: core
+
+Example:
+(def arity-test (arity (fn (a % b &key (sep \" \") trim) a)))
+(test::assert-equal 1 (get arity-test :required))
+(test::assert-equal 1 (get arity-test :optional))
+(test::assert-false (get arity-test :rest))
+(test::assert-equal [:sep :trim] (get arity-test :keys))
+(test::assert-true (get (arity (fn (& rest) rest)) :rest))
+(test::assert-error (arity 1))
+",
+    );
     add_builtin(
         env,
         "rem",
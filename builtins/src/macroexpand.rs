@@ -0,0 +1,506 @@
+use crate::add_builtin;
+use crate::SloshVm;
+use compile_state::state::SloshVmTrait;
+use slvm::{Interned, VMError, VMResult, Value};
+
+/// Items of the proper list exp (None if it is not a list or does not end in nil).
+fn list_items(vm: &SloshVm, exp: Value) -> Option<Vec<Value>> {
+    match exp {
+        Value::List(h, start) => Some(vm.get_vector(h)[start as usize..].to_vec()),
+        Value::Pair(_) => {
+            let mut items = Vec::new();
+            let mut next = exp;
+            while let Value::Pair(h) = next {
+                let (car, cdr) = vm.get_pair(h);
+                items.push(car);
+                next = cdr;
+            }
+            if next.is_nil() {
+                Some(items)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The macro the global name is bound to if it is one.
+fn global_macro(vm: &SloshVm, name: Interned) -> Option<Value> {
+    let global = vm.get_global(vm.global_intern_slot(name)?);
+    match global {
+        Value::Lambda(_) | Value::Closure(_)
+            if matches!(vm.get_heap_property(global, ":macro"), Some(Value::True)) =>
+        {
+            Some(global)
+        }
+        _ => None,
+    }
+}
+
+fn call_macro(vm: &mut SloshVm, mac: Value, args: &[Value]) -> VMResult<Value> {
+    match mac {
+        Value::Lambda(h) => {
+            let l = vm.get_lambda(h);
+            vm.do_call(l, args, None)
+        }
+        Value::Closure(h) => {
+            let (l, tcaps) = vm.get_closure(h);
+            let caps = Vec::from(tcaps);
+            vm.do_call(l, args, Some(&caps[..]))
+        }
+        _ => Err(VMError::new_vm("not a macro")),
+    }
+}
+
+/// If exp is a call to a macro (not shadowed by one of locals) return its expansion.
+fn expand_once(vm: &mut SloshVm, exp: Value, locals: &[Interned]) -> VMResult<Option<Value>> {
+    if let Some(items) = list_items(vm, exp) {
+        if let Some(Value::Symbol(name)) = items.first() {
+            if !locals.contains(name) {
+                if let Some(mac) = global_macro(vm, *name) {
+                    return call_macro(vm, mac, &items[1..]).map(Some);
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Expand exp once if it is a macro call, otherwise return None.  GC is paused while the macro
+/// runs (like the compiler does) so the form and its expansion are not collected.
+pub fn macroexpand_1(vm: &mut SloshVm, exp: Value) -> VMResult<Option<Value>> {
+    vm.pause_gc();
+    let res = expand_once(vm, exp, &[]);
+    vm.unpause_gc();
+    res
+}
+
+/// Expand every macro call in exp, including the ones produced by an expansion.  Quoted forms,
+/// the template of a back-quote (but not its unquotes), binding names and patterns are left alone.
+pub fn macroexpand_all(vm: &mut SloshVm, exp: Value) -> VMResult<Value> {
+    vm.pause_gc();
+    let res = Expander::new(vm).expand(vm, exp, &mut Vec::new());
+    vm.unpause_gc();
+    res
+}
+
+struct Expander {
+    unquote: Interned,
+    splice: Interned,
+    splice_bang: Interned,
+    when: Interned,
+    quote: Interned,
+    backquote: Interned,
+    defstruct: Interned,
+    fn_: Interned,
+    mac_: Interned,
+    hygienic_mac: Interned,
+    definline: Interned,
+    let_: Interned,
+    let_values: Interned,
+    def: Interned,
+    defconst: Interned,
+    set: Interned,
+    match_: Interned,
+    case_: Interned,
+    numeq: Interned,
+    key: Interned,
+}
+
+impl Expander {
+    fn new(vm: &mut SloshVm) -> Self {
+        Self {
+            unquote: vm.intern("unquote"),
+            splice: vm.intern("unquote-splice"),
+            splice_bang: vm.intern("unquote-splice!"),
+            when: vm.intern("when"),
+            quote: vm.specials().quote,
+            backquote: vm.specials().backquote,
+            defstruct: vm.specials().defstruct,
+            fn_: vm.specials().fn_,
+            mac_: vm.specials().mac_,
+            hygienic_mac: vm.specials().hygienic_mac,
+            definline: vm.specials().definline,
+            let_: vm.specials().let_,
+            let_values: vm.specials().let_values,
+            def: vm.specials().def,
+            defconst: vm.specials().defconst,
+            set: vm.specials().set,
+            match_: vm.specials().match_,
+            case_: vm.specials().case_,
+            numeq: vm.specials().numeq,
+            key: vm.specials().key,
+        }
+    }
+
+    /// Expand exp, locals are the names bound around it (they shadow macros and specials).
+    fn expand(&self, vm: &mut SloshVm, exp: Value, locals: &mut Vec<Interned>) -> VMResult<Value> {
+        if let Some(expanded) = expand_once(vm, exp, locals)? {
+            return self.expand(vm, expanded, locals);
+        }
+        let items = match list_items(vm, exp) {
+            Some(items) if !items.is_empty() => items,
+            _ => return Ok(exp),
+        };
+        let special = match items[0] {
+            Value::Symbol(name) if !locals.contains(&name) => {
+                match vm.global_intern_slot(name).map(|slot| vm.get_global(slot)) {
+                    Some(Value::Special(special)) => Some(special),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let mut items = items;
+        let changed = if let Some(special) = special {
+            self.expand_special(vm, special, &mut items, locals)?
+        } else {
+            // A call, the callable can be a form too.
+            self.expand_from(vm, &mut items, 0, locals)?
+        };
+        if changed {
+            Ok(vm.alloc_list_ro(items))
+        } else {
+            Ok(exp)
+        }
+    }
+
+    /// Expand items from start on (in place), return true if any changed.
+    fn expand_from(
+        &self,
+        vm: &mut SloshVm,
+        items: &mut [Value],
+        start: usize,
+        locals: &mut Vec<Interned>,
+    ) -> VMResult<bool> {
+        let mut changed = false;
+        for item in items.iter_mut().skip(start) {
+            let new_item = self.expand(vm, *item, locals)?;
+            if new_item != *item {
+                *item = new_item;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    fn expand_special(
+        &self,
+        vm: &mut SloshVm,
+        special: Interned,
+        items: &mut [Value],
+        locals: &mut Vec<Interned>,
+    ) -> VMResult<bool> {
+        let locals_len = locals.len();
+        let changed = if special == self.quote || special == self.defstruct {
+            false
+        } else if special == self.backquote {
+            self.expand_from_bq(vm, items, 1, 1, locals)?
+        } else if special == self.fn_ || special == self.mac_ || special == self.hygienic_mac {
+            // (fn params body*)
+            let mut changed = false;
+            if let Some(params) = items.get(1).copied() {
+                let new_params = self.expand_params(vm, params, locals)?;
+                changed = new_params != params;
+                items[1] = new_params;
+            }
+            self.expand_from(vm, items, 2, locals)? || changed
+        } else if special == self.definline {
+            // (definline name params body*)
+            if let Some(params) = items.get(2).copied() {
+                self.bind_names(vm, params, locals);
+            }
+            self.expand_from(vm, items, 3, locals)?
+        } else if special == self.let_ || special == self.let_values {
+            // (let (name value ...) body*), the names of let-values are lists.
+            let mut changed = false;
+            if let Some(bindings) = items.get(1).copied() {
+                let new_bindings = self.expand_bindings(vm, bindings, locals)?;
+                changed = new_bindings != bindings;
+                items[1] = new_bindings;
+            }
+            self.expand_from(vm, items, 2, locals)? || changed
+        } else if special == self.def || special == self.defconst || special == self.set {
+            // (def name doc-string? value), name is not evaluated.
+            self.expand_from(vm, items, 2, locals)?
+        } else if special == self.match_ || special == self.case_ {
+            // (match value (pattern body*)*) and (case value (keys body*)*)
+            let is_match = special == self.match_;
+            let mut changed = false;
+            if items.len() > 1 {
+                changed = self.expand_from(vm, &mut items[..2], 1, locals)?;
+            }
+            for clause in items.iter_mut().skip(2) {
+                let new_clause = self.expand_clause(vm, *clause, is_match, locals)?;
+                if new_clause != *clause {
+                    *clause = new_clause;
+                    changed = true;
+                }
+            }
+            changed
+        } else {
+            self.expand_from(vm, items, 1, locals)?
+        };
+        locals.truncate(locals_len);
+        Ok(changed)
+    }
+
+    /// Expand the default values in a fn parameter list and bind its names.
+    fn expand_params(
+        &self,
+        vm: &mut SloshVm,
+        params: Value,
+        locals: &mut Vec<Interned>,
+    ) -> VMResult<Value> {
+        let mut items = match list_items(vm, params) {
+            Some(items) => items,
+            None => {
+                self.bind_names(vm, params, locals);
+                return Ok(params);
+            }
+        };
+        let mut changed = false;
+        let mut in_keys = false;
+        for i in 0..items.len() {
+            let new_item = match items[i] {
+                Value::Symbol(s) if s == self.key => {
+                    in_keys = true;
+                    continue;
+                }
+                // The value after := is the default of an optional.
+                _ if i > 0 && items[i - 1] == Value::Keyword(self.numeq) => {
+                    self.expand(vm, items[i], locals)?
+                }
+                // &key (name default)
+                item if in_keys => match list_items(vm, item) {
+                    Some(mut key_items) if key_items.len() == 2 => {
+                        let changed = self.expand_from(vm, &mut key_items, 1, locals)?;
+                        self.bind_names(vm, key_items[0], locals);
+                        if changed {
+                            vm.alloc_list_ro(key_items)
+                        } else {
+                            item
+                        }
+                    }
+                    _ => {
+                        self.bind_names(vm, item, locals);
+                        item
+                    }
+                },
+                item => {
+                    self.bind_names(vm, item, locals);
+                    item
+                }
+            };
+            if new_item != items[i] {
+                items[i] = new_item;
+                changed = true;
+            }
+        }
+        if changed {
+            Ok(vm.alloc_list_ro(items))
+        } else {
+            Ok(params)
+        }
+    }
+
+    /// Expand the values of a let binding list (name value ...), each value sees the names
+    /// bound before it.
+    fn expand_bindings(
+        &self,
+        vm: &mut SloshVm,
+        bindings: Value,
+        locals: &mut Vec<Interned>,
+    ) -> VMResult<Value> {
+        let mut items = match list_items(vm, bindings) {
+            Some(items) => items,
+            None => return Ok(bindings),
+        };
+        let mut changed = false;
+        for i in (1..items.len()).step_by(2) {
+            let new_item = self.expand(vm, items[i], locals)?;
+            if new_item != items[i] {
+                items[i] = new_item;
+                changed = true;
+            }
+            self.bind_names(vm, items[i - 1], locals);
+        }
+        if changed {
+            Ok(vm.alloc_list_ro(items))
+        } else {
+            Ok(bindings)
+        }
+    }
+
+    /// Expand a match (pattern :when guard? body*) or case (keys body*) clause.
+    fn expand_clause(
+        &self,
+        vm: &mut SloshVm,
+        clause: Value,
+        is_match: bool,
+        locals: &mut Vec<Interned>,
+    ) -> VMResult<Value> {
+        let mut items = match list_items(vm, clause) {
+            Some(items) if !items.is_empty() => items,
+            _ => return Ok(clause),
+        };
+        let locals_len = locals.len();
+        if is_match {
+            self.bind_names(vm, items[0], locals);
+        }
+        let start = if is_match && items.get(1) == Some(&Value::Keyword(self.when)) {
+            2
+        } else {
+            1
+        };
+        let changed = self.expand_from(vm, &mut items, start, locals)?;
+        locals.truncate(locals_len);
+        if changed {
+            Ok(vm.alloc_list_ro(items))
+        } else {
+            Ok(clause)
+        }
+    }
+
+    /// Add every symbol in a binding name or pattern (symbol, vector, map or list) to locals,
+    /// skipping the defaults (after :=) a pattern can contain.
+    fn bind_names(&self, vm: &SloshVm, pattern: Value, locals: &mut Vec<Interned>) {
+        match pattern {
+            Value::Symbol(s) => locals.push(s),
+            Value::Vector(_) | Value::Pair(_) | Value::List(_, _) => {
+                let mut is_default = false;
+                for item in pattern.iter(vm) {
+                    if !is_default {
+                        self.bind_names(vm, item, locals);
+                    }
+                    is_default = item == Value::Keyword(self.numeq);
+                }
+            }
+            Value::Map(h) => {
+                for (key, _) in vm.get_map(h).iter() {
+                    self.bind_names(vm, *key, locals);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Expand only the unquoted forms in a back-quote template, depth is the number of
+    /// back-quotes (less unquotes) around exp.
+    fn expand_bq(
+        &self,
+        vm: &mut SloshVm,
+        exp: Value,
+        depth: usize,
+        locals: &mut Vec<Interned>,
+    ) -> VMResult<Value> {
+        if let Value::Vector(h) = exp {
+            let mut items = vm.get_vector(h).to_vec();
+            return if self.expand_from_bq(vm, &mut items, 0, depth, locals)? {
+                Ok(vm.alloc_vector_ro(items))
+            } else {
+                Ok(exp)
+            };
+        }
+        let mut items = match list_items(vm, exp) {
+            Some(items) if !items.is_empty() => items,
+            _ => return Ok(exp),
+        };
+        let changed = match items[0] {
+            Value::Symbol(s) if s == self.unquote || s == self.splice || s == self.splice_bang => {
+                if depth == 1 {
+                    self.expand_from(vm, &mut items, 1, locals)?
+                } else {
+                    self.expand_from_bq(vm, &mut items, 1, depth - 1, locals)?
+                }
+            }
+            Value::Symbol(s) if s == self.backquote => {
+                self.expand_from_bq(vm, &mut items, 1, depth + 1, locals)?
+            }
+            _ => self.expand_from_bq(vm, &mut items, 0, depth, locals)?,
+        };
+        if changed {
+            Ok(vm.alloc_list_ro(items))
+        } else {
+            Ok(exp)
+        }
+    }
+
+    fn expand_from_bq(
+        &self,
+        vm: &mut SloshVm,
+        items: &mut [Value],
+        start: usize,
+        depth: usize,
+        locals: &mut Vec<Interned>,
+    ) -> VMResult<bool> {
+        let mut changed = false;
+        for item in items.iter_mut().skip(start) {
+            let new_item = self.expand_bq(vm, *item, depth, locals)?;
+            if new_item != *item {
+                *item = new_item;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+}
+
+fn macroexpand_1_builtin(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [exp] = registers {
+        Ok(macroexpand_1(vm, *exp)?.unwrap_or(*exp))
+    } else {
+        Err(VMError::new_vm("macroexpand-1: takes one form"))
+    }
+}
+
+fn macroexpand_all_builtin(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [exp] = registers {
+        macroexpand_all(vm, *exp)
+    } else {
+        Err(VMError::new_vm("macroexpand-all: takes one form"))
+    }
+}
+
+pub fn add_macroexpand_builtins(env: &mut SloshVm) {
+    add_builtin(
+        env,
+        "macroexpand-1",
+        macroexpand_1_builtin,
+        "Usage: (macroexpand-1 form)
+
+If form is a call to a macro return its expansion (expanded once), otherwise
+return form unchanged.
+
+Section: core
+
+Example:
+(def mx-twice (macro (x) `(do ~x ~x)))
+(test::assert-equal '(do (prn 1) (prn 1)) (macroexpand-1 '(mx-twice (prn 1))))
+(test::assert-equal '(do (mx-twice 1) (mx-twice 1)) (macroexpand-1 '(mx-twice (mx-twice 1))))
+(test::assert-equal '(+ 1 2) (macroexpand-1 '(+ 1 2)))
+",
+    );
+    add_builtin(
+        env,
+        "macroexpand-all",
+        macroexpand_all_builtin,
+        "Usage: (macroexpand-all form)
+
+Return form with every macro call in it expanded (including the calls that
+expansions produce).  Quoted forms, back-quote templates (except what they
+unquote), binding names and patterns are not expanded, a local binding with
+the name of a macro hides the macro.
+
+Section: core
+
+Example:
+(def mx-twice (macro (x) `(do ~x ~x)))
+(test::assert-equal '(do (do 1 1) (do 1 1)) (macroexpand-all '(mx-twice (mx-twice 1))))
+(test::assert-equal '(fn (x) (do x x)) (macroexpand-all '(fn (x) (mx-twice x))))
+(test::assert-equal ''(mx-twice 1) (macroexpand-all ''(mx-twice 1)))
+(test::assert-equal '(let (mx-twice 1) (mx-twice 2)) (macroexpand-all '(let (mx-twice 1) (mx-twice 2))))
+",
+    );
+}
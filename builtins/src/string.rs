@@ -1,5 +1,5 @@
 use crate::{add_builtin, SloshVm};
-use slvm::{Handle, VMError, VMResult, Value};
+use slvm::{VMError, VMResult, Value};
 use unicode_segmentation::UnicodeSegmentation;
 
 fn str_trim(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
@@ -186,7 +186,7 @@ fn str_map_inner(vm: &mut SloshVm, func: Value, string: Value) -> VMResult<Strin
             }
             Value::Closure(handle) => {
                 let (func, caps) = vm.get_closure(handle);
-                let caps: Vec<Handle> = caps.to_vec();
+                let caps: Vec<Value> = caps.to_vec();
                 vm.do_call(func, &[param], Some(&caps[..]))
             }
             Value::Builtin(idx) => vm.get_builtin(idx)(vm, &[param]),
@@ -0,0 +1,488 @@
+use crate::print::pretty_value;
+use crate::{add_builtin, SloshVm};
+use compile_state::state::SloshVmTrait;
+use slvm::{VMError, VMResult, Value};
+
+/// Global holding the registered tests, a vector of #(name group fn).
+const TESTS_GLOBAL: &str = "test::*tests*";
+/// Global holding the registered fixtures, a vector of #(group kind fn).
+const FIXTURES_GLOBAL: &str = "test::*fixtures*";
+/// Global holding the name of the group tests are currently being defined in (or nil).
+const GROUP_GLOBAL: &str = "test::*group*";
+
+/// A test registered with deftest.
+pub struct TestCase {
+    pub name: String,
+    pub group: Option<String>,
+    pub func: Value,
+}
+
+/// Fixtures (setup and teardown functions) that apply to a test.
+#[derive(Default)]
+pub struct Fixtures {
+    pub before: Vec<Value>,
+    pub after: Vec<Value>,
+}
+
+fn get_named_global(vm: &mut SloshVm, name: &str) -> Value {
+    let i = vm.intern(name);
+    if let Some(idx) = vm.global_intern_slot(i) {
+        vm.get_global(idx)
+    } else {
+        Value::Undefined
+    }
+}
+
+fn string_arg(vm: &SloshVm, val: Value) -> Option<String> {
+    match val {
+        Value::StringConst(i) => Some(vm.get_interned(i).to_string()),
+        Value::String(h) => Some(vm.get_string(h).to_string()),
+        Value::Symbol(i) | Value::Keyword(i) => Some(vm.get_interned(i).to_string()),
+        _ => None,
+    }
+}
+
+fn push_global(vm: &mut SloshVm, name: &str, item: Value) -> VMResult<()> {
+    match get_named_global(vm, name) {
+        Value::Vector(h) => {
+            vm.get_vector_mut(h)?.push(item);
+            Ok(())
+        }
+        _ => Err(VMError::new_vm(format!("{name} is not a vector"))),
+    }
+}
+
+/// Build the message for an assertion failure, includes the optional user message.
+fn failure(vm: &SloshVm, assertion: &str, detail: String, msg: Option<&Value>) -> VMError {
+    let mut reason = format!("{assertion} failed");
+    if let Some(msg) = msg {
+        reason.push_str(": ");
+        reason.push_str(&pretty_value(vm, *msg));
+    }
+    reason.push('\n');
+    reason.push_str(&detail);
+    VMError::new("test", reason)
+}
+
+/// Find where two values that are not equal first differ, used to point out the problem in
+/// larger sequences and strings.
+fn first_difference(vm: &SloshVm, expected: Value, actual: Value) -> Option<String> {
+    match (expected, actual) {
+        (
+            Value::Vector(_) | Value::List(_, _) | Value::Pair(_),
+            Value::Vector(_) | Value::List(_, _) | Value::Pair(_),
+        ) => {
+            let mut e_iter = expected.iter(vm);
+            let mut a_iter = actual.iter(vm);
+            let mut idx = 0;
+            loop {
+                match (e_iter.next(), a_iter.next()) {
+                    (Some(e), Some(a)) => {
+                        if !vm.is_equal_pair(e, a).ok()?.is_true() {
+                            return Some(format!(
+                                "first difference at index {idx}: expected {}, got {}",
+                                pretty_value(vm, e),
+                                pretty_value(vm, a)
+                            ));
+                        }
+                    }
+                    (Some(e), None) => {
+                        return Some(format!(
+                            "actual is shorter, missing at index {idx}: {}",
+                            pretty_value(vm, e)
+                        ))
+                    }
+                    (None, Some(a)) => {
+                        return Some(format!(
+                            "actual is longer, extra at index {idx}: {}",
+                            pretty_value(vm, a)
+                        ))
+                    }
+                    (None, None) => return None,
+                }
+                idx += 1;
+            }
+        }
+        (Value::String(_) | Value::StringConst(_), Value::String(_) | Value::StringConst(_)) => {
+            let e = string_arg(vm, expected)?;
+            let a = string_arg(vm, actual)?;
+            let idx = e
+                .chars()
+                .zip(a.chars())
+                .position(|(ec, ac)| ec != ac)
+                .unwrap_or_else(|| e.chars().count().min(a.chars().count()));
+            Some(format!("strings differ at character {idx}"))
+        }
+        _ => None,
+    }
+}
+
+fn assert_equal(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let (expected, actual, msg) = match registers {
+        [expected, actual] => (*expected, *actual, None),
+        [expected, actual, msg] => (*expected, *actual, Some(msg)),
+        _ => {
+            return Err(VMError::new_vm(
+                "test::assert-equal: takes expected, actual and an optional message",
+            ))
+        }
+    };
+    if vm.is_equal_pair(expected, actual)?.is_true() {
+        Ok(Value::True)
+    } else {
+        let mut detail = format!(
+            "  expected: {}\n    actual: {}",
+            pretty_value(vm, expected),
+            pretty_value(vm, actual)
+        );
+        if expected.display_type(vm) != actual.display_type(vm) {
+            detail.push_str(&format!(
+                "\n     types: {} vs {}",
+                expected.display_type(vm),
+                actual.display_type(vm)
+            ));
+        }
+        if let Some(diff) = first_difference(vm, expected, actual) {
+            detail.push_str("\n  ");
+            detail.push_str(&diff);
+        }
+        Err(failure(vm, "assert-equal", detail, msg))
+    }
+}
+
+fn assert_not_equal(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let (expected, actual, msg) = match registers {
+        [expected, actual] => (*expected, *actual, None),
+        [expected, actual, msg] => (*expected, *actual, Some(msg)),
+        _ => {
+            return Err(VMError::new_vm(
+                "test::assert-not-equal: takes expected, actual and an optional message",
+            ))
+        }
+    };
+    if vm.is_equal_pair(expected, actual)?.is_true() {
+        let detail = format!("  both values: {}", pretty_value(vm, actual));
+        Err(failure(vm, "assert-not-equal", detail, msg))
+    } else {
+        Ok(Value::True)
+    }
+}
+
+fn assert_true(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let (val, msg) = match registers {
+        [val] => (*val, None),
+        [val, msg] => (*val, Some(msg)),
+        _ => {
+            return Err(VMError::new_vm(
+                "test::assert-true: takes a value and an optional message",
+            ))
+        }
+    };
+    if val.is_truethy() {
+        Ok(Value::True)
+    } else {
+        let detail = format!("  expected a true value, got: {}", pretty_value(vm, val));
+        Err(failure(vm, "assert-true", detail, msg))
+    }
+}
+
+fn assert_false(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    let (val, msg) = match registers {
+        [val] => (*val, None),
+        [val, msg] => (*val, Some(msg)),
+        _ => {
+            return Err(VMError::new_vm(
+                "test::assert-false: takes a value and an optional message",
+            ))
+        }
+    };
+    if val.is_falsey() {
+        Ok(Value::True)
+    } else {
+        let detail = format!("  expected a false value, got: {}", pretty_value(vm, val));
+        Err(failure(vm, "assert-false", detail, msg))
+    }
+}
+
+fn register(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [name, func] = registers {
+        if string_arg(vm, *name).is_none() {
+            return Err(VMError::new_vm(
+                "test::register: name must be a symbol or string",
+            ));
+        }
+        let group = get_named_global(vm, GROUP_GLOBAL);
+        let entry = vm.alloc_vector(vec![*name, group, *func]);
+        push_global(vm, TESTS_GLOBAL, entry)?;
+        Ok(*name)
+    } else {
+        Err(VMError::new_vm(
+            "test::register: takes a name and a function",
+        ))
+    }
+}
+
+fn add_fixture(vm: &mut SloshVm, kind: &'static str, registers: &[Value]) -> VMResult<Value> {
+    if let [func] = registers {
+        let group = get_named_global(vm, GROUP_GLOBAL);
+        let kind = Value::Keyword(vm.intern(kind));
+        let entry = vm.alloc_vector(vec![group, kind, *func]);
+        push_global(vm, FIXTURES_GLOBAL, entry)?;
+        Ok(*func)
+    } else {
+        Err(VMError::new_vm("test fixtures take one function"))
+    }
+}
+
+fn before_each(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    add_fixture(vm, "before", registers)
+}
+
+fn after_each(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    add_fixture(vm, "after", registers)
+}
+
+fn enter_group(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if let [name] = registers {
+        let name = string_arg(vm, *name)
+            .ok_or_else(|| VMError::new_vm("test::enter-group: name must be a string"))?;
+        if name.contains('/') {
+            // The current group is stored as the group names joined with /.
+            return Err(VMError::new_vm(format!(
+                "test::enter-group: group name {name} can not contain /"
+            )));
+        }
+        let group = match get_named_global(vm, GROUP_GLOBAL) {
+            Value::Nil => name,
+            outer => format!("{}/{}", pretty_value(vm, outer), name),
+        };
+        let group = vm.alloc_string(group);
+        vm.set_named_global(GROUP_GLOBAL, group);
+        Ok(group)
+    } else {
+        Err(VMError::new_vm("test::enter-group: takes one name"))
+    }
+}
+
+fn leave_group(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if !registers.is_empty() {
+        return Err(VMError::new_vm("test::leave-group: takes no arguments"));
+    }
+    let outer = match get_named_global(vm, GROUP_GLOBAL) {
+        Value::Nil => return Err(VMError::new_vm("test::leave-group: not in a group")),
+        group => match pretty_value(vm, group).rsplit_once('/') {
+            Some((outer, _)) => vm.alloc_string(outer.to_string()),
+            None => Value::Nil,
+        },
+    };
+    vm.set_named_global(GROUP_GLOBAL, outer);
+    Ok(Value::Nil)
+}
+
+/// Returns the tests registered with deftest in definition order.
+pub fn registered_tests(vm: &mut SloshVm) -> Vec<TestCase> {
+    let mut tests = Vec::new();
+    if let Value::Vector(h) = get_named_global(vm, TESTS_GLOBAL) {
+        for entry in vm.get_vector(h) {
+            if let Value::Vector(eh) = entry {
+                if let [name, group, func] = vm.get_vector(*eh) {
+                    tests.push(TestCase {
+                        name: string_arg(vm, *name).unwrap_or_default(),
+                        group: string_arg(vm, *group),
+                        func: *func,
+                    });
+                }
+            }
+        }
+    }
+    tests
+}
+
+/// Returns the fixtures that apply to tests in group.  Fixtures defined outside of any group
+/// apply to every test and fixtures of an outer group apply to tests in its inner groups.
+pub fn fixtures_for(vm: &mut SloshVm, group: Option<&str>) -> Fixtures {
+    let mut fixtures = Fixtures::default();
+    if let Value::Vector(h) = get_named_global(vm, FIXTURES_GLOBAL) {
+        for entry in vm.get_vector(h) {
+            if let Value::Vector(eh) = entry {
+                if let [fgroup, Value::Keyword(kind), func] = vm.get_vector(*eh) {
+                    let applies = match (string_arg(vm, *fgroup), group) {
+                        (None, _) => true,
+                        (Some(fgroup), Some(group)) => {
+                            group == fgroup || group.starts_with(&format!("{fgroup}/"))
+                        }
+                        (Some(_), None) => false,
+                    };
+                    if applies {
+                        if vm.get_interned(*kind) == "before" {
+                            fixtures.before.push(*func);
+                        } else {
+                            fixtures.after.push(*func);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    // Teardown runs innermost first.
+    fixtures.after.reverse();
+    fixtures
+}
+
+/// Call a function that takes no arguments (a test or fixture).
+pub fn call_thunk(vm: &mut SloshVm, func: Value) -> VMResult<Value> {
+    match func {
+        Value::Lambda(h) => {
+            let l = vm.get_lambda(h);
+            vm.do_call(l, &[], None)
+        }
+        Value::Closure(h) => {
+            let (l, tcaps) = vm.get_closure(h);
+            let caps = Vec::from(tcaps);
+            vm.do_call(l, &[], Some(&caps[..]))
+        }
+        _ => Err(VMError::new_vm(format!(
+            "not a function: {}",
+            pretty_value(vm, func)
+        ))),
+    }
+}
+
+pub fn add_test_builtins(env: &mut SloshVm) {
+    let tests = env.alloc_vector(Vec::new());
+    env.set_named_global(TESTS_GLOBAL, tests);
+    let fixtures = env.alloc_vector(Vec::new());
+    env.set_named_global(FIXTURES_GLOBAL, fixtures);
+    env.set_named_global(GROUP_GLOBAL, Value::Nil);
+
+    add_builtin(
+        env,
+        "test::assert-equal",
+        assert_equal,
+        "Usage: (test::assert-equal expected actual message?)
+
+Raise a :test error unless expected and actual are equal (as equal?).  The error
+shows both values and, for sequences and strings, where they first differ.
+
+Section: test
+
+Example:
+(test::assert-equal 3 (+ 1 2))
+(test::assert-equal '(1 2) (list 1 2) \"lists match\")
+(test::assert-error (test::assert-equal 1 2))
+",
+    );
+    add_builtin(
+        env,
+        "test::assert-not-equal",
+        assert_not_equal,
+        "Usage: (test::assert-not-equal expected actual message?)
+
+Raise a :test error if expected and actual are equal (as equal?).
+
+Section: test
+
+Example:
+(test::assert-not-equal 3 (+ 1 1))
+(test::assert-error (test::assert-not-equal 2 2))
+",
+    );
+    add_builtin(
+        env,
+        "test::assert-true",
+        assert_true,
+        "Usage: (test::assert-true value message?)
+
+Raise a :test error unless value is true (anything other than nil or #f).
+
+Section: test
+
+Example:
+(test::assert-true #t)
+(test::assert-true 1)
+(test::assert-error (test::assert-true nil))
+",
+    );
+    add_builtin(
+        env,
+        "test::assert-false",
+        assert_false,
+        "Usage: (test::assert-false value message?)
+
+Raise a :test error unless value is false (nil or #f).
+
+Section: test
+
+Example:
+(test::assert-false #f)
+(test::assert-false nil)
+(test::assert-error (test::assert-false 1))
+",
+    );
+    add_builtin(
+        env,
+        "test::register",
+        register,
+        "Usage: (test::register name function)
+
+Register a test function under name in the current test group.  Normally used
+through deftest.
+
+Section: test
+",
+    );
+    add_builtin(
+        env,
+        "test::before-each",
+        before_each,
+        "Usage: (test::before-each function)
+
+Register a fixture function to run before each test in the current group (or
+every test when used outside a group).
+
+Section: test
+
+Example:
+(def before-count 0)
+(test::before-each (fn () (inc! before-count)))
+",
+    );
+    add_builtin(
+        env,
+        "test::after-each",
+        after_each,
+        "Usage: (test::after-each function)
+
+Register a fixture function to run after each test in the current group (or
+every test when used outside a group).  It runs even if the test fails.
+
+Section: test
+
+Example:
+(test::after-each (fn () (prn \"test done\")))
+",
+    );
+    add_builtin(
+        env,
+        "test::enter-group",
+        enter_group,
+        "Usage: (test::enter-group name)
+
+Start a (possibly nested) test group, normally used through test::group.  The
+name can not contain /.
+
+Section: test
+",
+    );
+    add_builtin(
+        env,
+        "test::leave-group",
+        leave_group,
+        "Usage: (test::leave-group)
+
+End the current test group, normally used through test::group.
+
+Section: test
+",
+    );
+}
@@ -1,5 +1,6 @@
+use std::any::Any;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 use slvm::chunk::*;
@@ -28,6 +29,12 @@ pub struct Symbols {
     pub data: Rc<RefCell<SymbolsInt>>,
     outer: Option<Rc<RefCell<Symbols>>>,
     pub captures: Captures,
+    // Registers that have been read, shared by a function and its lets (for unused warnings).
+    used: Rc<RefCell<HashSet<usize>>>,
+    // Registers that change after a closure may have captured them so captures must be boxed.
+    mutated: Rc<RefCell<HashSet<usize>>>,
+    // Registers that are bound but not set yet (a capture now sees the value set later).
+    uninit: Rc<RefCell<HashSet<usize>>>,
 }
 
 impl Symbols {
@@ -40,6 +47,9 @@ impl Symbols {
             data,
             outer,
             captures: Rc::new(RefCell::new(Vec::new())),
+            used: Rc::new(RefCell::new(HashSet::new())),
+            mutated: Rc::new(RefCell::new(HashSet::new())),
+            uninit: Rc::new(RefCell::new(HashSet::new())),
         }
     }
 
@@ -58,6 +68,27 @@ impl Symbols {
             data,
             outer: source.borrow().outer.clone(),
             captures: source.borrow().captures.clone(),
+            used: source.borrow().used.clone(),
+            mutated: source.borrow().mutated.clone(),
+            uninit: source.borrow().uninit.clone(),
+        }
+    }
+
+    /// Scope for the body of an inlined function.  It sees none of the bindings of source (only
+    /// globals and what is inserted into it) but shares its register bookkeeping, the first new
+    /// binding will use first_reg.
+    pub fn with_inline(source: Rc<RefCell<Symbols>>, first_reg: usize) -> Symbols {
+        let data = Rc::new(RefCell::new(SymbolsInt {
+            syms: HashMap::new(),
+            count: first_reg,
+        }));
+        Symbols {
+            data,
+            outer: None,
+            captures: Rc::new(RefCell::new(Vec::new())),
+            used: source.borrow().used.clone(),
+            mutated: source.borrow().mutated.clone(),
+            uninit: source.borrow().uninit.clone(),
         }
     }
 
@@ -94,6 +125,58 @@ impl Symbols {
         None
     }
 
+    /// Record that the symbol in register reg has been read.
+    pub fn mark_used(&self, reg: usize) {
+        self.used.borrow_mut().insert(reg);
+    }
+
+    pub fn is_used(&self, reg: usize) -> bool {
+        self.used.borrow().contains(&reg)
+    }
+
+    /// Forget usage of registers from first_reg up (they are about to be reused).
+    pub fn clear_used(&self, first_reg: usize) {
+        self.used.borrow_mut().retain(|reg| *reg < first_reg);
+    }
+
+    /// Record that the local key is set after it was bound.  If it was captured from an outer
+    /// function then the binding there is marked as well.
+    pub fn mark_mutated(&self, key: Interned) {
+        if let Some(reg) = self.get(key) {
+            self.mark_reg_mutated(reg);
+            let captured = self
+                .captures
+                .borrow()
+                .iter()
+                .any(|(cap_key, cap_reg, _)| *cap_key == key && *cap_reg == reg);
+            if captured {
+                if let Some(outer) = &self.outer {
+                    outer.borrow().mark_mutated(key);
+                }
+            }
+        }
+    }
+
+    pub fn mark_reg_mutated(&self, reg: usize) {
+        self.mutated.borrow_mut().insert(reg);
+    }
+
+    /// The registers closures must box when they capture them, sorted.
+    pub fn mutated_regs(&self) -> Vec<u32> {
+        let mut regs: Vec<u32> = self.mutated.borrow().iter().map(|r| *r as u32).collect();
+        regs.sort_unstable();
+        regs
+    }
+
+    /// Mark reg as bound but not set yet (or clear that when it is set).
+    pub fn set_uninit(&self, reg: usize, uninit: bool) {
+        if uninit {
+            self.uninit.borrow_mut().insert(reg);
+        } else {
+            self.uninit.borrow_mut().remove(&reg);
+        }
+    }
+
     pub fn get(&self, key: Interned) -> Option<usize> {
         self.data.borrow().syms.get(&key).copied()
     }
@@ -125,6 +208,11 @@ impl Symbols {
     pub fn insert_capture(&self, _vm: &mut SloshVm, key: Interned) -> Option<usize> {
         let data_d = self.data.borrow();
         if let Some(idx) = data_d.syms.get(&key) {
+            self.mark_used(*idx);
+            if self.uninit.borrow().contains(idx) {
+                // Captured before it is set so the closure has to see the later set.
+                self.mark_reg_mutated(*idx);
+            }
             Some(*idx)
         } else {
             if let Some(outer) = &self.outer {
@@ -205,9 +293,18 @@ pub struct Specials {
     pub is_err: Interned,
     pub is_ok: Interned,
     pub ret: Interned,
+    pub match_: Interned,
+    pub defstruct: Interned,
+    pub definline: Interned,
+    pub values: Interned,
+    pub let_values: Interned,
+    pub case_: Interned,
+    pub hygienic_mac: Interned,
+    pub defconst: Interned,
 
     pub rest: Interned,
     pub optional: Interned,
+    pub key: Interned,
     pub scratch: Interned,
 }
 
@@ -381,9 +478,230 @@ Example:
             is_err: add_special(vm, "err?", ""),
             is_ok: add_special(vm, "ok?", ""),
             ret: add_special(vm, "return", ""),
+            match_: add_special(
+                vm,
+                "match",
+                "Usage: (match expression (pattern body*)+) or (match expression (pattern :when guard body*)+)
+
+Evaluate expression and match it against each clause's pattern in order.  The
+body of the first clause whose pattern matches (and whose guard, if any, is
+true) is evaluated with the pattern's symbols bound, its result is the result of
+the match.  It is an error (:match) if no clause matches.
+
+Patterns:
+- _ matches anything without binding it.
+- A symbol matches anything and binds it to the value.
+- A literal (number, string, char, keyword, nil, #t, #f or a quoted form)
+  matches a value that is equal? to it.
+- [p1 p2 .. pN] matches a vector of exactly N items that match p1..pN.
+- (p1 p2 .. pN) matches a list of exactly N items that match p1..pN.
+- Either sequence pattern can end with & rest to match N or more items (rest is
+  bound to a list of the leftovers) or just & to ignore them.
+- {p1 key1 .. pN keyN} matches a map containing each key with a value that
+  matches its pattern.
+
+Section: core
+
+Example:
+(def describe (fn (x)
+  (match x
+    (0 :zero)
+    (n :when (and (eq? (type n) :Int) (< n 0)) :negative)
+    ([a b] (list :pair a b))
+    ((a & rest) (list :list a rest))
+    ({v :value} (list :map v))
+    (_ :other))))
+(test::assert-equal :zero (describe 0))
+(test::assert-equal :negative (describe -5))
+(test::assert-equal '(:pair 1 2) (describe [1 2]))
+(test::assert-equal '(:list 1 (2 3)) (describe '(1 2 3)))
+(test::assert-equal '(:map 3) (describe {:value 3}))
+(test::assert-equal :other (describe \"str\"))
+(test::assert-error (match 1 (2 :two)))
+",
+            ),
+            defstruct: add_special(
+                vm,
+                "defstruct",
+                "Usage: (defstruct name field*)
+
+Define a struct (record) type called name with a fixed set of fields.  This
+defines the functions:
+- (make-name field*) make a new name with the fields in order.
+- (name? obj) true if obj is a name.
+- (name-field obj) return field of obj for each field.
+- (name-set-field! obj value) set field of obj to value (returns value) for
+  each field.
+Calls to these functions are compiled to indexed field access.  Each time
+defstruct is evaluated it creates a new type.  Structs are compared field by
+field with equal?, type returns the name as a keyword and they print as
+#S(name :field value ..).  As hash map keys structs (like strings and other heap
+objects) use identity, an equal? struct is a different key.
+
+Section: struct
+
+Example:
+(defstruct point x y)
+(def p (make-point 1 2))
+(test::assert-true (point? p))
+(test::assert-false (point? [1 2]))
+(test::assert-equal 1 (point-x p))
+(test::assert-equal 10 (point-set-y! p 10))
+(test::assert-equal 10 (point-y p))
+(test::assert-equal :point (type p))
+(test::assert-true (equal? (make-point 1 10) p))
+(def point-names (make-hash))
+(set! (get point-names p) \"p\")
+(test::assert-equal \"p\" (get point-names p))
+(test::assert-equal :none (point-names (make-point 1 10) :none))
+(test::assert-error (point-x [1 2]))
+",
+            ),
+            definline: add_special(
+                vm,
+                "definline",
+                "Usage: (definline name argument_list body)
+
+Define a global function (like defn) that the compiler expands in place where
+it is called so no call frame is made, use it for small helpers.  Each argument
+is evaluated once, in order, before the body and the body only sees its
+parameters and globals (not the locals of the caller).  The parameters must be
+symbols (no optional, rest or destructuring) and the body can not use recur,
+this-fn or return.  A call with the wrong number of arguments or a call to name
+from inside its own expansion is a normal call.
+
+Code compiled while name is inline has the body expanded in it so once a call
+has been expanded redefining name (with def, set!, defconst or a different
+definline) is a compile error.  Defining it again with the same params and body
+(loading a file twice) is allowed.  If no call has been expanded redefining
+name just ends inlining.
+
+Section: core
+
+Example:
+(definline inline-test (x y) (+ x y))
+(test::assert-equal 5 (inline-test 2 3))
+(def inline-test-caller (fn (x) (inline-test x 1)))
+(test::assert-equal 3 (inline-test-caller 2))
+(test::assert-error (eval '(def inline-test (fn (x y) (* x y)))))
+(definline inline-test (x y) (+ x y))
+(test::assert-equal 3 (inline-test-caller 2))
+",
+            ),
+            values: add_special(
+                vm,
+                "values",
+                "Usage: (values expression*)
+
+Evaluate each expression and produce all of them as separate values (no list or
+vector is made).  Where only one value is wanted (anything but let-values) this
+is the first value or nil if there are none.  As the last form of a function the
+values are returned to the caller.  Use let-values to receive them.
+
+Section: core
+
+Example:
+(test::assert-equal 1 (values 1 2 3))
+(test::assert-equal nil (values))
+(def values-test (fn (x) (values (+ x 1) (- x 1))))
+(test::assert-equal 3 (values-test 2))
+(test::assert-equal '(3 1) (let-values ((a b) (values-test 2)) (list a b)))
+",
+            ),
+            let_values: add_special(
+                vm,
+                "let-values",
+                "Usage: (let-values ((name*) expression*) body*)
+
+Like let but each expression is bound to a list of names that receive the
+values it produces with values.  The expression should be a call or a values
+form, the values from a function that ends with values (or a tail call to one)
+are passed straight through.  A call or values form that is the result of the
+expression (for instance a branch of if or case or the last form of let or do)
+also produces its values.  Any other expression produces one value, names
+without a value are nil and extra values are ignored.  Each expression can use
+the names bound before it.
+
+Section: core
+
+Example:
+(def let-values-test (fn (x) (values (* x 2) (* x 3))))
+(test::assert-equal '(4 6) (let-values ((a b) (let-values-test 2)) (list a b)))
+(test::assert-equal '(1 2 nil) (let-values ((a b c) (values 1 2)) (list a b c)))
+(test::assert-equal '(5 nil 10) (let-values ((a b) 5, (c) (* a 2)) (list a b c)))
+(test::assert-equal '(3 4) (let-values ((a b) (if nil (values 1 2) (values 3 4))) (list a b)))
+",
+            ),
+            case_: add_special(
+                vm,
+                "case",
+                "Usage: (case expression (key body*)+) or (case expression (key body*)+ (_ body*))
+
+Evaluate expression and then the body of the clause with a key equal to it (the
+result is the result of the body).  A key is an integer, char, keyword or symbol
+(not evaluated) or a list of them to share a body.  The last clause can use _ as
+its key to handle anything else, otherwise the result is nil if no key matches.
+Keys can not be repeated.  With many keys this jumps straight to the clause
+(using a table) instead of testing each key in turn.
+
+Section: core
+
+Example:
+(def case-test (fn (x) (case x (1 :one) ((2 3) :two-three) (\\a :a) (:b :b) (c 'c) (_ :other))))
+(test::assert-equal :one (case-test 1))
+(test::assert-equal :two-three (case-test 3))
+(test::assert-equal :a (case-test \\a))
+(test::assert-equal :b (case-test :b))
+(test::assert-equal 'c (case-test 'c))
+(test::assert-equal :other (case-test \"c\"))
+(test::assert-equal nil (case 5 (1 :one)))
+",
+            ),
+            hygienic_mac: add_special(
+                vm,
+                "hygienic-macro",
+                "Usage: (hygienic-macro (args) body*)
+
+Like macro but the names a back-quote template in body binds itself (the
+params of a fn or macro and the names of a let or let-values written in the
+template, not unquoted) are replaced with a new gensym each time the macro is
+expanded.  These bindings can then not capture (or be hidden by) variables in
+the code given to the macro.  A name is only replaced inside the form that
+binds it, uses outside that form, quoted symbols and def targets are left alone.
+
+Section: core
+
+Example:
+(def hygienic-swap (hygienic-macro (a b) `(let (tmp ~a) (set! ~a ~b) (set! ~b tmp))))
+(test::assert-equal '(2 1) (let (tmp 1 other 2) (hygienic-swap tmp other) (list tmp other)))
+(def plain-swap (macro (a b) `(let (tmp ~a) (set! ~a ~b) (set! ~b tmp))))
+(test::assert-equal '(1 2) (let (tmp 1 other 2) (plain-swap tmp other) (list tmp other)))
+",
+            ),
+            defconst: add_special(
+                vm,
+                "defconst",
+                "Usage: (defconst name value)
+
+Define the global name as a constant with value.  A constant can not be changed
+later, def, set! or another defconst of it is a compile error (or a runtime
+error if the compiler could not tell).  References to a constant number, char,
+symbol, keyword or boolean are compiled to the value itself.
+
+Section: core
+
+Example:
+(defconst defconst-test 10)
+(test::assert-equal 20 (+ defconst-test 10))
+(test::assert-true (constant? 'defconst-test))
+(test::assert-error (eval '(set! defconst-test 1)))
+(test::assert-equal 10 defconst-test)
+",
+            ),
 
             rest: vm.intern_static("&"),
             optional: vm.intern_static("%"),
+            key: vm.intern_static("&key"),
             scratch: vm.intern_static("[SCRATCH]"),
         }
     }
@@ -407,6 +725,11 @@ pub struct CompileState {
     pub tail: bool,
     pub defers: usize,
     pub doc_string: Option<Value>,
+    /// Compiling the body of a hygienic-macro, back-quotes rename the bindings they introduce.
+    pub hygienic: bool,
+    /// Registers (first, count) of the let-values names whose value is being compiled.  While
+    /// tail is set the result is that value, values and calls there store into these names.
+    pub values_target: Option<(usize, usize)>,
 }
 
 impl Default for CompileState {
@@ -425,6 +748,8 @@ impl CompileState {
             tail: false,
             defers: 0,
             doc_string: None,
+            hygienic: false,
+            values_target: None,
         }
     }
 
@@ -442,6 +767,8 @@ impl CompileState {
             tail: false,
             defers: 0,
             doc_string: None,
+            hygienic: false,
+            values_target: None,
         }
     }
 
@@ -453,6 +780,12 @@ impl CompileState {
         self.symbols.borrow().data.borrow().syms.get(&sym).copied()
     }
 
+    /// Record the registers that closures created in this chunk must box when captured.  Call
+    /// when a scope ends (all the code that could change its bindings has been compiled).
+    pub fn set_boxed_regs(&mut self) {
+        self.chunk.boxed_regs = self.symbols.borrow().mutated_regs();
+    }
+
     pub fn add_constant(&mut self, exp: Value) -> usize {
         if let Some(i) = self.constants.get(&exp) {
             *i
@@ -464,12 +797,47 @@ impl CompileState {
     }
 }
 
+/// A problem the compiler found that does not stop compilation (an unused binding for instance).
+/// Line and column are 1 based, 0 if not known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileWarning {
+    pub file_name: &'static str,
+    pub line: u32,
+    pub column: u32,
+    pub message: String,
+}
+
+/// A function defined with definline, calls to it are compiled by expanding body in place.  The
+/// forms in body are sticky on the heap while they are held here.
+#[derive(Clone, Debug)]
+pub struct InlineFn {
+    pub params: Vec<Interned>,
+    pub body: Vec<Value>,
+    /// File and line of each call that was expanded (this body is compiled in there).
+    pub call_sites: Vec<(&'static str, u32)>,
+}
+
 pub struct CompileEnvironment {
     use_line: bool,
     line: u32,
+    col: u32,
     specials: Option<Specials>,
     global_map: HashMap<Interned, usize>,
     gensym_idx: usize,
+    // Input of the reader that is currently running a reader macro (owned by the reader).
+    reader_input: Option<Box<dyn Any>>,
+    warnings: Vec<CompileWarning>,
+    warnings_as_errors: bool,
+    // Compile checks of annotated param and return types that run when functions are called.
+    check_types: bool,
+    // References to globals that had no value when compiled, checked once the code has run.
+    undefined_refs: Vec<(u32, CompileWarning)>,
+    // Global slots that a compiled def will set (maybe when a function is called).
+    defined_globals: HashSet<u32>,
+    // Functions defined with definline by global slot.
+    inline_fns: HashMap<u32, InlineFn>,
+    // Slots of the inline functions currently being expanded (innermost last).
+    inlining: Vec<u32>,
 }
 
 impl Default for CompileEnvironment {
@@ -483,9 +851,18 @@ impl CompileEnvironment {
         Self {
             use_line: true,
             line: 1,
+            col: 0,
             specials: None,
             global_map: HashMap::new(),
             gensym_idx: 0,
+            reader_input: None,
+            warnings: Vec::new(),
+            warnings_as_errors: false,
+            check_types: false,
+            undefined_refs: Vec::new(),
+            defined_globals: HashSet::new(),
+            inline_fns: HashMap::new(),
+            inlining: Vec::new(),
         }
     }
 
@@ -502,6 +879,102 @@ impl CompileEnvironment {
     pub fn global_defined(&self, i: Interned) -> bool {
         self.global_map.contains_key(&i)
     }
+
+    pub fn add_warning(&mut self, warning: CompileWarning) {
+        self.warnings.push(warning);
+    }
+
+    /// Remove and return the warnings produced since the last call.
+    pub fn take_warnings(&mut self) -> Vec<CompileWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn warnings_as_errors(&self) -> bool {
+        self.warnings_as_errors
+    }
+
+    /// If set then code that produces warnings is not run (load and the REPL fail instead).
+    pub fn set_warnings_as_errors(&mut self, warnings_as_errors: bool) {
+        self.warnings_as_errors = warnings_as_errors;
+    }
+
+    pub fn check_types(&self) -> bool {
+        self.check_types
+    }
+
+    /// If set then functions compiled from now on check the types of their annotated params and
+    /// return value when called (an error if they do not match).
+    pub fn set_check_types(&mut self, check_types: bool) {
+        self.check_types = check_types;
+    }
+
+    /// Remember a reference to the global in slot that has no value yet.
+    pub fn add_undefined_ref(&mut self, slot: u32, warning: CompileWarning) {
+        if !self.defined_globals.contains(&slot) {
+            self.undefined_refs.push((slot, warning));
+        }
+    }
+
+    /// Number of pending undefined references, use with drain_undefined_refs() to only check
+    /// references made after this point.
+    pub fn undefined_refs_len(&self) -> usize {
+        self.undefined_refs.len()
+    }
+
+    /// Remove and return the pending undefined references from start on.
+    pub fn drain_undefined_refs(&mut self, start: usize) -> Vec<(u32, CompileWarning)> {
+        let start = start.min(self.undefined_refs.len());
+        self.undefined_refs.drain(start..).collect()
+    }
+
+    /// Record that the global in slot has a def compiled for it.
+    pub fn set_global_defined(&mut self, slot: u32) {
+        self.defined_globals.insert(slot);
+    }
+
+    pub fn global_has_def(&self, slot: u32) -> bool {
+        self.defined_globals.contains(&slot)
+    }
+
+    pub fn inline_fn(&self, slot: u32) -> Option<&InlineFn> {
+        self.inline_fns.get(&slot)
+    }
+
+    /// Make the global in slot inline, returns the function it replaces if it was already inline.
+    pub fn set_inline_fn(&mut self, slot: u32, inline_fn: InlineFn) -> Option<InlineFn> {
+        self.inline_fns.insert(slot, inline_fn)
+    }
+
+    /// Note that a call to the inline function in slot was expanded at file and line.
+    pub fn add_inline_call_site(&mut self, slot: u32, file: &'static str, line: u32) {
+        if let Some(inline_fn) = self.inline_fns.get_mut(&slot) {
+            inline_fn.call_sites.push((file, line));
+        }
+    }
+
+    /// Global in slot is no longer inline, returns the function if it was.
+    pub fn remove_inline_fn(&mut self, slot: u32) -> Option<InlineFn> {
+        self.inline_fns.remove(&slot)
+    }
+
+    /// Is the inline function in slot being expanded (a call to it now is recursive)?
+    pub fn is_inlining(&self, slot: u32) -> bool {
+        self.inlining.contains(&slot)
+    }
+
+    pub fn push_inlining(&mut self, slot: u32) {
+        self.inlining.push(slot);
+    }
+
+    pub fn pop_inlining(&mut self) {
+        self.inlining.pop();
+    }
+
+    /// Replace the input of the active reader macro (if any) with input, returning the old one.
+    /// The reader parks its input here while calling a reader macro so builtins can consume it.
+    pub fn swap_reader_input(&mut self, input: Option<Box<dyn Any>>) -> Option<Box<dyn Any>> {
+        std::mem::replace(&mut self.reader_input, input)
+    }
 }
 
 pub type SloshVm = GVm<CompileEnvironment>;
@@ -516,6 +989,8 @@ pub trait SloshVmTrait {
     fn own_line(&self) -> Option<u32>;
     fn set_line_num(&mut self, line_num: u32);
     fn line_num(&self) -> u32;
+    /// Column of the last form compiled on line_num(), 0 if not known.
+    fn column_num(&self) -> u32;
     fn specials(&self) -> &Specials;
     fn global_intern_slot(&self, symbol: Interned) -> Option<u32>;
 }
@@ -535,8 +1010,29 @@ impl SloshVmTrait for SloshVm {
             self.get_heap_property(val, "dbg-file"),
         ) {
             let file_name = self.get_interned(file_intern);
-            if file_name == state.chunk.file_name && dline > self.env().line {
-                self.env_mut().line = dline;
+            let dcol = if let Some(Value::UInt32(dcol)) = self.get_heap_property(val, "dbg-col") {
+                dcol
+            } else {
+                0
+            };
+            if file_name == state.chunk.file_name {
+                let span =
+                    if let Some(Value::UInt32(span)) = self.get_heap_property(val, "dbg-span") {
+                        span
+                    } else {
+                        0
+                    };
+                state.chunk.set_dbg_pos(Some(SourcePos {
+                    line: dline,
+                    column: dcol,
+                    span,
+                }));
+                if dline > self.env().line {
+                    self.env_mut().line = dline;
+                    self.env_mut().col = dcol;
+                } else if dline == self.env().line && dcol > self.env().col {
+                    self.env_mut().col = dcol;
+                }
             }
         }
     }
@@ -596,6 +1092,7 @@ impl SloshVmTrait for SloshVm {
     fn set_line_num(&mut self, line_num: u32) {
         if self.env().use_line {
             self.env_mut().line = line_num;
+            self.env_mut().col = 0;
         }
     }
 
@@ -607,6 +1104,14 @@ impl SloshVmTrait for SloshVm {
         }
     }
 
+    fn column_num(&self) -> u32 {
+        if self.env().use_line {
+            self.env().col
+        } else {
+            0
+        }
+    }
+
     fn specials(&self) -> &Specials {
         self.env().specials.as_ref().expect("specials are missing!")
     }
@@ -0,0 +1,59 @@
+//! Closure heavy code where the captures are never set! (so they are copied into the closure) vs
+//! the same code with the captures forced into boxes (by a set! that never runs).
+//! Run with: cargo bench -p sl-compiler --bench captures
+
+use std::sync::Arc;
+
+use compile_state::state::{new_slosh_vm, CompileState, SloshVm};
+use criterion::{criterion_group, criterion_main, Criterion};
+use sl_compiler::pass1::pass1;
+use sl_compiler::{compile, Reader};
+use slvm::{Chunk, Value, RET};
+
+const COPIED: &str = "(let (total 0, i 0)
+    (while (< i 10000)
+      (let (a i, b 1, c 2, f (fn () (- (+ a b c) a c)))
+        (set! total (+ total (f))))
+      (inc! i))
+    total)";
+
+const BOXED: &str = "(let (total 0, i 0)
+    (while (< i 10000)
+      (let (a i, b 1, c 2, f (fn () (- (+ a b c) a c)))
+        (if #f (do (set! a 0) (set! b 0) (set! c 0)))
+        (set! total (+ total (f))))
+      (inc! i))
+    total)";
+
+/// Compile src (one form) to a chunk that returns its value.
+fn compile_src(vm: &mut SloshVm, src: &str) -> Arc<Chunk> {
+    let exp = Reader::from_string(src.to_string(), vm, "bench", 1, 0)
+        .next()
+        .expect("no form")
+        .expect("read failed");
+    vm.heap_sticky(exp);
+    let mut state = CompileState::new();
+    pass1(vm, &mut state, exp).expect("pass1 failed");
+    compile(vm, &mut state, exp, 0).expect("compile failed");
+    state.chunk.encode0(RET, Some(1)).expect("compile failed");
+    let chunk = Arc::new(state.chunk);
+    // Keep the chunk's constants from being collected.
+    let lambda = vm.alloc_lambda(chunk.clone());
+    vm.heap_sticky(lambda);
+    chunk
+}
+
+fn bench_captures(c: &mut Criterion) {
+    let mut group = c.benchmark_group("captures");
+    for (name, src) in [("copied", COPIED), ("boxed", BOXED)] {
+        let mut vm = new_slosh_vm();
+        let chunk = compile_src(&mut vm, src);
+        let result = vm.execute(chunk.clone()).expect("execute failed");
+        assert_eq!(result, Value::UInt32(10000));
+        group.bench_function(name, |b| b.iter(|| vm.execute(chunk.clone())));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_captures);
+criterion_main!(benches);
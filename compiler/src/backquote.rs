@@ -284,6 +284,205 @@ fn qq_expand_list(vm: &mut SloshVm, exp: Value, line: u32, depth: u32) -> VMResu
     }
 }
 
+/// Is exp a tagged form (unquote, splice or a nested back-quote), the hygiene walks stop at these.
+fn is_tagged(vm: &SloshVm, tag: &Tag, exp: Value) -> bool {
+    tag.is_unquote(vm, exp)
+        || tag.is_splice(vm, exp)
+        || tag.is_splice_bang(vm, exp)
+        || tag.is_backquote(vm, exp)
+}
+
+/// Add the symbols in a binding name or destructure pattern to names (not the defaults after :=
+/// or unquoted parts).
+fn pattern_names(vm: &SloshVm, tag: &Tag, pattern: Value, names: &mut Vec<Interned>) {
+    if is_tagged(vm, tag, pattern) {
+        return;
+    }
+    let specials = vm.specials();
+    match pattern {
+        Value::Symbol(i) if i == specials.rest || i == specials.optional || i == specials.key => {}
+        Value::Symbol(i) if !names.contains(&i) => names.push(i),
+        Value::Vector(_) | Value::Pair(_) | Value::List(_, _) => {
+            let mut is_default = false;
+            for item in pattern.iter(vm) {
+                if !is_default {
+                    pattern_names(vm, tag, item, names);
+                }
+                is_default = item == Value::Keyword(vm.specials().numeq);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Names bound by a fn or macro param list (including &key (name default) params).
+fn param_names(vm: &SloshVm, tag: &Tag, params: Value) -> Vec<Interned> {
+    let mut names = Vec::new();
+    for param in params.iter(vm) {
+        match param {
+            Value::Pair(_) | Value::List(_, _) if !is_tagged(vm, tag, param) => {
+                if let Some((name, _)) = param.get_pair(vm) {
+                    pattern_names(vm, tag, name, &mut names);
+                }
+            }
+            _ => pattern_names(vm, tag, param, &mut names),
+        }
+    }
+    names
+}
+
+/// Gensym'd names for a hygienic template, each is (name, local, ~local) where local is set to
+/// the gensym when the back-quote runs.
+struct Renames {
+    tag: Tag,
+    locals: Vec<(Interned, Value, Value)>,
+}
+
+impl Renames {
+    /// Scope with names (bound by a form in the template) added to scope.
+    fn bind(
+        &mut self,
+        vm: &mut SloshVm,
+        scope: &[(Interned, Value)],
+        names: &[Interned],
+    ) -> Vec<(Interned, Value)> {
+        let mut scope = scope.to_vec();
+        for name in names {
+            let unquoted = match self.locals.iter().find(|(n, _, _)| n == name) {
+                Some((_, _, unquoted)) => *unquoted,
+                None => {
+                    let local = format!("#<hygiene:{}>", vm.get_interned(*name));
+                    let local = Value::Symbol(vm.intern(&local));
+                    let unquoted = vm.alloc_list_ro(vec![Value::Symbol(self.tag.unquote), local]);
+                    self.locals.push((*name, local, unquoted));
+                    unquoted
+                }
+            };
+            scope.push((*name, unquoted));
+        }
+        scope
+    }
+
+    /// Rename the literal symbols in the template exp that are bound in scope or by binding
+    /// forms (fn, macro, let, let-values) in exp, only where those bindings are visible.
+    /// Quoted data, def targets and unquoted code are left alone.
+    fn rename(&mut self, vm: &mut SloshVm, exp: Value, scope: &[(Interned, Value)]) -> Value {
+        if is_tagged(vm, &self.tag, exp) {
+            return exp;
+        }
+        match exp {
+            Value::Symbol(i) => scope
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == i)
+                .map(|(_, to)| *to)
+                .unwrap_or(exp),
+            Value::Pair(_) | Value::List(_, _) => {
+                let (car, _) = exp.get_pair(vm).expect("Pair/List not a Pair or List?");
+                let specials = vm.specials();
+                let head = match car {
+                    Value::Symbol(i) => i,
+                    _ => return self.rename_pair(vm, exp, scope),
+                };
+                if head == specials.quote {
+                    return exp;
+                }
+                let items: Vec<Value> = exp.iter(vm).collect();
+                let items =
+                    if (head == specials.def || head == specials.defconst) && items.len() > 1 {
+                        let mut new_items = items[..2].to_vec();
+                        for item in &items[2..] {
+                            new_items.push(self.rename(vm, *item, scope));
+                        }
+                        new_items
+                    } else if (head == specials.fn_
+                        || head == specials.mac_
+                        || head == specials.hygienic_mac)
+                        && items.len() > 1
+                        && !is_tagged(vm, &self.tag, items[1])
+                    {
+                        let names = param_names(vm, &self.tag, items[1]);
+                        let scope = self.bind(vm, scope, &names);
+                        let mut new_items = vec![items[0]];
+                        for item in &items[1..] {
+                            new_items.push(self.rename(vm, *item, &scope));
+                        }
+                        new_items
+                    } else if (head == specials.let_ || head == specials.let_values)
+                        && items.len() > 1
+                        && matches!(items[1], Value::Pair(_) | Value::List(_, _))
+                        && !is_tagged(vm, &self.tag, items[1])
+                    {
+                        // Each value sees the bindings before it, the body sees all of them.
+                        let bindings: Vec<Value> = items[1].iter(vm).collect();
+                        let mut scope = scope.to_vec();
+                        let mut new_bindings = Vec::with_capacity(bindings.len());
+                        for binding in bindings.chunks(2) {
+                            let value = binding.get(1).map(|v| self.rename(vm, *v, &scope));
+                            let mut names = Vec::new();
+                            pattern_names(vm, &self.tag, binding[0], &mut names);
+                            scope = self.bind(vm, &scope, &names);
+                            new_bindings.push(self.rename(vm, binding[0], &scope));
+                            new_bindings.extend(value);
+                        }
+                        let mut new_items = vec![items[0], vm.alloc_list_ro(new_bindings)];
+                        for item in &items[2..] {
+                            new_items.push(self.rename(vm, *item, &scope));
+                        }
+                        new_items
+                    } else {
+                        return self.rename_pair(vm, exp, scope);
+                    };
+                vm.alloc_list_ro(items)
+            }
+            Value::Vector(h) => {
+                let items = vm.get_vector(h).to_vec();
+                let new_items: Vec<Value> =
+                    items.iter().map(|v| self.rename(vm, *v, scope)).collect();
+                if new_items != items {
+                    vm.alloc_vector_ro(new_items)
+                } else {
+                    exp
+                }
+            }
+            _ => exp,
+        }
+    }
+
+    /// Rename the car and cdr of exp (a form that binds nothing).
+    fn rename_pair(&mut self, vm: &mut SloshVm, exp: Value, scope: &[(Interned, Value)]) -> Value {
+        let (car, cdr) = exp.get_pair(vm).expect("Pair/List not a Pair or List?");
+        let new_car = self.rename(vm, car, scope);
+        let new_cdr = self.rename(vm, cdr, scope);
+        if new_car != car || new_cdr != cdr {
+            vm.alloc_pair_ro(new_car, new_cdr)
+        } else {
+            exp
+        }
+    }
+}
+
+/// Make the template exp hygienic, each name it binds itself becomes (within the scope of that
+/// binding) an unquoted local that is set to a new gensym when the back-quote runs:
+/// `(do (let (x ~v) x) x)` -> (let (#<hygiene:x> (gensym 'x)) `(do (let (~#<hygiene:x> ~v) ~#<hygiene:x>) x))
+/// Returns the bindings for the wrapping let and the renamed template.
+fn hygienic(vm: &mut SloshVm, exp: Value) -> (Vec<Value>, Value) {
+    let mut renames = Renames {
+        tag: Tag::new(vm),
+        locals: Vec::new(),
+    };
+    let exp = renames.rename(vm, exp, &[]);
+    let gensym = Value::Symbol(vm.intern_static("gensym"));
+    let mut bindings = Vec::new();
+    for (name, local, _) in renames.locals {
+        let quoted = quote(vm, Value::Symbol(name));
+        let gensym_call = vm.alloc_list_ro(vec![gensym, quoted]);
+        bindings.push(local);
+        bindings.push(gensym_call);
+    }
+    (bindings, exp)
+}
+
 pub fn backquote(
     env: &mut SloshVm,
     state: &mut CompileState,
@@ -292,7 +491,19 @@ pub fn backquote(
 ) -> VMResult<()> {
     env.pause_gc();
     let line = env.line_num();
+    let (bindings, exp) = if state.hygienic {
+        hygienic(env, exp)
+    } else {
+        (Vec::new(), exp)
+    };
     let result = qq_expand(env, exp, line, 0).and_then(|expand| {
+        let expand = if bindings.is_empty() {
+            expand
+        } else {
+            let let_ = Value::Symbol(env.specials().let_);
+            let bindings = env.alloc_list_ro(bindings);
+            env.alloc_list_ro(vec![let_, bindings, expand])
+        };
         // XXX disable line numbering?
         pass1(env, state, expand).and_then(|_| compile(env, state, expand, result))
     });
@@ -1,28 +1,39 @@
 use slvm::error::*;
 use slvm::opcodes::*;
 use slvm::value::*;
-use slvm::Handle;
 
 use crate::backquote::*;
 use crate::compile::compile_call::{
     compile_call, compile_call_myself, compile_call_reg, compile_callg,
 };
-use crate::compile::compile_cond::{compile_and, compile_if, compile_or, compile_while};
+use crate::compile::compile_cond::{
+    compile_and, compile_case, compile_if, compile_or, compile_while,
+};
 use crate::compile::compile_fn::compile_fn;
+use crate::compile::compile_inline::{compile_definline, compile_inline_call};
 use crate::compile::compile_let::compile_let;
+use crate::compile::compile_match::compile_match;
 use crate::compile::compile_math::compile_math;
 use crate::compile::compile_seq::{compile_cons, compile_vec};
-use crate::compile::compile_store::{compile_def, compile_set};
+use crate::compile::compile_store::{compile_def, compile_defconst, compile_set};
+use crate::compile::compile_struct::{compile_defstruct, compile_struct_call};
+use crate::compile::compile_values::{compile_let_values, compile_values};
 use crate::pass1::pass1;
+use crate::warnings::{check_arg_types, check_arity, note_undefined};
 use compile_state::state::*;
 
 mod compile_call;
 mod compile_cond;
 pub mod compile_fn;
+mod compile_inline;
 mod compile_let;
+mod compile_match;
 mod compile_math;
 mod compile_seq;
 mod compile_store;
+mod compile_struct;
+pub(crate) mod compile_types;
+mod compile_values;
 mod destructure;
 mod util;
 
@@ -68,6 +79,17 @@ fn compile_special(
                     return Err(VMError::new_compile("Malformed macro form."));
                 }
             }
+            Value::Special(i) if i == env.specials().hygienic_mac => {
+                if cdr.len() > 1 {
+                    let hygienic = state.hygienic;
+                    state.hygienic = true;
+                    let res = compile_fn(env, state, cdr[0], &cdr[1..], result, true);
+                    state.hygienic = hygienic;
+                    res?
+                } else {
+                    return Err(VMError::new_compile("Malformed hygienic-macro form."));
+                }
+            }
             Value::Special(i) if i == env.specials().if_ => {
                 compile_if(env, state, cdr, result)?;
             }
@@ -94,6 +116,14 @@ fn compile_special(
                 state.tail = false;
                 compile_def(env, state, cdr, result)?;
             }
+            Value::Special(i) if i == env.specials().defconst => {
+                state.tail = false;
+                compile_defconst(env, state, cdr, result)?;
+            }
+            Value::Special(i) if i == env.specials().definline => {
+                state.tail = false;
+                compile_definline(env, state, cdr, result)?;
+            }
             Value::Special(i) if i == env.specials().set => {
                 state.tail = false;
                 compile_set(env, state, cdr, result)?;
@@ -229,6 +259,21 @@ fn compile_special(
             Value::Special(i) if i == env.specials().let_ => {
                 compile_let(env, state, cdr, result)?;
             }
+            Value::Special(i) if i == env.specials().match_ => {
+                compile_match(env, state, cdr, result)?;
+            }
+            Value::Special(i) if i == env.specials().defstruct => {
+                compile_defstruct(env, state, cdr, result)?;
+            }
+            Value::Special(i) if i == env.specials().case_ => {
+                compile_case(env, state, cdr, result)?;
+            }
+            Value::Special(i) if i == env.specials().values => {
+                compile_values(env, state, cdr, result)?;
+            }
+            Value::Special(i) if i == env.specials().let_values => {
+                compile_let_values(env, state, cdr, result)?;
+            }
             Value::Special(i) if i == env.specials().call_cc => {
                 if cdr.len() != 1 {
                     return Err(VMError::new_compile("Requires one argument."));
@@ -356,15 +401,20 @@ fn compile_list(
     match car {
         Value::Symbol(i) => {
             if let Some(idx) = state.get_symbol(i) {
+                state.symbols.borrow().mark_used(idx);
                 compile_call_reg(env, state, idx as u16, cdr, result)?
             } else if let Some(slot) = env.global_intern_slot(i) {
                 // Have to at least pre-declare a global.
                 let global = env.get_global(slot);
                 if let Value::Undefined = global {
-                    eprintln!("Warning: {} not defined.", env.get_interned(i));
+                    note_undefined(env, state, i, slot);
                 }
-                if let Value::Special(_) = global {
+                if let Value::Special(si) = global {
                     compile_special(env, state, global, cdr, result)?;
+                    if si == env.specials().doc_string {
+                        // Keep the doc string for the form that follows it.
+                        return Ok(());
+                    }
                 } else if is_macro(env, global) {
                     let (mac, caps) = match global {
                         Value::Lambda(h) => (env.get_lambda(h), None),
@@ -372,7 +422,7 @@ fn compile_list(
                             let (mac, caps) = env.get_closure(h);
                             // Closures are read only so lets just break the lifetime away vs
                             // allocate the same thing again...
-                            let caps = unsafe { (caps as *const [Handle]).as_ref().unwrap() };
+                            let caps = unsafe { (caps as *const [Value]).as_ref().unwrap() };
                             (mac, Some(caps))
                         }
                         _ => panic!("Invalid macro!"),
@@ -382,7 +432,11 @@ fn compile_list(
                     env.unpause_gc();
                     pass1(env, state, exp)?;
                     compile(env, state, exp, result)?
-                } else {
+                } else if compile_inline_call(env, state, slot, cdr, result)? {
+                    // Body was expanded in place, no call.
+                } else if !compile_struct_call(env, state, i, global, cdr, result)? {
+                    check_arity(env, state, i, global, cdr.len());
+                    check_arg_types(env, state, i, global, cdr);
                     compile_callg(env, state, slot, cdr, result)?
                 }
             } else {
@@ -418,6 +472,28 @@ fn compile_list(
     Ok(())
 }
 
+/// Can a reference to a constant global with value val be replaced by the value?  Only values that
+/// are not (mutable) heap objects.
+fn is_inline_const(val: Value) -> bool {
+    matches!(
+        val,
+        Value::Byte(_)
+            | Value::Int32(_)
+            | Value::UInt32(_)
+            | Value::Int64(_)
+            | Value::UInt64(_)
+            | Value::Float64(_)
+            | Value::CodePoint(_)
+            | Value::CharCluster(_, _)
+            | Value::Symbol(_)
+            | Value::Keyword(_)
+            | Value::StringConst(_)
+            | Value::True
+            | Value::False
+            | Value::Nil
+    )
+}
+
 pub fn mkconst(
     env: &mut SloshVm,
     state: &mut CompileState,
@@ -464,6 +540,8 @@ pub fn compile(
     match exp {
         Value::Pair(_) | Value::List(_, _) => {
             let (car, cdr) = exp.get_pair(env).expect("Pair/List not a Pair or List?");
+            // Instructions after this form belong to the enclosing form again.
+            let outer_pos = state.chunk.dbg_pos();
             env.set_line_val(state, exp);
             if let Value::List(h, idx) = cdr {
                 // This unsafe should be fine (it breaks the lifetime away from env) since the
@@ -479,18 +557,28 @@ pub fn compile(
                 let cdr: Vec<Value> = cdr.iter(env).collect();
                 compile_list(env, state, car, &cdr[..], result)?;
             }
+            state.chunk.set_dbg_pos(outer_pos);
         }
         Value::Symbol(i) => {
             if let Some(idx) = state.get_symbol(i) {
+                state.symbols.borrow().mark_used(idx);
                 if result != idx {
                     state
                         .chunk
                         .encode2(MOV, result as u16, idx as u16, env.own_line())?;
                 }
             } else if let Some(slot) = env.global_intern_slot(i) {
-                state
-                    .chunk
-                    .encode_refi(result as u16, slot, env.own_line())?;
+                let global = env.get_global(slot);
+                if let Value::Undefined = global {
+                    note_undefined(env, state, i, slot);
+                }
+                if env.is_global_constant(slot) && is_inline_const(global) {
+                    mkconst(env, state, global, result)?;
+                } else {
+                    state
+                        .chunk
+                        .encode_refi(result as u16, slot, env.own_line())?;
+                }
             } else {
                 let sym = env.get_interned(i);
                 return Err(VMError::new_compile(format!("Symbol {sym} not defined (maybe you need to use 'def {sym}' to pre-declare it).")));
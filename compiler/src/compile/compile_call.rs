@@ -1,3 +1,4 @@
+use crate::compile::compile_values::{bind_values, values_target};
 use crate::{compile, CompileState, SloshVm};
 use compile_state::state::SloshVmTrait;
 use slvm::{VMResult, Value, BMOV, CALL, CALLM, CONST, MOV, TCALL, TCALLM};
@@ -33,7 +34,8 @@ pub(crate) fn compile_call(
         state.max_regs = b_reg;
     }
     let const_i = state.add_constant(callable);
-    let tail = state.tail && state.defers == 0;
+    let target = values_target(state);
+    let tail = state.tail && state.defers == 0 && target.is_none();
     state.tail = false;
     compile_params(env, state, cdr, result + 1, tail)?;
     let line = env.own_line();
@@ -49,6 +51,9 @@ pub(crate) fn compile_call(
             .chunk
             .encode3(CALL, b_reg as u16, cdr.len() as u16, result as u16, line)?;
     }
+    if let Some(target) = target {
+        bind_values(env, state, result, target)?;
+    }
     Ok(())
 }
 
@@ -59,7 +64,8 @@ pub(crate) fn compile_callg(
     cdr: &[Value],
     result: usize,
 ) -> VMResult<()> {
-    let tail = state.tail && state.defers == 0;
+    let target = values_target(state);
+    let tail = state.tail && state.defers == 0 && target.is_none();
     state.tail = false;
     compile_params(env, state, cdr, result + 1, tail)?;
     let line = env.own_line();
@@ -70,6 +76,9 @@ pub(crate) fn compile_callg(
             .chunk
             .encode_callg(global, cdr.len() as u16, result as u16, line)?;
     }
+    if let Some(target) = target {
+        bind_values(env, state, result, target)?;
+    }
     Ok(())
 }
 
@@ -80,7 +89,8 @@ pub(crate) fn compile_call_reg(
     cdr: &[Value],
     result: usize,
 ) -> VMResult<()> {
-    let tail = state.tail && state.defers == 0;
+    let target = values_target(state);
+    let tail = state.tail && state.defers == 0 && target.is_none();
     state.tail = false;
     let b_reg = if tail {
         let b_reg = result + cdr.len() + 2;
@@ -105,6 +115,9 @@ pub(crate) fn compile_call_reg(
             .chunk
             .encode3(CALL, reg, cdr.len() as u16, result as u16, line)?;
     }
+    if let Some(target) = target {
+        bind_values(env, state, result, target)?;
+    }
     Ok(())
 }
 
@@ -115,7 +128,8 @@ pub(crate) fn compile_call_myself(
     result: usize,
     force_tail: bool,
 ) -> VMResult<()> {
-    let tail = force_tail || (state.tail && state.defers == 0);
+    let target = values_target(state);
+    let tail = force_tail || (state.tail && state.defers == 0 && target.is_none());
     state.tail = false;
     compile_params(env, state, cdr, result + 1, tail)?;
     let line = env.own_line();
@@ -125,6 +139,9 @@ pub(crate) fn compile_call_myself(
         state
             .chunk
             .encode2(CALLM, cdr.len() as u16, result as u16, line)?;
+        if let Some(target) = target {
+            bind_values(env, state, result, target)?;
+        }
     }
     Ok(())
 }
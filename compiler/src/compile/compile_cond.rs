@@ -1,4 +1,5 @@
-use crate::{compile, CompileState, SloshVm};
+use crate::compile::util::get_args_iter;
+use crate::{compile, mkconst, CompileState, SloshVm};
 use compile_state::state::SloshVmTrait;
 use slvm::*;
 
@@ -146,3 +147,150 @@ pub(crate) fn compile_or(
     state.chunk.update_jump(jmp_idx, end_ip as u32);
     Ok(())
 }
+
+/// Use a CASE jump table (vs a chain of EQUAL tests) when case has at least this many keys.
+const CASE_TABLE_MIN_KEYS: usize = 6;
+
+/// Compile the body of a case clause into result, returns the jump index to patch to the end.
+fn compile_case_body(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    body: &[Value],
+    result: usize,
+    tail: bool,
+) -> VMResult<usize> {
+    let start_defers = state.defers;
+    if body.is_empty() {
+        mkconst(env, state, Value::Nil, result)?;
+    }
+    for (i, r) in body.iter().enumerate() {
+        if i == body.len() - 1 {
+            state.tail = tail;
+        }
+        compile(env, state, *r, result)?;
+    }
+    state.tail = false;
+    for _ in start_defers..state.defers {
+        state.chunk.encode0(DFRPOP, env.own_line())?;
+    }
+    state.defers = start_defers;
+    let end_jmp = state.chunk.add_jump(0);
+    state.chunk.encode1(JMP, end_jmp as u16, env.own_line())?;
+    Ok(end_jmp)
+}
+
+/// Compile (case expression (key body*)+), key is a literal or list of literals and _ (last) is
+/// the default.  Many keys are dispatched with a jump table, a few with EQUAL tests.
+pub(crate) fn compile_case(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    if cdr.is_empty() {
+        return Err(VMError::new_compile(
+            "Too few arguments, need at least 1 got 0.",
+        ));
+    }
+    let tail = state.tail;
+    state.tail = false;
+    let wildcard = env.intern("_");
+    // Each clause is (keys, body), keys is None for the default.
+    let mut clauses: Vec<(Option<Vec<Value>>, Vec<Value>)> = Vec::new();
+    let mut all_keys: Vec<CaseKey> = Vec::new();
+    for (i, clause) in cdr[1..].iter().enumerate() {
+        let clause: Vec<Value> = get_args_iter(env, *clause, "case clause")?.collect();
+        let keys = match clause.first() {
+            Some(Value::Symbol(s)) if *s == wildcard => {
+                if i != cdr.len() - 2 {
+                    return Err(VMError::new_compile("case: _ must be the last clause"));
+                }
+                None
+            }
+            Some(keys @ (Value::Pair(_) | Value::List(_, _))) => Some(keys.iter(env).collect()),
+            Some(key) => Some(vec![*key]),
+            None => return Err(VMError::new_compile("case: a clause needs at least a key")),
+        };
+        for key in keys.iter().flatten() {
+            let case_key = CaseKey::from_value(env, *key).ok_or_else(|| {
+                VMError::new_compile(format!(
+                    "case: {} is not a valid key (must be an integer, char, keyword or symbol)",
+                    key.display_value(env)
+                ))
+            })?;
+            if all_keys.contains(&case_key) {
+                return Err(VMError::new_compile(format!(
+                    "case: duplicate key {}",
+                    key.display_value(env)
+                )));
+            }
+            all_keys.push(case_key);
+        }
+        clauses.push((keys, clause[1..].to_vec()));
+    }
+    compile(env, state, cdr[0], result)?;
+    let default_jmp = state.chunk.add_jump(0);
+    let clause_jmps: Vec<usize> = clauses.iter().map(|_| state.chunk.add_jump(0)).collect();
+    let line = env.own_line();
+    if all_keys.len() >= CASE_TABLE_MIN_KEYS {
+        let mut entries = Vec::new();
+        let mut keys = all_keys.iter();
+        for ((clause_keys, _), jmp) in clauses.iter().zip(clause_jmps.iter()) {
+            for _ in clause_keys.iter().flatten() {
+                if let Some(key) = keys.next() {
+                    entries.push((*key, *jmp as u32));
+                }
+            }
+        }
+        let table = state.chunk.add_case_table(CaseTable::new(&entries));
+        state
+            .chunk
+            .encode3(CASE, result as u16, table as u16, default_jmp as u16, line)?;
+    } else {
+        if state.max_regs < result + 2 {
+            state.max_regs = result + 2;
+        }
+        for ((clause_keys, _), jmp) in clauses.iter().zip(clause_jmps.iter()) {
+            for key in clause_keys.iter().flatten() {
+                mkconst(env, state, *key, result + 1)?;
+                state.chunk.encode3(
+                    EQUAL,
+                    (result + 2) as u16,
+                    result as u16,
+                    (result + 1) as u16,
+                    line,
+                )?;
+                state
+                    .chunk
+                    .encode2(JMPT, (result + 2) as u16, *jmp as u16, line)?;
+            }
+        }
+        state.chunk.encode1(JMP, default_jmp as u16, line)?;
+    }
+    let mut end_jmps = Vec::new();
+    let mut has_default = false;
+    for ((clause_keys, body), jmp) in clauses.iter().zip(clause_jmps.iter()) {
+        if clause_keys.is_none() {
+            has_default = true;
+            state
+                .chunk
+                .update_jump(default_jmp, state.chunk.code.len() as u32);
+        }
+        state.chunk.update_jump(*jmp, state.chunk.code.len() as u32);
+        end_jmps.push(compile_case_body(env, state, body, result, tail)?);
+    }
+    if !has_default {
+        // Nothing matched.
+        state
+            .chunk
+            .update_jump(default_jmp, state.chunk.code.len() as u32);
+        mkconst(env, state, Value::Nil, result)?;
+    }
+    for jmp_idx in end_jmps {
+        state
+            .chunk
+            .update_jump(jmp_idx, state.chunk.code.len() as u32);
+    }
+    state.tail = tail;
+    Ok(())
+}
@@ -1,9 +1,11 @@
+use crate::compile::compile_types::compile_type_check;
 use crate::compile::destructure::{DestructState, DestructType};
 use crate::compile::util::get_args_iter;
 use crate::pass1::pass1;
+use crate::warnings::{check_return_type, check_shadow, check_unused};
 use crate::{compile, CompileState, SloshVm};
 use compile_state::state::SloshVmTrait;
-use slvm::{VMError, VMResult, Value, CLOSE, CONST, JMPNU, MOV, SRET};
+use slvm::{Interned, VMError, VMResult, Value, CLOSE, CONST, JMPNU, MOV, SRET};
 use std::sync::Arc;
 
 pub fn mk_state(
@@ -14,24 +16,59 @@ pub fn mk_state(
     let line = env.own_line().unwrap_or(1);
     let mut new_state =
         CompileState::new_state(state.chunk.file_name, line, Some(state.symbols.clone()));
+    // A fn in the body of a hygienic macro builds its expansion too.
+    new_state.hygienic = state.hygienic;
     let args_iter: Vec<Value> = get_args_iter(env, args, "fn")?.collect();
     let mut opt = false;
     let mut rest = false;
     let mut opt_comps = Vec::new();
     let mut destructures = Vec::new();
     let mut next_is_opt = false;
+    let mut keys: Option<Vec<(Interned, Value)>> = None;
     new_state.chunk.dbg_args = Some(Vec::new());
     let mut total_args = 0_usize;
+    let mut arg_types = Vec::new();
     for a in args_iter {
+        if let Some(keys) = keys.as_mut() {
+            keys.push(key_param(env, a)?);
+            continue;
+        }
         if next_is_opt {
             opt_comps.pop();
             opt_comps.push(a);
             next_is_opt = false;
             continue;
         }
+        let (a, arg_type) = match a {
+            Value::Pair(_) | Value::List(_, _) => {
+                let (name, arg_type) = typed_param(env, a)?;
+                (Value::Symbol(name), Some(arg_type))
+            }
+            _ => (a, None),
+        };
         match a {
             Value::Symbol(i) => {
-                if i == env.specials().rest {
+                if i == env.specials().key {
+                    if rest {
+                        return Err(VMError::new_compile(
+                            "invalid args, & and &key can not be used together",
+                        ));
+                    }
+                    // The key value pairs are a rest arg that is destructured into the keys.
+                    rest = true;
+                    new_state.symbols.borrow_mut().reserve_reg();
+                    if let Some(dbg_args) = new_state.chunk.dbg_args.as_mut() {
+                        dbg_args.push(env.specials().scratch);
+                    }
+                    if opt {
+                        new_state.chunk.opt_args += 1;
+                        opt_comps.push(Value::Nil);
+                    } else {
+                        new_state.chunk.args += 1;
+                    }
+                    total_args += 1;
+                    keys = Some(Vec::new());
+                } else if i == env.specials().rest {
                     rest = true;
                 } else if i == env.specials().optional {
                     opt = true;
@@ -47,6 +84,10 @@ pub fn mk_state(
                         new_state.chunk.args += 1;
                     }
                     total_args += 1;
+                    if arg_type.is_some() {
+                        arg_types.resize(total_args - 1, None);
+                        arg_types.push(arg_type);
+                    }
                 }
             }
             Value::Keyword(i) if i == env.specials().numeq => {
@@ -92,9 +133,52 @@ pub fn mk_state(
         }
     }
     new_state.chunk.rest = rest;
+    new_state.chunk.arg_types = arg_types;
+    if let Some(keys) = keys {
+        new_state.chunk.keys = keys.iter().map(|(key, _)| *key).collect();
+        destructures.push(DestructType::Keys(keys, total_args));
+    }
     Ok((new_state, opt_comps, destructures))
 }
 
+/// A param with a type annotation, (name :Type).
+fn typed_param(env: &SloshVm, param: Value) -> VMResult<(Interned, Interned)> {
+    let mut iter = param.iter(env);
+    match (iter.next(), iter.next(), iter.next()) {
+        (Some(Value::Symbol(i)), Some(Value::Keyword(t)), None)
+            if i != env.specials().rest
+                && i != env.specials().optional
+                && i != env.specials().key =>
+        {
+            Ok((i, t))
+        }
+        _ => Err(VMError::new_compile(
+            "invalid args, typed params must be (name :Type)",
+        )),
+    }
+}
+
+/// A &key param, name or (name default).
+fn key_param(env: &SloshVm, param: Value) -> VMResult<(Interned, Value)> {
+    match param {
+        Value::Symbol(i) if i != env.specials().rest && i != env.specials().optional => {
+            Ok((i, Value::Nil))
+        }
+        Value::Pair(_) | Value::List(_, _) => {
+            let mut iter = param.iter(env);
+            match (iter.next(), iter.next(), iter.next()) {
+                (Some(Value::Symbol(i)), Some(default), None) => Ok((i, default)),
+                _ => Err(VMError::new_compile(
+                    "invalid args, &key params must be name or (name default)",
+                )),
+            }
+        }
+        _ => Err(VMError::new_compile(
+            "invalid args, &key params must be name or (name default)",
+        )),
+    }
+}
+
 pub(crate) fn compile_fn(
     env: &mut SloshVm,
     state: &mut CompileState,
@@ -104,10 +188,46 @@ pub(crate) fn compile_fn(
     is_macro: bool,
 ) -> VMResult<()> {
     let (mut new_state, opt_comps, destructure_patterns) = mk_state(env, state, args)?;
+    // A keyword before the body is the type of the return value, (fn (x) :Int body).
+    let (ret_type, cdr) = match cdr {
+        [Value::Keyword(t), body @ ..] if !body.is_empty() => (Some(*t), body),
+        _ => (None, cdr),
+    };
+    new_state.chunk.ret_type = ret_type;
+    // Parameter i is in register i + 1, grab them before pass1 adds the captures.
+    let scratch = env.specials().scratch;
+    let params: Vec<(Interned, usize)> = new_state
+        .chunk
+        .dbg_args
+        .iter()
+        .flatten()
+        .enumerate()
+        .filter(|(_, name)| **name != scratch)
+        .map(|(i, name)| (*name, i + 1))
+        .collect();
+    for (name, _) in &params {
+        check_shadow(env, state, *name);
+    }
     for r in cdr.iter() {
         pass1(env, &mut new_state, *r)?;
     }
+    // Defaults can also capture.
+    for r in opt_comps.iter() {
+        pass1(env, &mut new_state, *r)?;
+    }
+    for destructure in &destructure_patterns {
+        if let DestructType::Keys(keys, _) = destructure {
+            for (_, default) in keys {
+                pass1(env, &mut new_state, *default)?;
+            }
+        }
+    }
     let reserved = new_state.reserved_regs();
+    // Optional args are set after the defaults run so captures in a default have to box them.
+    let first_opt = new_state.chunk.args as usize + 1;
+    for reg in first_opt..first_opt + opt_comps.len() {
+        new_state.symbols.borrow().set_uninit(reg, true);
+    }
     for (i, r) in opt_comps.into_iter().enumerate() {
         let target_reg = new_state.chunk.args as usize + i + 1;
         let jmp_idx = new_state.chunk.add_jump(0);
@@ -121,6 +241,7 @@ pub(crate) fn compile_fn(
         new_state
             .chunk
             .update_jump(jmp_idx, new_state.chunk.code.len() as u32);
+        new_state.symbols.borrow().set_uninit(target_reg, false);
     }
     let mut destruct_state = DestructState::new();
     for destruct_type in destructure_patterns {
@@ -129,16 +250,45 @@ pub(crate) fn compile_fn(
         destruct_state.compile(env, &mut new_state, &mut free_reg)?;
     }
     let reserved = new_state.reserved_regs();
+    let check_types = env.env().check_types();
+    if check_types {
+        let arg_types = new_state.chunk.arg_types.clone();
+        let dbg_args = new_state.chunk.dbg_args.clone().unwrap_or_default();
+        for (i, arg_type) in arg_types.iter().enumerate() {
+            if let (Some(arg_type), Some(name)) = (arg_type, dbg_args.get(i)) {
+                let what = format!("argument {}", env.get_interned(*name));
+                compile_type_check(env, &mut new_state, i + 1, *arg_type, &what, reserved)?;
+            }
+        }
+    }
+    if let (Some(ret_type), Some(last)) = (ret_type, cdr.last()) {
+        check_return_type(env, state, &new_state, ret_type, *last);
+    }
     let last_thing = cdr.len() - 1;
     for (i, r) in cdr.iter().enumerate() {
-        if i == last_thing {
+        // A tail call would skip the check of the return value.
+        if i == last_thing && !(check_types && ret_type.is_some()) {
             new_state.tail = true;
         }
         compile(env, &mut new_state, *r, reserved)?;
     }
+    if let (true, Some(ret_type)) = (check_types, ret_type) {
+        compile_type_check(
+            env,
+            &mut new_state,
+            reserved,
+            ret_type,
+            "return value",
+            reserved + 1,
+        )?;
+    }
     new_state
         .chunk
         .encode1(SRET, reserved as u16, env.own_line())?;
+    new_state.set_boxed_regs();
+    // Report unused parameters at the fn form.
+    new_state.chunk.set_dbg_pos(state.chunk.dbg_pos());
+    check_unused(env, &new_state, &params, 1);
     let mut closure = false;
     if !new_state.symbols.borrow().captures.borrow().is_empty() {
         let mut caps = Vec::new();
@@ -0,0 +1,219 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use slvm::error::*;
+use slvm::opcodes::*;
+use slvm::value::*;
+use slvm::Interned;
+
+use crate::compile::compile_fn::compile_fn;
+use crate::compile::destructure::setup_dbg;
+use crate::compile::util::get_args_iter;
+use crate::{compile, SloshVm};
+use compile_state::state::*;
+
+/// Does exp use recur, this-fn or return outside of a nested fn (in an inlined body these would
+/// apply to the caller)?
+fn uses_caller_frame(env: &SloshVm, exp: Value) -> bool {
+    match exp {
+        Value::Symbol(i) => {
+            let specials = env.specials();
+            i == specials.recur || i == specials.this_fn || i == specials.ret
+        }
+        Value::Pair(_) | Value::List(_, _) => {
+            let specials = env.specials();
+            match exp.get_pair(env) {
+                Some((Value::Symbol(i), _))
+                    if i == specials.fn_
+                        || i == specials.mac_
+                        || i == specials.hygienic_mac
+                        || i == specials.quote =>
+                {
+                    false
+                }
+                _ => exp.iter(env).any(|v| uses_caller_frame(env, v)),
+            }
+        }
+        Value::Vector(h) => env.get_vector(h).iter().any(|v| uses_caller_frame(env, *v)),
+        _ => false,
+    }
+}
+
+/// The global name in slot is being redefined by form, if it is inline it stops being inline.
+/// This is an error if calls to it have already been expanded (that code would keep the old body).
+pub(crate) fn clear_inline(
+    env: &mut SloshVm,
+    name: Interned,
+    slot: u32,
+    form: &str,
+) -> VMResult<()> {
+    if let Some(inline_fn) = env.env().inline_fn(slot) {
+        if let Some((file, line)) = inline_fn.call_sites.first() {
+            return Err(VMError::new_compile(format!(
+                "{form}: {} is inline and was expanded at {} call site(s) (first at {file}:{line}), redefining it would leave them with the old body.",
+                env.get_interned(name),
+                inline_fn.call_sites.len()
+            )));
+        }
+    }
+    if let Some(old) = env.env_mut().remove_inline_fn(slot) {
+        for form in old.body {
+            env.heap_unsticky(form);
+        }
+    }
+    Ok(())
+}
+
+/// Is the inline function in slot (if any) defined with the same params and body (so code that
+/// expanded it is still correct)?
+fn same_inline(env: &SloshVm, slot: u32, params: &[Interned], body: &[Value]) -> VMResult<bool> {
+    match env.env().inline_fn(slot) {
+        Some(old) if old.params == params && old.body.len() == body.len() => {
+            for (old, new) in old.body.iter().zip(body) {
+                if env.is_equal_pair(*old, *new)? != Value::True {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Compile (definline name args body*), this is def of a fn that also records args and body so
+/// later calls can be expanded in place.
+pub(crate) fn compile_definline(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    let name = match cdr.first() {
+        Some(Value::Symbol(name)) if cdr.len() > 2 => *name,
+        _ => return Err(VMError::new_compile("Malformed definline form.")),
+    };
+    let mut params = Vec::new();
+    for param in get_args_iter(env, cdr[1], "definline")? {
+        match param {
+            Value::Symbol(i) if i != env.specials().rest && i != env.specials().optional => {
+                params.push(i)
+            }
+            _ => {
+                return Err(VMError::new_compile(format!(
+                    "definline {}: parameters must be plain symbols.",
+                    env.get_interned(name)
+                )))
+            }
+        }
+    }
+    let body = &cdr[2..];
+    if body.iter().any(|exp| uses_caller_frame(env, *exp)) {
+        return Err(VMError::new_compile(format!(
+            "definline {}: body can not use recur, this-fn or return.",
+            env.get_interned(name)
+        )));
+    }
+    let slot = env.get_reserve_global(name);
+    // Defining it again the same way (loading a file twice) keeps the expanded calls valid.
+    let same = if same_inline(env, slot, &params, body)? {
+        env.env_mut().remove_inline_fn(slot)
+    } else {
+        clear_inline(env, name, slot, "definline")?;
+        None
+    };
+    // Calls to name in its own body are calls to the new function not an expansion of the old.
+    env.env_mut().set_global_defined(slot);
+    if let Some(doc_string) = state.doc_string {
+        let key = env.intern("doc-string");
+        env.set_global_property(slot, key, doc_string);
+    }
+    let compiled = compile_fn(env, state, cdr[1], body, result, false);
+    let is_same = same.is_some();
+    if let Some(inline_fn) = same {
+        env.env_mut().set_inline_fn(slot, inline_fn);
+    }
+    compiled?;
+    state
+        .chunk
+        .encode_def(result as u16, slot, env.own_line(), false)?;
+    if !is_same {
+        for form in body {
+            env.heap_sticky(*form);
+        }
+        let inline_fn = InlineFn {
+            params,
+            body: body.to_vec(),
+            call_sites: Vec::new(),
+        };
+        env.env_mut().set_inline_fn(slot, inline_fn);
+    }
+    Ok(())
+}
+
+/// If the global in slot is an inline function (and this is not a recursive call or the wrong
+/// number of args) then compile its body in place into result and return true.  The args are
+/// evaluated in order into the registers after result which become the parameters of a scope
+/// that only sees them (and globals).
+pub(crate) fn compile_inline_call(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    slot: u32,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<bool> {
+    let inline_fn = match env.env().inline_fn(slot) {
+        Some(inline_fn) if inline_fn.params.len() == cdr.len() && !env.env().is_inlining(slot) => {
+            inline_fn.clone()
+        }
+        _ => return Ok(false),
+    };
+    let line = env.line_num();
+    env.env_mut()
+        .add_inline_call_site(slot, state.chunk.file_name, line);
+    let old_tail = state.tail;
+    state.tail = false;
+    for (i, arg) in cdr.iter().enumerate() {
+        compile(env, state, *arg, result + i + 1)?;
+    }
+    // The body goes in the register after the params so its temporaries can not clobber them.
+    let body_reg = result + cdr.len() + 1;
+    let old_symbols = state.symbols.clone();
+    let old_defers = state.defers;
+    let symbols = Rc::new(RefCell::new(Symbols::with_inline(
+        old_symbols.clone(),
+        body_reg + 1,
+    )));
+    state.symbols = symbols.clone();
+    for (i, param) in inline_fn.params.iter().enumerate() {
+        symbols.borrow_mut().insert_reserved(*param, result + i + 1);
+        setup_dbg(env, state, result + i + 1, *param);
+    }
+    env.env_mut().push_inlining(slot);
+    let last_thing = inline_fn.body.len() - 1;
+    let mut body_result = Ok(());
+    for (i, exp) in inline_fn.body.iter().enumerate() {
+        if i == last_thing {
+            state.tail = old_tail;
+        }
+        body_result = compile(env, state, *exp, body_reg);
+        if body_result.is_err() {
+            break;
+        }
+    }
+    env.env_mut().pop_inlining();
+    state.set_boxed_regs();
+    state.tail = old_tail;
+    state.symbols = old_symbols;
+    body_result?;
+    state
+        .chunk
+        .encode2(MOV, result as u16, body_reg as u16, env.own_line())?;
+    for _ in old_defers..state.defers {
+        state.chunk.encode0(DFRPOP, env.own_line())?;
+    }
+    state.defers = old_defers;
+    for i in result + 1..symbols.borrow().regs_count() {
+        state.chunk.encode1(CLRREG, i as u16, env.own_line())?;
+    }
+    Ok(true)
+}
@@ -8,6 +8,7 @@ use slvm::Interned;
 
 use crate::compile::destructure::{setup_dbg, DestructState, DestructType};
 use crate::compile::util::get_args_iter;
+use crate::warnings::{check_shadow, check_unused};
 use crate::{compile, SloshVm};
 use compile_state::state::*;
 
@@ -32,6 +33,7 @@ fn let_inner(
     let args = cdr_iter.next().unwrap(); // unwrap safe, length is at least 1
     let mut right_exps: Vec<RightSideExp> = Vec::new();
     let mut destruct_state = DestructState::new();
+    let mut bindings = Vec::new();
     let args: Vec<Value> = get_args_iter(env, *args, "let")?.collect();
     let mut args_iter = args.iter();
     while let Some(a) = args_iter.next() {
@@ -45,13 +47,18 @@ fn let_inner(
         };
         match a {
             Value::Symbol(i) => {
+                check_shadow(env, state, *i);
                 if symbols.borrow().contains_symbol(*i) {
                     let reg = symbols.borrow_mut().reserve_reg();
                     right_exps.push((Some(*i), Some(reg), value, None));
+                    bindings.push((*i, reg));
                 } else {
                     let reg = symbols.borrow_mut().insert(*i);
+                    // Bound now but not set until its value is compiled below.
+                    symbols.borrow().set_uninit(reg, true);
                     setup_dbg(env, state, reg, *i);
                     right_exps.push((None, Some(reg), value, None));
+                    bindings.push((*i, reg));
                 }
             }
             Value::Vector(h) => {
@@ -84,6 +91,7 @@ fn let_inner(
             }
             (None, Some(reg), None) => {
                 compile(env, state, val, reg)?;
+                symbols.borrow().set_uninit(reg, false);
                 if free_reg < reg + 1 {
                     free_reg = reg + 1;
                 }
@@ -107,6 +115,7 @@ fn let_inner(
         }
         compile(env, state, *r, free_reg)?;
     }
+    check_unused(env, state, &bindings, first_reg);
     if free_reg != result {
         state
             .chunk
@@ -140,6 +149,7 @@ pub(crate) fn compile_let(
     state.tail = false;
     let old_defers = state.defers;
     let result = let_inner(env, state, cdr, result, old_tail);
+    state.set_boxed_regs();
     state.tail = old_tail;
     state.symbols = old_symbols;
     state.defers = old_defers;
@@ -398,6 +408,14 @@ mod tests {
         exec_runtime_error(&mut env, "(let ([a b c] '(1 2)) nil)");
         exec_runtime_error(&mut env, "(let ([a b c] [1 2]) nil)");
 
+        // A map pattern on a vector or list takes the value after the first matching item.
+        let result = exec(
+            &mut env,
+            "(let ({a :a} [1 :a 2], {b :b} '(1 :b 3)) (list a b))",
+        );
+        let expected = read_test(&mut env, "(2 3)");
+        assert_vals(&env, expected, result);
+
         exec_runtime_error(&mut env, "(let ({a :a, b :b, c :c} {}) nil)");
         exec_runtime_error(&mut env, "(let ({a :a, b :b, c :c} {:a 1}) nil)");
         exec_runtime_error(&mut env, "(let ({a :a, b :b, c :c} {:a 1, :b 2}) nil)");
@@ -0,0 +1,525 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use slvm::error::*;
+use slvm::opcodes::*;
+use slvm::value::*;
+use slvm::Handle;
+
+use crate::compile::destructure::setup_dbg;
+use crate::compile::util::get_args_iter;
+use crate::{compile, mkconst, SloshVm};
+use compile_state::state::*;
+
+/// Reserve len contiguous registers in the current scope, returns the first.
+fn reserve_regs(state: &mut CompileState, len: usize) -> usize {
+    let start = state.symbols.borrow().regs_count();
+    for _ in 0..len {
+        state.symbols.borrow_mut().reserve_reg();
+    }
+    if state.max_regs < start + len {
+        state.max_regs = start + len;
+    }
+    start
+}
+
+fn add_fail_jump(
+    env: &SloshVm,
+    state: &mut CompileState,
+    op: OpCode,
+    reg: usize,
+    len: Option<usize>,
+    fails: &mut Vec<usize>,
+) -> VMResult<()> {
+    let jmp_idx = state.chunk.add_jump(0);
+    if let Some(len) = len {
+        state
+            .chunk
+            .encode3(op, reg as u16, len as u16, jmp_idx as u16, env.own_line())?;
+    } else {
+        state
+            .chunk
+            .encode2(op, reg as u16, jmp_idx as u16, env.own_line())?;
+    }
+    fails.push(jmp_idx);
+    Ok(())
+}
+
+/// Compile a sequence (list or vector) pattern against the value in reg.
+fn compile_seq_pattern(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    patterns: &[Value],
+    reg: usize,
+    is_vec: bool,
+    fails: &mut Vec<usize>,
+) -> VMResult<()> {
+    let mut items = Vec::new();
+    let mut rest = None;
+    let mut has_rest = false;
+    for p in patterns {
+        match p {
+            Value::Symbol(i) if *i == env.specials().rest => {
+                if has_rest {
+                    return Err(VMError::new_compile("match: invalid pattern (invalid &)"));
+                }
+                has_rest = true;
+            }
+            Value::Symbol(i) if *i == env.specials().optional => {
+                return Err(VMError::new_compile(
+                    "match: optionals (%) are not valid in a pattern",
+                ));
+            }
+            _ if has_rest => {
+                if rest.is_some() {
+                    return Err(VMError::new_compile("match: invalid pattern (invalid &)"));
+                }
+                rest = Some(*p);
+            }
+            _ => items.push(*p),
+        }
+    }
+    let op = match (is_vec, has_rest) {
+        (true, false) => JMPNVC,
+        (true, true) => JMPNVCR,
+        (false, false) => JMPNLS,
+        (false, true) => JMPNLSR,
+    };
+    add_fail_jump(env, state, op, reg, Some(items.len()), fails)?;
+    let len = items.len() + usize::from(rest.is_some());
+    if len > 0 {
+        let start = reserve_regs(state, len);
+        let op = if rest.is_some() { LDSCR } else { LDSC };
+        state
+            .chunk
+            .encode3(op, start as u16, len as u16, reg as u16, env.own_line())?;
+        for (i, p) in items.iter().chain(rest.iter()).enumerate() {
+            compile_pattern(env, state, *p, start + i, true, fails)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compile a map pattern ({pattern key ..}) against the value in reg.
+fn compile_map_pattern(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    map: Handle,
+    reg: usize,
+    fails: &mut Vec<usize>,
+) -> VMResult<()> {
+    add_fail_jump(env, state, JMPNMP, reg, None, fails)?;
+    let entries: Vec<(Value, Value)> = env
+        .get_map(map)
+        .iter()
+        .map(|(pattern, key)| (*pattern, *key))
+        .collect();
+    if !entries.is_empty() {
+        let start = reserve_regs(state, entries.len());
+        for (i, (_, key)) in entries.iter().enumerate() {
+            compile(env, state, *key, start + i)?;
+        }
+        state.chunk.encode3(
+            MDSC,
+            start as u16,
+            entries.len() as u16,
+            reg as u16,
+            env.own_line(),
+        )?;
+        // A missing key is a failed match.
+        add_fail_jump(env, state, JMPRU, start, Some(entries.len()), fails)?;
+        for (i, (pattern, _)) in entries.iter().enumerate() {
+            compile_pattern(env, state, *pattern, start + i, true, fails)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compile the code to match pattern against the value in reg.  Bindings are added to the current
+/// symbols and a jump (to be pointed at the next clause) is added to fails for every test.
+/// If owned is true then reg is a temp for this pattern and symbols can be bound to it directly.
+fn compile_pattern(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    pattern: Value,
+    reg: usize,
+    owned: bool,
+    fails: &mut Vec<usize>,
+) -> VMResult<()> {
+    let wildcard = env.intern("_");
+    match pattern {
+        Value::Symbol(i) if i == wildcard => {}
+        Value::Symbol(i) if i == env.specials().rest || i == env.specials().optional => {
+            return Err(VMError::new_compile(format!(
+                "match: {} is not valid here in a pattern",
+                env.get_interned(i)
+            )));
+        }
+        Value::Symbol(i) => {
+            if owned {
+                state.symbols.borrow_mut().insert_reserved(i, reg);
+                setup_dbg(env, state, reg, i);
+            } else {
+                let sym_reg = state.symbols.borrow_mut().insert(i);
+                setup_dbg(env, state, sym_reg, i);
+                if state.max_regs < sym_reg + 1 {
+                    state.max_regs = sym_reg + 1;
+                }
+                state
+                    .chunk
+                    .encode2(MOV, sym_reg as u16, reg as u16, env.own_line())?;
+            }
+        }
+        Value::Vector(h) => {
+            let patterns: Vec<Value> = env.get_vector(h).to_vec();
+            compile_seq_pattern(env, state, &patterns, reg, true, fails)?;
+        }
+        Value::Map(h) => compile_map_pattern(env, state, h, reg, fails)?,
+        Value::Pair(_) | Value::List(_, _) if !matches!(pattern.get_pair(env), Some((Value::Symbol(i), _)) if i == env.specials().quote) =>
+        {
+            let patterns: Vec<Value> = pattern.iter(env).collect();
+            compile_seq_pattern(env, state, &patterns, reg, false, fails)?;
+        }
+        _ => {
+            // A literal (or quoted form), EQUAL needs the values in contiguous registers.
+            let start = reserve_regs(state, 3);
+            state
+                .chunk
+                .encode2(MOV, (start + 1) as u16, reg as u16, env.own_line())?;
+            compile(env, state, pattern, start + 2)?;
+            state.chunk.encode3(
+                EQUAL,
+                start as u16,
+                (start + 1) as u16,
+                (start + 2) as u16,
+                env.own_line(),
+            )?;
+            add_fail_jump(env, state, JMPF, start, None, fails)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compile one match clause, returns the jump index to patch to the end of the match.
+fn compile_clause(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    clause: Value,
+    val_reg: usize,
+    result: usize,
+    old_tail: bool,
+) -> VMResult<usize> {
+    let clause: Vec<Value> = get_args_iter(env, clause, "match clause")?.collect();
+    if clause.is_empty() {
+        return Err(VMError::new_compile(
+            "match: a clause needs at least a pattern",
+        ));
+    }
+    let when = Value::Keyword(env.intern("when"));
+    let start_defers = state.defers;
+    let mut fails = Vec::new();
+    compile_pattern(env, state, clause[0], val_reg, false, &mut fails)?;
+    let body = if clause.len() > 1 && clause[1] == when {
+        if clause.len() < 3 {
+            return Err(VMError::new_compile("match: :when requires a guard"));
+        }
+        let guard_reg = reserve_regs(state, 1);
+        compile(env, state, clause[2], guard_reg)?;
+        add_fail_jump(env, state, JMPF, guard_reg, None, &mut fails)?;
+        &clause[3..]
+    } else {
+        &clause[1..]
+    };
+    let free_reg = state.symbols.borrow().regs_count();
+    if body.is_empty() {
+        mkconst(env, state, Value::Nil, result)?;
+    } else {
+        for (i, r) in body.iter().enumerate() {
+            if i == body.len() - 1 {
+                state.tail = old_tail;
+            }
+            compile(env, state, *r, free_reg)?;
+        }
+        state.tail = false;
+        state
+            .chunk
+            .encode2(MOV, result as u16, free_reg as u16, env.own_line())?;
+    }
+    for _ in start_defers..state.defers {
+        state.chunk.encode0(DFRPOP, env.own_line())?;
+    }
+    state.defers = start_defers;
+    let end_jmp = state.chunk.add_jump(0);
+    state.chunk.encode1(JMP, end_jmp as u16, env.own_line())?;
+    for jmp_idx in fails {
+        state
+            .chunk
+            .update_jump(jmp_idx, state.chunk.code.len() as u32);
+    }
+    Ok(end_jmp)
+}
+
+fn match_inner(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+    old_tail: bool,
+) -> VMResult<()> {
+    let symbols = Rc::new(RefCell::new(Symbols::with_let(state.symbols.clone())));
+    state.symbols = symbols.clone();
+    let mut first_reg = symbols.borrow().regs_count();
+    while first_reg <= result {
+        // Make sure we do not step on the result or any other regs in temp use below it.
+        first_reg = symbols.borrow_mut().reserve_reg();
+    }
+    let val_reg = symbols.borrow_mut().reserve_reg();
+    setup_dbg(env, state, val_reg, env.specials().scratch);
+    compile(env, state, cdr[0], val_reg)?;
+    let mut max_reg = val_reg + 1;
+    let mut end_jumps = Vec::new();
+    for clause in &cdr[1..] {
+        // Each clause gets it's own scope for bindings and temps.
+        state.symbols = Rc::new(RefCell::new(Symbols::with_let(symbols.clone())));
+        end_jumps.push(compile_clause(
+            env, state, *clause, val_reg, result, old_tail,
+        )?);
+        max_reg = max_reg.max(state.symbols.borrow().regs_count());
+        state.symbols = symbols.clone();
+    }
+    // Nothing matched.
+    let err_reg = reserve_regs(state, 3);
+    let kw = Value::Keyword(env.intern("match"));
+    let err_str = Value::StringConst(env.intern("no match for "));
+    mkconst(env, state, kw, err_reg)?;
+    mkconst(env, state, err_str, err_reg + 1)?;
+    state
+        .chunk
+        .encode2(MOV, (err_reg + 2) as u16, val_reg as u16, env.own_line())?;
+    state.chunk.encode3(
+        STR,
+        (err_reg + 1) as u16,
+        (err_reg + 1) as u16,
+        (err_reg + 2) as u16,
+        env.own_line(),
+    )?;
+    state
+        .chunk
+        .encode2(ERR, err_reg as u16, (err_reg + 1) as u16, env.own_line())?;
+    for jmp_idx in end_jumps {
+        state
+            .chunk
+            .update_jump(jmp_idx, state.chunk.code.len() as u32);
+    }
+    max_reg = max_reg.max(err_reg + 3);
+    for i in first_reg..max_reg {
+        if i != result {
+            state.chunk.encode1(CLRREG, i as u16, env.own_line())?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn compile_match(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    if cdr.is_empty() {
+        return Err(VMError::new_compile(
+            "Too few arguments, need at least 1 got 0.",
+        ));
+    }
+    let old_symbols = state.symbols.clone();
+    let old_tail = state.tail;
+    state.tail = false;
+    let old_defers = state.defers;
+    let result = match_inner(env, state, cdr, result, old_tail);
+    state.set_boxed_regs();
+    state.tail = old_tail;
+    state.symbols = old_symbols;
+    state.defers = old_defers;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assert_vals, exec, exec_compile_error, exec_runtime_error, read_test};
+    use builtins::collections::make_hash;
+
+    #[test]
+    fn test_match_literals() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(&mut env, "(match 1 (0 :zero) (1 :one) (_ :other))");
+        let expected = read_test(&mut env, ":one");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match 5 (0 :zero) (1 :one) (_ :other))");
+        let expected = read_test(&mut env, ":other");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(match \"two\" (\"one\" 1) (\"two\" 2) (_ :other))",
+        );
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match :b (:a 1) (:b 2) (_ :other))");
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match nil (#t 1) (#f 2) (nil 3))");
+        let expected = read_test(&mut env, "3");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match 'sym ('other 1) ('sym 2))");
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match '(1 2) ('(1 2) :quoted) (_ :other))");
+        let expected = read_test(&mut env, ":quoted");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match 10 (x (+ x 1)))");
+        let expected = read_test(&mut env, "11");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match 10 (_))");
+        let expected = read_test(&mut env, "nil");
+        assert_vals(&env, expected, result);
+
+        exec_runtime_error(&mut env, "(match 3 (1 :one) (2 :two))");
+        exec_compile_error(&mut env, "(match)");
+        exec_compile_error(&mut env, "(match 1 ())");
+        exec_compile_error(&mut env, "(match 1 (x :when))");
+    }
+
+    #[test]
+    fn test_match_sequences() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(
+            &mut env,
+            "(match [1 2 3] ([a b] (list :two a b)) ([a b c] (list :three a b c)))",
+        );
+        let expected = read_test(&mut env, "(:three 1 2 3)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(match '(1 2) ([a b] (list :vec a b)) ((a b) (list :list a b)))",
+        );
+        let expected = read_test(&mut env, "(:list 1 2)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match [1 2 3 4] ([a b & rest] (list a b rest)))");
+        let expected = read_test(&mut env, "(1 2 (3 4))");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match [1 2] ([a b & rest] (list a b rest)))");
+        let expected = read_test(&mut env, "(1 2 nil)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match [1] ([a b & rest] :long) ([a &] :short))");
+        let expected = read_test(&mut env, ":short");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match '(1 2 3) ((1 & rest) rest) (_ :other))");
+        let expected = read_test(&mut env, "(2 3)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match [] ([] :empty) (_ :other))");
+        let expected = read_test(&mut env, ":empty");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(match [1 [2 (3 4)] 5] ([a [b (c _)] d] (list a b c d)))",
+        );
+        let expected = read_test(&mut env, "(1 2 3 5)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(match [1 [2 (3 4)] 5] ([a [b (c)] d] :no) ([a [b (_ _ _)] d] :no) (_ :yes))",
+        );
+        let expected = read_test(&mut env, ":yes");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match 1 ([a] :vec) ((a) :list) (_ :other))");
+        let expected = read_test(&mut env, ":other");
+        assert_vals(&env, expected, result);
+
+        exec_compile_error(&mut env, "(match [1] ([a & b c] :bad))");
+        exec_compile_error(&mut env, "(match [1] ([a % b] :bad))");
+    }
+
+    #[test]
+    fn test_match_maps_guards() {
+        let mut env = new_slosh_vm();
+        env.set_global_builtin("make-hash", make_hash);
+
+        let result = exec(
+            &mut env,
+            "(match {:a 1, :b 2} ({x :a, y :c} (list :c x y)) ({x :a, y :b} (list :b x y)))",
+        );
+        let expected = read_test(&mut env, "(:b 1 2)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(match {:type :circle, :r 2} ({:square :type} :square) ({:circle :type, r :r} r))",
+        );
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match {:point [1 2]} ({[x y] :point} (+ x y)))");
+        let expected = read_test(&mut env, "3");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(match [1 2] ({x :a} :map) (_ :other))");
+        let expected = read_test(&mut env, ":other");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(match 5 (n :when (< n 0) :negative) (n :when (> n 3) :big) (_ :small))",
+        );
+        let expected = read_test(&mut env, ":big");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(match [3 3] ([a b] :when (= a b) :same) ([a b] :different))",
+        );
+        let expected = read_test(&mut env, ":same");
+        assert_vals(&env, expected, result);
+
+        // Bindings are scoped to their clause.
+        let result = exec(
+            &mut env,
+            "(let (x 1) (list (match [10] ([x] :when (> x 20) x) (_ x)) x))",
+        );
+        let expected = read_test(&mut env, "(1 1)");
+        assert_vals(&env, expected, result);
+
+        // Tail position inside a function.
+        let result = exec(
+            &mut env,
+            "(do (def count-down (fn (n) (match n (0 :done) (_ (count-down (- n 1)))))) (count-down 10000))",
+        );
+        let expected = read_test(&mut env, ":done");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(let (f (fn (v) (match v ([a b] (fn () (+ a b))) (_ nil)))) ((f [1 2])))",
+        );
+        let expected = read_test(&mut env, "3");
+        assert_vals(&env, expected, result);
+    }
+}
@@ -39,6 +39,7 @@ pub(crate) fn compile_math(
         Value::Special(i) if i == env.specials().inc => {
             let dest = if let Value::Symbol(si) = cdr[0] {
                 if let Some(idx) = state.get_symbol(si) {
+                    state.symbols.borrow().mark_mutated(si);
                     idx
                 } else if let Some(slot) = env.global_intern_slot(i) {
                     state
@@ -78,6 +79,7 @@ pub(crate) fn compile_math(
         Value::Special(i) if i == env.specials().dec => {
             let dest = if let Value::Symbol(si) = cdr[0] {
                 if let Some(idx) = state.get_symbol(si) {
+                    state.symbols.borrow().mark_mutated(si);
                     idx
                 } else if let Some(slot) = env.global_intern_slot(i) {
                     state
@@ -1,3 +1,4 @@
+use crate::compile::compile_inline::clear_inline;
 use crate::{compile, CompileState, SloshVm};
 use compile_state::state::SloshVmTrait;
 use slvm::*;
@@ -20,6 +21,9 @@ pub(crate) fn compile_def(
         }
         (2, Some(Value::Symbol(si))) => {
             let si_const = env.get_reserve_global(*si);
+            check_not_constant(env, *si, si_const, "def")?;
+            clear_inline(env, *si, si_const, "def")?;
+            env.env_mut().set_global_defined(si_const);
             if let Some(doc_string) = state.doc_string {
                 let key = env.intern("doc-string");
                 env.set_global_property(si_const, key, doc_string);
@@ -34,6 +38,45 @@ pub(crate) fn compile_def(
     Ok(())
 }
 
+/// Error if the global si in slot is a constant (from defconst), form is the form trying to
+/// change it.
+fn check_not_constant(env: &SloshVm, si: Interned, slot: u32, form: &str) -> VMResult<()> {
+    if env.is_global_constant(slot) {
+        let sym = env.get_interned(si);
+        Err(VMError::new_compile(format!(
+            "{form}: {sym} is a constant and can not be changed."
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Compile (defconst name value), like def but the global becomes a constant.
+pub(crate) fn compile_defconst(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    if let [Value::Symbol(si), value] = cdr {
+        let si_const = env.get_reserve_global(*si);
+        check_not_constant(env, *si, si_const, "defconst")?;
+        clear_inline(env, *si, si_const, "defconst")?;
+        env.env_mut().set_global_defined(si_const);
+        if let Some(doc_string) = state.doc_string {
+            let key = env.intern("doc-string");
+            env.set_global_property(si_const, key, doc_string);
+        }
+        compile(env, state, *value, result)?;
+        state
+            .chunk
+            .encode_defc(result as u16, si_const, env.own_line())?;
+        Ok(())
+    } else {
+        Err(VMError::new_compile("defconst: expected symbol and value"))
+    }
+}
+
 pub(crate) fn compile_set(
     env: &mut SloshVm,
     state: &mut CompileState,
@@ -43,11 +86,14 @@ pub(crate) fn compile_set(
     if cdr.len() == 2 {
         if let Value::Symbol(si) = cdr[0] {
             if let Some(idx) = state.get_symbol(si) {
+                state.symbols.borrow().mark_mutated(si);
                 compile(env, state, cdr[1], result)?;
                 state
                     .chunk
                     .encode2(SET, idx as u16, result as u16, env.own_line())?;
             } else if let Some(si_const) = env.global_intern_slot(si) {
+                check_not_constant(env, si, si_const, "set!")?;
+                clear_inline(env, si, si_const, "set!")?;
                 compile(env, state, cdr[1], result)?;
                 state
                     .chunk
@@ -0,0 +1,392 @@
+use std::sync::Arc;
+
+use slvm::error::*;
+use slvm::opcodes::*;
+use slvm::value::*;
+use slvm::Interned;
+
+use crate::{compile, mkconst, SloshVm};
+use compile_state::state::*;
+
+/// The generated functions for a struct (record) type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum StructOp {
+    Make(usize),
+    Pred,
+    Get(usize),
+    Set(usize),
+}
+
+impl StructOp {
+    fn args(&self) -> usize {
+        match self {
+            StructOp::Make(len) => *len,
+            StructOp::Pred | StructOp::Get(_) => 1,
+            StructOp::Set(_) => 2,
+        }
+    }
+
+    fn to_props(self, env: &mut SloshVm) -> (Value, Value) {
+        let (op, field) = match self {
+            StructOp::Make(len) => ("make", len),
+            StructOp::Pred => ("pred", 0),
+            StructOp::Get(idx) => ("get", idx),
+            StructOp::Set(idx) => ("set", idx),
+        };
+        (Value::Keyword(env.intern(op)), Value::UInt32(field as u32))
+    }
+
+    fn from_props(env: &SloshVm, op: Value, field: Value) -> Option<Self> {
+        let field = if let Value::UInt32(field) = field {
+            field as usize
+        } else {
+            return None;
+        };
+        if let Value::Keyword(op) = op {
+            match env.get_interned(op) {
+                "make" => Some(StructOp::Make(field)),
+                "pred" => Some(StructOp::Pred),
+                "get" => Some(StructOp::Get(field)),
+                "set" => Some(StructOp::Set(field)),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// Compile a struct operation with args into result, the type descriptor and args go in the
+/// registers after result.
+fn compile_struct_op(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    op: StructOp,
+    desc: Value,
+    args: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    mkconst(env, state, desc, result + 1)?;
+    for (i, arg) in args.iter().enumerate() {
+        compile(env, state, *arg, result + i + 2)?;
+    }
+    if state.max_regs < result + args.len() + 1 {
+        state.max_regs = result + args.len() + 1;
+    }
+    let line = env.own_line();
+    let desc_reg = (result + 1) as u16;
+    match op {
+        StructOp::Make(len) => {
+            state
+                .chunk
+                .encode3(STRUCT, result as u16, desc_reg, len as u16, line)?
+        }
+        StructOp::Pred => state
+            .chunk
+            .encode2(STRUCTP, result as u16, desc_reg, line)?,
+        StructOp::Get(idx) => {
+            state
+                .chunk
+                .encode3(SGET, result as u16, desc_reg, idx as u16, line)?
+        }
+        StructOp::Set(idx) => {
+            let val_reg = (result + 3) as u16;
+            state
+                .chunk
+                .encode3(SSET, desc_reg, val_reg, idx as u16, line)?;
+            state.chunk.encode2(MOV, result as u16, val_reg, line)?;
+        }
+    }
+    Ok(())
+}
+
+/// If global is a function generated by defstruct then compile a call to it inline (an indexed
+/// op vs a call) and return true.
+pub(crate) fn compile_struct_call(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    name: Interned,
+    global: Value,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<bool> {
+    if !matches!(global, Value::Lambda(_)) {
+        return Ok(false);
+    }
+    let props = (
+        env.get_heap_property(global, ":struct-type"),
+        env.get_heap_property(global, ":struct-op"),
+        env.get_heap_property(global, ":struct-field"),
+    );
+    if let (Some(desc), Some(op), Some(field)) = props {
+        if let Some(op) = StructOp::from_props(env, op, field) {
+            if cdr.len() != op.args() {
+                return Err(VMError::new_compile(format!(
+                    "{}: wrong number of args, expected {} got {}",
+                    env.get_interned(name),
+                    op.args(),
+                    cdr.len()
+                )));
+            }
+            let tail = state.tail;
+            state.tail = false;
+            let res = compile_struct_op(env, state, op, desc, cdr, result);
+            state.tail = tail;
+            res?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Make the lambda for a generated struct function, arg_names are the parameter names.
+fn make_struct_fn(
+    env: &mut SloshVm,
+    state: &CompileState,
+    op: StructOp,
+    desc: Value,
+    arg_names: &[Interned],
+) -> VMResult<Value> {
+    let line = env.own_line().unwrap_or(1);
+    let mut new_state = CompileState::new_state(state.chunk.file_name, line, None);
+    new_state.chunk.dbg_args = Some(Vec::new());
+    let mut args = Vec::with_capacity(arg_names.len());
+    for name in arg_names {
+        new_state.symbols.borrow_mut().insert(*name);
+        if let Some(dbg_args) = new_state.chunk.dbg_args.as_mut() {
+            dbg_args.push(*name);
+        }
+        args.push(Value::Symbol(*name));
+    }
+    new_state.chunk.args = arg_names.len() as u16;
+    let reserved = new_state.reserved_regs();
+    compile_struct_op(env, &mut new_state, op, desc, &args, reserved)?;
+    new_state
+        .chunk
+        .encode1(SRET, reserved as u16, env.own_line())?;
+    new_state.chunk.input_regs = reserved;
+    new_state.chunk.extra_regs = new_state.max_regs - reserved;
+    let lambda = env.alloc_lambda(Arc::new(new_state.chunk));
+    let (op, field) = op.to_props(env);
+    env.set_heap_property(lambda, ":struct-type", desc);
+    env.set_heap_property(lambda, ":struct-op", op);
+    env.set_heap_property(lambda, ":struct-field", field);
+    Ok(lambda)
+}
+
+/// Define global name as lambda (with a doc string).
+fn def_struct_fn(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    name: &str,
+    lambda: Value,
+    doc: String,
+    result: usize,
+) -> VMResult<()> {
+    let name = env.intern(name);
+    let si_const = env.get_reserve_global(name);
+    env.env_mut().set_global_defined(si_const);
+    let key = env.intern("doc-string");
+    let doc = Value::StringConst(env.intern(&doc));
+    env.set_global_property(si_const, key, doc);
+    mkconst(env, state, lambda, result)?;
+    state
+        .chunk
+        .encode_def(result as u16, si_const, env.own_line(), false)
+}
+
+fn defstruct_inner(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    let name = if let Some(Value::Symbol(name)) = cdr.first() {
+        *name
+    } else {
+        return Err(VMError::new_compile("defstruct: expected a name symbol"));
+    };
+    let mut fields = Vec::with_capacity(cdr.len() - 1);
+    for field in &cdr[1..] {
+        if let Value::Symbol(f) = field {
+            if fields.contains(f) {
+                return Err(VMError::new_compile(format!(
+                    "defstruct: duplicate field {}",
+                    env.get_interned(*f)
+                )));
+            }
+            fields.push(*f);
+        } else {
+            return Err(VMError::new_compile(format!(
+                "defstruct: fields must be symbols, got {}",
+                field.display_value(env)
+            )));
+        }
+    }
+    let type_name = env.get_interned(name).to_string();
+    let field_names: Vec<String> = fields
+        .iter()
+        .map(|f| env.get_interned(*f).to_string())
+        .collect();
+
+    // The type descriptor, a new one each time defstruct is evaluated.
+    let mut desc = vec![Value::Symbol(name)];
+    desc.extend(fields.iter().map(|f| Value::Symbol(*f)));
+    let desc = env.alloc_vector_ro(desc);
+
+    let obj = env.intern("obj");
+    let value = env.intern("value");
+    let lambda = make_struct_fn(env, state, StructOp::Make(fields.len()), desc, &fields)?;
+    let doc = format!(
+        "Usage: (make-{type_name} {})\n\nMake a new {type_name} struct.\n\nSection: {type_name}\n",
+        field_names.join(" ")
+    );
+    def_struct_fn(
+        env,
+        state,
+        &format!("make-{type_name}"),
+        lambda,
+        doc,
+        result,
+    )?;
+
+    let lambda = make_struct_fn(env, state, StructOp::Pred, desc, &[obj])?;
+    let doc = format!(
+        "Usage: ({type_name}? obj)\n\nTrue if obj is a {type_name} struct.\n\nSection: {type_name}\n"
+    );
+    def_struct_fn(env, state, &format!("{type_name}?"), lambda, doc, result)?;
+
+    for (idx, field) in field_names.iter().enumerate() {
+        let lambda = make_struct_fn(env, state, StructOp::Get(idx), desc, &[obj])?;
+        let doc = format!(
+            "Usage: ({type_name}-{field} obj)\n\nReturn the {field} field of the {type_name} obj.\n\nSection: {type_name}\n"
+        );
+        def_struct_fn(
+            env,
+            state,
+            &format!("{type_name}-{field}"),
+            lambda,
+            doc,
+            result,
+        )?;
+
+        let lambda = make_struct_fn(env, state, StructOp::Set(idx), desc, &[obj, value])?;
+        let doc = format!(
+            "Usage: ({type_name}-set-{field}! obj value)\n\nSet the {field} field of the {type_name} obj to value, returns value.\n\nSection: {type_name}\n"
+        );
+        def_struct_fn(
+            env,
+            state,
+            &format!("{type_name}-set-{field}!"),
+            lambda,
+            doc,
+            result,
+        )?;
+    }
+    mkconst(env, state, Value::Symbol(name), result)
+}
+
+pub(crate) fn compile_defstruct(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    // Do not collect the type descriptor or lambdas before they are referenced by the chunk.
+    env.pause_gc();
+    let res = defstruct_inner(env, state, cdr, result);
+    env.unpause_gc();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{assert_vals, exec, exec_compile_error, exec_runtime_error, read_test};
+
+    #[test]
+    fn test_defstruct() {
+        let mut env = new_slosh_vm();
+
+        let result = exec(&mut env, "(defstruct point x y)");
+        let expected = read_test(&mut env, "point");
+        assert_vals(&env, expected, result);
+        exec(&mut env, "(def p (make-point 1 2))");
+
+        let result = exec(&mut env, "(list (point-x p) (point-y p))");
+        let expected = read_test(&mut env, "(1 2)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(list (point? p) (point? [1 2]) (point? nil))");
+        let expected = read_test(&mut env, "(#t #f #f)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(point-set-x! p 10)");
+        let expected = read_test(&mut env, "10");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(point-x p)");
+        assert_vals(&env, expected, result);
+
+        let result = exec(&mut env, "(type p)");
+        let expected = read_test(&mut env, ":point");
+        assert_vals(&env, expected, result);
+
+        let result = exec(
+            &mut env,
+            "(list (equal? p (make-point 10 2)) (equal? p (make-point 10 3)) (eq? p (make-point 10 2)))",
+        );
+        let expected = read_test(&mut env, "(#t #f #f)");
+        assert_vals(&env, expected, result);
+
+        // Generated functions are first class values.
+        let result = exec(&mut env, "(let (get-y point-y) (get-y p))");
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+
+        // Used in the same form it is defined in (not inlined).
+        let result = exec(
+            &mut env,
+            "(defstruct pair3 a b c) (def t3 (make-pair3 1 2 3)) (pair3-set-c! t3 4) (list (pair3-a t3) (pair3-c t3))",
+        );
+        let expected = read_test(&mut env, "(1 4)");
+        assert_vals(&env, expected, result);
+
+        // Field access is compiled to indexed ops.
+        let result = exec(
+            &mut env,
+            "(let (q (make-point 5 6)) (+ (point-x q) (point-y q)))",
+        );
+        let expected = read_test(&mut env, "11");
+        assert_vals(&env, expected, result);
+
+        // As map keys structs use identity (like other heap objects), not equal?.
+        env.set_global_builtin("make-hash", builtins::collections::make_hash);
+        let result = exec(
+            &mut env,
+            "(let (m (make-hash)) (set! (get m p) 1) (list (m p 0) (m (make-point 10 2) 0)))",
+        );
+        let expected = read_test(&mut env, "(1 0)");
+        assert_vals(&env, expected, result);
+
+        // Each defstruct is a new type.
+        exec(&mut env, "(defstruct point x y)");
+        let result = exec(&mut env, "(point? p)");
+        let expected = read_test(&mut env, "#f");
+        assert_vals(&env, expected, result);
+
+        exec(&mut env, "(defstruct empty)");
+        let result = exec(&mut env, "(empty? (make-empty))");
+        let expected = read_test(&mut env, "#t");
+        assert_vals(&env, expected, result);
+
+        exec_runtime_error(&mut env, "(point-x p)");
+        exec_runtime_error(&mut env, "(point-x [1 2])");
+        exec_runtime_error(&mut env, "(point-set-y! '(1 2) 3)");
+        exec_compile_error(&mut env, "(point-x p 1)");
+        exec_compile_error(&mut env, "(make-point 1)");
+        exec_compile_error(&mut env, "(defstruct)");
+        exec_compile_error(&mut env, "(defstruct 1 x)");
+        exec_compile_error(&mut env, "(defstruct bad x 1)");
+        exec_compile_error(&mut env, "(defstruct bad x x)");
+    }
+}
@@ -1,7 +1,7 @@
 #[cfg(test)]
 mod tests {
     use super::super::*;
-    use crate::test_utils::{assert_vals, exec, read_test};
+    use crate::test_utils::{assert_vals, exec, exec_compile_error, exec_runtime_error, read_test};
     use builtins::print::{dasm, prn};
 
     #[test]
@@ -21,6 +21,24 @@ mod tests {
         assert_vals(&env, expected, result);
     }
 
+    #[test]
+    fn test_doc_string() {
+        let mut env = new_slosh_vm();
+        exec(
+            &mut env,
+            "(do (doc-string \"Doc for x.\") (def x 3) (def y 4))",
+        );
+        let key = env.intern("doc-string");
+        let x = env.intern("x");
+        let x = env.global_intern_slot(x).unwrap();
+        let doc = env.get_global_property(x, key).unwrap();
+        let expected = read_test(&mut env, "\"Doc for x.\"");
+        assert_vals(&env, expected, doc);
+        let y = env.intern("y");
+        let y = env.global_intern_slot(y).unwrap();
+        assert!(env.get_global_property(y, key).is_none());
+    }
+
     #[test]
     fn test_if() {
         let mut env = new_slosh_vm();
@@ -157,6 +175,447 @@ mod tests {
         assert_vals(&env, expected, result);
     }
 
+    #[test]
+    fn test_key_args() {
+        let mut env = new_slosh_vm();
+        exec(
+            &mut env,
+            "(def fnk (fn (a &key (sep \" \") (trim #f) x) (list a sep trim x)))",
+        );
+        let result = exec(&mut env, "(fnk 1)");
+        let expected = read_test(&mut env, "(1 \" \" #f nil)");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(fnk 1 :trim #t :x 3 :sep \",\")");
+        let expected = read_test(&mut env, "(1 \",\" #t 3)");
+        assert_vals(&env, expected, result);
+        // Only keys are searched, a value that looks like a key is a value.
+        let result = exec(&mut env, "(fnk 1 :sep :trim :trim 1)");
+        let expected = read_test(&mut env, "(1 :trim 1 nil)");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(fnk 1 :trim :x)");
+        let expected = read_test(&mut env, "(1 \" \" :x nil)");
+        assert_vals(&env, expected, result);
+        exec_runtime_error(&mut env, "(fnk 1 :bad 1)");
+        exec_runtime_error(&mut env, "(fnk 1 :trim)");
+        exec_runtime_error(&mut env, "(fnk)");
+
+        // Defaults can use the earlier params, optional args come before the keys.
+        let result = exec(&mut env, "((fn (a % b &key (c (+ a 1))) (list a b c)) 1)");
+        let expected = read_test(&mut env, "(1 nil 2)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "((fn (a % b &key (c (+ a 1))) (list a b c)) 1 2 :c 5)",
+        );
+        let expected = read_test(&mut env, "(1 2 5)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let (k 10, f (fn (&key (v k)) (fn () v))) ((f)))",
+        );
+        let expected = read_test(&mut env, "10");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(let (k 10, f (fn (% v := k) v)) (f))");
+        let expected = read_test(&mut env, "10");
+        assert_vals(&env, expected, result);
+
+        let fnk = env.intern("fnk");
+        assert_eq!(
+            builtins::docs::lambda_usage(&env, fnk).as_deref(),
+            Some("(fnk a &key sep trim x)")
+        );
+
+        exec_compile_error(&mut env, "(fn (a & rest &key b) a)");
+        exec_compile_error(&mut env, "(fn (a &key (b 1 2)) a)");
+        exec_compile_error(&mut env, "(fn (a &key [b]) a)");
+    }
+
+    #[test]
+    fn test_values() {
+        let mut env = new_slosh_vm();
+        exec(&mut env, "(def reverse (fn (l) (let (r '()) (while l (set! r (cons (car l) r)) (set! l (cdr l))) r)))");
+        exec(
+            &mut env,
+            "(def split-at (fn (n l) (let (head '()) (while (> n 0) (set! head (cons (car l) head)) (set! l (cdr l)) (set! n (- n 1))) (values (reverse head) l))))",
+        );
+        let result = exec(
+            &mut env,
+            "(let-values ((h t) (split-at 2 '(1 2 3 4))) (list h t))",
+        );
+        let expected = read_test(&mut env, "((1 2) (3 4))");
+        assert_vals(&env, expected, result);
+        // Only the first value is seen outside of let-values.
+        let result = exec(&mut env, "(split-at 1 '(1 2 3))");
+        let expected = read_test(&mut env, "(1)");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(values)");
+        assert_vals(&env, Value::Nil, result);
+
+        // Tail calls pass the values through, other calls only return one value.
+        exec(
+            &mut env,
+            "(def vals3 (fn (x) (values x (+ x 1) (+ x 2.5))))",
+        );
+        let result = exec(
+            &mut env,
+            "(let-values ((a b c) ((fn (y) (vals3 y)) 1)) (list a b c))",
+        );
+        let expected = read_test(&mut env, "(1 2 3.5)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) ((fn (y) (vals3 y) 7) 1)) (list a b))",
+        );
+        let expected = read_test(&mut env, "(7 nil)");
+        assert_vals(&env, expected, result);
+        // Only values that produce the result are bound.
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) (do (values 1 2) 5), (c d) (values a)) (list a b c d))",
+        );
+        let expected = read_test(&mut env, "(5 nil 5 nil)");
+        assert_vals(&env, expected, result);
+        // Values pass through if, let, do, case and match.
+        exec(&mut env, "(def c nil)");
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) (if c (values 1 2) (values 3 4))) (list a b))",
+        );
+        let expected = read_test(&mut env, "(3 4)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) (if (vals3 1) (vals3 2) 5), (c d) (if a 6 (values 7 8))) (list a b c d))",
+        );
+        let expected = read_test(&mut env, "(2 3 6 nil)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a b c) (let (x 1) (defer (set! x 3)) (vals3 x))) (list a b c))",
+        );
+        let expected = read_test(&mut env, "(1 2 3.5)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) (let (x 1) (values x 2) (values 3 x))) (list a b))",
+        );
+        let expected = read_test(&mut env, "(3 1)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) (case 2 (1 (values 1 2)) (2 (vals3 4)) (_ 0))) (list a b))",
+        );
+        let expected = read_test(&mut env, "(4 5)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) (case 3 (1 (values 1 2)) (_ 0))) (list a b))",
+        );
+        let expected = read_test(&mut env, "(0 nil)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) (match '(1 2) ((x y) (values y x)) (_ nil))) (list a b))",
+        );
+        let expected = read_test(&mut env, "(2 1)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) (let-values ((c d) (values 1 2)) (values d c))) (list a b))",
+        );
+        let expected = read_test(&mut env, "(2 1)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let-values ((a) (vals3 1), (b c d e) (vals3 a)) (list a b c d e))",
+        );
+        let expected = read_test(&mut env, "(1 1 2 3.5 nil)");
+        assert_vals(&env, expected, result);
+
+        // Defers run before the values are returned.
+        exec(&mut env, "(def dx 0)");
+        let result = exec(
+            &mut env,
+            "(let-values ((a b) ((fn () (defer (set! dx 1)) (values dx 2)))) (list a b dx))",
+        );
+        let expected = read_test(&mut env, "(0 2 1)");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(((fn (x) (let-values ((a b) (vals3 x)) (fn () (+ a b)))) 10))",
+        );
+        let expected = read_test(&mut env, "21");
+        assert_vals(&env, expected, result);
+
+        exec_compile_error(&mut env, "(let-values ((a b)) a)");
+        exec_compile_error(&mut env, "(let-values ((a 1) 1) a)");
+        exec_compile_error(&mut env, "(let-values (() 1) 1)");
+    }
+
+    #[test]
+    fn test_case() {
+        let mut env = new_slosh_vm();
+        // Few keys are tested in turn, many use a jump table (dense ints are indexed).
+        exec(
+            &mut env,
+            "(def small (fn (x) (case x (1 :one) ((2 :two) :two) (_ :other))))",
+        );
+        exec(
+            &mut env,
+            "(def dense (fn (x) (case x (1 :one) (2 :two) ((3 4) :three-four) (5 :five) (6 :six) (_ :other))))",
+        );
+        exec(
+            &mut env,
+            "(def sparse (fn (x) (case x (-100 :neg) (7 :seven) (1000000 :big) (\\a :a) (:k :k) ((sym other) :sym))))",
+        );
+        let case_tables = |env: &mut SloshVm, name: &str| {
+            let i = env.intern(name);
+            let slot = env.global_intern_slot(i).unwrap();
+            match env.get_global(slot) {
+                Value::Lambda(h) => env.get_lambda(h).case_tables.clone(),
+                _ => panic!("{name} not a lambda"),
+            }
+        };
+        assert!(case_tables(&mut env, "small").is_empty());
+        assert!(matches!(
+            case_tables(&mut env, "dense").as_slice(),
+            [slvm::CaseTable::Indexed { .. }]
+        ));
+        assert!(matches!(
+            case_tables(&mut env, "sparse").as_slice(),
+            [slvm::CaseTable::Hashed(_)]
+        ));
+
+        for (exp, res) in [
+            ("(small 1)", ":one"),
+            ("(small :two)", ":two"),
+            ("(small 3)", ":other"),
+            ("(dense 1)", ":one"),
+            ("(dense 4)", ":three-four"),
+            ("(dense (+ 3 3))", ":six"),
+            ("(dense 6.0)", ":six"),
+            ("(dense 0)", ":other"),
+            ("(dense 7)", ":other"),
+            ("(dense :x)", ":other"),
+            ("(sparse -100)", ":neg"),
+            ("(sparse 1000000)", ":big"),
+            ("(sparse \\a)", ":a"),
+            ("(sparse :k)", ":k"),
+            ("(sparse 'other)", ":sym"),
+            ("(sparse 8)", "nil"),
+            ("(sparse \"k\")", "nil"),
+        ] {
+            let result = exec(&mut env, exp);
+            let expected = read_test(&mut env, res);
+            assert_vals(&env, expected, result);
+        }
+
+        // Bodies are in tail position and clean up their defers.
+        exec(&mut env, "(def dx 0)");
+        let result = exec(
+            &mut env,
+            "((fn (n acc) (case n (0 acc) (_ (recur (- n 1) (+ acc n))))) 100000 0)",
+        );
+        let expected = read_test(&mut env, "5000050000");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(list (case 2 (1 1) (2 (defer (set! dx 5)) dx)) dx)",
+        );
+        let expected = read_test(&mut env, "(0 5)");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(case 1 (1))");
+        assert_vals(&env, Value::Nil, result);
+
+        exec_compile_error(&mut env, "(case 1 (1 :a) (1 :b))");
+        exec_compile_error(&mut env, "(case 1 ((1 2) :a) ((3 2) :b))");
+        exec_compile_error(&mut env, "(case 1 (\"s\" :a))");
+        exec_compile_error(&mut env, "(case 1 (_ :a) (1 :b))");
+        exec_compile_error(&mut env, "(case)");
+    }
+
+    #[test]
+    fn test_macroexpand() {
+        let mut env = new_slosh_vm();
+        builtins::macroexpand::add_macroexpand_builtins(&mut env);
+        exec(&mut env, "(def twice (macro (x) `(do ~x ~x)))");
+        exec(&mut env, "(def inc1 (macro (x) `(+ ~x 1)))");
+        let result = exec(&mut env, "(macroexpand-1 '(twice (inc1 2)))");
+        let expected = read_test(&mut env, "(do (inc1 2) (inc1 2))");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(macroexpand-1 '(inc2 2))");
+        let expected = read_test(&mut env, "(inc2 2)");
+        assert_vals(&env, expected, result);
+
+        // Nested calls and the calls an expansion produces are expanded.
+        let result = exec(&mut env, "(macroexpand-all '(twice (inc1 2)))");
+        let expected = read_test(&mut env, "(do (+ 2 1) (+ 2 1))");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(macroexpand-all '(fn (x % y := (inc1 1)) (twice y)))",
+        );
+        let expected = read_test(&mut env, "(fn (x % y := (+ 1 1)) (do y y))");
+        assert_vals(&env, expected, result);
+
+        // Quoted forms and back-quote templates are left alone, unquotes are not.
+        let result = exec(
+            &mut env,
+            "(macroexpand-all '(list '(inc1 1) `(inc1 ~(inc1 2))))",
+        );
+        let expected = read_test(&mut env, "(list '(inc1 1) `(inc1 ~(+ 2 1)))");
+        assert_vals(&env, expected, result);
+
+        // Local bindings hide macros.
+        let result = exec(
+            &mut env,
+            "(macroexpand-all '(let (inc1 (fn (x) x)) (inc1 (twice 1))))",
+        );
+        let expected = read_test(&mut env, "(let (inc1 (fn (x) x)) (inc1 (do 1 1)))");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(macroexpand-all '(fn (twice) (twice (inc1 1))))");
+        let expected = read_test(&mut env, "(fn (twice) (twice (+ 1 1)))");
+        assert_vals(&env, expected, result);
+    }
+
+    #[test]
+    fn test_hygienic_macro() {
+        let mut env = new_slosh_vm();
+        builtins::add_misc_builtins(&mut env);
+        exec(
+            &mut env,
+            "(def add-one (hygienic-macro (& body) `(let (one 1) (+ one (do ~@body)))))",
+        );
+        exec(
+            &mut env,
+            "(def add-one-plain (macro (& body) `(let (one 1) (+ one (do ~@body)))))",
+        );
+        let result = exec(&mut env, "(let (one 10) (add-one one))");
+        assert_vals(&env, Value::Int32(11), result);
+        // The plain macro's binding captures the caller's one.
+        let result = exec(&mut env, "(let (one 10) (add-one-plain one))");
+        assert_vals(&env, Value::Int32(2), result);
+
+        // fn params and let-values names are renamed too, unquoted names are not.
+        exec(
+            &mut env,
+            "(def bind-k (hygienic-macro (name x) `((fn (k) (let-values ((a ~name) (values k ~x)) (list a ~name))) 1)))",
+        );
+        let result = exec(&mut env, "(let (k 5 a 6) (bind-k b (list k a)))");
+        let expected = read_test(&mut env, "(1 (5 6))");
+        assert_vals(&env, expected, result);
+
+        // Each expansion gets new names so nesting works.
+        let result = exec(&mut env, "(let (one 10) (add-one (add-one one)))");
+        assert_vals(&env, Value::Int32(12), result);
+        // Bindings the template only uses (not binds) are left alone.
+        exec(&mut env, "(def two 2)");
+        exec(&mut env, "(def add-two (hygienic-macro (x) `(+ two ~x)))");
+        let result = exec(&mut env, "(add-two 1)");
+        assert_vals(&env, Value::Int32(3), result);
+        // Names are only renamed inside the form that binds them, not free uses outside it.
+        exec(&mut env, "(def x 100)");
+        exec(
+            &mut env,
+            "(def let-x (hygienic-macro (v) `(list (let (x ~v) x) x)))",
+        );
+        let result = exec(&mut env, "(let-x 1)");
+        let expected = read_test(&mut env, "(1 100)");
+        assert_vals(&env, expected, result);
+        // Quoted symbols and def targets are data, not references to the binding.
+        exec(
+            &mut env,
+            "(def quote-x (hygienic-macro (v) `(let (x ~v) (def x x) (list x 'x))))",
+        );
+        let result = exec(&mut env, "(list (quote-x 2) x)");
+        let expected = read_test(&mut env, "((2 x) 2)");
+        assert_vals(&env, expected, result);
+    }
+
+    #[test]
+    fn test_defconst() {
+        let mut env = new_slosh_vm();
+        exec(&mut env, "(defconst k 42)");
+        exec(&mut env, "(def f (fn () (+ k 1)))");
+        let result = exec(&mut env, "(f)");
+        assert_vals(&env, Value::Int32(43), result);
+        // The reference was replaced by the value (this can only change from Rust).
+        let k = env.intern("k");
+        let slot = env.global_intern_slot(k).unwrap();
+        env.set_global(slot, Value::Int32(1));
+        let result = exec(&mut env, "(f)");
+        assert_vals(&env, Value::Int32(43), result);
+
+        exec_compile_error(&mut env, "(def k 1)");
+        exec_compile_error(&mut env, "(set! k 1)");
+        exec_compile_error(&mut env, "(defconst k 1)");
+        exec_compile_error(&mut env, "(defconst 1 1)");
+        // The compiler can not see this one coming, the VM stops it.
+        exec_runtime_error(&mut env, "(do (defconst j 1) (def j 2))");
+        let result = exec(&mut env, "j");
+        assert_vals(&env, Value::Int32(1), result);
+        exec_runtime_error(&mut env, "(do (defconst i 1) (defconst i 2))");
+
+        // Heap values are constants too but are not inlined.
+        exec(&mut env, "(defconst v (vec 1 2))");
+        let result = exec(&mut env, "(do (vec-push! v 3) v)");
+        let expected = read_test(&mut env, "[1 2 3]");
+        assert_vals(&env, expected, result);
+        exec_compile_error(&mut env, "(set! v [1])");
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        let mut env = new_slosh_vm();
+        let result = exec(&mut env, "(fn (x (s :String)) :String (str x s))");
+        let Value::Lambda(h) = result else {
+            panic!("expected a lambda got {result:?}");
+        };
+        let lambda = env.get_lambda(h);
+        let (int, string) = (env.intern("Int"), env.intern("String"));
+        assert_eq!(lambda.arg_types, [None, Some(string)]);
+        assert_eq!(lambda.ret_type, Some(string));
+        // A lone keyword is the return value not a type.
+        let result = exec(&mut env, "((fn () :Int))");
+        assert_vals(&env, Value::Keyword(int), result);
+        exec_compile_error(&mut env, "(fn ((1 :Int)) 1)");
+        exec_compile_error(&mut env, "(fn ((x Int)) 1)");
+
+        // Without check-types the annotations are not checked at runtime.
+        exec(&mut env, "(def f (fn ((x :Int)) :Int x))");
+        let result = exec(&mut env, "(f \"a\")");
+        let expected = read_test(&mut env, "\"a\"");
+        assert_vals(&env, expected, result);
+
+        env.env_mut().set_check_types(true);
+        exec(
+            &mut env,
+            "(def f (fn ((x :Int) % (y :Float) := 1.5) (+ x y)))",
+        );
+        let result = exec(&mut env, "(f -1)");
+        let expected = read_test(&mut env, "0.5");
+        assert_vals(&env, expected, result);
+        // Int also takes the non-negative (UInt) integers.
+        let result = exec(&mut env, "(f 1 2.0)");
+        let expected = read_test(&mut env, "3.0");
+        assert_vals(&env, expected, result);
+        exec_runtime_error(&mut env, "(f 1.0)");
+        exec_runtime_error(&mut env, "(f 1 2)");
+
+        exec(&mut env, "(def g (fn (x) :String x))");
+        let result = exec(&mut env, "(g \"a\")");
+        let expected = read_test(&mut env, "\"a\"");
+        assert_vals(&env, expected, result);
+        exec_runtime_error(&mut env, "(g 1)");
+        // Checking the return value does not break recur.
+        exec(
+            &mut env,
+            "(def count (fn ((n :Int) acc) :Int (if (= n 0) acc (recur (- n 1) (+ acc 1)))))",
+        );
+        let result = exec(&mut env, "(count 10000 0)");
+        assert_vals(&env, Value::UInt32(10000), result);
+    }
+
     #[test]
     fn test_captures() {
         let mut env = new_slosh_vm();
@@ -188,6 +647,129 @@ mod tests {
         assert_vals(&env, expected, result);
     }
 
+    #[test]
+    fn test_capture_boxing() {
+        let mut env = new_slosh_vm();
+
+        // Never set so the closure gets a copy.
+        let result = exec(&mut env, "(let (x 1) (fn () x))");
+        let Value::Closure(h) = result else {
+            panic!("expected a closure got {result:?}");
+        };
+        assert!(!matches!(env.get_closure(h).1, [Value::Value(_)]));
+        let result = exec(&mut env, "(let (x 1, f (fn () x)) (set! x 2) f)");
+        let Value::Closure(h) = result else {
+            panic!("expected a closure got {result:?}");
+        };
+        assert!(matches!(env.get_closure(h).1, [Value::Value(_)]));
+
+        // Set after the closure is made (in the outer scope or the closure).
+        let result = exec(&mut env, "(let (x 1, f (fn () x)) (set! x 2) (f))");
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let (n 0, inc (fn () (set! n (+ n 1)))) (inc) (inc) n)",
+        );
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(let (x 1, f (fn () (fn () x))) (set! x 2) ((f)))",
+        );
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+
+        // Captured before the let sets it.
+        let result = exec(
+            &mut env,
+            "(let (f (fn (n) (if (= n 0) :done (f (- n 1))))) (f 3))",
+        );
+        let expected = read_test(&mut env, ":done");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "(let (a (fn () (b)), b (fn () 7)) (a))");
+        let expected = read_test(&mut env, "7");
+        assert_vals(&env, expected, result);
+
+        // Copies of numbers must not share the register's storage.
+        let result = exec(
+            &mut env,
+            "(let (x (+ 1.5 1.0)) (def fl (fn () x))) (let (y (+ 10.5 1.0)) (fl))",
+        );
+        let expected = read_test(&mut env, "2.5");
+        assert_vals(&env, expected, result);
+    }
+
+    #[test]
+    fn test_definline() {
+        let mut env = new_slosh_vm();
+
+        // Args are evaluated once, in order and before the body.
+        let result = exec(
+            &mut env,
+            "(do (def log nil) (definline sub2 (a b) (- a b))
+              (sub2 (do (set! log (cons 1 log)) 5) (do (set! log (cons 2 log)) 3)))",
+        );
+        let expected = read_test(&mut env, "2");
+        assert_vals(&env, expected, result);
+        let result = exec(&mut env, "log");
+        let expected = read_test(&mut env, "(2 1)");
+        assert_vals(&env, expected, result);
+
+        // The body sees its params and globals, not the caller's locals.
+        let result = exec(&mut env, "(let (a 10, b 1) (sub2 b a))");
+        let expected = read_test(&mut env, "-9");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(do (def g 5) (definline addg (x) (+ x g)) (let (g 100, f (fn () (addg 1))) (f)))",
+        );
+        let expected = read_test(&mut env, "6");
+        assert_vals(&env, expected, result);
+
+        // Still a function when not called directly or called with the wrong arity.
+        let result = exec(&mut env, "(do (def h sub2) (h 7 2))");
+        let expected = read_test(&mut env, "5");
+        assert_vals(&env, expected, result);
+        exec_runtime_error(&mut env, "(sub2 1)");
+
+        // Once a call has been expanded redefining it is an error (the call would keep the old
+        // body), defining it the same way again (loading a file twice) is not.
+        exec_compile_error(&mut env, "(def addg (fn (x) 0))");
+        exec_compile_error(&mut env, "(set! addg (fn (x) 0))");
+        exec_compile_error(&mut env, "(defconst addg 0)");
+        exec_compile_error(&mut env, "(definline addg (x) (- x g))");
+        exec(&mut env, "(definline addg (x) (+ x g))");
+        let result = exec(&mut env, "(addg 1)");
+        let expected = read_test(&mut env, "6");
+        assert_vals(&env, expected, result);
+        // Never expanded so redefining just ends inlining.
+        exec(&mut env, "(definline unused (x) x)");
+        exec(&mut env, "(def unused (fn (x) 0))");
+        let result = exec(&mut env, "(list (unused 1) ((fn () (unused 1))))");
+        let expected = read_test(&mut env, "(0 0)");
+        assert_vals(&env, expected, result);
+
+        // Recursive calls are not expanded.
+        let result = exec(
+            &mut env,
+            "(do (definline cnt (n) (if (= n 0) :done (cnt (- n 1)))) (cnt 5))",
+        );
+        let expected = read_test(&mut env, ":done");
+        assert_vals(&env, expected, result);
+        let result = exec(
+            &mut env,
+            "(do (def od?) (definline ev? (n) (if (= n 0) #t (od? (- n 1))))
+              (definline od? (n) (if (= n 0) #f (ev? (- n 1)))) (list (ev? 10) (od? 10)))",
+        );
+        let expected = read_test(&mut env, "(#t #f)");
+        assert_vals(&env, expected, result);
+
+        exec_compile_error(&mut env, "(definline bad (& rest) rest)");
+        exec_compile_error(&mut env, "(definline bad (x) (if x (recur nil) 1))");
+        exec_compile_error(&mut env, "(definline bad (x))");
+    }
+
     #[test]
     fn test_on_error() {
         let mut env = new_slosh_vm();
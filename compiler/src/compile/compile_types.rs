@@ -0,0 +1,103 @@
+use slvm::error::*;
+use slvm::opcodes::*;
+use slvm::value::*;
+use slvm::Interned;
+
+use crate::SloshVm;
+use compile_state::state::*;
+
+/// Does a value of type actual (a name the TYPE opcode returns) satisfy the annotation expected?
+/// Non-negative integers are UInts so an Int annotation also takes those.
+pub(crate) fn type_matches(env: &SloshVm, expected: Interned, actual: Interned) -> bool {
+    expected == actual
+        || (env.get_interned(expected) == "Int" && env.get_interned(actual) == "UInt")
+}
+
+/// Type (as TYPE would name it) of the value exp produces if that is known at compile time: for
+/// literals, quoted forms, str, fn and calls to functions with an annotated return type.
+pub(crate) fn infer_type(env: &mut SloshVm, state: &CompileState, exp: Value) -> Option<Interned> {
+    match exp {
+        Value::Symbol(i) => {
+            if state.get_symbol(i).is_some() || state.symbols.borrow().can_capture(i) {
+                return None;
+            }
+            let slot = env.global_intern_slot(i)?;
+            let global = env.get_global(slot);
+            if env.is_global_constant(slot) && !global.is_undef() {
+                Some(env.intern_static(global.display_type(env)))
+            } else {
+                None
+            }
+        }
+        Value::Pair(_) | Value::List(_, _) => {
+            let (car, cdr) = exp.get_pair(env)?;
+            let Value::Symbol(i) = car else {
+                return None;
+            };
+            if state.get_symbol(i).is_some() || state.symbols.borrow().can_capture(i) {
+                return None;
+            }
+            let slot = env.global_intern_slot(i)?;
+            match env.get_global(slot) {
+                Value::Special(s) if s == env.specials().quote => {
+                    let (quoted, _) = cdr.get_pair(env)?;
+                    Some(env.intern_static(quoted.display_type(env)))
+                }
+                Value::Special(s) if s == env.specials().str_ => Some(env.intern("String")),
+                Value::Special(s)
+                    if s == env.specials().fn_
+                        || s == env.specials().mac_
+                        || s == env.specials().hygienic_mac =>
+                {
+                    Some(env.intern("Lambda"))
+                }
+                Value::Lambda(h) => env.get_lambda(h).ret_type,
+                Value::Closure(h) => env.get_closure(h).0.ret_type,
+                _ => None,
+            }
+        }
+        Value::Undefined => None,
+        // Everything else is a literal.
+        _ => Some(env.intern_static(exp.display_type(env))),
+    }
+}
+
+/// Compile a check that the value in reg has type expected, raising a :type error (with what in
+/// the message) if not.  Uses scratch to scratch + 2 as temporaries.
+pub(crate) fn compile_type_check(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    reg: usize,
+    expected: Interned,
+    what: &str,
+    scratch: usize,
+) -> VMResult<()> {
+    let line = env.own_line();
+    let (actual, expect, test) = (scratch as u16, scratch as u16 + 1, scratch as u16 + 2);
+    let mut types = vec![expected];
+    if env.get_interned(expected) == "Int" {
+        types.push(env.intern("UInt"));
+    }
+    let ok = state.chunk.add_jump(0);
+    state.chunk.encode2(TYPE, actual, reg as u16, line)?;
+    for t in types {
+        let t = state.add_constant(Value::Keyword(t));
+        state.chunk.encode2(CONST, expect, t as u16, line)?;
+        state.chunk.encode3(EQ, test, actual, expect, line)?;
+        state.chunk.encode2(JMPT, test, ok as u16, line)?;
+    }
+    let message = format!("{what} expects :{}, got ", env.get_interned(expected));
+    let message = Value::StringConst(env.intern(&message));
+    let message = state.add_constant(message);
+    state.chunk.encode2(CONST, expect, message as u16, line)?;
+    state.chunk.encode2(MOV, test, actual, line)?;
+    state.chunk.encode3(STR, expect, expect, test, line)?;
+    let key = state.add_constant(Value::Keyword(env.intern("type")));
+    state.chunk.encode2(CONST, actual, key as u16, line)?;
+    state.chunk.encode2(ERR, actual, expect, line)?;
+    state.chunk.update_jump(ok, state.chunk.code.len() as u32);
+    if state.max_regs < scratch + 2 {
+        state.max_regs = scratch + 2;
+    }
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use slvm::error::*;
+use slvm::opcodes::*;
+use slvm::value::*;
+use slvm::Interned;
+
+use crate::compile::destructure::setup_dbg;
+use crate::compile::util::get_args_iter;
+use crate::warnings::{check_shadow, check_unused};
+use crate::{compile, SloshVm};
+use compile_state::state::*;
+
+/// The let-values names (first register and count) when compiling the result of their value, a
+/// values form or call there binds its values to them.
+pub(crate) fn values_target(state: &CompileState) -> Option<(usize, usize)> {
+    if state.tail {
+        state.values_target
+    } else {
+        None
+    }
+}
+
+/// Bind the values left by the VALUES or call into result to the let-values names at first, the
+/// first name is set from result once the value is done.
+pub(crate) fn bind_values(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    result: usize,
+    target: (usize, usize),
+) -> VMResult<()> {
+    let (first, count) = target;
+    if result + count > state.max_regs {
+        state.max_regs = result + count;
+    }
+    let line = env.own_line();
+    state
+        .chunk
+        .encode2(MVBIND, result as u16, count as u16, line)?;
+    for i in 1..count {
+        state
+            .chunk
+            .encode2(MOV, (first + i) as u16, (result + i) as u16, line)?;
+    }
+    Ok(())
+}
+
+/// Compile (values exp*) into result.  In tail position this returns the values (SRETV), as the
+/// result of a let-values value they are bound to its names otherwise only the first is used.
+pub(crate) fn compile_values(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    let target = values_target(state);
+    let tail = state.tail;
+    state.tail = false;
+    for (i, exp) in cdr.iter().enumerate() {
+        compile(env, state, *exp, result + i + 1)?;
+    }
+    let line = env.own_line();
+    if tail && target.is_none() {
+        state
+            .chunk
+            .encode2(SRETV, (result + 1) as u16, cdr.len() as u16, line)?;
+    } else {
+        state.chunk.encode3(
+            VALUES,
+            result as u16,
+            (result + 1) as u16,
+            cdr.len() as u16,
+            line,
+        )?;
+        if let Some(target) = target {
+            bind_values(env, state, result, target)?;
+        }
+    }
+    Ok(())
+}
+
+fn let_values_inner(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+    old_tail: bool,
+) -> VMResult<()> {
+    let start_defers = state.defers;
+    let symbols = Rc::new(RefCell::new(Symbols::with_let(state.symbols.clone())));
+    state.symbols = symbols.clone();
+    let mut first_reg = symbols.borrow().regs_count();
+    while first_reg <= result {
+        // Make sure we do not step on the result or any other regs in temp use below it.
+        first_reg = symbols.borrow_mut().reserve_reg();
+    }
+    let mut bindings = Vec::new();
+    let args: Vec<Value> = get_args_iter(env, cdr[0], "let-values")?.collect();
+    let mut args_iter = args.iter();
+    while let Some(names) = args_iter.next() {
+        let value = if let Some(r) = args_iter.next() {
+            *r
+        } else {
+            return Err(VMError::new_compile(format!(
+                "let-values: names {} must have a value",
+                names.display_value(env)
+            )));
+        };
+        let mut names_list: Vec<Interned> = Vec::new();
+        for name in get_args_iter(env, *names, "let-values")? {
+            if let Value::Symbol(i) = name {
+                names_list.push(i);
+            } else {
+                return Err(VMError::new_compile("let-values: names must be symbols"));
+            }
+        }
+        if names_list.is_empty() {
+            return Err(VMError::new_compile("let-values: need at least one name"));
+        }
+        // The names get a run of registers, the value is compiled above them as the result.  A
+        // values form or call that produces the result (through if, let, do etc) binds the rest
+        // of the names, otherwise they stay nil.
+        let first = symbols.borrow_mut().reserve_reg();
+        for i in 1..names_list.len() {
+            symbols.borrow_mut().reserve_reg();
+            state
+                .chunk
+                .encode1(REGN, (first + i) as u16, env.own_line())?;
+        }
+        let value_reg = symbols.borrow().regs_count();
+        let old_target = state.values_target.replace((first, names_list.len()));
+        state.tail = true;
+        let res = compile(env, state, value, value_reg);
+        state.tail = false;
+        state.values_target = old_target;
+        res?;
+        state
+            .chunk
+            .encode2(MOV, first as u16, value_reg as u16, env.own_line())?;
+        for (i, name) in names_list.iter().enumerate() {
+            check_shadow(env, state, *name);
+            symbols.borrow_mut().insert_reserved(*name, first + i);
+            setup_dbg(env, state, first + i, *name);
+            bindings.push((*name, first + i));
+        }
+    }
+    let free_reg = symbols.borrow().regs_count();
+    let last_thing = if cdr.len() > 1 { cdr.len() - 2 } else { 0 };
+    for (i, r) in cdr[1..].iter().enumerate() {
+        if i == last_thing {
+            state.tail = old_tail;
+        }
+        compile(env, state, *r, free_reg)?;
+    }
+    check_unused(env, state, &bindings, first_reg);
+    state
+        .chunk
+        .encode2(MOV, result as u16, free_reg as u16, env.own_line())?;
+    for _ in start_defers..state.defers {
+        state.chunk.encode0(DFRPOP, env.own_line())?;
+    }
+    for i in first_reg..symbols.borrow().regs_count() {
+        state.chunk.encode1(CLRREG, i as u16, env.own_line())?;
+    }
+    Ok(())
+}
+
+/// Compile (let-values ((name*) exp, ...) body*), the values each exp produces are bound to its
+/// names.
+pub(crate) fn compile_let_values(
+    env: &mut SloshVm,
+    state: &mut CompileState,
+    cdr: &[Value],
+    result: usize,
+) -> VMResult<()> {
+    if cdr.is_empty() {
+        return Err(VMError::new_compile(
+            "Too few arguments, need at least 1 got 0.",
+        ));
+    }
+    let old_symbols = state.symbols.clone();
+    let old_tail = state.tail;
+    state.tail = false;
+    let old_defers = state.defers;
+    let result = let_values_inner(env, state, cdr, result, old_tail);
+    state.set_boxed_regs();
+    state.tail = old_tail;
+    state.symbols = old_symbols;
+    state.defers = old_defers;
+    result
+}
@@ -25,6 +25,8 @@ pub struct Destructure {
 pub enum DestructType {
     Vector(Handle, usize),
     Map(Handle, usize),
+    // &key params (name and default) from the list of key value pairs in the register.
+    Keys(Vec<(Interned, Value)>, usize),
 }
 
 pub struct DestructState {
@@ -125,6 +127,10 @@ impl DestructState {
         free_reg: usize,
     ) -> VMResult<()> {
         for opt_comps in &self.all_optionals {
+            // Set after the defaults run so captures in a default have to box them.
+            for (target_reg, _) in opt_comps {
+                state.symbols.borrow().set_uninit(*target_reg, true);
+            }
             for (target_reg, default) in opt_comps {
                 let jmp_idx = state.chunk.add_jump(0);
                 state
@@ -137,6 +143,7 @@ impl DestructState {
                 state
                     .chunk
                     .update_jump(jmp_idx, state.chunk.code.len() as u32);
+                state.symbols.borrow().set_uninit(*target_reg, false);
             }
         }
         Ok(())
@@ -304,6 +311,35 @@ impl DestructState {
         Ok(())
     }
 
+    fn do_keys_destructure(
+        &mut self,
+        keys: Vec<(Interned, Value)>,
+        current_reg: usize,
+        next_reg: &mut usize,
+    ) {
+        let start_reg = *next_reg;
+        let mut map_keys = Vec::new();
+        let mut opt_comps = Vec::new();
+        let mut register_labels = Vec::new();
+        for (key, default) in keys {
+            register_labels.push(Register::Named(key, *next_reg as u16));
+            map_keys.push(Value::Keyword(key));
+            // Every key is optional, nil if it has no default.
+            opt_comps.push((*next_reg, default));
+            *next_reg += 1;
+        }
+        self.destructures.push(Destructure {
+            start_reg: start_reg as u16,
+            len: map_keys.len() as u16,
+            reg: current_reg as u16,
+            map_keys: Some(map_keys),
+            rest: false,
+            allow_extra: false,
+            register_labels,
+        });
+        self.all_optionals.push(opt_comps);
+    }
+
     pub fn do_destructure(
         &mut self,
         env: &mut SloshVm,
@@ -323,6 +359,7 @@ impl DestructState {
                 DestructType::Map(map, reg) => {
                     self.do_map_destructure(env, map, reg, &mut stack, &mut next_reg)?
                 }
+                DestructType::Keys(keys, reg) => self.do_keys_destructure(keys, reg, &mut next_reg),
             }
         }
         Ok(())
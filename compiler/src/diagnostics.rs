@@ -0,0 +1,227 @@
+use std::fmt;
+
+use compile_state::state::{SloshVm, SloshVmTrait};
+use slvm::Value;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Location of an error in the source.  Lines and columns are 1 based, a line or column of 0
+/// means it is not known.  Span is the number of characters the error covers starting at column,
+/// 0 means work it out from the source (the whole form at column).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceLoc {
+    pub file_name: &'static str,
+    pub line: usize,
+    pub column: usize,
+    pub span: usize,
+}
+
+impl SourceLoc {
+    pub fn new(file_name: &'static str, line: usize, column: usize, span: usize) -> Self {
+        Self {
+            file_name,
+            line,
+            column,
+            span,
+        }
+    }
+
+    /// Location of a form from the debug properties the reader set on it.
+    pub fn from_value(vm: &SloshVm, val: Value) -> Option<Self> {
+        if let (
+            Some(Value::StringConst(file)),
+            Some(Value::UInt32(line)),
+            Some(Value::UInt32(col)),
+        ) = (
+            vm.get_heap_property(val, "dbg-file"),
+            vm.get_heap_property(val, "dbg-line"),
+            vm.get_heap_property(val, "dbg-col"),
+        ) {
+            let span = if let Some(Value::UInt32(span)) = vm.get_heap_property(val, "dbg-span") {
+                span as usize
+            } else {
+                0
+            };
+            Some(Self::new(
+                vm.get_interned(file),
+                line as usize,
+                col as usize,
+                span,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Location of a compile error in exp.  This is the last form the compiler got to (if it is
+    /// known) otherwise the start of exp.
+    pub fn compile_error(vm: &SloshVm, file_name: &'static str, exp: Value) -> Self {
+        let line = vm.line_num() as usize;
+        let column = vm.column_num() as usize;
+        if line > 0 && column > 0 {
+            Self::new(file_name, line, column, 0)
+        } else if let Some(loc) = Self::from_value(vm, exp) {
+            loc
+        } else {
+            Self::new(file_name, line, 0, 0)
+        }
+    }
+
+    /// Number of characters this covers in source, span if it is set otherwise the form that
+    /// starts at column (1 if the line is not in source).
+    pub fn span_in(&self, source: &str) -> usize {
+        if self.span > 0 {
+            return self.span;
+        }
+        match source.lines().nth(self.line.saturating_sub(1)) {
+            Some(line) if self.line > 0 && self.column > 0 => {
+                let chars: Vec<&str> = line.graphemes(true).collect();
+                let col = (self.column - 1).min(chars.len());
+                form_span(&chars[col..])
+            }
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file_name = if self.file_name.is_empty() {
+            "<input>"
+        } else {
+            self.file_name
+        };
+        if self.column > 0 {
+            write!(f, "{}:{}:{}", file_name, self.line, self.column)
+        } else {
+            write!(f, "{}:{}", file_name, self.line)
+        }
+    }
+}
+
+/// Number of characters in the form that starts at the beginning of line (stops at the end of the
+/// line if the form is not closed on it).
+fn form_span(line: &[&str]) -> usize {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escape = false;
+    for (i, ch) in line.iter().enumerate() {
+        if in_string {
+            if escape {
+                escape = false;
+            } else if *ch == "\\" {
+                escape = true;
+            } else if *ch == "\"" {
+                in_string = false;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            continue;
+        }
+        match *ch {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            "\"" => in_string = true,
+            " " | "\t" | ")" | "]" | "}" if depth == 0 => return i.max(1),
+            _ => {}
+        }
+    }
+    line.len().max(1)
+}
+
+/// Render an error with the source line it happened on and a caret underline, for example:
+///
+/// ```text
+/// error: Unexpected ')'
+///  --> test.slosh:1:12
+///   |
+/// 1 | (def x 10))
+///   |           ^
+/// ```
+///
+/// Source is the text that was read, if None then loc.file_name is read from disk to find the
+/// line (the snippet is left off if that fails).
+pub fn render_error(kind: &str, reason: &str, loc: &SourceLoc, source: Option<&str>) -> String {
+    let mut out = format!("{kind}: {reason}\n --> {loc}\n");
+    let file_text;
+    let source = match source {
+        Some(source) => Some(source),
+        None if !loc.file_name.is_empty() => {
+            file_text = std::fs::read_to_string(loc.file_name).ok();
+            file_text.as_deref()
+        }
+        None => None,
+    };
+    let src_line = if loc.line > 0 {
+        source.and_then(|s| s.lines().nth(loc.line - 1))
+    } else {
+        None
+    };
+    if let Some(src_line) = src_line {
+        let gutter = " ".repeat(loc.line.to_string().len());
+        out.push_str(&format!("{gutter} |\n{} | {src_line}\n", loc.line));
+        if loc.column > 0 {
+            let chars: Vec<&str> = src_line.graphemes(true).collect();
+            let col = (loc.column - 1).min(chars.len());
+            // Keep tabs so the caret lines up with the source line.
+            let pad: String = chars[..col]
+                .iter()
+                .map(|ch| if *ch == "\t" { "\t" } else { " " })
+                .collect();
+            let span = if loc.span > 0 {
+                loc.span
+            } else {
+                form_span(&chars[col..])
+            };
+            out.push_str(&format!("{gutter} | {pad}{}\n", "^".repeat(span)));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_error() {
+        let src = "(def x 1)\n(def y (+ x\t(car 1 2)) 3)\n";
+        let loc = SourceLoc::new("test.slosh", 2, 13, 0);
+        let out = render_error("error", "bad car", &loc, Some(src));
+        assert_eq!(
+            out,
+            "error: bad car\n --> test.slosh:2:13\n  |\n2 | (def y (+ x\t(car 1 2)) 3)\n  |            \t^^^^^^^^^\n"
+        );
+        let loc = SourceLoc::new("", 1, 8, 1);
+        let out = render_error("read error", "Unexpected ')'", &loc, Some("(def x))"));
+        assert_eq!(
+            out,
+            "read error: Unexpected ')'\n --> <input>:1:8\n  |\n1 | (def x))\n  |        ^\n"
+        );
+        // Symbols and strings underline the whole token.
+        let loc = SourceLoc::new("", 1, 8, 0);
+        let out = render_error("error", "not defined", &loc, Some("(def x zz \"s\")"));
+        assert!(out.ends_with("|        ^^\n"));
+        let loc = SourceLoc::new("", 1, 11, 0);
+        let out = render_error("error", "bad", &loc, Some("(def x zz \"s s\")"));
+        assert!(out.ends_with("|           ^^^^^\n"));
+        // No source, no snippet.
+        let loc = SourceLoc::new("", 3, 2, 0);
+        let out = render_error("error", "bad", &loc, None);
+        assert_eq!(out, "error: bad\n --> <input>:3:2\n");
+    }
+
+    #[test]
+    fn test_span_in() {
+        let src = "(def x 1)\n(def y (car x 2))\n";
+        assert_eq!(SourceLoc::new("", 2, 8, 0).span_in(src), 9);
+        assert_eq!(SourceLoc::new("", 2, 6, 0).span_in(src), 1);
+        assert_eq!(SourceLoc::new("", 2, 8, 3).span_in(src), 3);
+        assert_eq!(SourceLoc::new("", 5, 1, 0).span_in(src), 1);
+    }
+}
@@ -0,0 +1,45 @@
+use compile_state::state::{SloshVm, SloshVmTrait};
+use slvm::Value;
+
+/// Which globals find_globals() returns.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GlobalKind {
+    /// Lambdas, builtins, specials, etc.
+    Callable,
+    /// Everything that is not callable.
+    Value,
+    Any,
+}
+
+/// Is val something that is called (a function or special form)?
+pub fn is_callable(val: Value) -> bool {
+    matches!(
+        val,
+        Value::Lambda(_)
+            | Value::Closure(_)
+            | Value::Continuation(_)
+            | Value::Builtin(_)
+            | Value::Special(_)
+    )
+}
+
+/// Names of the globals of kind that start with start (all of them if start is empty).
+pub fn find_globals(vm: &SloshVm, start: &str, kind: GlobalKind) -> Vec<&'static str> {
+    // XXX TODO support namespaces when we have them.
+    let mut names = Vec::new();
+    for (i, idx) in vm.globals() {
+        let key = vm.get_interned(*i);
+        if start.is_empty() || key.starts_with(start) {
+            let global_val = vm.get_global((*idx).try_into().expect("to many globals"));
+            let matches = match kind {
+                GlobalKind::Callable => is_callable(global_val),
+                GlobalKind::Value => !is_callable(global_val),
+                GlobalKind::Any => true,
+            };
+            if matches {
+                names.push(key);
+            }
+        }
+    }
+    names
+}
@@ -9,8 +9,14 @@ pub use crate::config::*;
 pub mod backquote;
 pub use crate::backquote::*;
 
+pub mod diagnostics;
+pub use crate::diagnostics::*;
+
+pub mod globals;
+
 pub mod compile;
 pub mod pass1;
+pub mod warnings;
 
 #[cfg(test)]
 pub mod test_utils;
@@ -5,12 +5,13 @@ use slvm::{VMResult, Value};
 pub fn pass1(env: &mut SloshVm, state: &mut CompileState, exp: Value) -> VMResult<()> {
     let fn_ = env.intern("fn");
     let mac_ = env.intern("macro");
+    let hygienic_mac = env.intern("hygienic-macro");
     match exp {
         Value::Pair(_) | Value::List(_, _) => {
             let (car, cdr) = exp.get_pair(env).expect("Pair/List not a Pair or List?");
             // Do an extra pass1 on lambda's so we can get all captures upfront.
             if let Value::Symbol(i) = car {
-                if i == fn_ || i == mac_ {
+                if i == fn_ || i == mac_ || i == hygienic_mac {
                     // XXX boo on this collect.
                     let cdr = cdr.iter(env).collect::<Vec<Value>>();
                     if !cdr.is_empty() {
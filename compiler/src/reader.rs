@@ -4,12 +4,19 @@ use std::error::Error;
 use std::fmt;
 use std::fs::File;
 use std::io::{BufReader, Cursor};
-use std::num::{ParseFloatError, ParseIntError};
+use std::num::IntErrorKind;
+use std::rc::Rc;
 
-use compile_state::state::SloshVm;
+use crate::diagnostics::{render_error, SourceLoc};
+use builtins::add_builtin;
+use compile_state::state::{SloshVm, SloshVmTrait};
 use slvm::value::*;
-use slvm::Chunk;
+use slvm::{VMError, VMResult};
 use unicode_reader::Graphemes;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Global holding the read table, a map of character (or #x dispatch sequence) to reader macro.
+const READ_TABLE_GLOBAL: &str = "*read-table*";
 
 pub trait PeekableIterator: std::iter::Iterator {
     fn peek(&mut self) -> Option<&Self::Item>;
@@ -55,13 +62,55 @@ impl PeekableIterator for ReaderCharIter {
 #[derive(Clone, Debug)]
 pub struct ReadError {
     pub reason: String,
+    /// Where the error is, the reader fills this in with its position if it is not set.
+    pub loc: SourceLoc,
+    /// The input ended in the middle of a form (an unclosed list, string, comment, etc), more
+    /// input may complete it.
+    pub incomplete: bool,
+}
+
+impl ReadError {
+    pub fn new(reason: String) -> Self {
+        Self {
+            reason,
+            loc: SourceLoc::default(),
+            incomplete: false,
+        }
+    }
+
+    /// Error for input that ended before the form that starts at loc was finished.
+    pub fn incomplete(reason: String, loc: SourceLoc) -> Self {
+        Self {
+            reason,
+            loc,
+            incomplete: true,
+        }
+    }
+
+    /// Error that covers the source at loc rather than the reader's current position.
+    pub fn at(reason: String, loc: SourceLoc) -> Self {
+        Self {
+            reason,
+            loc,
+            incomplete: false,
+        }
+    }
+
+    /// Render this error with its line of source (see render_error()).
+    pub fn render(&self, source: Option<&str>) -> String {
+        render_error("Read error", &self.reason, &self.loc, source)
+    }
 }
 
 impl Error for ReadError {}
 
 impl fmt::Display for ReadError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.reason)
+        if self.loc.line > 0 {
+            write!(f, "{}: {}", self.loc, self.reason)
+        } else {
+            write!(f, "{}", self.reason)
+        }
     }
 }
 
@@ -69,6 +118,16 @@ pub struct Reader<'vm> {
     vm: &'vm mut SloshVm,
     char_iter: Option<Box<ReaderCharIter>>,
     file_name: &'static str,
+    // Reader macros from *read-table*, read_table_src is a copy of the entries they were last
+    // loaded from (compared by content since the map can be replaced or changed in place).
+    read_table: Rc<HashMap<&'static str, Value>>,
+    read_table_src: HashMap<Value, Value>,
+}
+
+/// Reader input parked in the VM while a reader macro runs.
+struct MacroInput {
+    char_iter: Box<ReaderCharIter>,
+    file_name: &'static str,
 }
 
 impl<'vm> Iterator for Reader<'vm> {
@@ -108,9 +167,9 @@ fn char_to_hex_num(ch: &str) -> Result<u8, ReadError> {
             "E" => Ok(14),
             "f" => Ok(15),
             "F" => Ok(15),
-            _ => Err(ReadError {
-                reason: format!("Invalid hex digit {ch}, expected 0-9 or A-F."),
-            }),
+            _ => Err(ReadError::new(format!(
+                "Invalid hex digit {ch}, expected 0-9 or A-F."
+            ))),
         }
     }
 }
@@ -133,6 +192,202 @@ fn is_digit(ch: &str) -> bool {
     )
 }
 
+/// A numeric literal that has been parsed but not allocated yet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum NumLit {
+    /// No suffix, use the smallest int that holds it (see alloc_int).
+    Int(i64),
+    Byte(u8),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Float(f64),
+}
+
+/// Split a type suffix (u8, i32, u32, i64, u64 and if allow_float f64) off the end of lit.
+fn split_suffix(lit: &str, allow_float: bool) -> (&str, Option<&'static str>) {
+    for suffix in ["u8", "i32", "u32", "i64", "u64", "f64"] {
+        if suffix == "f64" && !allow_float {
+            continue;
+        }
+        if let Some(body) = lit.strip_suffix(suffix) {
+            if !body.is_empty() {
+                return (body, Some(suffix));
+            }
+        }
+    }
+    (lit, None)
+}
+
+/// Is body a decimal float (digits with an optional decimal point and exponent)?
+fn is_float(body: &str) -> bool {
+    let (mantissa, exp) = match body.find(['e', 'E']) {
+        Some(idx) => (&body[..idx], Some(&body[idx + 1..])),
+        None => (body, None),
+    };
+    let mut digits = 0;
+    let mut decimals = 0;
+    for ch in mantissa.chars() {
+        match ch {
+            '0'..='9' => digits += 1,
+            '.' => decimals += 1,
+            _ => return false,
+        }
+    }
+    if digits == 0 || decimals > 1 {
+        return false;
+    }
+    match exp {
+        Some(exp) => {
+            let exp = exp.strip_prefix(['+', '-']).unwrap_or(exp);
+            !exp.is_empty() && exp.chars().all(|ch| ch.is_ascii_digit())
+        }
+        None => true,
+    }
+}
+
+/// Apply the sign and suffix to an integer magnitude, checking that it fits the type.
+fn int_lit(lit: &str, neg: bool, mag: u128, suffix: Option<&str>) -> Result<NumLit, String> {
+    let out_of_range = |ty: &str| format!("Integer literal {lit} out of range for {ty}");
+    let val = if neg {
+        -(i128::try_from(mag).map_err(|_| out_of_range("Int64"))?)
+    } else {
+        i128::try_from(mag).map_err(|_| out_of_range("UInt64"))?
+    };
+    match suffix {
+        None => i64::try_from(val)
+            .map(NumLit::Int)
+            .map_err(|_| out_of_range("Int64")),
+        Some("u8") => u8::try_from(val)
+            .map(NumLit::Byte)
+            .map_err(|_| out_of_range("Byte")),
+        Some("i32") => i32::try_from(val)
+            .map(NumLit::Int32)
+            .map_err(|_| out_of_range("Int32")),
+        Some("u32") => u32::try_from(val)
+            .map(NumLit::UInt32)
+            .map_err(|_| out_of_range("UInt32")),
+        Some("i64") => i64::try_from(val)
+            .map(NumLit::Int64)
+            .map_err(|_| out_of_range("Int64")),
+        Some("u64") => u64::try_from(val)
+            .map(NumLit::UInt64)
+            .map_err(|_| out_of_range("UInt64")),
+        Some(suffix) => Err(format!("Invalid suffix {suffix} on integer literal {lit}")),
+    }
+}
+
+/// Parse a hex float (for instance 1.8p3 is 1.5 * 2^3), body is the literal after the prefix.
+fn hex_float(lit: &str, neg: bool, body: &str) -> Result<NumLit, String> {
+    let invalid = || format!("Invalid hex float literal {lit}");
+    let (mantissa, exp) = match body.find(['p', 'P']) {
+        Some(idx) => (&body[..idx], &body[idx + 1..]),
+        None => (body, "0"),
+    };
+    let exp: i32 = exp.parse().map_err(|_| invalid())?;
+    let (whole, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if whole.is_empty() && frac.is_empty() {
+        return Err(invalid());
+    }
+    let mut val = 0.0_f64;
+    for ch in whole.chars().chain(frac.chars()) {
+        let digit = ch.to_digit(16).ok_or_else(invalid)?;
+        val = val * 16.0 + digit as f64;
+    }
+    let val = val * 2.0_f64.powi(exp - 4 * frac.len() as i32);
+    Ok(NumLit::Float(if neg { -val } else { val }))
+}
+
+/// Parse an int (with an optional suffix) or hex float in radix, body is the literal after the
+/// prefix (#x, 0x, etc) and lit is the whole literal for errors.
+fn radix_number(lit: &str, radix: u32, body: &str) -> Result<NumLit, String> {
+    let (neg, body) = match body.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, body.strip_prefix('+').unwrap_or(body)),
+    };
+    let body = body.replace('_', "");
+    if radix == 16 && body.contains(['.', 'p', 'P']) {
+        return hex_float(lit, neg, &body);
+    }
+    let (digits, suffix) = split_suffix(&body, false);
+    match u128::from_str_radix(digits, radix) {
+        Ok(mag) if !digits.starts_with(['+', '-']) => int_lit(lit, neg, mag, suffix),
+        Err(e) if matches!(e.kind(), IntErrorKind::PosOverflow) => {
+            Err(format!("Integer literal {lit} out of range for UInt64"))
+        }
+        _ => {
+            let name = match radix {
+                2 => "binary",
+                8 => "octal",
+                16 => "hex",
+                _ => "decimal",
+            };
+            Err(format!("Invalid {name} literal {lit}"))
+        }
+    }
+}
+
+/// Parse a numeric literal, returns None if lit is not a number (so it is a symbol).
+/// Supports digit separators (1_000), type suffixes (10u8, 10i32, 10u32, 10i64, 10u64,
+/// 1.5f64), exponents (1.5e10), 0x/0o/0b prefixes, hex floats (0x1.8p3) and the special floats
+/// inf.0 and nan.0 (with an optional sign).
+fn parse_number(lit: &str) -> Result<Option<NumLit>, String> {
+    let (neg, rest) = match lit.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, lit.strip_prefix('+').unwrap_or(lit)),
+    };
+    match rest {
+        "inf.0" if neg => return Ok(Some(NumLit::Float(f64::NEG_INFINITY))),
+        "inf.0" => return Ok(Some(NumLit::Float(f64::INFINITY))),
+        "nan.0" => return Ok(Some(NumLit::Float(f64::NAN))),
+        _ => {}
+    }
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some('0'..='9'), _) | (Some('.'), Some('0'..='9')) => {}
+        _ => return Ok(None),
+    }
+    for (prefix, radix) in [("0x", 16), ("0X", 16), ("0o", 8), ("0b", 2)] {
+        if let Some(body) = rest.strip_prefix(prefix) {
+            let body = if neg {
+                format!("-{body}")
+            } else {
+                body.to_string()
+            };
+            return radix_number(lit, radix, &body).map(Some);
+        }
+    }
+    let rest = rest.replace('_', "");
+    let (body, suffix) = split_suffix(&rest, true);
+    if body.chars().all(|ch| ch.is_ascii_digit()) && suffix != Some("f64") {
+        let int = body
+            .parse::<u128>()
+            .map_err(|_| format!("Integer literal {lit} out of range for UInt64"))
+            .and_then(|mag| int_lit(lit, neg, mag, suffix));
+        match int {
+            // Without a suffix an integer too big for an Int64 is read as a float.
+            Err(_) if suffix.is_none() => {
+                let val: f64 = body
+                    .parse()
+                    .map_err(|_| format!("Invalid float literal {lit}"))?;
+                Ok(Some(NumLit::Float(if neg { -val } else { val })))
+            }
+            int => int.map(Some),
+        }
+    } else if is_float(body) {
+        if let Some(suffix @ ("u8" | "i32" | "u32" | "i64" | "u64")) = suffix {
+            return Err(format!("Invalid suffix {suffix} on float literal {lit}"));
+        }
+        let val: f64 = body
+            .parse()
+            .map_err(|_| format!("Invalid float literal {lit}"))?;
+        Ok(Some(NumLit::Float(if neg { -val } else { val })))
+    } else {
+        Ok(None)
+    }
+}
+
 enum ReadReturn {
     None,
     List,
@@ -168,6 +423,8 @@ impl<'vm> Reader<'vm> {
             vm,
             char_iter: Some(char_iter),
             file_name,
+            read_table: Rc::new(HashMap::new()),
+            read_table_src: HashMap::new(),
         }
     }
 
@@ -198,6 +455,8 @@ impl<'vm> Reader<'vm> {
             vm,
             char_iter: Some(char_iter),
             file_name,
+            read_table: Rc::new(HashMap::new()),
+            read_table_src: HashMap::new(),
         }
     }
 
@@ -246,16 +505,16 @@ impl<'vm> Reader<'vm> {
         if let (Some(ch1), Some(ch2)) = (self.chars().next(), self.chars().next()) {
             let ch_n: u8 = (char_to_hex_num(&ch1)? * 16) + (char_to_hex_num(&ch2)?);
             if ch_n > 0x7f {
-                Err(ReadError {
-                    reason: "Invalid hex ascii code, must be less then \\x7f.".to_string(),
-                })
+                Err(ReadError::new(
+                    "Invalid hex ascii code, must be less then \\x7f.".to_string(),
+                ))
             } else {
                 Ok(ch_n as char)
             }
         } else {
-            Err(ReadError {
-                reason: "Invalid hex ascii code, expected two digits.".to_string(),
-            })
+            Err(ReadError::new(
+                "Invalid hex ascii code, expected two digits.".to_string(),
+            ))
         }
     }
 
@@ -271,7 +530,9 @@ impl<'vm> Reader<'vm> {
         }
     }
 
-    fn consume_block_comment(&mut self) {
+    fn consume_block_comment(&mut self) -> Result<(), ReadError> {
+        // Point at the #| that opened the comment.
+        let start = self.loc(self.line(), self.column().saturating_sub(1));
         let mut depth = 1;
         let mut last_ch = Cow::Borrowed(" ");
         while let Some(ch) = self.chars().next() {
@@ -283,9 +544,13 @@ impl<'vm> Reader<'vm> {
             }
             last_ch = ch;
             if depth == 0 {
-                break;
+                return Ok(());
             }
         }
+        Err(ReadError::incomplete(
+            "Unclosed block comment".to_string(),
+            start,
+        ))
     }
 
     fn do_char(
@@ -325,13 +590,9 @@ impl<'vm> Reader<'vm> {
                                 "return" => return Ok(Value::CodePoint('\r')),
                                 "backspace" => return Ok(Value::CodePoint('\u{0008}')),
                                 _ => {
-                                    let reason = format!(
-                                        "Not a valid char [{}]: line {}, col: {}",
-                                        buffer,
-                                        self.line(),
-                                        self.column()
-                                    );
-                                    return Err(ReadError { reason });
+                                    return Err(ReadError::new(format!(
+                                        "Not a valid char [{buffer}]"
+                                    )));
                                 }
                             }
                         }
@@ -340,12 +601,10 @@ impl<'vm> Reader<'vm> {
             }
             Ok(self.vm.alloc_char(&ch))
         } else {
-            let reason = format!(
-                "Not a valid char, missing: line {}, col: {}",
-                self.line(),
-                self.column()
-            );
-            Err(ReadError { reason })
+            Err(ReadError::incomplete(
+                "Not a valid char, missing".to_string(),
+                self.loc(self.line(), self.column()),
+            ))
         }
     }
 
@@ -354,9 +613,9 @@ impl<'vm> Reader<'vm> {
             if let Some(val) = std::char::from_u32(char_u32) {
                 Ok(val)
             } else {
-                Err(ReadError {
-                    reason: format!("Invalid unicode scalar, {char_u32:x} not a valid utf scalar.",),
-                })
+                Err(ReadError::new(format!(
+                    "Invalid unicode scalar, {char_u32:x} not a valid utf scalar.",
+                )))
             }
         }
         let mut first = true;
@@ -366,9 +625,9 @@ impl<'vm> Reader<'vm> {
         while let Some(ch) = self.chars().next() {
             if ch == "\n" {
                 if has_bracket {
-                    return Err(ReadError {
-                        reason: "Invalid unicode scalar, unexpected newline.".to_string(),
-                    });
+                    return Err(ReadError::new(
+                        "Invalid unicode scalar, unexpected newline.".to_string(),
+                    ));
                 } else {
                     return finish(char_u32);
                 }
@@ -383,9 +642,9 @@ impl<'vm> Reader<'vm> {
                 return finish(char_u32);
             }
             if nibbles >= 8 {
-                return Err(ReadError {
-                    reason: "Invalid unicode scalar, too many bytes (4 max).".to_string(),
-                });
+                return Err(ReadError::new(
+                    "Invalid unicode scalar, too many bytes (4 max).".to_string(),
+                ));
             }
             nibbles += 1;
             let nib = char_to_hex_num(&ch)?;
@@ -397,9 +656,9 @@ impl<'vm> Reader<'vm> {
             }
         }
         if has_bracket {
-            Err(ReadError {
-                reason: "Invalid unicode scalar, failed to parse.".to_string(),
-            })
+            Err(ReadError::new(
+                "Invalid unicode scalar, failed to parse.".to_string(),
+            ))
         } else {
             finish(char_u32)
         }
@@ -422,34 +681,42 @@ impl<'vm> Reader<'vm> {
         false
     }
 
+    /// Push the char for the escape sequence \ch onto symbol.
+    fn read_escape(&mut self, ch: &str, symbol: &mut String) -> Result<(), ReadError> {
+        match ch {
+            "n" => symbol.push('\n'),
+            "r" => symbol.push('\r'),
+            "t" => symbol.push('\t'),
+            "x" => {
+                let res = self.escape_to_char()?;
+                symbol.push(res);
+            }
+            "u" => {
+                let res = self.read_utf_scalar()?;
+                symbol.push(res);
+            }
+            _ => {
+                symbol.push_str(ch);
+            }
+        }
+        Ok(())
+    }
+
     fn read_string(&mut self, symbol: &mut String, doc_string: bool) -> Result<Value, ReadError> {
         symbol.clear();
         let mut last_ch_escape = false;
         let mut args = vec![];
         let line = self.line() as u32;
         let column = self.column() as u32;
+        let mut closed = false;
 
         while let Some(ch) = self.chars().next() {
             if last_ch_escape {
-                match &*ch {
-                    "n" => symbol.push('\n'),
-                    "r" => symbol.push('\r'),
-                    "t" => symbol.push('\t'),
-                    "x" => {
-                        let res = self.escape_to_char()?;
-                        symbol.push(res);
-                    }
-                    "u" => {
-                        let res = self.read_utf_scalar()?;
-                        symbol.push(res);
-                    }
-                    _ => {
-                        symbol.push_str(&ch);
-                    }
-                }
+                self.read_escape(&ch, symbol)?;
                 last_ch_escape = false;
             } else {
                 if self.end_string(&ch, doc_string) {
+                    closed = true;
                     break;
                 }
                 if ch == "{" {
@@ -465,9 +732,9 @@ impl<'vm> Reader<'vm> {
                     symbol.clear();
                     let ch = self.chars().next();
                     if ch != Some("}".into()) {
-                        return Err(ReadError {
-                            reason: "invalid str format, missing '}'".to_string(),
-                        });
+                        return Err(ReadError::new(
+                            "invalid str format, missing '}'".to_string(),
+                        ));
                     }
                 } else if ch == "\\" {
                     last_ch_escape = true;
@@ -476,7 +743,17 @@ impl<'vm> Reader<'vm> {
                 }
             }
         }
-        if args.is_empty() {
+        if !closed {
+            let (reason, start) = if doc_string {
+                ("Unclosed doc string", column.saturating_sub(1))
+            } else {
+                ("Unclosed string", column)
+            };
+            Err(ReadError::incomplete(
+                reason.to_string(),
+                self.loc(line as usize, start as usize),
+            ))
+        } else if args.is_empty() {
             Ok(Value::StringConst(self.vm.intern(symbol)))
         } else {
             if !symbol.is_empty() {
@@ -487,17 +764,93 @@ impl<'vm> Reader<'vm> {
         }
     }
 
+    /// Read an interpolated string ($"Hello ${name}"), the $" has been consumed.  Each ${form}
+    /// is read as a form and the result is a (str ...) call (or a plain string if nothing was
+    /// interpolated).  Use \$ for a literal $ followed by {.
+    fn read_interp_string(
+        &mut self,
+        symbol: &mut String,
+        in_back_quote: bool,
+    ) -> Result<Value, ReadError> {
+        symbol.clear();
+        let line = self.line() as u32;
+        // Point at the $ that started the string.
+        let column = self.column().saturating_sub(1) as u32;
+        let mut args = vec![Value::Symbol(self.vm.intern("str"))];
+        let mut buffer = String::new();
+        let mut closed = false;
+
+        while let Some(ch) = self.chars().next() {
+            match &*ch {
+                "\\" => {
+                    if let Some(ch) = self.chars().next() {
+                        self.read_escape(&ch, symbol)?;
+                    }
+                }
+                "\"" => {
+                    closed = true;
+                    break;
+                }
+                "$" if self.chars().peek().map(|ch| ch == "{").unwrap_or(false) => {
+                    self.chars().next();
+                    if !symbol.is_empty() {
+                        args.push(Value::StringConst(self.vm.intern(symbol)));
+                        symbol.clear();
+                    }
+                    self.consume_whitespace();
+                    if self.chars().peek().map(|ch| ch == "}").unwrap_or(false) {
+                        return Err(ReadError::new(
+                            "Empty ${} in interpolated string".to_string(),
+                        ));
+                    }
+                    match self.read_inner(&mut buffer, in_back_quote, ReadReturn::None)? {
+                        Some(exp) => args.push(exp),
+                        None => break,
+                    }
+                    self.consume_whitespace();
+                    match self.chars().next() {
+                        Some(ch) if ch == "}" => {}
+                        Some(_) => {
+                            return Err(ReadError::new(
+                                "Expected '}' after interpolated form".to_string(),
+                            ))
+                        }
+                        None => break,
+                    }
+                }
+                _ => symbol.push_str(&ch),
+            }
+        }
+        if !closed {
+            return Err(ReadError::incomplete(
+                "Unclosed interpolated string".to_string(),
+                self.loc(line as usize, column as usize),
+            ));
+        }
+        if args.len() == 1 {
+            return Ok(Value::StringConst(self.vm.intern(symbol)));
+        }
+        if !symbol.is_empty() {
+            args.push(Value::StringConst(self.vm.intern(symbol)));
+            symbol.clear();
+        }
+        Ok(self.alloc_list(args, line, column))
+    }
+
     fn read_string_literal<'sym>(
         &mut self,
         symbol: &'sym mut String,
     ) -> Result<&'sym mut String, ReadError> {
         symbol.clear();
+        // Point at the #" that started the literal.
+        let start = self.loc(self.line(), self.column().saturating_sub(1));
         let end_ch = if let Some(ch) = self.chars().next() {
             ch
         } else {
-            return Err(ReadError {
-                reason: "Unexpected stream end on string literal".to_string(),
-            });
+            return Err(ReadError::incomplete(
+                "Unexpected stream end on string literal".to_string(),
+                start,
+            ));
         };
 
         while let Some(ch) = self.chars().next() {
@@ -512,37 +865,34 @@ impl<'vm> Reader<'vm> {
             }
             symbol.push_str(&ch);
         }
-        Err(ReadError {
-            reason: "Unexpected end of string literal".to_string(),
-        })
+        Err(ReadError::incomplete(
+            "Unexpected end of string literal".to_string(),
+            start,
+        ))
     }
 
-    fn do_atom(&mut self, symbol: &str, is_number: bool) -> Value {
-        if is_number {
-            let mut num_str = symbol.to_string();
-            num_str.retain(|ch| ch != '_');
-            let potential_int: Result<i64, ParseIntError> = num_str.parse();
-            match potential_int {
-                Ok(v) => self.vm.alloc_int(v),
-                Err(_) => {
-                    let potential_float: Result<f64, ParseFloatError> = num_str.parse();
-                    match potential_float {
-                        Ok(f) => self.vm.alloc_f64(f),
-                        Err(_) => Value::Symbol(self.vm.intern(symbol)),
-                    }
-                }
-            }
+    fn alloc_number(&mut self, num: NumLit) -> Value {
+        match num {
+            NumLit::Int(i) => self.vm.alloc_int(i),
+            NumLit::Byte(b) => Value::Byte(b),
+            NumLit::Int32(i) => Value::Int32(i),
+            NumLit::UInt32(u) => Value::UInt32(u),
+            NumLit::Int64(i) => self.vm.alloc_i64(i),
+            NumLit::UInt64(u) => self.vm.alloc_u64(u),
+            NumLit::Float(f) => self.vm.alloc_f64(f),
+        }
+    }
+
+    fn do_atom(&mut self, symbol: &str) -> Value {
+        if symbol.is_empty() {
+            return Value::Nil;
+        }
+        if symbol == "nil" {
+            Value::Nil
+        } else if symbol.len() > 1 && symbol.starts_with(':') {
+            Value::Keyword(self.vm.intern(&symbol[1..]))
         } else {
-            if symbol.is_empty() {
-                return Value::Nil;
-            }
-            if symbol == "nil" {
-                Value::Nil
-            } else if symbol.len() > 1 && symbol.starts_with(':') {
-                Value::Keyword(self.vm.intern(&symbol[1..]))
-            } else {
-                Value::Symbol(self.vm.intern(symbol))
-            }
+            Value::Symbol(self.vm.intern(symbol))
         }
     }
 
@@ -552,43 +902,12 @@ impl<'vm> Reader<'vm> {
         for_ch: bool,
         skip_underscore: bool,
         read_table_term: &HashMap<&'static str, Value>,
-    ) -> bool {
-        fn maybe_number(
-            ch: &str,
-            has_e: &mut bool,
-            last_e: &mut bool,
-            has_decimal: &mut bool,
-        ) -> bool {
-            if ch == "." {
-                if *has_decimal {
-                    false
-                } else {
-                    *has_decimal = true;
-                    true
-                }
-            } else if !*has_e && ch == "e" {
-                *has_e = true;
-                *last_e = true;
-                true
-            } else {
-                is_digit(ch) || ch == "." || ch == "_" || (*last_e && (ch == "+" || ch == "-"))
-            }
-        }
-
+    ) {
         let mut has_peek;
         let mut push_next = false;
-        let mut is_number = buffer.is_empty()
-            || (buffer.len() == 1
-                && (is_digit(&buffer[..])
-                    || (&buffer[..] == "+")
-                    || (&buffer[..] == "-")
-                    || (&buffer[..] == ".")));
-        let mut has_decimal = buffer.len() == 1 && &buffer[..] == ".";
-        let mut has_e = false;
-        let mut last_e = false;
         if let Some(ch) = self.chars().peek() {
             if end_symbol(ch, read_table_term) && !for_ch {
-                return buffer.len() == 1 && is_digit(&buffer[..]);
+                return;
             }
         };
         let mut next_ch = self.chars().next();
@@ -604,16 +923,10 @@ impl<'vm> Reader<'vm> {
             if ch == "\\" && has_peek && !for_ch {
                 push_next = true;
             } else if !skip_underscore || ch != "_" {
-                if is_number {
-                    is_number = maybe_number(&ch, &mut has_e, &mut last_e, &mut has_decimal);
-                }
                 buffer.push_str(&ch);
             }
             if push_next {
                 let next_ch = self.chars().next().unwrap();
-                if is_number {
-                    is_number = maybe_number(&ch, &mut has_e, &mut last_e, &mut has_decimal);
-                }
                 buffer.push_str(&next_ch);
                 push_next = false;
             } else if ch == "." && peek_ch == "~" {
@@ -623,7 +936,6 @@ impl<'vm> Reader<'vm> {
             }
             next_ch = self.chars().next();
         }
-        is_number
     }
 
     fn next2(&mut self) -> Option<(Cow<'static, str>, Cow<'static, str>)> {
@@ -653,14 +965,23 @@ impl<'vm> Reader<'vm> {
         buffer: &mut String,
         radix: u32,
         read_table_term: &HashMap<&'static str, Value>,
-    ) -> Result<i64, ReadError> {
+    ) -> Result<Value, ReadError> {
         buffer.clear();
+        // Point errors at the # that started the literal.
+        let mut start = self.loc(self.line(), self.column().saturating_sub(1));
         self.read_symbol(buffer, true, true, read_table_term);
-        match i64::from_str_radix(buffer, radix) {
-            Ok(n) => Ok(n),
-            Err(e) => Err(ReadError {
-                reason: e.to_string(),
-            }),
+        let prefix = match radix {
+            2 => "#b",
+            8 => "#o",
+            _ => "#x",
+        };
+        let lit = format!("{prefix}{buffer}");
+        match radix_number(&lit, radix, buffer) {
+            Ok(num) => Ok(self.alloc_number(num)),
+            Err(reason) => {
+                start.span = lit.graphemes(true).count();
+                Err(ReadError::at(reason, start))
+            }
         }
     }
 
@@ -671,6 +992,7 @@ impl<'vm> Reader<'vm> {
     ) -> Result<Vec<Value>, ReadError> {
         let mut v: Vec<Value> = Vec::new();
         let mut cont = true;
+        let start = self.loc(self.line(), self.column());
 
         let close_intern = self.vm.intern("]");
         while cont {
@@ -694,9 +1016,7 @@ impl<'vm> Reader<'vm> {
                 cont = false;
             }
         }
-        Err(ReadError {
-            reason: "Unclosed vector".to_string(),
-        })
+        Err(ReadError::incomplete("Unclosed vector".to_string(), start))
     }
 
     fn read_map(
@@ -706,6 +1026,7 @@ impl<'vm> Reader<'vm> {
     ) -> Result<HashMap<Value, Value>, ReadError> {
         let mut map: HashMap<Value, Value> = HashMap::new();
         let mut cont = true;
+        let start = self.loc(self.line(), self.column());
 
         let close_intern = self.vm.intern("}");
         while cont {
@@ -726,9 +1047,7 @@ impl<'vm> Reader<'vm> {
                 Ok(exp) => {
                     if let Some(Value::Symbol(i)) = &exp {
                         if *i == close_intern {
-                            return Err(ReadError {
-                                reason: "Map missing value".to_string(),
-                            });
+                            return Err(ReadError::new("Map missing value".to_string()));
                         }
                     }
                     exp
@@ -744,9 +1063,7 @@ impl<'vm> Reader<'vm> {
                 cont = false;
             }
         }
-        Err(ReadError {
-            reason: "Unclosed map".to_string(),
-        })
+        Err(ReadError::incomplete("Unclosed map".to_string(), start))
     }
 
     fn get_unquote_lst(&mut self, exp: Value) -> Option<Value> {
@@ -825,15 +1142,14 @@ impl<'vm> Reader<'vm> {
             let pch = self.chars().peek();
             if let Some(exp) = exp {
                 if list.is_empty() && dot {
-                    return Err(ReadError {
-                        reason: "Invalid dotted pair syntax (nothing before dot).".to_string(),
-                    });
+                    return Err(ReadError::new(
+                        "Invalid dotted pair syntax (nothing before dot).".to_string(),
+                    ));
                 } else if dot {
                     if self.unquote_splice(exp) {
-                        return Err(ReadError {
-                            reason: "Invalid dotted pair syntax with unquote-splice (,@/,.)."
-                                .to_string(),
-                        });
+                        return Err(ReadError::new(
+                            "Invalid dotted pair syntax with unquote-splice (,@/,.).".to_string(),
+                        ));
                     }
                     let exp = if let Some(uqexp) = self.get_unquote_lst(exp) {
                         // Do this so `(x y . ,z) works
@@ -844,9 +1160,9 @@ impl<'vm> Reader<'vm> {
                             i += 1;
                         }
                         if i != 1 {
-                            return Err(ReadError {
-                                reason: "Invalid dotted pair syntax with unquote.".to_string(),
-                            });
+                            return Err(ReadError::new(
+                                "Invalid dotted pair syntax with unquote.".to_string(),
+                            ));
                         }
                         self.vm.alloc_vector_ro(v)
                     } else {
@@ -863,16 +1179,16 @@ impl<'vm> Reader<'vm> {
                 dot_count += 1;
             }
             if dot_count > 1 {
-                return Err(ReadError {
-                    reason: "Invalid dotted pair syntax (more than object follows dot)."
-                        .to_string(),
-                });
+                return Err(ReadError::new(
+                    "Invalid dotted pair syntax (more than object follows dot).".to_string(),
+                ));
             }
         }
         if !closed {
-            Err(ReadError {
-                reason: "Unclosed list".to_string(),
-            })
+            Err(ReadError::incomplete(
+                "Unclosed list".to_string(),
+                self.loc(line as usize, column as usize),
+            ))
         } else if dot {
             let mut list_iter = list.iter().rev();
             if let Some(last) = list_iter.next() {
@@ -880,6 +1196,7 @@ impl<'vm> Reader<'vm> {
                 for v in list_iter {
                     last = self.alloc_pair(*v, last, line, column);
                 }
+                self.set_span(last, line, column);
                 Ok(last)
             } else {
                 Ok(Value::Nil)
@@ -887,7 +1204,19 @@ impl<'vm> Reader<'vm> {
         } else if list.is_empty() {
             Ok(Value::Nil)
         } else {
-            Ok(self.alloc_list(list, line, column))
+            let list = self.alloc_list(list, line, column);
+            self.set_span(list, line, column);
+            Ok(list)
+        }
+    }
+
+    /// Record how many characters a list that started at line/column and just closed covers.
+    /// Only done for lists on one line (otherwise it is left unknown).
+    fn set_span(&mut self, list: Value, line: u32, column: u32) {
+        if self.line() as u32 == line && self.column() as u32 >= column {
+            let span = self.column() as u32 - column + 1;
+            self.vm
+                .set_heap_property(list, "dbg-span", Value::UInt32(span));
         }
     }
 
@@ -947,14 +1276,17 @@ impl<'vm> Reader<'vm> {
         return_close: ReadReturn,
     ) -> Result<Option<Value>, ReadError> {
         self.consume_whitespace();
-        let read_table_term: HashMap<&'static str, Value> = HashMap::new();
-        let _read_table: HashMap<&'static str, Chunk> = HashMap::new();
+        self.sync_read_table();
+        let read_table_term = self.read_table.clone();
 
         let i_quote = self.vm.intern("quote");
         let i_backquote = self.vm.intern("back-quote");
         while let Some((ch, peek_ch)) = self.next2() {
             let line = self.line() as u32;
             let column = self.column() as u32;
+            if let Some((key, func)) = read_table_term.get_key_value(&*ch) {
+                return Ok(Some(self.call_read_macro(key, *func)?));
+            }
             match &*ch {
                 "\"" => {
                     match self.read_string(buffer, false) {
@@ -962,6 +1294,10 @@ impl<'vm> Reader<'vm> {
                         Err(e) => return Err(e),
                     };
                 }
+                "$" if peek_ch == "\"" => {
+                    self.chars().next();
+                    return Ok(Some(self.read_interp_string(buffer, in_back_quote)?));
+                }
                 "'" => match self.read_inner(buffer, in_back_quote, ReadReturn::None) {
                     Ok(Some(exp)) => {
                         let cdr = self.alloc_pair(exp, Value::Nil, line, column);
@@ -969,9 +1305,10 @@ impl<'vm> Reader<'vm> {
                         return Ok(Some(qlist));
                     }
                     Ok(None) => {
-                        return Err(ReadError {
-                            reason: "Invalid quote".to_string(),
-                        });
+                        return Err(ReadError::incomplete(
+                            "Invalid quote".to_string(),
+                            self.loc(line as usize, column as usize),
+                        ));
                     }
                     Err(err) => {
                         return Err(err);
@@ -984,9 +1321,10 @@ impl<'vm> Reader<'vm> {
                         return Ok(Some(qlist));
                     }
                     Ok(None) => {
-                        return Err(ReadError {
-                            reason: "Invalid back-quote".to_string(),
-                        });
+                        return Err(ReadError::incomplete(
+                            "Invalid back-quote".to_string(),
+                            self.loc(line as usize, column as usize),
+                        ));
                     }
                     Err(err) => {
                         return Err(err);
@@ -1008,9 +1346,10 @@ impl<'vm> Reader<'vm> {
                             return Ok(Some(self.alloc_pair(sym, cdr, line, column)));
                         }
                         Ok(None) => {
-                            return Err(ReadError {
-                                reason: "Invalid back-quote".to_string(),
-                            });
+                            return Err(ReadError::incomplete(
+                                "Invalid back-quote".to_string(),
+                                self.loc(line as usize, column as usize),
+                            ));
                         }
                         Err(err) => {
                             return Err(err);
@@ -1018,9 +1357,9 @@ impl<'vm> Reader<'vm> {
                     }
                 }
                 "~" => {
-                    return Err(ReadError {
-                        reason: "Unquote outside of a back-quote".to_string(),
-                    })
+                    return Err(ReadError::new(
+                        "Unquote outside of a back-quote".to_string(),
+                    ))
                 }
                 "\\" => {
                     return Ok(Some(self.do_char(buffer, &read_table_term)?));
@@ -1028,7 +1367,7 @@ impl<'vm> Reader<'vm> {
                 "#" => {
                     self.chars().next();
                     match &*peek_ch {
-                        "|" => self.consume_block_comment(),
+                        "|" => self.consume_block_comment()?,
                         "!" => {
                             let line = self.line() as u32;
                             let column = self.column() as u32;
@@ -1044,12 +1383,7 @@ impl<'vm> Reader<'vm> {
                             };
                         }
                         "<" => {
-                            let reason = format!(
-                                "Found an unreadable token: line {}, col: {}",
-                                self.line(),
-                                self.column()
-                            );
-                            return Err(ReadError { reason });
+                            return Err(ReadError::new("Found an unreadable token".to_string()));
                         }
                         "t" => {
                             return Ok(Some(Value::True));
@@ -1067,17 +1401,17 @@ impl<'vm> Reader<'vm> {
                         // Read an octal int
                         "o" => {
                             let exp = self.read_num_radix(buffer, 8, &read_table_term)?;
-                            return Ok(Some(self.vm.alloc_int(exp)));
+                            return Ok(Some(exp));
                         }
                         // Read a hex int
                         "x" => {
                             let exp = self.read_num_radix(buffer, 16, &read_table_term)?;
-                            return Ok(Some(self.vm.alloc_int(exp)));
+                            return Ok(Some(exp));
                         }
                         // Read a binary int
                         "b" => {
                             let exp = self.read_num_radix(buffer, 2, &read_table_term)?;
-                            return Ok(Some(self.vm.alloc_int(exp)));
+                            return Ok(Some(exp));
                         }
                         ";" => {
                             match self.read_inner(buffer, in_back_quote, ReadReturn::None) {
@@ -1091,13 +1425,13 @@ impl<'vm> Reader<'vm> {
                             }
                         }
                         _ => {
-                            let reason = format!(
-                                "Found # with invalid char {}: line {}, col: {}",
-                                peek_ch,
-                                self.line(),
-                                self.column()
-                            );
-                            return Err(ReadError { reason });
+                            let dispatch = format!("#{peek_ch}");
+                            if let Some((key, func)) = read_table_term.get_key_value(&*dispatch) {
+                                return Ok(Some(self.call_read_macro(key, *func)?));
+                            }
+                            return Err(ReadError::new(format!(
+                                "Found # with invalid char {peek_ch}"
+                            )));
                         }
                     }
                 }
@@ -1109,9 +1443,7 @@ impl<'vm> Reader<'vm> {
                     return Ok(Some(Value::Symbol(self.vm.intern(")"))));
                 }
                 ")" => {
-                    let reason =
-                        format!("Unexpected ')': line {} col {}", self.line(), self.column());
-                    return Err(ReadError { reason });
+                    return Err(ReadError::new("Unexpected ')'".to_string()));
                 }
                 "{" => {
                     let exp = self.read_map(buffer, in_back_quote)?;
@@ -1121,12 +1453,7 @@ impl<'vm> Reader<'vm> {
                     return Ok(Some(Value::Symbol(self.vm.intern("}"))));
                 }
                 "}" => {
-                    let reason = format!(
-                        "Unexpected '}}': line {} col {}",
-                        self.line(),
-                        self.column()
-                    );
-                    return Err(ReadError { reason });
+                    return Err(ReadError::new("Unexpected '}'".to_string()));
                 }
                 "[" => {
                     let exp = self.read_vector(buffer, in_back_quote)?;
@@ -1136,21 +1463,26 @@ impl<'vm> Reader<'vm> {
                     return Ok(Some(Value::Symbol(self.vm.intern("]"))));
                 }
                 "]" => {
-                    let reason =
-                        format!("Unexpected ']': line {} col {}", self.line(), self.column());
-                    return Err(ReadError { reason });
+                    return Err(ReadError::new("Unexpected ']'".to_string()));
                 }
                 ";" => self.consume_line_comment(),
                 _ => {
                     buffer.clear();
                     buffer.push_str(&ch);
-                    let is_number = self.read_symbol(buffer, false, false, &read_table_term);
-                    if is_number {
-                        return Ok(Some(self.do_atom(buffer, is_number)));
-                    } else if let Some(get) = self.parse_get(buffer) {
+                    let mut start = self.loc(self.line(), self.column());
+                    self.read_symbol(buffer, false, false, &read_table_term);
+                    match parse_number(buffer) {
+                        Ok(Some(num)) => return Ok(Some(self.alloc_number(num))),
+                        Ok(None) => {}
+                        Err(reason) => {
+                            start.span = buffer.graphemes(true).count();
+                            return Err(ReadError::at(reason, start));
+                        }
+                    }
+                    if let Some(get) = self.parse_get(buffer) {
                         return Ok(Some(get));
                     } else {
-                        return Ok(Some(self.do_atom(buffer, is_number)));
+                        return Ok(Some(self.do_atom(buffer)));
                     }
                 }
             }
@@ -1164,10 +1496,316 @@ impl<'vm> Reader<'vm> {
         let mut buffer = String::new();
         let res = self.read_inner(&mut buffer, false, ReadReturn::None);
         self.vm.unpause_gc();
+        res.map_err(|mut err| {
+            if err.loc.line == 0 {
+                err.loc = self.loc(self.line(), self.column());
+            }
+            err
+        })
+    }
+
+    /// Location of a one character error at line and column.
+    fn loc(&self, line: usize, column: usize) -> SourceLoc {
+        SourceLoc::new(self.file_name, line, column, 1)
+    }
+
+    /// Reload the reader macros if *read-table* has been replaced or changed since the last read.
+    fn sync_read_table(&mut self) {
+        let table = match read_table(self.vm) {
+            Value::Map(h) => Some(self.vm.get_map(h)),
+            _ => None,
+        };
+        let unchanged = match table {
+            Some(table) => *table == self.read_table_src,
+            None => self.read_table_src.is_empty(),
+        };
+        if unchanged {
+            return;
+        }
+        let table = table.cloned().unwrap_or_default();
+        let mut read_table = HashMap::new();
+        for (key, func) in &table {
+            if let Value::StringConst(i) = key {
+                read_table.insert(self.vm.get_interned(*i), *func);
+            }
+        }
+        self.read_table = Rc::new(read_table);
+        self.read_table_src = table;
+    }
+
+    /// Call the reader macro func registered for key.  The input is parked in the VM while the
+    /// macro runs so it can consume it with the reader:: builtins, the result is the form read.
+    fn call_read_macro(&mut self, key: &'static str, func: Value) -> Result<Value, ReadError> {
+        let char_iter = self.char_iter.take().expect("Invalid Reader!");
+        let input = MacroInput {
+            char_iter,
+            file_name: self.file_name,
+        };
+        let old_input = self.vm.env_mut().swap_reader_input(Some(Box::new(input)));
+        let key_val = Value::StringConst(self.vm.intern_static(key));
+        let res = call_func(self.vm, func, &[key_val]);
+        let input = self
+            .vm
+            .env_mut()
+            .swap_reader_input(old_input)
+            .and_then(|input| input.downcast::<MacroInput>().ok())
+            .expect("Reader macro input missing!");
+        self.char_iter = Some(input.char_iter);
+        res.map_err(|e| {
+            ReadError::new(format!("Reader macro {key} failed: {}", e.display(self.vm)))
+        })
+    }
+}
+
+/// Return the current read table.  Save this before loading a file and restore it with
+/// set_read_table() afterwards to keep the file's reader macros local to it.
+pub fn read_table(vm: &mut SloshVm) -> Value {
+    let sym = vm.intern_static(READ_TABLE_GLOBAL);
+    if let Some(slot) = vm.global_intern_slot(sym) {
+        vm.get_global(slot)
+    } else {
+        Value::Nil
+    }
+}
+
+/// Replace the read table (see read_table()).
+pub fn set_read_table(vm: &mut SloshVm, table: Value) {
+    vm.set_named_global(READ_TABLE_GLOBAL, table);
+}
+
+fn call_func(vm: &mut SloshVm, func: Value, args: &[Value]) -> VMResult<Value> {
+    match func {
+        Value::Lambda(h) => {
+            let l = vm.get_lambda(h);
+            vm.do_call(l, args, None)
+        }
+        Value::Closure(h) => {
+            let (l, tcaps) = vm.get_closure(h);
+            let caps = Vec::from(tcaps);
+            vm.do_call(l, args, Some(&caps[..]))
+        }
+        _ => Err(VMError::new_vm(format!(
+            "not a function: {}",
+            func.display_value(vm)
+        ))),
+    }
+}
+
+/// Run f with the input of the active reader macro.
+fn with_macro_input<T>(
+    vm: &mut SloshVm,
+    name: &str,
+    f: impl FnOnce(&mut SloshVm, &mut MacroInput) -> T,
+) -> VMResult<T> {
+    let input = vm
+        .env_mut()
+        .swap_reader_input(None)
+        .and_then(|input| input.downcast::<MacroInput>().ok());
+    if let Some(mut input) = input {
+        let res = f(vm, &mut input);
+        vm.env_mut().swap_reader_input(Some(input));
+        Ok(res)
+    } else {
+        Err(VMError::new_vm(format!(
+            "{name}: can only be used in a reader macro"
+        )))
+    }
+}
+
+/// True if key can name a reader macro: a character that is not already syntax or a # followed by
+/// a character that is not a builtin dispatch.
+fn valid_macro_key(key: &str) -> bool {
+    let chars: Vec<&str> = key.graphemes(true).collect();
+    match chars[..] {
+        ["#", ch] => {
+            !is_whitespace(ch)
+                && !matches!(
+                    ch,
+                    "|" | "!" | "<" | "t" | "f" | "\"" | "o" | "x" | "b" | ";"
+                )
+        }
+        [ch] => !is_whitespace(ch) && !end_symbol(ch, &HashMap::new()) && !is_digit(ch),
+        _ => false,
+    }
+}
+
+fn set_reader_macro(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if registers.len() != 2 {
+        return Err(VMError::new_vm(
+            "set-reader-macro: takes two arguments".to_string(),
+        ));
+    }
+    let key = match registers[0].unref(vm) {
+        Value::StringConst(i) => vm.get_interned(i),
+        Value::String(h) => {
+            let s = vm.get_string(h).to_string();
+            let i = vm.intern(&s);
+            vm.get_interned(i)
+        }
+        _ => {
+            return Err(VMError::new_vm(
+                "set-reader-macro: key must be a string".to_string(),
+            ))
+        }
+    };
+    if !valid_macro_key(key) {
+        return Err(VMError::new_vm(format!(
+            "set-reader-macro: invalid key {key}, must be one character or # and one character (not already syntax)"
+        )));
+    }
+    let func = registers[1].unref(vm);
+    if !matches!(func, Value::Lambda(_) | Value::Closure(_) | Value::Nil) {
+        return Err(VMError::new_vm(format!(
+            "set-reader-macro: not a function: {}",
+            func.display_value(vm)
+        )));
+    }
+    // Always build a new table so a saved table (see read_table()) is never changed.
+    let mut table = if let Value::Map(h) = read_table(vm) {
+        vm.get_map(h).clone()
+    } else {
+        HashMap::new()
+    };
+    let key = Value::StringConst(vm.intern_static(key));
+    if func.is_nil() {
+        table.remove(&key);
+    } else {
+        table.insert(key, func);
+    }
+    let table = vm.alloc_map_ro(table);
+    set_read_table(vm, table);
+    Ok(Value::Nil)
+}
+
+fn reader_next_char(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if !registers.is_empty() {
+        return Err(VMError::new_vm(
+            "reader::next-char: takes no arguments".to_string(),
+        ));
+    }
+    with_macro_input(vm, "reader::next-char", |vm, input| {
+        if let Some(ch) = input.char_iter.next() {
+            vm.alloc_char(&ch)
+        } else {
+            Value::Nil
+        }
+    })
+}
+
+fn reader_peek_char(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if !registers.is_empty() {
+        return Err(VMError::new_vm(
+            "reader::peek-char: takes no arguments".to_string(),
+        ));
+    }
+    with_macro_input(vm, "reader::peek-char", |vm, input| {
+        if let Some(ch) = input.char_iter.peek() {
+            let ch = ch.clone();
+            vm.alloc_char(&ch)
+        } else {
+            Value::Nil
+        }
+    })
+}
+
+fn reader_read_form(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    if !registers.is_empty() {
+        return Err(VMError::new_vm(
+            "reader::read-form: takes no arguments".to_string(),
+        ));
+    }
+    let res = with_macro_input(vm, "reader::read-form", |vm, input| {
+        let char_iter = std::mem::replace(
+            &mut input.char_iter,
+            Box::new(ReaderCharIter {
+                inner: Box::new(std::iter::empty::<Cow<'static, str>>().peekable()),
+                line: 0,
+                column: 0,
+            }),
+        );
+        let mut reader = Reader {
+            vm,
+            char_iter: Some(char_iter),
+            file_name: input.file_name,
+            read_table: Rc::new(HashMap::new()),
+            read_table_src: HashMap::new(),
+        };
+        let res = reader.read_form();
+        input.char_iter = reader.char_iter.take().expect("Invalid Reader!");
         res
+    })?;
+    match res {
+        Ok(Some(form)) => Ok(form),
+        Ok(None) => Err(VMError::new_vm(
+            "reader::read-form: end of input".to_string(),
+        )),
+        Err(err) => Err(VMError::new("read", err.reason)),
     }
 }
 
+pub fn add_reader_builtins(env: &mut SloshVm) {
+    let table = env.alloc_map_ro(HashMap::new());
+    set_read_table(env, table);
+    add_builtin(
+        env,
+        "set-reader-macro",
+        set_reader_macro,
+        "Usage: (set-reader-macro key function)
+
+Register function as the reader macro for key.  Key is a string with either a single character that
+is not already syntax (the character will also end a symbol) or # followed by a character that is
+not a builtin dispatch (#t, #f, #x, etc).  When the reader sees key it calls function with key, the
+function consumes input with reader::next-char, reader::peek-char and reader::read-form and returns
+the form that was read.  A nil function removes the reader macro.  The read table is in the global
+*read-table*, a file's reader macros are only in effect until the end of that file.
+
+Section: reader
+
+Example:
+(set-reader-macro \"#p\" (fn (_key) (str \"/paths/\" (reader::read-form))))
+(test::assert-equal \"/paths/abc\" #p\"abc\")
+(set-reader-macro \"#p\" nil)
+(test::assert-error (set-reader-macro \"(\" (fn (_key) nil)))
+",
+    );
+    add_builtin(
+        env,
+        "reader::next-char",
+        reader_next_char,
+        "Usage: (reader::next-char)
+
+Consume and return the next character of the input being read, nil at the end of the input.
+Only valid inside a reader macro (see set-reader-macro).
+
+Section: reader
+",
+    );
+    add_builtin(
+        env,
+        "reader::peek-char",
+        reader_peek_char,
+        "Usage: (reader::peek-char)
+
+Return the next character of the input being read without consuming it, nil at the end of the input.
+Only valid inside a reader macro (see set-reader-macro).
+
+Section: reader
+",
+    );
+    add_builtin(
+        env,
+        "reader::read-form",
+        reader_read_form,
+        "Usage: (reader::read-form)
+
+Read and return the next form of the input being read (reader macros apply).
+Only valid inside a reader macro (see set-reader-macro).
+
+Section: reader
+",
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1713,6 +2351,93 @@ two""#
         tokenize_err(&mut vm, input);
         let input = "#o80";
         tokenize_err(&mut vm, input);
+
+        let input = "1_000_000 10u8 10i32 10u32 10i64 10u64 -5 -5i64 0xff 0XFF_FF 0o17 0b1010 -0x10 #xFFu8 #b1_0000_0000u32 #x-1 18446744073709551615u64";
+        let tokens = tokenize(&mut vm, input);
+        assert_eq!(
+            tokens,
+            vec![
+                "[",
+                "UInt:1000000",
+                "Byte:10",
+                "Int:10",
+                "UInt:10",
+                "Int:10",
+                "UInt:10",
+                "Int:-5",
+                "Int:-5",
+                "UInt:255",
+                "UInt:65535",
+                "UInt:15",
+                "UInt:10",
+                "Int:-16",
+                "Byte:255",
+                "UInt:256",
+                "Int:-1",
+                "UInt:18446744073709551615",
+                "]",
+            ]
+        );
+        // Things that look a bit like numbers but are symbols.
+        let tokens = tokenize(&mut vm, "1+ 2x 10u7 _1 +");
+        assert_eq!(
+            tokens,
+            vec![
+                "[",
+                "Symbol:1+",
+                "Symbol:2x",
+                "Symbol:10u7",
+                "Symbol:_1",
+                "Symbol:+",
+                "]"
+            ]
+        );
+        // Without a suffix an int that does not fit an Int64 reads as a float.
+        let tokens = tokenize(
+            &mut vm,
+            "9223372036854775807 9223372036854775808 -9223372036854775809 18446744073709551615 1000000000000000000000000000000000000000000",
+        );
+        assert_eq!(
+            tokens,
+            vec![
+                "[",
+                "Int:9223372036854775807",
+                "Float:9223372036854776000",
+                "Float:-9223372036854776000",
+                "Float:18446744073709552000",
+                "Float:1000000000000000000000000000000000000000000",
+                "]"
+            ]
+        );
+        // Overflow is an error for every other form of int.
+        for input in [
+            "256u8",
+            "-1u8",
+            "2147483648i32",
+            "4294967296u32",
+            "-1u32",
+            "9223372036854775808i64",
+            "18446744073709551616u64",
+            "-1u64",
+            "#x1FFu8",
+            "#x1_0000_0000_0000_0000",
+            "#b1_0000_0000u8",
+            "#o400u8",
+            "0x100u8",
+            "0b1_0000_0000u8",
+            "999999999999999999999999999999999999999999u64",
+        ] {
+            let err = tokenize_err(&mut vm, input);
+            assert!(
+                err.reason.contains("out of range"),
+                "{input}: {}",
+                err.reason
+            );
+        }
+        for input in ["0xZZ", "0b102", "0o8", "#x", "1.5u8"] {
+            let err = tokenize_err(&mut vm, input);
+            assert!(err.reason.starts_with("Invalid"), "{input}: {}", err.reason);
+        }
     }
 
     #[test]
@@ -1731,6 +2456,38 @@ two""#
         assert!(tokens[7] == "Symbol:23e-5e+4");
         assert!(tokens[8] == "Float:23.123");
         assert!(tokens[9] == "]");
+
+        let input =
+            "1.5f64 10f64 1_000.5 1.5E3 -2.5e-1 .5 +inf.0 -inf.0 inf.0 0x1.8p3 #x1p-2 -0x.8 #xAp0";
+        let tokens = tokenize(&mut vm, input);
+        assert_eq!(
+            tokens,
+            vec![
+                "[",
+                "Float:1.5",
+                "Float:10",
+                "Float:1000.5",
+                "Float:1500",
+                "Float:-0.25",
+                "Float:0.5",
+                "Float:inf",
+                "Float:-inf",
+                "Float:inf",
+                "Float:12",
+                "Float:0.25",
+                "Float:-0.5",
+                "Float:10",
+                "]",
+            ]
+        );
+        let tokens = tokenize(&mut vm, "nan.0 -nan.0");
+        assert_eq!(tokens, vec!["[", "Float:NaN", "Float:NaN", "]"]);
+        let tokens = tokenize(&mut vm, "inf nan");
+        assert_eq!(tokens, vec!["[", "Symbol:inf", "Symbol:nan", "]"]);
+        for input in ["0x1.8pz", "0x1.g"] {
+            let err = tokenize_err(&mut vm, input);
+            assert!(err.reason.starts_with("Invalid"), "{input}: {}", err.reason);
+        }
     }
 
     #[test]
@@ -1769,4 +2526,184 @@ two""#
         assert!(tokens[13] == ")");
         assert!(tokens[14] == "]");
     }
+
+    #[test]
+    fn test_reader_macros() {
+        use crate::test_utils::{exec, exec_runtime_error};
+
+        let mut vm = new_slosh_vm();
+        add_reader_builtins(&mut vm);
+        vm.set_global_builtin("make-hash", builtins::collections::make_hash);
+        exec(
+            &mut vm,
+            "(set-reader-macro \"#r\" (fn (key) `(regex ~(reader::read-form))))",
+        );
+        let tokens = tokenize(&mut vm, "#r\"a+b\"");
+        assert_eq!(tokens, vec!["(", "Symbol:regex", "String:\"a+b\"", ")"]);
+
+        // A character macro also ends a symbol and can consume input a char at a time.
+        exec(
+            &mut vm,
+            "(set-reader-macro \"@\" (fn (key) (list 'at (reader::next-char) (reader::read-form))))",
+        );
+        let tokens = tokenize(&mut vm, "(x@y z)");
+        assert_eq!(
+            tokens,
+            vec![
+                "(",
+                "Symbol:x",
+                "(",
+                "Symbol:at",
+                "Char:\\y",
+                "Symbol:z",
+                ")",
+                ")"
+            ]
+        );
+        // Macros apply inside reader::read-form as well.
+        let tokens = tokenize(&mut vm, "#r@a b");
+        assert_eq!(
+            tokens,
+            vec![
+                "(",
+                "Symbol:regex",
+                "(",
+                "Symbol:at",
+                "Char:\\a",
+                "Symbol:b",
+                ")",
+                ")"
+            ]
+        );
+
+        // *read-table* is a normal map, replacing it or changing it in place is picked up.
+        exec(&mut vm, "(def *read-table* (make-hash))");
+        tokenize_err(&mut vm, "#q a");
+        exec(
+            &mut vm,
+            "(set! (get *read-table* \"#q\") (fn (key) (list 'q (reader::read-form))))",
+        );
+        let tokens = tokenize(&mut vm, "#q a");
+        assert_eq!(tokens, vec!["(", "Symbol:q", "Symbol:a", ")"]);
+
+        // The macro can return what a builtin returns (a tail call) directly.
+        exec(
+            &mut vm,
+            "(set-reader-macro \"#i\" (fn (key) (reader::read-form)))",
+        );
+        let tokens = tokenize(&mut vm, "#i(a b)");
+        assert_eq!(tokens, vec!["(", "Symbol:a", "Symbol:b", ")"]);
+
+        exec(&mut vm, "(set-reader-macro \"#r\" nil)");
+        tokenize_err(&mut vm, "#r\"a+b\"");
+        exec_runtime_error(&mut vm, "(set-reader-macro \"(\" (fn (key) nil))");
+        exec_runtime_error(&mut vm, "(set-reader-macro \"#t\" (fn (key) nil))");
+        exec_runtime_error(&mut vm, "(set-reader-macro \"ab\" (fn (key) nil))");
+        exec_runtime_error(&mut vm, "(reader::read-form)");
+        exec(
+            &mut vm,
+            "(set-reader-macro \"$\" (fn (key) (err :bad \"no good\")))",
+        );
+        let err = tokenize_err(&mut vm, "$x");
+        assert!(err.reason.contains("no good"));
+    }
+
+    #[test]
+    fn test_read_error_loc() {
+        let mut vm = new_slosh_vm();
+        let err = tokenize_err(&mut vm, "(def x 1))");
+        assert_eq!(err.reason, "Unexpected ')'");
+        assert_eq!(err.loc, SourceLoc::new("", 1, 10, 1));
+        assert_eq!(err.to_string(), "<input>:1:10: Unexpected ')'");
+        // Unclosed forms point at where they start.
+        let err = tokenize_err(&mut vm, "(def x 1)\n  [1 2\n3");
+        assert_eq!(err.reason, "Unclosed vector");
+        assert_eq!(err.loc, SourceLoc::new("", 2, 3, 1));
+        let err = tokenize_err(&mut vm, "(def x 1)\n  (def y 2");
+        assert_eq!(err.reason, "Unclosed list");
+        assert_eq!(err.loc, SourceLoc::new("", 2, 3, 1));
+        let err = tokenize_err(&mut vm, "\n #<x>");
+        assert_eq!(err.loc, SourceLoc::new("", 2, 3, 1));
+        assert_eq!(
+            err.render(Some("\n #<x>")),
+            "Read error: Found an unreadable token\n --> <input>:2:3\n  |\n2 |  #<x>\n  |   ^\n"
+        );
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let mut vm = new_slosh_vm();
+        for input in [
+            "(def x",
+            "(def x [1 2",
+            "{:a 1",
+            "(prn \"abc",
+            "#\"abc",
+            "#|comment",
+            "#!doc",
+            "'",
+            "(def x 1) (",
+        ] {
+            let err = tokenize_err(&mut vm, input);
+            assert!(err.incomplete, "{input} should be incomplete: {err}");
+        }
+        for input in ["(def x))", "(1 . 2 3)", "#<x>", "{:a}"] {
+            let err = tokenize_err(&mut vm, input);
+            assert!(!err.incomplete, "{input} should not be incomplete: {err}");
+        }
+        let err = tokenize_err(&mut vm, "(prn\n  \"abc)\n");
+        assert_eq!(err.reason, "Unclosed string");
+        assert_eq!(err.loc, SourceLoc::new("", 2, 3, 1));
+        let tokens = tokenize(&mut vm, "#| a |# 1");
+        assert_eq!(tokens, vec!["UInt:1"]);
+    }
+
+    #[test]
+    fn test_interp_string() {
+        let mut vm = new_slosh_vm();
+        let tokens = tokenize(&mut vm, "$\"Hello ${name}, you have ${ (len xs) } items\"");
+        assert_eq!(
+            tokens,
+            vec![
+                "(",
+                "Symbol:str",
+                "String:\"Hello \"",
+                "Symbol:name",
+                "String:\", you have \"",
+                "(",
+                "Symbol:len",
+                "Symbol:xs",
+                ")",
+                "String:\" items\"",
+                ")"
+            ]
+        );
+        // Nested strings, maps and escapes.
+        let tokens = tokenize(&mut vm, "$\"${(get {:a \"}\"} :a)}\\t\\${x} {y} $z\"");
+        assert_eq!(
+            tokens,
+            vec![
+                "(",
+                "Symbol:str",
+                "(",
+                "Symbol:get",
+                "Map:{:a \"}\"\n}",
+                "Keyword::a",
+                ")",
+                "String:\"\t${x} {y} $z\"",
+                ")"
+            ]
+        );
+        // No interpolation is just a string.
+        let tokens = tokenize(&mut vm, "$\"plain\"");
+        assert_eq!(tokens, vec!["String:\"plain\""]);
+        let err = tokenize_err(&mut vm, "$\"a ${}\"");
+        assert!(!err.incomplete);
+        let err = tokenize_err(&mut vm, "$\"a ${x y}\"");
+        assert_eq!(err.reason, "Expected '}' after interpolated form");
+        for input in ["$\"abc", "$\"a ${x", "$\"a ${(x"] {
+            let err = tokenize_err(&mut vm, input);
+            assert!(err.incomplete, "{input} should be incomplete: {err}");
+        }
+    }
 }
@@ -0,0 +1,295 @@
+//! Compile time warnings.  These are found while compiling (unused or shadowing bindings, calls
+//! with the wrong number or types of arguments and globals that are never defined) and do not
+//! stop the compile.  They are collected in the compile environment until report_warnings() is
+//! called.
+
+use compile_state::state::{CompileState, CompileWarning, SloshVm, SloshVmTrait};
+use slvm::{Interned, VMError, VMResult, Value};
+
+use crate::compile::compile_types::{infer_type, type_matches};
+use crate::diagnostics::{render_error, SourceLoc};
+
+/// Warning with the location of the form state is compiling.
+fn warning(env: &SloshVm, state: &CompileState, message: String) -> CompileWarning {
+    let (line, column) = match state.chunk.dbg_pos() {
+        Some(pos) => (pos.line, pos.column),
+        None => (env.line_num(), env.column_num()),
+    };
+    CompileWarning {
+        file_name: state.chunk.file_name,
+        line,
+        column,
+        message,
+    }
+}
+
+/// Names that start with _ (and gensyms) are never warned about.
+fn ignored(env: &SloshVm, name: Interned) -> bool {
+    let name = env.get_interned(name);
+    name.starts_with('_') || name.starts_with("#<")
+}
+
+/// Warn if a new binding of name (in a let or fn) hides a local from an enclosing scope.
+pub(crate) fn check_shadow(env: &mut SloshVm, state: &CompileState, name: Interned) {
+    let shadows = {
+        let symbols = state.symbols.borrow();
+        symbols.contains_symbol(name) || symbols.can_capture(name)
+    };
+    if shadows && !ignored(env, name) {
+        let message = format!("{} shadows an outer binding.", env.get_interned(name));
+        let warning = warning(env, state, message);
+        env.env_mut().add_warning(warning);
+    }
+}
+
+/// Warn about each (name, register) in bindings that was never read.  Registers from
+/// first_reg up are then forgotten so they can be reused.
+pub(crate) fn check_unused(
+    env: &mut SloshVm,
+    state: &CompileState,
+    bindings: &[(Interned, usize)],
+    first_reg: usize,
+) {
+    for (name, reg) in bindings {
+        if !state.symbols.borrow().is_used(*reg) && !ignored(env, *name) {
+            let message = format!("{} is bound but never used.", env.get_interned(*name));
+            let warning = warning(env, state, message);
+            env.env_mut().add_warning(warning);
+        }
+    }
+    state.symbols.borrow().clear_used(first_reg);
+}
+
+/// Warn if a call to global name (with value global) has the wrong number of arguments.
+pub(crate) fn check_arity(
+    env: &mut SloshVm,
+    state: &CompileState,
+    name: Interned,
+    global: Value,
+    num_args: usize,
+) {
+    let chunk = match global {
+        Value::Lambda(h) => env.get_lambda(h),
+        Value::Closure(h) => env.get_closure(h).0,
+        _ => return,
+    };
+    // Same rules as the VM uses when the call is made.
+    let args = chunk.args as usize;
+    let max_args = args + chunk.opt_args as usize;
+    let message = if chunk.rest && num_args < args.saturating_sub(1) {
+        format!(
+            "{}: too few arguments, expected at least {} got {num_args}.",
+            env.get_interned(name),
+            args - 1
+        )
+    } else if !chunk.rest && num_args < args {
+        format!(
+            "{}: too few arguments, expected at least {args} got {num_args}.",
+            env.get_interned(name)
+        )
+    } else if !chunk.rest && num_args > max_args {
+        format!(
+            "{}: too many arguments, expected no more than {max_args} got {num_args}.",
+            env.get_interned(name)
+        )
+    } else {
+        return;
+    };
+    let warning = warning(env, state, message);
+    env.env_mut().add_warning(warning);
+}
+
+/// Warn about arguments in a call to global name (with value global) whose type is known at
+/// compile time and does not match the type annotation of their param.
+pub(crate) fn check_arg_types(
+    env: &mut SloshVm,
+    state: &CompileState,
+    name: Interned,
+    global: Value,
+    args: &[Value],
+) {
+    let chunk = match global {
+        Value::Lambda(h) => env.get_lambda(h),
+        Value::Closure(h) => env.get_closure(h).0,
+        _ => return,
+    };
+    // The rest param gets a list of the remaining args, not one of them.
+    let typed = chunk.arg_types.len().min(args.len());
+    let typed = if chunk.rest {
+        typed.min(chunk.args as usize + chunk.opt_args as usize - 1)
+    } else {
+        typed
+    };
+    for (i, arg) in args[..typed].iter().enumerate() {
+        let Some(expected) = chunk.arg_types[i] else {
+            continue;
+        };
+        match infer_type(env, state, *arg) {
+            Some(actual) if !type_matches(env, expected, actual) => {
+                let param = chunk
+                    .dbg_args
+                    .as_ref()
+                    .and_then(|args| args.get(i))
+                    .map(|p| env.get_interned(*p))
+                    .unwrap_or("?");
+                let message = format!(
+                    "{}: argument {param} expects :{}, got :{}.",
+                    env.get_interned(name),
+                    env.get_interned(expected),
+                    env.get_interned(actual)
+                );
+                let warning = warning(env, state, message);
+                env.env_mut().add_warning(warning);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Warn if the type of the last form (body) of a fn is known at compile time and does not match
+/// its return type annotation.  Reported at the fn form being compiled in state.
+pub(crate) fn check_return_type(
+    env: &mut SloshVm,
+    state: &CompileState,
+    fn_state: &CompileState,
+    ret_type: Interned,
+    body: Value,
+) {
+    match infer_type(env, fn_state, body) {
+        Some(actual) if !type_matches(env, ret_type, actual) => {
+            let message = format!(
+                "return value expects :{}, got :{}.",
+                env.get_interned(ret_type),
+                env.get_interned(actual)
+            );
+            let warning = warning(env, state, message);
+            env.env_mut().add_warning(warning);
+        }
+        _ => {}
+    }
+}
+
+/// Note a reference to the global name in slot which has no value yet.  This becomes a warning
+/// if it is still not defined when check_undefined_globals() is called.
+pub(crate) fn note_undefined(env: &mut SloshVm, state: &CompileState, name: Interned, slot: u32) {
+    let message = format!("{} is declared but never defined.", env.get_interned(name));
+    let warning = warning(env, state, message);
+    env.env_mut().add_undefined_ref(slot, warning);
+}
+
+/// Turn the references to undefined globals noted since start (see
+/// CompileEnvironment::undefined_refs_len()) into warnings if they are still undefined and
+/// nothing compiled will define them.  Call after running the code that was compiled.
+pub fn check_undefined_globals(env: &mut SloshVm, start: usize) {
+    for (slot, warning) in env.env_mut().drain_undefined_refs(start) {
+        if !env.env().global_has_def(slot) && matches!(env.get_global(slot), Value::Undefined) {
+            env.env_mut().add_warning(warning);
+        }
+    }
+}
+
+/// Print (to stderr) and clear the pending warnings, source is the text that was read (if None
+/// the warning's file is read for the source line).  If warnings are errors (see
+/// CompileEnvironment::set_warnings_as_errors()) and there were any then returns an error.
+pub fn report_warnings(env: &mut SloshVm, source: Option<&str>) -> VMResult<()> {
+    let warnings = env.env_mut().take_warnings();
+    for warning in &warnings {
+        let loc = SourceLoc::new(
+            warning.file_name,
+            warning.line as usize,
+            warning.column as usize,
+            0,
+        );
+        eprint!(
+            "{}",
+            render_error("warning", &warning.message, &loc, source)
+        );
+    }
+    if env.env().warnings_as_errors() && !warnings.is_empty() {
+        Err(VMError::new_compile(format!(
+            "{} warning(s) treated as errors.",
+            warnings.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::exec;
+    use compile_state::state::new_slosh_vm;
+
+    fn messages(vm: &mut SloshVm) -> Vec<String> {
+        vm.env_mut()
+            .take_warnings()
+            .into_iter()
+            .map(|w| w.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mut vm = new_slosh_vm();
+        exec(&mut vm, "(def f (fn (a b _c) (let (x 1 y 2) (+ a y))))");
+        assert_eq!(
+            messages(&mut vm),
+            ["x is bound but never used.", "b is bound but never used."]
+        );
+        exec(&mut vm, "(def g (fn (a) (let (a (+ a 1)) (fn () a))))");
+        assert_eq!(messages(&mut vm), ["a shadows an outer binding."]);
+
+        exec(&mut vm, "(def h (fn (a % b) (list a b)))");
+        exec(&mut vm, "(def r (fn (a & rest) (list a rest)))");
+        assert!(messages(&mut vm).is_empty());
+        exec(&mut vm, "(def k (fn () (h) (h 1) (h 1 2 3) (r) (r 1 2 3)))");
+        assert_eq!(
+            messages(&mut vm),
+            [
+                "h: too few arguments, expected at least 1 got 0.",
+                "h: too many arguments, expected no more than 2 got 3.",
+                "r: too few arguments, expected at least 1 got 0."
+            ]
+        );
+
+        let start = vm.env().undefined_refs_len();
+        exec(&mut vm, "(def u) (def v) (def w (fn () (u) v (def v 1)))");
+        check_undefined_globals(&mut vm, start);
+        assert_eq!(messages(&mut vm), ["u is declared but never defined."]);
+
+        assert!(report_warnings(&mut vm, None).is_ok());
+        vm.env_mut().set_warnings_as_errors(true);
+        exec(&mut vm, "(def z (fn (a) 1))");
+        assert!(report_warnings(&mut vm, None).is_err());
+        assert!(report_warnings(&mut vm, None).is_ok());
+    }
+
+    #[test]
+    fn test_type_warnings() {
+        let mut vm = new_slosh_vm();
+        exec(
+            &mut vm,
+            "(def f (fn ((x :Int) (s :String)) :String (str s x)))",
+        );
+        exec(
+            &mut vm,
+            "(def g (fn (a) (f a a) (f -1 \"a\") (f 1 (f 2 \"b\"))))",
+        );
+        assert!(messages(&mut vm).is_empty());
+        exec(&mut vm, "(def h (fn () (f \"a\" 1) (f (f 1 \"b\") \"c\")))");
+        assert_eq!(
+            messages(&mut vm),
+            [
+                "f: argument x expects :Int, got :String.",
+                "f: argument s expects :String, got :UInt.",
+                "f: argument x expects :Int, got :String."
+            ]
+        );
+        exec(&mut vm, "(def r (fn () :Int \"a\"))");
+        assert_eq!(
+            messages(&mut vm),
+            ["return value expects :Int, got :String."]
+        );
+    }
+}
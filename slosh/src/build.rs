@@ -0,0 +1,362 @@
+//! Standalone executables, `slosh build` appends a compiled program (an image) to a copy of this
+//! binary and the copy runs it on startup instead of acting as slosh.
+//!
+//! The executable ends with the image, its length (u64, little endian) and MAGIC.  The image is a
+//! version, a manifest of the globals the program was compiled against (name and builtin index by
+//! slot) and then the chunks of the program's top level forms (see slvm::chunk::image).
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+use builtins::macroexpand::macroexpand_all;
+use compile_state::state::{SloshVm, SloshVmTrait};
+use sl_compiler::warnings::{check_undefined_globals, report_warnings};
+use sl_compiler::{read_table, set_read_table, Reader};
+use slvm::chunk::image::{read_chunks, write_chunks};
+use slvm::{Chunk, VMError, VMResult, Value};
+
+use slosh_lib::load_eval::{expand_load_path, load_one_expression};
+
+const MAGIC: &[u8; 8] = b"SLOSHIMG";
+const IMAGE_VERSION: u32 = 1;
+
+/// Compile program, and any file it loads at the top level with a literal name, into an
+/// executable at output.  Forms that only define functions, macros or literals are also run so
+/// later forms can use the macros, nothing else is run until the executable is.
+pub(crate) fn build(vm: &mut SloshVm, program: &str, output: &str) -> VMResult<()> {
+    let mut builder = Builder {
+        chunks: Vec::new(),
+        files: Vec::new(),
+    };
+    let undefined_start = vm.env().undefined_refs_len();
+    let program = vm.intern(program);
+    let program = vm.get_interned(program);
+    builder.compile_file(vm, program)?;
+    check_undefined_globals(vm, undefined_start);
+    report_warnings(vm, None)?;
+
+    let mut image = Vec::new();
+    image.extend_from_slice(&IMAGE_VERSION.to_le_bytes());
+    write_manifest(vm, &mut image);
+    write_chunks(vm, &builder.chunks, &mut image)?;
+
+    let exe = std::env::current_exe()?;
+    let mut bytes = std::fs::read(&exe)?;
+    // Building from a built executable would otherwise carry its program along.
+    if let Some(len) = image_len(&bytes) {
+        bytes.truncate(bytes.len() - len - 16);
+    }
+    bytes.extend_from_slice(&image);
+    bytes.extend_from_slice(&(image.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(MAGIC);
+    std::fs::write(output, bytes).map_err(|e| VMError::new("io", format!("{output}: {e}")))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(output, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// The length of the image at the end of bytes (before the trailer) if it has one.
+fn image_len(bytes: &[u8]) -> Option<usize> {
+    let trailer = bytes.len().checked_sub(16)?;
+    if &bytes[trailer + 8..] != MAGIC {
+        return None;
+    }
+    let mut len = [0_u8; 8];
+    len.copy_from_slice(&bytes[trailer..trailer + 8]);
+    let len = u64::from_le_bytes(len) as usize;
+    if len <= trailer {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// The image appended to the running executable if it was made by build().
+pub(crate) fn embedded_image() -> Option<Vec<u8>> {
+    let mut file = File::open(std::env::current_exe().ok()?).ok()?;
+    let end = file.seek(SeekFrom::End(-16)).ok()?;
+    let mut trailer = [0_u8; 16];
+    file.read_exact(&mut trailer).ok()?;
+    if &trailer[8..] != MAGIC {
+        return None;
+    }
+    let mut len = [0_u8; 8];
+    len.copy_from_slice(&trailer[..8]);
+    let len = u64::from_le_bytes(len);
+    if len > end {
+        return None;
+    }
+    file.seek(SeekFrom::Start(end - len)).ok()?;
+    let mut image = vec![0_u8; len as usize];
+    file.read_exact(&mut image).ok()?;
+    Some(image)
+}
+
+/// Load an image made by build() into vm and run its forms in order.
+pub(crate) fn run_image(vm: &mut SloshVm, image: &[u8]) -> VMResult<Value> {
+    let mut reader = ManifestReader { image, pos: 0 };
+    let version = reader.u32()?;
+    if version != IMAGE_VERSION {
+        return Err(VMError::new_image(format!(
+            "unsupported image version {version}"
+        )));
+    }
+    check_manifest(vm, &mut reader)?;
+    vm.pause_gc();
+    let chunks = read_chunks(vm, &image[reader.pos..]);
+    if let Ok((chunks, _)) = &chunks {
+        for chunk in chunks {
+            // The program's constants are only reachable from its chunks.
+            let lambda = vm.alloc_lambda(chunk.clone());
+            vm.heap_sticky(lambda);
+        }
+    }
+    vm.unpause_gc();
+    let (chunks, _) = chunks?;
+    let mut last = Value::Nil;
+    for chunk in chunks {
+        last = vm.execute(chunk)?;
+    }
+    Ok(last)
+}
+
+struct Builder {
+    chunks: Vec<Arc<Chunk>>,
+    // Files being compiled, to catch a file that (indirectly) loads itself.
+    files: Vec<&'static str>,
+}
+
+impl Builder {
+    fn compile_file(&mut self, vm: &mut SloshVm, name: &'static str) -> VMResult<()> {
+        if self.files.contains(&name) {
+            return Err(VMError::new_compile(format!("build: {name} loads itself")));
+        }
+        let file = File::open(name).map_err(|e| VMError::new("io", format!("{name}: {e}")))?;
+        self.files.push(name);
+        let old_line_num = vm.line_num();
+        vm.set_line_num(1);
+        // Reader macros set by the file end with it, same as load.
+        let old_read_table = read_table(vm);
+        vm.heap_sticky(old_read_table);
+        let mut reader = Reader::from_file(file, vm, name, 1, 0);
+        let result = self.compile_forms(&mut reader, name);
+        let vm = reader.vm();
+        vm.heap_unsticky(old_read_table);
+        set_read_table(vm, old_read_table);
+        vm.set_line_num(old_line_num);
+        self.files.pop();
+        result
+    }
+
+    fn compile_forms(&mut self, reader: &mut Reader, name: &'static str) -> VMResult<()> {
+        let mut doc_string = None;
+        while let Some(exp) = reader.next() {
+            let vm = reader.vm();
+            let exp = exp.map_err(|e| {
+                print!("{}", e.render(None));
+                VMError::new("read", e.to_string())
+            })?;
+            if let Some(file) = literal_load(vm, exp) {
+                let file = expand_load_path(vm, file);
+                self.compile_file(vm, file)?;
+                continue;
+            }
+            vm.heap_sticky(exp);
+            let result = load_one_expression(vm, exp, name, doc_string, None);
+            if let Ok((chunk, _)) = &result {
+                // Keep the constants of forms that are not run from being collected.
+                vm.pause_gc();
+                let lambda = vm.alloc_lambda(chunk.clone());
+                vm.heap_sticky(lambda);
+                vm.unpause_gc();
+            }
+            let run = result.is_ok() && is_definition(vm, exp);
+            let bad_reader_macro = !run && result.is_ok() && is_reader_macro_call(vm, exp);
+            vm.heap_unsticky(exp);
+            if bad_reader_macro {
+                return Err(VMError::new_compile(format!(
+                    "build: {name}:{}: set-reader-macro needs a literal key and a fn (or global) to run while building",
+                    vm.line_num()
+                )));
+            }
+            if let Some(doc_string) = doc_string {
+                vm.heap_unsticky(doc_string);
+            }
+            let (chunk, new_doc_string) = result?;
+            doc_string = new_doc_string;
+            if let Some(doc_string) = doc_string {
+                vm.heap_sticky(doc_string);
+            }
+            if run {
+                vm.execute(chunk.clone())?;
+            }
+            self.chunks.push(chunk);
+        }
+        Ok(())
+    }
+}
+
+/// The file name if exp is (load "file") with a literal string.
+fn literal_load(vm: &mut SloshVm, exp: Value) -> Option<&'static str> {
+    if !matches!(exp, Value::Pair(_) | Value::List(_, _)) {
+        return None;
+    }
+    let load = vm.intern("load");
+    let items: Vec<Value> = exp.iter(vm).collect();
+    let file = match items[..] {
+        [Value::Symbol(s), Value::StringConst(i)] if s == load => return Some(vm.get_interned(i)),
+        [Value::Symbol(s), Value::String(h)] if s == load => vm.get_string(h).to_string(),
+        _ => return None,
+    };
+    let file = vm.intern(&file);
+    Some(vm.get_interned(file))
+}
+
+/// True if exp is a call to set-reader-macro.
+fn is_reader_macro_call(vm: &mut SloshVm, exp: Value) -> bool {
+    let set_reader_macro = vm.intern("set-reader-macro");
+    matches!(exp, Value::Pair(_) | Value::List(_, _))
+        && exp.iter(vm).next() == Some(Value::Symbol(set_reader_macro))
+}
+
+/// True if exp (once macro expanded) only defines globals as functions, macros, structs or
+/// literals or registers a reader macro (so the rest of the file reads with it), so running it
+/// while building has no side effects outside the build.
+fn is_definition(vm: &mut SloshVm, exp: Value) -> bool {
+    match macroexpand_all(vm, exp) {
+        Ok(exp) => {
+            vm.heap_sticky(exp);
+            let res = is_definition_expanded(vm, exp);
+            vm.heap_unsticky(exp);
+            res
+        }
+        Err(_) => false,
+    }
+}
+
+fn is_definition_expanded(vm: &mut SloshVm, exp: Value) -> bool {
+    if !matches!(exp, Value::Pair(_) | Value::List(_, _)) {
+        return false;
+    }
+    let set_reader_macro = vm.intern("set-reader-macro");
+    let specials = vm.specials();
+    let items: Vec<Value> = exp.iter(vm).collect();
+    match items.first() {
+        // (set-reader-macro "key" function)
+        Some(Value::Symbol(s)) if *s == set_reader_macro => {
+            items.len() == 3
+                && matches!(items[1], Value::StringConst(_) | Value::String(_))
+                && is_pure_value(vm, items[2])
+        }
+        Some(Value::Symbol(s)) if *s == specials.definline || *s == specials.defstruct => true,
+        Some(Value::Symbol(s)) if *s == specials.do_ => items[1..]
+            .iter()
+            .all(|item| is_definition_expanded(vm, *item)),
+        Some(Value::Symbol(s)) if *s == specials.def || *s == specials.defconst => {
+            match items.len() {
+                2 => true,
+                // (def name value) or (def name "doc string" value)
+                3 | 4 => is_pure_value(vm, items[items.len() - 1]),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// True if evaluating val can not have side effects.
+fn is_pure_value(vm: &SloshVm, val: Value) -> bool {
+    let specials = vm.specials();
+    match val {
+        Value::Pair(_) | Value::List(_, _) => matches!(
+            val.iter(vm).next(),
+            Some(Value::Symbol(s)) if s == specials.fn_
+                || s == specials.mac_
+                || s == specials.hygienic_mac
+                || s == specials.quote
+        ),
+        _ => true,
+    }
+}
+
+/// Write the global names in slot order with the builtin index of the ones that are builtins.
+fn write_manifest(vm: &SloshVm, out: &mut Vec<u8>) {
+    let mut names = vec![None; vm.globals().len()];
+    for (name, slot) in vm.globals() {
+        names[*slot] = Some(*name);
+    }
+    out.extend_from_slice(&(names.len() as u32).to_le_bytes());
+    for (slot, name) in names.iter().enumerate() {
+        let name = name.map(|i| vm.get_interned(i)).unwrap_or_default();
+        out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        match vm.get_global(slot as u32) {
+            Value::Builtin(idx) => {
+                out.push(1);
+                out.extend_from_slice(&idx.to_le_bytes());
+            }
+            _ => out.push(0),
+        }
+    }
+}
+
+/// Give the image's globals the slots its code uses, an error if this binary does not have the
+/// same builtins (it was built by a different slosh).
+fn check_manifest(vm: &mut SloshVm, reader: &mut ManifestReader) -> VMResult<()> {
+    for slot in 0..reader.u32()? {
+        let name = reader.str()?;
+        let name = vm.intern(name);
+        if vm.get_reserve_global(name) != slot {
+            return Err(VMError::new_image(format!(
+                "global {} is in a different slot, the image was built by a different slosh",
+                vm.get_interned(name)
+            )));
+        }
+        if reader.u8()? == 1 {
+            let idx = reader.u32()?;
+            if vm.get_global(slot) != Value::Builtin(idx) {
+                return Err(VMError::new_image(format!(
+                    "builtin {} is missing, the image was built by a different slosh",
+                    vm.get_interned(name)
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+struct ManifestReader<'b> {
+    image: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> ManifestReader<'b> {
+    fn take(&mut self, len: usize) -> VMResult<&'b [u8]> {
+        let image = self.image;
+        let bytes = image
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| VMError::new_image("image is truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> VMResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> VMResult<u32> {
+        let mut b = [0_u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn str(&mut self) -> VMResult<&'b str> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?)
+            .map_err(|_| VMError::new_image("image has an invalid global name"))
+    }
+}
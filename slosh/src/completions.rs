@@ -7,6 +7,7 @@ use std::path::Path;
 use crate::ENV;
 use shell::builtins::compress_tilde;
 use shell::builtins::expand_tilde;
+use sl_compiler::globals::{find_globals, GlobalKind};
 use slvm::{VMResult, Value};
 
 /// Unescape filenames for the completer so that special characters will be properly shown.
@@ -393,36 +394,16 @@ fn find_lisp_things(
     symbols: bool,
     need_quote: bool,
 ) {
-    fn save_val(comps: &mut Vec<String>, data: Value, val: String, symbols: bool) {
-        match data {
-            Value::Lambda(_)
-            | Value::Closure(_)
-            | Value::Continuation(_)
-            | Value::Builtin(_)
-            | Value::Special(_) => {
-                if !symbols {
-                    comps.push(val);
-                }
-            }
-            _ => {
-                if symbols {
-                    comps.push(val);
-                }
-            }
-        }
-    }
-
-    // XXX TODO support namespaces when we have them.
-    for (i, idx) in environment.globals() {
-        let key = environment.get_interned(*i);
-        if start.is_empty() || key.starts_with(start) {
-            let val = if need_quote {
-                format!("'{}", key)
-            } else {
-                (*key).to_string()
-            };
-            let global_val = environment.get_global((*idx).try_into().expect("to many globals"));
-            save_val(comps, global_val, val, symbols);
+    let kind = if symbols {
+        GlobalKind::Value
+    } else {
+        GlobalKind::Callable
+    };
+    for key in find_globals(environment, start, kind) {
+        if need_quote {
+            comps.push(format!("'{}", key));
+        } else {
+            comps.push(key.to_string());
         }
     }
 }
@@ -1,28 +1,51 @@
 use std::env;
 use std::ffi::OsString;
 
+use slosh_lib::VERSION_STRING;
+
 pub struct Config {
     pub command: Option<String>,
     pub script: Option<String>,
     pub args: Vec<String>,
+    pub test: bool,
+    pub doc_test: bool,
+    pub gen_docs: Option<String>,
+    pub expand: bool,
+    pub check_types: bool,
+    pub build: Option<String>,
+    pub output: Option<String>,
 }
 
-pub const VERSION_STRING: &str = env!("VERSION_STRING");
-
 const HELP: &str = r#"slosh - Experimental Lisp REPL
 
 USAGE:
     slosh [FLAGS] [OPTIONS] [args]
+    slosh build <file> [-o <output>]
 
 FLAGS:
     -v, --version  Print the version, platform and revision of sl-sh then exit.
     -h, --help     Print help (this) and exit.
+    --test         Run the tests (deftest) in the files given as args then exit.
+    --doc-test     Run the doc string examples of all globals (including any
+                   defined in the files given as args) then exit.
+    --expand       Print each form entered at the REPL with all macros expanded
+                   before running it (sets *show-expansion*).
+    --check-types  Compile functions with checks of their param and return
+                   type annotations (see check-types).
 
 OPTIONS:
     -c             Command to run instead of entering the REPL.
+    --gen-docs     Print an API reference (markdown or html) of all documented
+                   globals (including any defined in the files given as args)
+                   then exit.
 
 ARGS:
-    <args>...      Script to run with arguments."#;
+    <args>...      Script to run with arguments.
+
+BUILD:
+    Compile file (and the files it loads) into a standalone executable at
+    output (the file name without its extension if not given).  The
+    executable runs the program with *args* set to its arguments."#;
 
 fn help(_name: &str) {
     println!("{}", HELP);
@@ -46,6 +69,14 @@ pub fn get_config() -> Option<Config> {
     let mut command: Option<String> = None;
     let mut script: Option<String> = None;
     let mut command_args: Vec<String> = Vec::new();
+    let mut test = false;
+    let mut doc_test = false;
+    let mut gen_docs: Option<String> = None;
+    let mut expand = false;
+    let mut check_types = false;
+    let mut build = false;
+    let mut build_program: Option<String> = None;
+    let mut output: Option<String> = None;
 
     let mut args: Vec<OsString> = env::args_os().collect();
 
@@ -71,8 +102,68 @@ pub fn get_config() -> Option<Config> {
                         help(&exe_name);
                         return None;
                     }
+                    "--test" => {
+                        if command.is_some() || script.is_some() {
+                            help(&exe_name);
+                            return None;
+                        }
+                        test = true;
+                    }
+                    "--doc-test" => {
+                        if command.is_some() || script.is_some() {
+                            help(&exe_name);
+                            return None;
+                        }
+                        doc_test = true;
+                    }
+                    "--expand" => {
+                        expand = true;
+                    }
+                    "--check-types" => {
+                        check_types = true;
+                    }
+                    "build"
+                        if !build
+                            && command.is_none()
+                            && script.is_none()
+                            && !test
+                            && !doc_test
+                            && gen_docs.is_none() =>
+                    {
+                        build = true;
+                    }
+                    "-o" if build => {
+                        if output.is_some() {
+                            help(&exe_name);
+                            return None;
+                        }
+                        output = Some(get_arg(&exe_name, &mut args)?);
+                    }
+                    "--gen-docs" => {
+                        if command.is_some() || script.is_some() || gen_docs.is_some() {
+                            help(&exe_name);
+                            return None;
+                        }
+                        let format = get_arg(&exe_name, &mut args)?;
+                        if format != "markdown" && format != "html" {
+                            help(&exe_name);
+                            return None;
+                        }
+                        gen_docs = Some(format);
+                    }
                     _ => {
-                        if command.is_none() && script.is_none() {
+                        if build {
+                            if build_program.is_some() {
+                                help(&exe_name);
+                                return None;
+                            }
+                            build_program = Some(arg);
+                        } else if command.is_none()
+                            && script.is_none()
+                            && !test
+                            && !doc_test
+                            && gen_docs.is_none()
+                        {
                             script = Some(arg);
                         } else {
                             command_args.push(arg);
@@ -85,9 +176,20 @@ pub fn get_config() -> Option<Config> {
             }
         }
     }
+    if build && build_program.is_none() {
+        help(&exe_name);
+        return None;
+    }
     Some(Config {
         command,
         script,
         args: command_args,
+        test,
+        doc_test,
+        gen_docs,
+        expand,
+        check_types,
+        build: build_program,
+        output,
     })
 }
@@ -210,21 +210,17 @@ pub fn debug(env: &mut SloshVm) {
             }
             Some(Ok(Value::Keyword(k))) if k == stack => {
                 if let Some(frame) = env.err_frame() {
-                    let line = frame.current_line().unwrap_or(0);
                     println!(
-                        "ERROR Frame: {} line: {} ip: {:#010x}",
-                        frame.chunk.file_name,
-                        line,
+                        "ERROR Frame: {} ip: {:#010x}",
+                        frame.current_location(),
                         frame.current_offset()
                     );
                 }
                 for frame in env.get_call_stack() {
-                    let line = frame.current_line().unwrap_or(0);
                     println!(
-                        "ID: {} {} line: {} ip: {:#010x}",
+                        "ID: {} {} ip: {:#010x}",
                         frame.id,
-                        frame.chunk.file_name,
-                        line,
+                        frame.current_location(),
                         frame.current_offset()
                     );
                 }
@@ -0,0 +1,89 @@
+//! The slosh environment (builtins, load/eval, shell builtins and the test library) as a library
+//! so other programs, like the debug adapter, can run code in the same environment as slosh.
+
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsString;
+
+use builtins::add_misc_builtins;
+use builtins::collections::setup_collection_builtins;
+use builtins::conversions::add_conv_builtins;
+use builtins::docs::add_doc_builtins;
+use builtins::freeze_all_globals;
+use builtins::io::add_io_builtins;
+use builtins::macroexpand::add_macroexpand_builtins;
+use builtins::print::add_print_builtins;
+use builtins::string::add_str_builtins;
+use compile_state::state::{SloshVm, SloshVmTrait};
+use shell::platform::{Platform, Sys};
+use sl_compiler::reader::add_reader_builtins;
+use slvm::Value;
+
+pub mod debug;
+pub mod load_eval;
+mod shell_builtins;
+pub mod test_runner;
+
+use crate::debug::builtin_dump_regs;
+use crate::load_eval::{add_load_builtins, load_str};
+use crate::shell_builtins::add_shell_builtins;
+use crate::test_runner::add_test_lib;
+
+pub const VERSION_STRING: &str = env!("VERSION_STRING");
+
+/// Core definitions (defn, loop, etc) written in slosh.
+const CORE_LIB: &str = include_str!("../../lisp/core.slosh");
+
+thread_local! {
+    /// Env (job control status, etc) for the shell.
+    pub static SHELL_ENV: RefCell<shell::jobs::Jobs> = RefCell::new(shell::jobs::Jobs::new(true));
+}
+
+/// Add the builtins and standard globals to a new VM.
+pub fn set_builtins(env: &mut SloshVm) {
+    add_shell_builtins(env);
+    setup_collection_builtins(env);
+    add_print_builtins(env);
+    add_load_builtins(env);
+    add_str_builtins(env);
+    add_misc_builtins(env);
+    add_io_builtins(env);
+    add_macroexpand_builtins(env);
+    add_conv_builtins(env);
+    add_doc_builtins(env);
+    add_reader_builtins(env);
+    add_test_lib(env);
+    if let Err(err) = load_str(env, "core.slosh", CORE_LIB) {
+        eprintln!("ERROR loading the core library: {}", err.display(env));
+    }
+    env.set_global_builtin("dump-regs", builtin_dump_regs);
+    env.set_named_global("*uid*", Value::UInt32(Sys::current_uid()));
+    env.set_named_global("*euid*", Value::UInt32(Sys::effective_uid()));
+    env.set_named_global("*last-status*", Value::Int32(0));
+    env.set_named_global("*show-expansion*", Value::False);
+    set_args(env, &[]);
+}
+
+/// Set *args* to a vector of args (the arguments a script or built executable was run with).
+pub fn set_args(env: &mut SloshVm, args: &[String]) {
+    let args = args.iter().map(|a| env.alloc_string(a.clone())).collect();
+    let args = env.alloc_vector(args);
+    env.set_named_global("*args*", args);
+}
+
+/// Add the builtins and set the environment variables slosh provides.  The builtins and core
+/// definitions are frozen so init.slosh and scripts can not redefine them.
+pub fn init_env(env: &mut SloshVm) {
+    set_builtins(env);
+    freeze_all_globals(env);
+    let uid = Sys::current_uid();
+    let euid = Sys::effective_uid();
+    env::set_var("UID", format!("{uid}"));
+    env::set_var("EUID", format!("{euid}"));
+    // Initialize the HOST variable
+    let host: OsString = Sys::gethostname().unwrap_or_else(|| "???".into());
+    env::set_var("HOST", host);
+    if let Ok(dir) = env::current_dir() {
+        env::set_var("PWD", dir);
+    }
+}
@@ -1,65 +1,122 @@
+use builtins::add_builtin;
 use compile_state::state::{CompileState, SloshVm, SloshVmTrait};
 use shell::builtins::expand_tilde;
 use sl_compiler::pass1::pass1;
-use sl_compiler::{compile, Reader};
+use sl_compiler::warnings::{check_undefined_globals, report_warnings};
+use sl_compiler::{compile, read_table, render_error, set_read_table, Reader, SourceLoc};
 use slvm::{Chunk, VMError, VMResult, Value, RET};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 
-fn load_one_expression(
+/// Print a compile error in exp with the line of source it is on.
+fn print_compile_error(
+    vm: &SloshVm,
+    kind: &str,
+    name: &'static str,
+    exp: Value,
+    source: Option<&str>,
+    err: &VMError,
+) {
+    let loc = SourceLoc::compile_error(vm, name, exp);
+    print!("{}", render_error(kind, &err.to_string(), &loc, source));
+}
+
+pub fn load_one_expression(
     vm: &mut SloshVm,
     exp: Value,
     name: &'static str,
     doc_string: Option<Value>,
+    source: Option<&str>,
 ) -> VMResult<(Arc<Chunk>, Option<Value>)> {
     let line_num = vm.line_num();
     let mut state = CompileState::new_state(name, line_num, None);
     state.chunk.dbg_args = Some(Vec::new());
     state.doc_string = doc_string;
     if let Err(e) = pass1(vm, &mut state, exp) {
-        println!(
-            "Compile error (pass one), {}, line {}: {}",
-            name,
-            vm.line_num(),
-            e
-        );
+        print_compile_error(vm, "Compile error (pass one)", name, exp, source, &e);
         return Err(e);
     }
     if let Err(e) = compile(vm, &mut state, exp, 0) {
-        println!(
-            "Compile error, {} line {}: {} exp: {}",
-            name,
-            vm.line_num(),
-            e,
-            exp.display_value(vm)
-        );
+        print_compile_error(vm, "Compile error", name, exp, source, &e);
         return Err(e);
     }
     if let Err(e) = state.chunk.encode0(RET, vm.own_line()) {
-        println!("Compile error, {} line {}: {}", name, vm.line_num(), e);
+        print_compile_error(vm, "Compile error", name, exp, source, &e);
         return Err(e);
     }
+    report_warnings(vm, source)?;
     state.chunk.extra_regs = state.max_regs;
     Ok((Arc::new(state.chunk), state.doc_string))
 }
 
-pub(crate) fn load_internal(vm: &mut SloshVm, name: &'static str) -> VMResult<Value> {
+pub fn load_internal(vm: &mut SloshVm, name: &'static str) -> VMResult<Value> {
     let file = std::fs::File::open(name).map_err(|e| VMError::new("io", format!("{name}: {e}")))?;
 
+    let reader = Reader::from_file(file, vm, name, 1, 0);
+    // Errors will read the file name for the source lines.
+    load_reader(reader, name, None)
+}
+
+/// Load (compile and execute) the source in a string, name is used for error reporting.
+pub(crate) fn load_str(vm: &mut SloshVm, name: &'static str, src: &str) -> VMResult<Value> {
+    let reader = Reader::from_string(src.to_string(), vm, name, 1, 0);
+    load_reader(reader, name, Some(src))
+}
+
+fn load_reader(mut reader: Reader, name: &'static str, source: Option<&str>) -> VMResult<Value> {
+    let old_line_num = reader.vm().line_num();
+    reader.vm().set_line_num(1);
+    // Reader macros set by the file end with it, keep the caller's table alive until restored.
+    let old_read_table = read_table(reader.vm());
+    reader.vm().heap_sticky(old_read_table);
+    let undefined_start = reader.vm().env().undefined_refs_len();
+    let result = load_reader_inner(&mut reader, name, source);
+    reader.vm().heap_unsticky(old_read_table);
+    set_read_table(reader.vm(), old_read_table);
+    reader.vm().set_line_num(old_line_num);
+    // Globals used by the file but never defined can only be found once it has all run.
+    let vm = reader.vm();
+    match result {
+        Ok(last) => {
+            check_undefined_globals(vm, undefined_start);
+            report_warnings(vm, source)?;
+            Ok(last)
+        }
+        Err(err) => {
+            vm.env_mut().drain_undefined_refs(undefined_start);
+            Err(err)
+        }
+    }
+}
+
+fn load_reader_inner(
+    reader: &mut Reader,
+    name: &'static str,
+    source: Option<&str>,
+) -> VMResult<Value> {
     let mut last = Value::Nil;
-    let mut reader = Reader::from_file(file, vm, name, 1, 0);
     let mut doc_string = None;
     while let Some(exp) = reader.next() {
         let reader_vm = reader.vm();
-        let exp = exp.map_err(|e| VMError::new("read", e.to_string()))?;
+        let exp = exp.map_err(|e| {
+            print!("{}", e.render(source));
+            VMError::new("read", e.to_string())
+        })?;
         reader_vm.heap_sticky(exp);
 
-        let result = load_one_expression(reader_vm, exp, name, doc_string);
+        let result = load_one_expression(reader_vm, exp, name, doc_string, source);
 
         reader_vm.heap_unsticky(exp);
+        if let Some(doc_string) = doc_string {
+            reader_vm.heap_unsticky(doc_string);
+        }
         let (chunk, new_doc_string) = result?;
         doc_string = new_doc_string;
+        if let Some(doc_string) = doc_string {
+            // Keep the doc string from being collected until the next form uses it.
+            reader_vm.heap_sticky(doc_string);
+        }
         last = reader_vm.execute(chunk)?;
     }
     Ok(last)
@@ -81,7 +138,13 @@ fn load(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
         }
         _ => return Err(VMError::new_vm("load: Not a string.")),
     };
-    let name = if name.contains('~') {
+    let name = expand_load_path(vm, name);
+    load_internal(vm, name)
+}
+
+/// The file name to load for name (with a leading ~ expanded).
+pub fn expand_load_path(vm: &mut SloshVm, name: &'static str) -> &'static str {
+    if name.contains('~') {
         let name_path = PathBuf::from_str(name).expect("PathBuf from_str failed!");
         let name_exp = expand_tilde(name_path.clone());
         if name_exp == name_path {
@@ -92,12 +155,7 @@ fn load(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
         }
     } else {
         name
-    };
-    let olf_line_num = vm.line_num();
-    vm.set_line_num(1);
-    let r = load_internal(vm, name);
-    vm.set_line_num(olf_line_num);
-    r
+    }
 }
 
 fn eval(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
@@ -117,7 +175,86 @@ fn eval(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
     }
 }
 
+fn warnings_as_errors(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    match registers {
+        [] => {}
+        [on] => vm.env_mut().set_warnings_as_errors(on.is_truethy()),
+        _ => {
+            return Err(VMError::new_vm(
+                "warnings-as-errors: wrong number of args, expected zero or one",
+            ))
+        }
+    }
+    Ok(if vm.env().warnings_as_errors() {
+        Value::True
+    } else {
+        Value::False
+    })
+}
+
+fn check_types(vm: &mut SloshVm, registers: &[Value]) -> VMResult<Value> {
+    match registers {
+        [] => {}
+        [on] => vm.env_mut().set_check_types(on.is_truethy()),
+        _ => {
+            return Err(VMError::new_vm(
+                "check-types: wrong number of args, expected zero or one",
+            ))
+        }
+    }
+    Ok(if vm.env().check_types() {
+        Value::True
+    } else {
+        Value::False
+    })
+}
+
 pub fn add_load_builtins(env: &mut SloshVm) {
     env.set_global_builtin("load", load);
     env.set_global_builtin("eval", eval);
+    add_builtin(
+        env,
+        "warnings-as-errors",
+        warnings_as_errors,
+        "Usage: (warnings-as-errors) or (warnings-as-errors on)
+
+Return true if compile warnings (unused or shadowing bindings, calls with the
+wrong number of arguments, globals that are never defined) are treated as
+errors.  With an argument turn this on or off first.  When on load and the REPL
+refuse to run a form that has warnings.
+
+Section: core
+
+Example:
+(def old-wae (warnings-as-errors))
+(test::assert-true (warnings-as-errors #t))
+(test::assert-true (warnings-as-errors))
+(test::assert-false (warnings-as-errors #f))
+(warnings-as-errors old-wae)
+",
+    );
+    add_builtin(
+        env,
+        "check-types",
+        check_types,
+        "Usage: (check-types) or (check-types on)
+
+Return true if functions are compiled with checks of their type annotations.
+With an argument turn this on or off first.  When on a function with annotated
+params, (fn ((x :Int) (s :String)) ...), or return value, (fn (x) :String ...),
+raises a :type error when called with (or returning) a value whose type (as
+returned by type) does not match.  Only affects functions compiled after it is
+changed.  Mismatches that are known at compile time are always warnings.
+
+Section: core
+
+Example:
+(def old-ct (check-types))
+(check-types #t)
+(def check-types-test (fn ((x :Int)) :String (str x)))
+(check-types old-ct)
+(test::assert-equal \"1\" (check-types-test 1))
+(test::assert-error (check-types-test \"1\"))
+",
+    );
 }
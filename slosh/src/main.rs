@@ -2,7 +2,6 @@ extern crate sl_liner;
 
 use std::cell::RefCell;
 use std::env;
-use std::ffi::OsString;
 use std::fs::create_dir_all;
 use std::io::{BufRead, ErrorKind};
 use std::sync::Arc;
@@ -11,38 +10,32 @@ use slvm::opcodes::*;
 
 use compile_state::state::*;
 use sl_compiler::compile::*;
+use sl_compiler::diagnostics::{render_error, SourceLoc};
 use sl_compiler::reader::*;
 
-use builtins::add_misc_builtins;
-use builtins::collections::setup_collection_builtins;
-use builtins::conversions::add_conv_builtins;
-use builtins::io::add_io_builtins;
-use builtins::print::{add_print_builtins, display_value};
-use builtins::string::add_str_builtins;
+use builtins::docs::{docs_html, docs_markdown};
+use builtins::macroexpand::macroexpand_all;
+use builtins::print::display_value;
 use sl_liner::vi::AlphanumericAndVariableKeywordRule;
 use sl_liner::{keymap, ColorClosure, Context, Prompt};
 
+mod build;
 mod completions;
 mod config;
-pub mod debug;
 mod liner_rules;
-mod load_eval;
-mod shell_builtins;
 
+use crate::build::{build, embedded_image, run_image};
 use crate::completions::ShellCompleter;
 use crate::liner_rules::make_editor_rules;
-use crate::load_eval::{add_load_builtins, load_internal};
-use crate::shell_builtins::add_shell_builtins;
 use config::*;
-use debug::*;
 use shell::platform::{Platform, Sys, STDIN_FILENO};
 use sl_compiler::pass1::pass1;
-use slvm::Value;
-
-thread_local! {
-    /// Env (job control status, etc) for the shell.
-    pub static SHELL_ENV: RefCell<shell::jobs::Jobs> = RefCell::new(shell::jobs::Jobs::new(true));
-}
+use sl_compiler::warnings::{check_undefined_globals, report_warnings};
+use slosh_lib::debug::debug;
+use slosh_lib::load_eval::load_internal;
+use slosh_lib::test_runner::{run_doc_tests, run_test_files, vm_with_files};
+use slosh_lib::{init_env, set_args, SHELL_ENV};
+use slvm::{VMError, Value};
 
 thread_local! {
     /// Env (job control status, etc) for the shell.
@@ -50,6 +43,8 @@ thread_local! {
 }
 
 const PROMPT_FN: &str = "prompt";
+// Prompt when more lines are needed to finish a form.
+const CONTINUE_PROMPT: &str = "... ";
 
 fn get_prompt(env: &mut SloshVm) -> String {
     let i_val = env.intern("__prompt");
@@ -186,33 +181,84 @@ fn get_color_closure() -> Option<ColorClosure> {
     })
 }
 
+/// Run the program built into this executable (see build) and exit.
+fn run_embedded(image: Vec<u8>) -> ! {
+    let status = ENV.with(|renv| {
+        let mut env = renv.borrow_mut();
+        init_env(&mut env);
+        let args: Vec<String> = env::args().skip(1).collect();
+        set_args(&mut env, &args);
+        match run_image(&mut env, &image) {
+            Ok(_) => 0,
+            Err(err) => {
+                eprintln!("ERROR: {}", err.display(&env));
+                1
+            }
+        }
+    });
+    SHELL_ENV.with(|jobs| {
+        jobs.borrow_mut().reap_procs();
+    });
+    std::process::exit(status);
+}
+
 fn main() {
+    if let Some(image) = embedded_image() {
+        run_embedded(image);
+    }
     if let Some(config) = get_config() {
+        if config.test {
+            std::process::exit(run_test_files(&config.args));
+        }
+        if config.doc_test {
+            std::process::exit(run_doc_tests(&config.args));
+        }
+        if let Some(format) = config.gen_docs {
+            match vm_with_files(&config.args) {
+                Ok(mut vm) => {
+                    if format == "html" {
+                        print!("{}", docs_html(&mut vm));
+                    } else {
+                        print!("{}", docs_markdown(&mut vm));
+                    }
+                }
+                Err(err) => {
+                    eprintln!("ERROR: {err}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
         ENV.with(|renv| {
             let mut env = renv.borrow_mut();
-            add_shell_builtins(&mut env);
-            setup_collection_builtins(&mut env);
-            add_print_builtins(&mut env);
-            add_load_builtins(&mut env);
-            add_str_builtins(&mut env);
-            add_misc_builtins(&mut env);
-            add_io_builtins(&mut env);
-            add_conv_builtins(&mut env);
-            env.set_global_builtin("dump-regs", builtin_dump_regs);
-            let uid = Sys::current_uid();
-            let euid = Sys::effective_uid();
-            env::set_var("UID", format!("{uid}"));
-            env::set_var("EUID", format!("{euid}"));
-            env.set_named_global("*uid*", Value::UInt32(uid));
-            env.set_named_global("*euid*", Value::UInt32(euid));
-            env.set_named_global("*last-status*", Value::Int32(0));
-            // Initialize the HOST variable
-            let host: OsString = Sys::gethostname().unwrap_or_else(|| "???".into());
-            env::set_var("HOST", host);
-            if let Ok(dir) = env::current_dir() {
-                env::set_var("PWD", dir);
+            init_env(&mut env);
+            if config.expand {
+                env.set_named_global("*show-expansion*", Value::True);
+            }
+            if config.check_types {
+                env.env_mut().set_check_types(true);
             }
         });
+        if let Some(program) = config.build {
+            let output = config.output.unwrap_or_else(|| {
+                let path = std::path::Path::new(&program);
+                path.file_stem()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .to_string()
+            });
+            let status = ENV.with(|renv| {
+                let mut env = renv.borrow_mut();
+                match build(&mut env, &program, &output) {
+                    Ok(()) => 0,
+                    Err(err) => {
+                        eprintln!("ERROR: {}", err.display(&env));
+                        1
+                    }
+                }
+            });
+            std::process::exit(status);
+        }
         if config.command.is_none() && config.script.is_none() {
             load_sloshrc();
             if Sys::is_tty(STDIN_FILENO) {
@@ -271,17 +317,40 @@ fn main() {
                         continue;
                     }
 
-                    let res = if res.contains("\\\n") {
+                    let mut res = if res.contains("\\\n") {
                         res.replace("\\\n", "")
                     } else {
                         res
                     };
-                    con.history.push(&res).expect("Failed to push history.");
                     if res.starts_with('(') {
-                        ENV.with(|env| {
-                            exec_expression(res, &mut env.borrow_mut());
-                        });
+                        // Keep reading lines until the forms are complete.
+                        while !ENV
+                            .with(|env| exec_expression(res.clone(), &mut env.borrow_mut(), true))
+                        {
+                            match con.read_line(Prompt::from(CONTINUE_PROMPT), get_color_closure())
+                            {
+                                Ok(more) => {
+                                    res.push('\n');
+                                    res.push_str(&more);
+                                }
+                                Err(err) => {
+                                    if err.kind() == ErrorKind::UnexpectedEof {
+                                        // Report what is wrong with the partial input.
+                                        ENV.with(|env| {
+                                            exec_expression(
+                                                res.clone(),
+                                                &mut env.borrow_mut(),
+                                                false,
+                                            )
+                                        });
+                                    }
+                                    break;
+                                }
+                            }
+                        }
+                        con.history.push(&res).expect("Failed to push history.");
                     } else {
+                        con.history.push(&res).expect("Failed to push history.");
                         let status = SHELL_ENV.with(|jobs| {
                             match shell::run::run_one_command(&res, &mut jobs.borrow_mut()) {
                                 Ok(status) => status,
@@ -306,15 +375,24 @@ fn main() {
                         jobs.borrow_mut().reap_procs();
                     });
                     if bytes == 0 {
+                        if res.starts_with('(') {
+                            // Input ended in the middle of a form, report it.
+                            ENV.with(|env| {
+                                exec_expression(res.clone(), &mut env.borrow_mut(), false)
+                            });
+                        }
                         break;
                     }
                     if res.is_empty() {
                         continue;
                     }
                     if res.starts_with('(') {
-                        ENV.with(|env| {
-                            exec_expression(res.clone(), &mut env.borrow_mut());
-                        });
+                        if !ENV
+                            .with(|env| exec_expression(res.clone(), &mut env.borrow_mut(), true))
+                        {
+                            // Incomplete form, keep the input and add the next line to it.
+                            continue;
+                        }
                     } else {
                         let status = SHELL_ENV.with(|jobs| {
                             match shell::run::run_one_command(&res, &mut jobs.borrow_mut()) {
@@ -360,6 +438,7 @@ fn main() {
         } else if let Some(script) = config.script {
             ENV.with(|renv| {
                 let mut env = renv.borrow_mut();
+                set_args(&mut env, &config.args);
                 let script = env.intern(&script);
                 let script = env.get_interned(script);
                 match load_internal(&mut env, script) {
@@ -371,29 +450,62 @@ fn main() {
     }
 }
 
-fn exec_expression(res: String, env: &mut SloshVm) {
-    let reader = Reader::from_string(res, env, "", 1, 0);
+/// True if *show-expansion* is set, the REPL then prints each form fully macro expanded before
+/// compiling it.
+fn show_expansion(env: &mut SloshVm) -> bool {
+    let sym = env.intern("*show-expansion*");
+    env.global_intern_slot(sym)
+        .map(|slot| !env.get_global(slot).is_falsey())
+        .unwrap_or(false)
+}
+
+/// Read, compile and run the forms in res.  If allow_incomplete is true and res ends in the middle
+/// of a form (an unclosed list, string, etc) then nothing is run and false is returned so the
+/// caller can add more input and try again.
+fn exec_expression(res: String, env: &mut SloshVm, allow_incomplete: bool) -> bool {
+    let source = res.clone();
+    // Each input starts on line 1.
+    env.set_line_num(1);
+    let reader = Reader::from_string(res, env, PROMPT_FN, 1, 0);
     let exps: Result<Vec<Value>, ReadError> = reader.collect();
+    let compile_error = |env: &SloshVm, kind: &str, exp: Value, err: &VMError| {
+        let loc = SourceLoc::compile_error(env, PROMPT_FN, exp);
+        eprint!(
+            "{}",
+            render_error(kind, &err.to_string(), &loc, Some(&source))
+        );
+    };
     match exps {
         Ok(exps) => {
+            let undefined_start = env.env().undefined_refs_len();
+            let show_expansion = show_expansion(env);
             for exp in exps {
                 let line_num = env.line_num();
+                if show_expansion {
+                    match macroexpand_all(env, exp) {
+                        Ok(expanded) => println!("{}", display_value(env, expanded)),
+                        Err(e) => {
+                            compile_error(env, "Macro expansion error", exp, &e);
+                            break;
+                        }
+                    }
+                }
                 let mut state = CompileState::new_state(PROMPT_FN, line_num, None);
                 if let Err(e) = pass1(env, &mut state, exp) {
-                    eprintln!("Compile error (pass1), line {}: {}", env.line_num(), e);
-                    return;
+                    compile_error(env, "Compile error (pass1)", exp, &e);
+                    break;
                 }
                 if let Err(e) = compile(env, &mut state, exp, 0) {
-                    eprintln!("Compile error, line {}: {}", env.line_num(), e);
-                    return;
+                    compile_error(env, "Compile error", exp, &e);
+                    break;
                 }
                 if let Err(e) = state.chunk.encode0(RET, env.own_line()) {
-                    eprintln!(
-                        "Compile error (failed to add return...), line {}: {}",
-                        env.line_num(),
-                        e
-                    );
-                    return;
+                    compile_error(env, "Compile error (failed to add return...)", exp, &e);
+                    break;
+                }
+                if let Err(e) = report_warnings(env, Some(&source)) {
+                    eprintln!("ERROR: {e}");
+                    break;
                 }
                 let chunk = Arc::new(state.chunk.clone());
                 match env.execute(chunk.clone()) {
@@ -405,11 +517,9 @@ fn exec_expression(res: String, env: &mut SloshVm) {
                     Err(err) => {
                         eprintln!("ERROR: {}", err.display(env));
                         if let Some(err_frame) = env.err_frame() {
-                            let line = err_frame.current_line().unwrap_or(0);
                             eprintln!(
-                                "{} line: {} ip: {:#010x}",
-                                err_frame.chunk.file_name,
-                                line,
+                                "{} ip: {:#010x}",
+                                err_frame.current_location(),
                                 err_frame.current_offset()
                             );
                         }
@@ -417,7 +527,13 @@ fn exec_expression(res: String, env: &mut SloshVm) {
                     }
                 }
             }
+            // The code has run so any globals it uses should be defined now.
+            check_undefined_globals(env, undefined_start);
+            // Already ran, warnings as errors has nothing left to stop.
+            let _ = report_warnings(env, Some(&source));
         }
-        Err(err) => println!("Reader error: {err}"),
+        Err(err) if allow_incomplete && err.incomplete => return false,
+        Err(err) => eprint!("{}", err.render(Some(&source))),
     }
+    true
 }
@@ -1,5 +1,4 @@
-use crate::config::VERSION_STRING;
-use crate::SHELL_ENV;
+use crate::{SHELL_ENV, VERSION_STRING};
 use builtins::add_builtin;
 use compile_state::state::SloshVm;
 use shell::platform::{FromFileDesc, Platform, Sys};
@@ -0,0 +1,210 @@
+use builtins::test::{add_test_builtins, call_thunk, fixtures_for, registered_tests};
+use compile_state::state::{new_slosh_vm, SloshVm, SloshVmTrait};
+use slvm::{VMError, VMResult, Value};
+
+use crate::load_eval::{load_internal, load_str};
+use crate::set_builtins;
+
+/// Lisp side of the test library (macros like deftest and test::assert-error).
+const TEST_LIB: &str = include_str!("../../lisp/test.slosh");
+
+/// Add the test builtins and macros to a VM.
+pub(crate) fn add_test_lib(env: &mut SloshVm) {
+    add_test_builtins(env);
+    if let Err(err) = load_str(env, "test.slosh", TEST_LIB) {
+        eprintln!("ERROR loading the test library: {}", err.display(env));
+    }
+}
+
+#[derive(Default)]
+struct TestResults {
+    passed: usize,
+    failed: usize,
+    failures: Vec<(String, String)>,
+}
+
+fn run_one_test(vm: &mut SloshVm, group: Option<&str>, func: Value) -> VMResult<()> {
+    let fixtures = fixtures_for(vm, group);
+    let mut result = Ok(());
+    for before in fixtures.before {
+        result = call_thunk(vm, before).map(|_| ());
+        if result.is_err() {
+            break;
+        }
+    }
+    if result.is_ok() {
+        result = call_thunk(vm, func).map(|_| ());
+    }
+    // Always tear down, report the first error.
+    for after in fixtures.after {
+        let after_result = call_thunk(vm, after).map(|_| ());
+        if result.is_ok() {
+            result = after_result;
+        }
+    }
+    result
+}
+
+fn run_file(file: &str, results: &mut TestResults) {
+    let mut vm = new_slosh_vm();
+    set_builtins(&mut vm);
+    let name = vm.intern(file);
+    let name = vm.get_interned(name);
+    if let Err(err) = load_internal(&mut vm, name) {
+        println!("test {file} ... FAILED to load");
+        results.failed += 1;
+        results
+            .failures
+            .push((file.to_string(), load_error(&vm, err)));
+        return;
+    }
+    let tests = registered_tests(&mut vm);
+    println!("\nrunning {} tests from {file}", tests.len());
+    for test in tests {
+        let test_name = match &test.group {
+            Some(group) => format!("{group}/{}", test.name),
+            None => test.name.clone(),
+        };
+        vm.reset();
+        match run_one_test(&mut vm, test.group.as_deref(), test.func) {
+            Ok(()) => {
+                println!("test {test_name} ... ok");
+                results.passed += 1;
+            }
+            Err(err) => {
+                println!("test {test_name} ... FAILED");
+                results.failed += 1;
+                results
+                    .failures
+                    .push((format!("{test_name} ({file})"), err.display(&vm)));
+            }
+        }
+    }
+}
+
+fn load_error(vm: &SloshVm, err: VMError) -> String {
+    let mut msg = err.display(vm);
+    if let Some(err_frame) = vm.err_frame() {
+        msg.push_str(&format!("\n{}", err_frame.current_location()));
+    }
+    msg
+}
+
+/// Create a VM with the builtins and files loaded (used for each doc test and generating docs).
+pub fn vm_with_files(files: &[String]) -> Result<SloshVm, String> {
+    let mut vm = new_slosh_vm();
+    set_builtins(&mut vm);
+    for file in files {
+        let name = vm.intern(file);
+        let name = vm.get_interned(name);
+        if let Err(err) = load_internal(&mut vm, name) {
+            return Err(format!("{file}: {}", load_error(&vm, err)));
+        }
+    }
+    Ok(vm)
+}
+
+fn run_doc_example(files: &[String], sym: &str, example: &str) -> Result<(), String> {
+    let mut vm = vm_with_files(files)?;
+    let name = vm.intern(&format!("{sym} (doc example)"));
+    let name = vm.get_interned(name);
+    load_str(&mut vm, name, example)
+        .map(|_| ())
+        .map_err(|err| load_error(&vm, err))
+}
+
+/// Return the text of the Example section of a doc string (everything after the "Example:"
+/// line) or None if it does not have one.
+fn doc_example(doc: &str) -> Option<&str> {
+    let mut offset = 0;
+    for line in doc.split_inclusive('\n') {
+        offset += line.len();
+        if line.trim() == "Example:" {
+            let example = &doc[offset..];
+            return if example.trim().is_empty() {
+                None
+            } else {
+                Some(example)
+            };
+        }
+    }
+    None
+}
+
+/// Return (symbol, example) for every global with an Example in its doc string, sorted by symbol.
+fn doc_examples(vm: &mut SloshVm) -> Vec<(String, String)> {
+    let key = vm.intern("doc-string");
+    let mut examples = Vec::new();
+    for (sym, slot) in vm.globals() {
+        let doc = match vm.get_global_property(*slot as u32, key) {
+            Some(Value::String(h)) => vm.get_string(h),
+            Some(Value::StringConst(i)) => vm.get_interned(i),
+            _ => continue,
+        };
+        if let Some(example) = doc_example(doc) {
+            examples.push((vm.get_interned(*sym).to_string(), example.to_string()));
+        }
+    }
+    examples.sort();
+    examples
+}
+
+/// Run the Example section of every documented global as a test.  Globals are the builtins plus
+/// anything defined (with doc comments) in files, each example is run in a fresh VM with files
+/// loaded.  Returns the exit status, 0 if every example passed.
+pub fn run_doc_tests(files: &[String]) -> i32 {
+    let mut results = TestResults::default();
+    let examples = match vm_with_files(files) {
+        Ok(mut vm) => doc_examples(&mut vm),
+        Err(err) => {
+            println!("{err}");
+            return 1;
+        }
+    };
+    println!("\nrunning {} doc tests", examples.len());
+    for (sym, example) in examples {
+        match run_doc_example(files, &sym, &example) {
+            Ok(()) => {
+                println!("doc-test {sym} ... ok");
+                results.passed += 1;
+            }
+            Err(msg) => {
+                println!("doc-test {sym} ... FAILED");
+                results.failed += 1;
+                results.failures.push((sym, msg));
+            }
+        }
+    }
+    report(&results)
+}
+
+/// Run the tests defined (with deftest) in files, each file gets a fresh VM.
+/// Returns the exit status, 0 if every test passed.
+pub fn run_test_files(files: &[String]) -> i32 {
+    let mut results = TestResults::default();
+    for file in files {
+        run_file(file, &mut results);
+    }
+    report(&results)
+}
+
+/// Print the failures and summary, returns the exit status.
+fn report(results: &TestResults) -> i32 {
+    if !results.failures.is_empty() {
+        println!("\nfailures:");
+        for (name, msg) in &results.failures {
+            println!("\n---- {name} ----\n{msg}");
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if results.failed == 0 { "ok" } else { "FAILED" },
+        results.passed,
+        results.failed
+    );
+    if results.failed == 0 {
+        0
+    } else {
+        1
+    }
+}
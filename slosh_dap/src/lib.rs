@@ -0,0 +1,8 @@
+//! Debug Adapter Protocol server for slosh.  Runs a script in a VM with a debug hook so a client
+//! (editor) can set line breakpoints, step, pause and look at the call stack and the named
+//! locals (registers) of each frame.  If the script fails the session stops on the error so its
+//! state can be inspected before it exits.
+//!
+//! Only one script file is debugged, breakpoints in other files are accepted but can not be hit.
+
+pub mod session;
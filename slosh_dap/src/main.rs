@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::os::fd::FromRawFd;
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+
+use nix::unistd::{close, dup, dup2, pipe};
+use slosh_dap::session::{run, Client};
+use slosh_lsp::protocol::{read_message, write_message};
+
+/// The file the slosh shell loads at startup, its definitions are available to the script.
+fn init_file() -> Option<PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    Some(PathBuf::from(home).join(".config/slosh/init.slosh"))
+}
+
+fn main() -> nix::Result<()> {
+    // The protocol uses stdout so anything the script writes to it goes through a pipe and is
+    // sent to the client as output events.
+    let mut protocol_out = unsafe { File::from_raw_fd(dup(1)?) };
+    let (pipe_read, pipe_write) = pipe()?;
+    dup2(pipe_write, 1)?;
+    close(pipe_write)?;
+
+    let client = Client::new_shared(Box::new(move |msg| {
+        if let Err(err) = write_message(&mut protocol_out, &msg) {
+            eprintln!("slosh-dap: {err}");
+            std::process::exit(1);
+        }
+    }));
+    let output_client = client.clone();
+    std::thread::spawn(move || {
+        let mut script_out = unsafe { File::from_raw_fd(pipe_read) };
+        let mut buf = [0_u8; 4096];
+        while let Ok(n) = script_out.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let output = String::from_utf8_lossy(&buf[..n]);
+            if let Ok(mut client) = output_client.lock() {
+                client.output("stdout", &output);
+            }
+        }
+    });
+
+    let (requests, input) = channel();
+    std::thread::spawn(move || {
+        let mut stdin = BufReader::new(io::stdin());
+        loop {
+            match read_message(&mut stdin) {
+                Ok(Some(msg)) => {
+                    if requests.send(msg).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => eprintln!("slosh-dap: {err}"),
+            }
+        }
+    });
+    run(input, client, init_file());
+    Ok(())
+}
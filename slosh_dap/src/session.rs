@@ -0,0 +1,777 @@
+//! A debug session: handles the DAP requests and runs the program with a VM debug hook that
+//! implements breakpoints and stepping.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+use compile_state::state::{new_slosh_vm, SloshVm, SloshVmTrait};
+use serde_json::{json, Value as Json};
+use sl_compiler::{Reader, SourceLoc};
+use slosh_lib::load_eval::load_internal;
+use slosh_lib::{init_env, set_args};
+use slosh_lsp::analysis::compile_form;
+use slvm::{CallFrame, Chunk, VMError, VMResult, Value};
+
+/// Where the messages (responses and events) for the client go.
+pub type Sink = Box<dyn FnMut(Json) + Send>;
+
+/// Scripts run on a single thread.
+const THREAD_ID: u32 = 1;
+
+/// Sends messages to the client, shared so the program's output can be sent from another thread.
+pub struct Client {
+    sink: Sink,
+    seq: u64,
+}
+
+pub type SharedClient = Arc<Mutex<Client>>;
+
+impl Client {
+    pub fn new(sink: Sink) -> Self {
+        Self { sink, seq: 0 }
+    }
+
+    pub fn new_shared(sink: Sink) -> SharedClient {
+        Arc::new(Mutex::new(Self::new(sink)))
+    }
+
+    fn send(&mut self, mut msg: Json) {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        (self.sink)(msg);
+    }
+
+    pub fn event(&mut self, event: &str, body: Json) {
+        let mut msg = json!({"type": "event", "event": event});
+        if !body.is_null() {
+            msg["body"] = body;
+        }
+        self.send(msg);
+    }
+
+    /// Send an output event, category is stdout, stderr or console.
+    pub fn output(&mut self, category: &str, output: &str) {
+        self.event("output", json!({"category": category, "output": output}));
+    }
+
+    fn respond(&mut self, req: &Json, body: Json) {
+        let mut msg = json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "success": true,
+            "command": req["command"],
+        });
+        if !body.is_null() {
+            msg["body"] = body;
+        }
+        self.send(msg);
+    }
+
+    fn respond_err(&mut self, req: &Json, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "success": false,
+            "command": req["command"],
+            "message": message,
+        }));
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Step {
+    /// Run until a breakpoint.
+    Run,
+    /// Stop at the next line, with this reason (entry or pause).
+    Pause(&'static str),
+    /// Stop at the next line in any frame.
+    In,
+    /// Stop at the next line in a frame at or above depth.
+    Over(usize),
+    /// Stop at the next line in a frame above depth.
+    Out(usize),
+}
+
+struct Launch {
+    program: String,
+    /// The script's arguments (*args*).
+    args: Vec<String>,
+    stop_on_entry: bool,
+}
+
+struct Session {
+    input: Receiver<Json>,
+    client: SharedClient,
+    /// Breakpoint lines by (canonical) file path.
+    breakpoints: HashMap<String, Vec<u32>>,
+    step: Step,
+    /// The frame (stack top and call frame id), chunk and line of each active frame (innermost
+    /// last).  Used to tell when a frame starts a new line (so a call returning to its line is
+    /// not a new line).
+    lines: Vec<((usize, Option<usize>), usize, u32)>,
+    disconnected: bool,
+}
+
+thread_local! {
+    static SESSION: RefCell<Option<Session>> = const { RefCell::new(None) };
+}
+
+fn debug_hook(vm: &mut SloshVm, chunk: &Arc<Chunk>) -> VMResult<()> {
+    SESSION.with(|session| match session.borrow_mut().as_mut() {
+        Some(session) => session.on_instruction(vm, chunk),
+        None => Ok(()),
+    })
+}
+
+fn disconnected() -> VMError {
+    VMError::new_vm("Debugger disconnected")
+}
+
+fn command(req: &Json) -> &str {
+    req["command"].as_str().unwrap_or_default()
+}
+
+fn canonical_path(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Name of the global that holds the function a frame is running.
+fn frame_name(vm: &SloshVm, frame: &CallFrame) -> String {
+    let this_fn = match frame.this_fn {
+        Some(this_fn) => this_fn,
+        None => return "<top level>".to_string(),
+    };
+    for (i, idx) in vm.globals() {
+        if vm.get_global((*idx).try_into().expect("to many globals")) == this_fn {
+            return vm.get_interned(*i).to_string();
+        }
+    }
+    "<fn>".to_string()
+}
+
+fn stack_trace(vm: &SloshVm, frames: &[CallFrame]) -> Json {
+    let stack_frames: Vec<Json> = frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let file_name = frame.chunk.file_name;
+            let mut stack_frame = json!({
+                "id": i + 1,
+                "name": frame_name(vm, frame),
+                "line": frame.current_line().unwrap_or(0),
+                "column": frame.current_pos().map(|pos| pos.column).unwrap_or(1),
+            });
+            if !file_name.is_empty() && file_name != "no_file" {
+                let name = Path::new(file_name)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                stack_frame["source"] = json!({"name": name, "path": file_name});
+            }
+            stack_frame
+        })
+        .collect();
+    json!({"stackFrames": stack_frames, "totalFrames": frames.len()})
+}
+
+/// The named registers of frame (from the chunk's dbg_args, like the slosh debugger's regs).
+fn variables(vm: &SloshVm, frame: &CallFrame) -> Json {
+    let start = frame.stack_top;
+    let end = start + frame.chunk.input_regs + frame.chunk.extra_regs + 1;
+    let regs = vm.get_registers(start, end);
+    let mut vars = Vec::new();
+    if let Some(names) = frame.chunk.dbg_args.as_ref() {
+        // Register 0 is params/result.
+        for (name, reg) in names.iter().zip(regs.iter().skip(1)) {
+            let name = vm.get_interned(*name);
+            if name == "[SCRATCH]" {
+                continue;
+            }
+            let val = reg.unref(vm);
+            vars.push(json!({
+                "name": name,
+                "value": val.display_value(vm),
+                "type": val.display_type(vm),
+                "variablesReference": 0,
+            }));
+        }
+    }
+    json!({ "variables": vars })
+}
+
+/// Execute chunk with the debug hook, session is available to the hook while it runs.
+fn execute(session: Session, vm: &mut SloshVm, chunk: Arc<Chunk>) -> (Session, VMResult<Value>) {
+    SESSION.with(|s| *s.borrow_mut() = Some(session));
+    vm.set_debug_hook(Some(debug_hook));
+    let result = vm.execute(chunk);
+    vm.set_debug_hook(None);
+    let session = SESSION
+        .with(|s| s.borrow_mut().take())
+        .expect("Debug session missing!");
+    (session, result)
+}
+
+impl Session {
+    fn new(input: Receiver<Json>, client: SharedClient) -> Self {
+        Self {
+            input,
+            client,
+            breakpoints: HashMap::new(),
+            step: Step::Run,
+            lines: Vec::new(),
+            disconnected: false,
+        }
+    }
+
+    fn client(&self) -> std::sync::MutexGuard<'_, Client> {
+        self.client.lock().expect("Client lock poisoned!")
+    }
+
+    fn respond(&self, req: &Json, body: Json) {
+        self.client().respond(req, body);
+    }
+
+    fn next_message(&mut self) -> Option<Json> {
+        match self.input.recv() {
+            Ok(msg) => Some(msg),
+            Err(_) => {
+                self.disconnected = true;
+                None
+            }
+        }
+    }
+
+    /// Handle the requests that do not depend on the state of the program.
+    fn common(&mut self, req: &Json) {
+        match command(req) {
+            "setBreakpoints" => {
+                let args = &req["arguments"];
+                let path = canonical_path(args["source"]["path"].as_str().unwrap_or_default());
+                let lines: Vec<u32> = args["breakpoints"]
+                    .as_array()
+                    .map(|bps| {
+                        bps.iter()
+                            .filter_map(|bp| bp["line"].as_u64())
+                            .map(|line| line as u32)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let breakpoints: Vec<Json> = lines
+                    .iter()
+                    .map(|line| json!({"verified": true, "line": line}))
+                    .collect();
+                self.breakpoints.insert(path, lines);
+                self.respond(req, json!({ "breakpoints": breakpoints }));
+            }
+            "setExceptionBreakpoints" => self.respond(req, Json::Null),
+            "threads" => self.respond(req, json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+            "pause" => {
+                self.step = Step::Pause("pause");
+                self.respond(req, Json::Null);
+            }
+            cmd => {
+                let msg = format!("Unsupported request {cmd}");
+                self.client().respond_err(req, &msg);
+            }
+        }
+    }
+
+    /// Handle requests until the program can be launched (None if the client disconnects first).
+    fn configure(&mut self) -> Option<Launch> {
+        let mut launch = None;
+        let mut configured = false;
+        while let Some(req) = self.next_message() {
+            match command(&req) {
+                "initialize" => {
+                    self.respond(&req, json!({"supportsConfigurationDoneRequest": true}));
+                    self.client().event("initialized", Json::Null);
+                }
+                "launch" => {
+                    let args = &req["arguments"];
+                    match args["program"].as_str() {
+                        Some(program) => {
+                            let script_args = args["args"]
+                                .as_array()
+                                .map(|a| a.iter().filter_map(|a| a.as_str().map(String::from)))
+                                .map(|a| a.collect())
+                                .unwrap_or_default();
+                            launch = Some(Launch {
+                                program: canonical_path(program),
+                                args: script_args,
+                                stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+                            });
+                            self.respond(&req, Json::Null);
+                        }
+                        None => self
+                            .client()
+                            .respond_err(&req, "launch: program is required"),
+                    }
+                }
+                "configurationDone" => {
+                    configured = true;
+                    self.respond(&req, Json::Null);
+                }
+                "disconnect" | "terminate" => {
+                    self.respond(&req, Json::Null);
+                    self.disconnected = true;
+                    return None;
+                }
+                _ => self.common(&req),
+            }
+            if configured && launch.is_some() {
+                return launch;
+            }
+        }
+        None
+    }
+
+    /// Handle any requests that came in while the program is running.
+    fn poll(&mut self) -> VMResult<()> {
+        loop {
+            match self.input.try_recv() {
+                Ok(req) if matches!(command(&req), "disconnect" | "terminate") => {
+                    self.respond(&req, Json::Null);
+                    self.disconnected = true;
+                    return Err(disconnected());
+                }
+                Ok(req) => self.common(&req),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    return Err(disconnected());
+                }
+            }
+        }
+    }
+
+    /// Called before each instruction, stops if this starts a line that is a breakpoint or ends
+    /// a step.
+    fn on_instruction(&mut self, vm: &mut SloshVm, chunk: &Arc<Chunk>) -> VMResult<()> {
+        let frame = vm.current_frame(chunk.clone());
+        let line = match frame.current_line() {
+            Some(line) if line > 0 => line,
+            _ => return Ok(()),
+        };
+        let chunk_id = Arc::as_ptr(chunk) as usize;
+        // A call can reuse its caller's stack top so the frame id is part of the key.
+        let frame_key = (frame.stack_top, vm.call_frame().map(|f| f.id));
+        match self.lines.iter().rposition(|(key, _, _)| *key == frame_key) {
+            Some(idx) => {
+                // Drop the frames that have returned.
+                self.lines.truncate(idx + 1);
+                let last = &mut self.lines[idx];
+                if last.1 == chunk_id && last.2 == line {
+                    return Ok(());
+                }
+                *last = (frame_key, chunk_id, line);
+            }
+            None => self.lines.push((frame_key, chunk_id, line)),
+        }
+        self.poll()?;
+        let depth = self.lines.len();
+        let reason = match self.step {
+            Step::Pause(reason) => Some(reason),
+            Step::In => Some("step"),
+            Step::Over(step_depth) if depth <= step_depth => Some("step"),
+            Step::Out(step_depth) if depth < step_depth => Some("step"),
+            _ => None,
+        };
+        let reason = reason.or_else(|| {
+            self.breakpoints
+                .get(chunk.file_name)
+                .filter(|lines| lines.contains(&line))
+                .map(|_| "breakpoint")
+        });
+        if let Some(reason) = reason {
+            let mut frames = vec![frame];
+            frames.extend(vm.get_call_stack().cloned());
+            self.stopped(vm, &frames, reason, None)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the client the program stopped and handle requests until it is resumed.
+    fn stopped(
+        &mut self,
+        vm: &SloshVm,
+        frames: &[CallFrame],
+        reason: &str,
+        text: Option<&str>,
+    ) -> VMResult<()> {
+        let _ = io::stdout().flush();
+        let mut body = json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true});
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.client().event("stopped", body);
+        while let Some(req) = self.next_message() {
+            let depth = self.lines.len();
+            let step = match command(&req) {
+                "continue" => Step::Run,
+                "next" => Step::Over(depth),
+                "stepIn" => Step::In,
+                "stepOut" => Step::Out(depth),
+                "stackTrace" => {
+                    self.respond(&req, stack_trace(vm, frames));
+                    continue;
+                }
+                "scopes" => {
+                    let frame_id = req["arguments"]["frameId"].as_u64().unwrap_or(0);
+                    let scopes = json!({"scopes": [{
+                        "name": "Locals",
+                        "variablesReference": frame_id,
+                        "expensive": false,
+                    }]});
+                    self.respond(&req, scopes);
+                    continue;
+                }
+                "variables" => {
+                    let var_ref = req["arguments"]["variablesReference"].as_u64().unwrap_or(0);
+                    match frames.get((var_ref as usize).wrapping_sub(1)) {
+                        Some(frame) => self.respond(&req, variables(vm, frame)),
+                        None => self.respond(&req, json!({"variables": []})),
+                    }
+                    continue;
+                }
+                "disconnect" | "terminate" => {
+                    self.respond(&req, Json::Null);
+                    self.disconnected = true;
+                    return Err(disconnected());
+                }
+                _ => {
+                    self.common(&req);
+                    continue;
+                }
+            };
+            self.step = step;
+            if step == Step::Run {
+                self.respond(&req, json!({"allThreadsContinued": true}));
+            } else {
+                self.respond(&req, Json::Null);
+            }
+            return Ok(());
+        }
+        Err(disconnected())
+    }
+
+    /// Read, compile and run the program.  Returns the exit code (1 if it failed).
+    fn run_program(mut self, vm: &mut SloshVm, launch: &Launch) -> (Self, i32) {
+        let src = match fs::read_to_string(&launch.program) {
+            Ok(src) => src,
+            Err(err) => {
+                let msg = format!("Unable to read {}: {err}\n", launch.program);
+                self.client().output("stderr", &msg);
+                return (self, 1);
+            }
+        };
+        if launch.stop_on_entry {
+            self.step = Step::Pause("entry");
+        }
+        let name = vm.intern(&launch.program);
+        let name = vm.get_interned(name);
+        let mut reader = Reader::from_string(src, vm, name, 1, 0);
+        reader.vm().set_line_num(1);
+        let mut doc_string = None;
+        while let Some(exp) = reader.next() {
+            let vm = reader.vm();
+            let exp = match exp {
+                Ok(exp) => exp,
+                Err(err) => {
+                    let msg = format!("{}: {}\n", err.loc, err.reason);
+                    self.client().output("stderr", &msg);
+                    return (self, 1);
+                }
+            };
+            vm.heap_sticky(exp);
+            let result = compile_form(vm, name, exp, doc_string);
+            if let Some(doc_string) = doc_string {
+                vm.heap_unsticky(doc_string);
+            }
+            for warning in vm.env_mut().take_warnings() {
+                let loc = SourceLoc::new(
+                    warning.file_name,
+                    warning.line as usize,
+                    warning.column as usize,
+                    0,
+                );
+                let msg = format!("{loc}: warning: {}\n", warning.message);
+                self.client().output("stderr", &msg);
+            }
+            let chunk = match result {
+                Ok((chunk, new_doc_string)) => {
+                    doc_string = new_doc_string;
+                    if let Some(doc_string) = doc_string {
+                        vm.heap_sticky(doc_string);
+                    }
+                    chunk
+                }
+                Err(err) => {
+                    let loc = SourceLoc::compile_error(vm, name, exp);
+                    let msg = format!("{loc}: {}\n", err.display(vm));
+                    self.client().output("stderr", &msg);
+                    vm.heap_unsticky(exp);
+                    return (self, 1);
+                }
+            };
+            let (session, result) = execute(self, vm, chunk);
+            self = session;
+            vm.heap_unsticky(exp);
+            if let Err(err) = result {
+                if !self.disconnected {
+                    // Stop so the client can look at the state of the program (post mortem).
+                    let msg = err.display(vm);
+                    self.client().output("stderr", &format!("{msg}\n"));
+                    let mut frames: Vec<CallFrame> = vm.err_frame().iter().cloned().collect();
+                    frames.extend(vm.get_call_stack().cloned());
+                    let _ = self.stopped(vm, &frames, "exception", Some(&msg));
+                }
+                vm.reset();
+                return (self, 1);
+            }
+        }
+        (self, 0)
+    }
+
+    /// The program is done, tell the client and wait for it to disconnect.
+    fn finish(&mut self, exit_code: i32) {
+        if self.disconnected {
+            return;
+        }
+        let _ = io::stdout().flush();
+        self.client()
+            .event("exited", json!({ "exitCode": exit_code }));
+        self.client().event("terminated", Json::Null);
+        while let Some(req) = self.next_message() {
+            if matches!(command(&req), "disconnect" | "terminate") {
+                self.respond(&req, Json::Null);
+                return;
+            }
+            self.common(&req);
+        }
+    }
+}
+
+/// VM set up the way slosh sets one up to run a script (builtins, load, eval, shell builtins and
+/// *args*) with init_file (if any) loaded.
+fn new_debuggee_vm(session: &Session, launch: &Launch, init_file: Option<&Path>) -> SloshVm {
+    let mut vm = new_slosh_vm();
+    init_env(&mut vm);
+    set_args(&mut vm, &launch.args);
+    if let Some(init_file) = init_file.filter(|f| f.exists()) {
+        let name = vm.intern(&init_file.to_string_lossy());
+        let name = vm.get_interned(name);
+        if let Err(err) = load_internal(&mut vm, name) {
+            let msg = format!("{name}: {}\n", err.display(&vm));
+            session.client().output("stderr", &msg);
+        }
+    }
+    vm
+}
+
+/// Run a debug session.  Requests from the client arrive on input and the responses and events
+/// go to client.  The program runs in a VM with the builtins slosh has plus init_file.  Returns
+/// when the client disconnects.
+pub fn run(input: Receiver<Json>, client: SharedClient, init_file: Option<PathBuf>) {
+    let mut session = Session::new(input, client);
+    let launch = match session.configure() {
+        Some(launch) => launch,
+        None => return,
+    };
+    let mut vm = new_debuggee_vm(&session, &launch, init_file.as_deref());
+    let (mut session, exit_code) = session.run_program(&mut vm, &launch);
+    session.finish(exit_code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Sender};
+    use std::time::Duration;
+
+    struct TestClient {
+        requests: Sender<Json>,
+        messages: Receiver<Json>,
+        seq: u64,
+    }
+
+    impl TestClient {
+        fn request(&mut self, command: &str, arguments: Json) -> Json {
+            self.seq += 1;
+            let seq = self.seq;
+            self.requests
+                .send(json!({"seq": seq, "type": "request", "command": command, "arguments": arguments}))
+                .unwrap();
+            let resp = self.expect(|msg| msg["type"] == "response");
+            assert_eq!(resp["request_seq"], seq);
+            assert_eq!(resp["success"], true, "{resp}");
+            resp
+        }
+
+        fn expect(&mut self, pred: impl Fn(&Json) -> bool) -> Json {
+            loop {
+                let msg = self
+                    .messages
+                    .recv_timeout(Duration::from_secs(10))
+                    .expect("no message from the debugger");
+                if pred(&msg) {
+                    return msg;
+                }
+            }
+        }
+
+        fn event(&mut self, event: &str) -> Json {
+            self.expect(|msg| msg["event"] == event)
+        }
+
+        fn top_frame(&mut self) -> Json {
+            let trace = self.request("stackTrace", json!({"threadId": 1}));
+            trace["body"]["stackFrames"][0].clone()
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let dir = std::env::temp_dir().join(format!("slosh-dap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("test.slosh");
+        fs::write(
+            &program,
+            "(def x 1)\n(def f (fn (a)\n  (let (b (+ a x))\n    (* b 2))))\n(def y (f 10))\n(def z (f 20))\n(f \"s\")\n",
+        )
+        .unwrap();
+        let program = canonical_path(&program.to_string_lossy());
+
+        let (requests, input) = channel();
+        let (output, messages) = channel();
+        let path = program.clone();
+        let client_thread = std::thread::spawn(move || {
+            let mut client = TestClient {
+                requests,
+                messages,
+                seq: 0,
+            };
+            let resp = client.request("initialize", json!({"adapterID": "slosh"}));
+            assert_eq!(resp["body"]["supportsConfigurationDoneRequest"], true);
+            client.event("initialized");
+            client.request("launch", json!({ "program": path }));
+            let resp = client.request(
+                "setBreakpoints",
+                json!({"source": {"path": path}, "breakpoints": [{"line": 3}]}),
+            );
+            assert_eq!(resp["body"]["breakpoints"][0]["verified"], true);
+            client.request("configurationDone", Json::Null);
+
+            let stopped = client.event("stopped");
+            assert_eq!(stopped["body"]["reason"], "breakpoint");
+            let trace = client.request("stackTrace", json!({"threadId": 1}));
+            let frames = &trace["body"]["stackFrames"];
+            assert_eq!(frames[0]["name"], "f");
+            assert_eq!(frames[0]["line"], 3);
+            assert_eq!(frames[0]["source"]["path"], path.as_str());
+            assert_eq!(frames[1]["name"], "<top level>");
+            assert_eq!(frames[1]["line"], 5);
+            let scopes = client.request("scopes", json!({"frameId": 1}));
+            let var_ref = scopes["body"]["scopes"][0]["variablesReference"].clone();
+            let vars = client.request("variables", json!({ "variablesReference": var_ref }));
+            let vars = &vars["body"]["variables"];
+            assert_eq!(vars[0]["name"], "a");
+            assert_eq!(vars[0]["value"], "10");
+
+            client.request("next", json!({"threadId": 1}));
+            assert_eq!(client.event("stopped")["body"]["reason"], "step");
+            let frame = client.top_frame();
+            assert_eq!(
+                (frame["name"].as_str(), frame["line"].as_u64()),
+                (Some("f"), Some(4))
+            );
+            // Stepping over the end of f stops on the next line of the caller (not line 5 again).
+            client.request("next", json!({"threadId": 1}));
+            assert_eq!(client.event("stopped")["body"]["reason"], "step");
+            assert_eq!(client.top_frame()["line"], 6);
+            // Breakpoints hit every time.
+            client.request("continue", json!({"threadId": 1}));
+            assert_eq!(client.event("stopped")["body"]["reason"], "breakpoint");
+            client.request("stepOut", json!({"threadId": 1}));
+            assert_eq!(client.event("stopped")["body"]["reason"], "step");
+            assert_eq!(client.top_frame()["line"], 7);
+
+            client.request(
+                "setBreakpoints",
+                json!({"source": {"path": path}, "breakpoints": []}),
+            );
+            client.request("continue", json!({"threadId": 1}));
+            let stopped = client.event("stopped");
+            assert_eq!(stopped["body"]["reason"], "exception");
+            let frame = client.top_frame();
+            assert_eq!(
+                (frame["name"].as_str(), frame["line"].as_u64()),
+                (Some("f"), Some(3))
+            );
+            client.request("continue", json!({"threadId": 1}));
+            assert_eq!(client.event("exited")["body"]["exitCode"], 1);
+            client.event("terminated");
+            client.request("disconnect", Json::Null);
+        });
+        run(
+            input,
+            Client::new_shared(Box::new(move |msg| {
+                let _ = output.send(msg);
+            })),
+            None,
+        );
+        client_thread.join().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_slosh_env() {
+        // The script runs with what slosh provides: *args*, load and eval.
+        let dir = std::env::temp_dir().join(format!("slosh-dap-env-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lib = dir.join("lib.slosh");
+        fs::write(&lib, "(def from-lib (fn () \"a\"))\n").unwrap();
+        let program = dir.join("test.slosh");
+        fs::write(
+            &program,
+            format!(
+                "(load \"{}\")\n(if (equal? (eval '(from-lib)) (vec-nth *args* 0)) nil (err :test \"bad\"))\n",
+                lib.display()
+            ),
+        )
+        .unwrap();
+        let program = canonical_path(&program.to_string_lossy());
+
+        let (requests, input) = channel();
+        let (output, messages) = channel();
+        let client_thread = std::thread::spawn(move || {
+            let mut client = TestClient {
+                requests,
+                messages,
+                seq: 0,
+            };
+            client.request("initialize", json!({"adapterID": "slosh"}));
+            client.request("launch", json!({ "program": program, "args": ["a"] }));
+            client.request("configurationDone", Json::Null);
+            let done = client.expect(|msg| msg["event"] == "exited" || msg["event"] == "stopped");
+            assert_eq!(done["body"]["exitCode"], 0, "{done}");
+            client.event("terminated");
+            client.request("disconnect", Json::Null);
+        });
+        run(
+            input,
+            Client::new_shared(Box::new(move |msg| {
+                let _ = output.send(msg);
+            })),
+            None,
+        );
+        client_thread.join().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
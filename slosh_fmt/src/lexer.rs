@@ -0,0 +1,371 @@
+use std::fmt;
+
+/// Kinds of token in slosh source.  Unlike the Reader nothing is thrown away, comments and doc
+/// strings are tokens so they can be written back out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    /// ( [ or {
+    Open,
+    /// ) ] or }
+    Close,
+    /// Reader prefix that applies to the next form: ' ` ~ ~@ or #;
+    Prefix,
+    /// Symbols, keywords, numbers, chars and other # forms (#t, #xFF etc).
+    Atom,
+    /// "string", $"interpolated ${string}" or #"X raw string X"
+    Str,
+    /// ; comment to the end of the line.
+    LineComment,
+    /// #| block comment |#
+    BlockComment,
+    /// #! doc string !#
+    DocComment,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Token<'src> {
+    pub kind: TokenKind,
+    /// Source text of the token, exactly as it appears in the input.
+    pub text: &'src str,
+    /// Number of line endings between this token and the previous one.
+    pub newlines: usize,
+    /// True if there was any whitespace between this token and the previous one.
+    pub space_before: bool,
+    /// Line (1 based) the token starts on.
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FmtError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl FmtError {
+    pub fn new(line: usize, reason: impl Into<String>) -> Self {
+        Self {
+            line,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::error::Error for FmtError {}
+
+impl fmt::Display for FmtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+fn is_whitespace(ch: char) -> bool {
+    matches!(ch, ' ' | '\t' | '\n' | '\r' | ',')
+}
+
+/// Same terminators the Reader uses for symbols.
+fn end_symbol(ch: char) -> bool {
+    is_whitespace(ch)
+        || matches!(
+            ch,
+            '(' | ')' | '#' | '"' | '~' | '\'' | '`' | '[' | ']' | '{' | '}' | '\\' | ';'
+        )
+}
+
+struct Lexer<'src> {
+    src: &'src str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'src> Lexer<'src> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+        }
+        Some(ch)
+    }
+
+    fn unclosed(&self, line: usize, what: &str) -> FmtError {
+        FmtError::new(line, format!("Unclosed {what}"))
+    }
+
+    /// Skip a string body up to and including the closing ", the opening quote has been consumed.
+    /// Strings can contain {form} (or ${form} if interp) which may contain strings of their own.
+    fn string(&mut self, start: usize, interp: bool) -> Result<(), FmtError> {
+        while let Some(ch) = self.bump() {
+            match ch {
+                '\\' => {
+                    self.bump();
+                }
+                '"' => return Ok(()),
+                '{' if !interp => self.form_region(start)?,
+                '$' if interp && self.peek() == Some('{') => {
+                    self.bump();
+                    self.form_region(start)?;
+                }
+                _ => {}
+            }
+        }
+        Err(self.unclosed(start, "string"))
+    }
+
+    /// Skip the forms in a {} in a string, the { has been consumed.
+    fn form_region(&mut self, start: usize) -> Result<(), FmtError> {
+        let mut depth = 1;
+        while let Some(ch) = self.bump() {
+            match ch {
+                '\\' => {
+                    self.bump();
+                }
+                '"' => self.string(start, false)?,
+                '$' if self.peek() == Some('"') => {
+                    self.bump();
+                    self.string(start, true)?;
+                }
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(self.unclosed(start, "string"))
+    }
+
+    /// Skip to the end of a #| |# comment (they nest), the #| has been consumed.
+    fn block_comment(&mut self, start: usize) -> Result<(), FmtError> {
+        let mut depth = 1;
+        let mut last = ' ';
+        while let Some(ch) = self.bump() {
+            if last == '|' && ch == '#' {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+                last = ' ';
+                continue;
+            }
+            if last == '#' && ch == '|' {
+                depth += 1;
+                last = ' ';
+                continue;
+            }
+            last = ch;
+        }
+        Err(self.unclosed(start, "block comment"))
+    }
+
+    /// Skip to the end of the terminator, the opening chars have been consumed.
+    fn until(&mut self, start: usize, end: &str, what: &str) -> Result<(), FmtError> {
+        match self.src[self.pos..].find(end) {
+            Some(idx) => {
+                let target = self.pos + idx + end.len();
+                while self.pos < target {
+                    self.bump();
+                }
+                Ok(())
+            }
+            None => Err(self.unclosed(start, what)),
+        }
+    }
+
+    /// Skip the rest of a symbol (or other atom), handles \ escapes like the Reader.
+    fn symbol(&mut self) {
+        while let Some(ch) = self.peek() {
+            if ch == '\\' {
+                self.bump();
+                self.bump();
+            } else if end_symbol(ch) {
+                break;
+            } else {
+                self.bump();
+            }
+        }
+    }
+
+    fn next_kind(&mut self, start: usize) -> Result<TokenKind, FmtError> {
+        let ch = self.bump().expect("next_kind called at end of input");
+        Ok(match ch {
+            '(' | '[' | '{' => TokenKind::Open,
+            ')' | ']' | '}' => TokenKind::Close,
+            '\'' | '`' => TokenKind::Prefix,
+            '~' => {
+                if self.peek() == Some('@') {
+                    self.bump();
+                }
+                TokenKind::Prefix
+            }
+            ';' => {
+                while let Some(ch) = self.peek() {
+                    if ch == '\n' {
+                        break;
+                    }
+                    self.bump();
+                }
+                TokenKind::LineComment
+            }
+            '"' => {
+                self.string(start, false)?;
+                TokenKind::Str
+            }
+            '$' if self.peek() == Some('"') => {
+                self.bump();
+                self.string(start, true)?;
+                TokenKind::Str
+            }
+            '\\' => {
+                // A char, the first char after the \ is always part of it (\( is a char).
+                self.bump();
+                self.symbol();
+                TokenKind::Atom
+            }
+            '#' => match self.peek() {
+                Some('|') => {
+                    self.bump();
+                    self.block_comment(start)?;
+                    TokenKind::BlockComment
+                }
+                Some('!') => {
+                    self.bump();
+                    self.until(start, "!#", "doc string")?;
+                    TokenKind::DocComment
+                }
+                Some(';') => {
+                    self.bump();
+                    TokenKind::Prefix
+                }
+                Some('"') => {
+                    self.bump();
+                    match self.bump() {
+                        Some(delim) => {
+                            let mut end = delim.to_string();
+                            end.push('"');
+                            self.until(start, &end, "string literal")?;
+                        }
+                        None => return Err(self.unclosed(start, "string literal")),
+                    }
+                    TokenKind::Str
+                }
+                Some(ch) if !is_whitespace(ch) => {
+                    self.bump();
+                    self.symbol();
+                    TokenKind::Atom
+                }
+                _ => TokenKind::Atom,
+            },
+            _ => {
+                self.symbol();
+                TokenKind::Atom
+            }
+        })
+    }
+}
+
+/// Split slosh source into tokens, keeping comments.
+pub fn tokenize(src: &str) -> Result<Vec<Token<'_>>, FmtError> {
+    let mut lexer = Lexer {
+        src,
+        pos: 0,
+        line: 1,
+    };
+    let mut tokens = Vec::new();
+    loop {
+        let mut newlines = 0;
+        let mut space_before = false;
+        while let Some(ch) = lexer.peek() {
+            if !is_whitespace(ch) {
+                break;
+            }
+            if ch == '\n' {
+                newlines += 1;
+            }
+            space_before = true;
+            lexer.bump();
+        }
+        if lexer.peek().is_none() {
+            break;
+        }
+        let line = lexer.line;
+        let start = lexer.pos;
+        let kind = lexer.next_kind(line)?;
+        tokens.push(Token {
+            kind,
+            text: &src[start..lexer.pos],
+            newlines,
+            space_before,
+            line,
+        });
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(src)
+            .unwrap()
+            .iter()
+            .map(|t| (t.kind, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("(def x '[1 \"a{(str \"}\")}\"]) ; done\n#| a #| b |# |# `(~@x \\( #t)"),
+            vec![
+                (Open, "("),
+                (Atom, "def"),
+                (Atom, "x"),
+                (Prefix, "'"),
+                (Open, "["),
+                (Atom, "1"),
+                (Str, "\"a{(str \"}\")}\""),
+                (Close, "]"),
+                (Close, ")"),
+                (LineComment, "; done"),
+                (BlockComment, "#| a #| b |# |#"),
+                (Prefix, "`"),
+                (Open, "("),
+                (Prefix, "~@"),
+                (Atom, "x"),
+                (Atom, "\\("),
+                (Atom, "#t"),
+                (Close, ")"),
+            ]
+        );
+        assert_eq!(
+            kinds("#!\ndoc \"x\n!# $\"a ${(f \"}\")}\" #\"|a\"b|\" #;x"),
+            vec![
+                (DocComment, "#!\ndoc \"x\n!#"),
+                (Str, "$\"a ${(f \"}\")}\""),
+                (Str, "#\"|a\"b|\""),
+                (Prefix, "#;"),
+                (Atom, "x"),
+            ]
+        );
+        let tokens = tokenize("a\n\n  b").unwrap();
+        assert_eq!(tokens[1].newlines, 2);
+        assert_eq!(tokens[1].line, 3);
+        assert_eq!(
+            tokenize("\"abc").unwrap_err(),
+            FmtError::new(1, "Unclosed string")
+        );
+        assert_eq!(
+            tokenize("x\n#| abc").unwrap_err(),
+            FmtError::new(2, "Unclosed block comment")
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Source preserving formatter for slosh code.
+//!
+//! Line breaks, comments (; #| |# and #! !#) and blank lines are kept (runs of blank lines are
+//! squashed to one), everything else is re-indented:
+//! - Data literals ([] {} and quoted lists) indent their contents one past the open delimiter.
+//! - Forms with a body (defn, let, fn, if is not one, etc) indent the body two spaces.
+//! - Other calls line up with the first argument if it is on the same line as the function,
+//!   otherwise one past the open paren.
+//!
+//! Closing delimiters are pulled up onto the line of the last form they close (unless that line
+//! ends with a comment) and whitespace between forms on a line becomes a single space.
+
+pub mod lexer;
+
+pub use crate::lexer::{tokenize, FmtError, Token, TokenKind};
+
+/// Forms that have a body and how many arguments come before it.  Arguments before the body
+/// that start on their own line are indented four spaces, the body two.  The listed argument
+/// positions are lists of names or bindings (not calls) so they are indented like data.
+const BODY_FORMS: &[(&str, usize, &[usize])] = &[
+    ("def", 1, &[]),
+    ("defn", 2, &[2]),
+    ("definline", 2, &[2]),
+    ("defmacro", 2, &[2]),
+    ("defstruct", 1, &[]),
+    ("deftest", 1, &[]),
+    ("fn", 1, &[1]),
+    ("macro", 1, &[1]),
+    ("hygienic-macro", 1, &[1]),
+    ("let", 1, &[1]),
+    ("let*", 1, &[1]),
+    ("let-values", 1, &[1]),
+    ("do", 0, &[]),
+    ("defer", 0, &[]),
+    ("while", 1, &[]),
+    ("match", 1, &[]),
+    ("case", 1, &[]),
+    ("block", 1, &[]),
+    ("loop", 2, &[1, 2]),
+    ("dotimes", 1, &[]),
+    ("dotimes-i", 2, &[]),
+];
+
+/// Body form rule for a form starting with head (other def* and with-* forms are treated like
+/// def).
+fn body_form(head: &str) -> Option<(usize, &'static [usize])> {
+    if let Some((_, body, data)) = BODY_FORMS.iter().find(|(name, _, _)| *name == head) {
+        Some((*body, data))
+    } else if head.starts_with("def") || head.starts_with("with-") {
+        Some((1, &[]))
+    } else {
+        None
+    }
+}
+
+struct Frame {
+    open: char,
+    /// Output column of the open delimiter.
+    col: usize,
+    /// Source line of the open delimiter (for errors).
+    src_line: usize,
+    /// Contents are data not code (quoted list, binding list, etc).
+    data: bool,
+    quoted: bool,
+    rule: Option<(usize, &'static [usize])>,
+    /// Number of elements started so far (the head is element 1).
+    count: usize,
+    head_line: usize,
+    /// Column of the first argument if it is on the head's line.
+    arg_col: Option<usize>,
+}
+
+impl Frame {
+    /// Indent for a line that starts with element number elem.
+    fn indent(&self, elem: usize) -> usize {
+        if self.open != '(' || self.data {
+            self.col + 1
+        } else if let Some((body, _)) = self.rule {
+            if elem > 1 && elem <= body + 1 {
+                self.col + 4
+            } else {
+                self.col + 2
+            }
+        } else if let Some(arg_col) = self.arg_col {
+            arg_col
+        } else {
+            self.col + 1
+        }
+    }
+}
+
+fn matching_close(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+/// Format slosh source, returns the formatted text or an error if src can not be tokenized or
+/// has unbalanced delimiters.
+pub fn format_source(src: &str) -> Result<String, FmtError> {
+    let tokens = tokenize(src)?;
+    let mut out = String::with_capacity(src.len());
+    let mut col = 0;
+    let mut line = 1;
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut prev: Option<Token> = None;
+
+    for token in tokens {
+        let after_prefix = matches!(prev, Some(p) if p.kind == TokenKind::Prefix);
+        let starts_elem = !after_prefix
+            && !matches!(
+                token.kind,
+                TokenKind::Close | TokenKind::LineComment | TokenKind::BlockComment
+            );
+        if let Some(prev) = prev {
+            let newline = prev.kind == TokenKind::LineComment
+                || (token.newlines > 0 && token.kind != TokenKind::Close);
+            if newline {
+                let lines = token.newlines.clamp(1, 2);
+                out.push_str(&"\n".repeat(lines));
+                line += lines;
+                col = stack.last().map(|f| f.indent(f.count + 1)).unwrap_or(0);
+                out.push_str(&" ".repeat(col));
+            } else if token.kind != TokenKind::Close
+                && prev.kind != TokenKind::Open
+                && prev.kind != TokenKind::Prefix
+                && (token.space_before
+                    || prev.kind == TokenKind::Close
+                    || token.kind == TokenKind::Open)
+            {
+                out.push(' ');
+                col += 1;
+            }
+        }
+        if starts_elem {
+            if let Some(frame) = stack.last_mut() {
+                frame.count += 1;
+                if frame.count == 1 {
+                    frame.head_line = line;
+                    if token.kind == TokenKind::Atom && frame.open == '(' && !frame.data {
+                        frame.rule = body_form(token.text);
+                    }
+                } else if frame.count == 2 && frame.head_line == line {
+                    frame.arg_col = Some(col);
+                }
+            }
+        }
+        match token.kind {
+            TokenKind::Open => {
+                let quoted = matches!(prev, Some(p) if p.kind == TokenKind::Prefix && p.text == "'")
+                    || stack.last().map(|f| f.quoted).unwrap_or(false);
+                let binding = stack
+                    .last()
+                    .and_then(|f| {
+                        f.rule
+                            .map(|(_, data)| data.contains(&f.count.saturating_sub(1)))
+                    })
+                    .unwrap_or(false);
+                stack.push(Frame {
+                    open: token.text.chars().next().unwrap_or('('),
+                    col,
+                    src_line: token.line,
+                    data: quoted || binding,
+                    quoted,
+                    rule: None,
+                    count: 0,
+                    head_line: line,
+                    arg_col: None,
+                });
+            }
+            TokenKind::Close => {
+                let close = token.text.chars().next().unwrap_or(')');
+                match stack.pop() {
+                    Some(frame) if matching_close(frame.open) == close => {}
+                    Some(frame) => {
+                        return Err(FmtError::new(
+                            token.line,
+                            format!(
+                                "Found {close} but expected {} to close the {} from line {}",
+                                matching_close(frame.open),
+                                frame.open,
+                                frame.src_line
+                            ),
+                        ))
+                    }
+                    None => return Err(FmtError::new(token.line, format!("Unexpected {close}"))),
+                }
+            }
+            _ => {}
+        }
+        let text = if token.kind == TokenKind::LineComment {
+            token.text.trim_end()
+        } else {
+            token.text
+        };
+        out.push_str(text);
+        match text.rfind('\n') {
+            Some(idx) => {
+                line += text.matches('\n').count();
+                col = text[idx + 1..].chars().count();
+            }
+            None => col += text.chars().count(),
+        }
+        prev = Some(token);
+    }
+    if let Some(frame) = stack.last() {
+        return Err(FmtError::new(
+            frame.src_line,
+            format!("Unclosed {}", frame.open),
+        ));
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(src: &str, expected: &str) {
+        let out = format_source(src).unwrap();
+        assert_eq!(out, expected);
+        // Formatting is stable.
+        assert_eq!(format_source(&out).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format() {
+        check(
+            "  (defn f (x)\n(let (a 1\nb 2)\n      (if (= a x) a\n b))\n    )",
+            "(defn f (x)\n  (let (a 1\n        b 2)\n    (if (= a x) a\n        b)))\n",
+        );
+        // Comments and blank lines are kept, runs of blank lines squashed.
+        check(
+            ";; header\n\n\n\n(def x   1) ; one\n#| block\n   comment |#\n(do\n; inside\n(prn x)\n)\n",
+            ";; header\n\n(def x 1) ; one\n#| block\n   comment |#\n(do\n  ; inside\n  (prn x))\n",
+        );
+        // A close after a line comment stays on its own line.
+        check("(do (prn 1) ; c\n)", "(do (prn 1) ; c\n  )\n");
+        // Data literals and quoted lists.
+        check(
+            "(def v [1\n2\n  {:a 1\n:b 2}])\n(def l '(a\nb (c\nd)))",
+            "(def v [1\n        2\n        {:a 1\n         :b 2}])\n(def l '(a\n         b (c\n            d)))\n",
+        );
+        // Calls without an argument on the first line, fn and match bodies.
+        check(
+            "(str\n\"a\"\n\"b\")\n(match x\n(1 :one)\n(_ :other))\n(map (fn (x)\n(+ x 1)) l)",
+            "(str\n \"a\"\n \"b\")\n(match x\n  (1 :one)\n  (_ :other))\n(map (fn (x)\n       (+ x 1)) l)\n",
+        );
+        // Doc strings and strings are left alone.
+        check(
+            "#!\nUsage: (f)\n  indented!#\n(defn f () \"a\n   b\")",
+            "#!\nUsage: (f)\n  indented!#\n(defn f () \"a\n   b\")\n",
+        );
+        // Back-quote templates.
+        check(
+            "(defmacro m (x)\n`(let (~x 1)\n~@body))",
+            "(defmacro m (x)\n  `(let (~x 1)\n     ~@body))\n",
+        );
+        check("", "");
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert_eq!(
+            format_source("(def x 1))").unwrap_err(),
+            FmtError::new(1, "Unexpected )")
+        );
+        assert_eq!(
+            format_source("\n(def x [1 2)").unwrap_err(),
+            FmtError::new(2, "Found ) but expected ] to close the [ from line 2")
+        );
+        assert_eq!(
+            format_source("(do\n (def x 1)").unwrap_err(),
+            FmtError::new(1, "Unclosed (")
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+
+use slosh_fmt::format_source;
+
+const HELP: &str = r#"slosh-fmt - Format slosh source files
+
+USAGE:
+    slosh-fmt [FLAGS] [files]...
+
+FLAGS:
+    -h, --help     Print help (this) and exit.
+    --check        Do not write anything, list the files that are not formatted
+                   and exit with status 1 if there are any.
+
+ARGS:
+    <files>...     Files to format in place, if none (or -) then format stdin to
+                   stdout."#;
+
+/// Format one source, returns true if it was already formatted.
+fn format_one(name: &str, src: &str, check: bool) -> Result<bool, String> {
+    let out = format_source(src).map_err(|e| format!("{name}:{}: {}", e.line, e.reason))?;
+    if out == src {
+        return Ok(true);
+    }
+    if check {
+        println!("{name}");
+    } else if name == "-" {
+        io::stdout()
+            .write_all(out.as_bytes())
+            .map_err(|e| format!("{name}: {e}"))?;
+    } else {
+        fs::write(name, out).map_err(|e| format!("{name}: {e}"))?;
+    }
+    Ok(false)
+}
+
+fn main() {
+    let mut check = false;
+    let mut files = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match &arg[..] {
+            "-h" | "--help" => {
+                println!("{HELP}");
+                return;
+            }
+            "--check" => check = true,
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut formatted = true;
+    let mut failed = false;
+    for name in &files {
+        let src = if name == "-" {
+            let mut src = String::new();
+            io::stdin().read_to_string(&mut src).map(|_| src)
+        } else {
+            fs::read_to_string(name)
+        };
+        let res = match src {
+            Ok(src) => {
+                let res = format_one(name, &src, check);
+                // Stdin still goes to stdout when it was already formatted.
+                if matches!(res, Ok(true)) && name == "-" && !check {
+                    print!("{src}");
+                }
+                res
+            }
+            Err(e) => Err(format!("{name}: {e}")),
+        };
+        match res {
+            Ok(true) => {}
+            Ok(false) => formatted = false,
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(2);
+    }
+    if check && !formatted {
+        process::exit(1);
+    }
+}
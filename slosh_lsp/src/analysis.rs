@@ -0,0 +1,358 @@
+//! Read and compile slosh source (without running it) to find errors and definitions.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use builtins::add_misc_builtins;
+use builtins::collections::setup_collection_builtins;
+use builtins::conversions::add_conv_builtins;
+use builtins::docs::{add_doc_builtins, doc_string_for};
+use builtins::io::add_io_builtins;
+use builtins::macroexpand::add_macroexpand_builtins;
+use builtins::print::add_print_builtins;
+use builtins::string::add_str_builtins;
+use builtins::test::add_test_builtins;
+use compile_state::state::{new_slosh_vm, CompileState, SloshVm, SloshVmTrait};
+use sl_compiler::pass1::pass1;
+use sl_compiler::warnings::check_undefined_globals;
+use sl_compiler::{add_reader_builtins, compile, Reader, SourceLoc};
+use slvm::{Chunk, VMError, VMResult, Value, RET};
+
+const TEST_LIB: &str = include_str!("../../lisp/test.slosh");
+const CORE_LIB: &str = include_str!("../../lisp/core.slosh");
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub loc: SourceLoc,
+    pub message: String,
+    /// A warning not an error (a symbol that is not defined may be defined by another file).
+    pub warning: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DefKind {
+    Function,
+    Macro,
+    Variable,
+    Constant,
+    Struct,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Definition {
+    pub name: String,
+    pub kind: DefKind,
+    /// Location of the defining form.
+    pub loc: SourceLoc,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub definitions: Vec<Definition>,
+}
+
+/// VM to analyze a document in.  It has the specials and builtins (except the ones only the
+/// slosh shell has), the test library, the core definitions (so defn, etc expand) and if
+/// init_file is given it is loaded (run).
+pub fn new_analysis_vm(init_file: Option<&Path>) -> SloshVm {
+    let mut vm = new_slosh_vm();
+    setup_collection_builtins(&mut vm);
+    add_print_builtins(&mut vm);
+    add_str_builtins(&mut vm);
+    add_misc_builtins(&mut vm);
+    add_io_builtins(&mut vm);
+    add_macroexpand_builtins(&mut vm);
+    add_conv_builtins(&mut vm);
+    add_doc_builtins(&mut vm);
+    add_reader_builtins(&mut vm);
+    add_test_builtins(&mut vm);
+    run_source(&mut vm, "test.slosh", TEST_LIB, true);
+    run_source(&mut vm, "core.slosh", CORE_LIB, true);
+    if let Some(init_file) = init_file {
+        if let Ok(src) = std::fs::read_to_string(init_file) {
+            let name = vm.intern(&init_file.to_string_lossy());
+            let name = vm.get_interned(name);
+            run_source(&mut vm, name, &src, true);
+        }
+    }
+    vm
+}
+
+/// Compile exp (a top level form from file name) to a chunk that can be executed.  Returns the
+/// chunk and the doc string to pass to the next form (set if exp was a doc comment).
+pub fn compile_form(
+    vm: &mut SloshVm,
+    name: &'static str,
+    exp: Value,
+    doc_string: Option<Value>,
+) -> VMResult<(Arc<Chunk>, Option<Value>)> {
+    let line_num = vm.line_num();
+    let mut state = CompileState::new_state(name, line_num, None);
+    state.chunk.dbg_args = Some(Vec::new());
+    state.doc_string = doc_string;
+    pass1(vm, &mut state, exp)?;
+    compile(vm, &mut state, exp, 0)?;
+    state.chunk.encode0(RET, vm.own_line())?;
+    state.chunk.extra_regs = state.max_regs;
+    Ok((Arc::new(state.chunk), state.doc_string))
+}
+
+fn symbol_name(vm: &SloshVm, val: Value) -> Option<&'static str> {
+    match val {
+        Value::Symbol(i) => Some(vm.get_interned(i)),
+        _ => None,
+    }
+}
+
+/// If exp is a def form (def, defn, definline, defmacro, defconst, defstruct) return what it
+/// defines.
+fn definition(vm: &SloshVm, exp: Value) -> Option<Definition> {
+    if !matches!(exp, Value::Pair(_) | Value::List(_, _)) {
+        return None;
+    }
+    let mut items = exp.iter(vm);
+    let head = symbol_name(vm, items.next()?)?;
+    let name = symbol_name(vm, items.next()?)?;
+    let kind = match head {
+        "defn" | "definline" => DefKind::Function,
+        "defmacro" => DefKind::Macro,
+        "defconst" => DefKind::Constant,
+        "defstruct" => DefKind::Struct,
+        "def" => match items.next().and_then(|v| v.iter(vm).next()) {
+            Some(v) if symbol_name(vm, v) == Some("fn") => DefKind::Function,
+            Some(v) if symbol_name(vm, v) == Some("macro") => DefKind::Macro,
+            _ => DefKind::Variable,
+        },
+        _ => return None,
+    };
+    let loc = SourceLoc::from_value(vm, exp)?;
+    Some(Definition {
+        name: name.to_string(),
+        kind,
+        loc,
+    })
+}
+
+fn diagnostic(loc: SourceLoc, err: &VMError, vm: &SloshVm) -> Diagnostic {
+    let message = err.display(vm);
+    let warning = message.contains(" not defined");
+    Diagnostic {
+        loc,
+        message,
+        warning,
+    }
+}
+
+/// Move the compile warnings for file name into analysis (others are dropped).
+fn add_warnings(vm: &mut SloshVm, name: &'static str, analysis: &mut Analysis) {
+    for warning in vm.env_mut().take_warnings() {
+        if warning.file_name == name {
+            analysis.diagnostics.push(Diagnostic {
+                loc: SourceLoc::new(name, warning.line as usize, warning.column as usize, 0),
+                message: warning.message,
+                warning: true,
+            });
+        }
+    }
+}
+
+/// Read and compile src.  Forms are only run if run_all is set, otherwise just macro definitions
+/// are run (so the rest of the source can use them).
+fn run_source(vm: &mut SloshVm, name: &'static str, src: &str, run_all: bool) -> Analysis {
+    let mut analysis = Analysis::default();
+    let mut reader = Reader::from_string(src.to_string(), vm, name, 1, 0);
+    reader.vm().set_line_num(1);
+    let undefined_start = reader.vm().env().undefined_refs_len();
+    let mut doc_string = None;
+    while let Some(exp) = reader.next() {
+        let vm = reader.vm();
+        let exp = match exp {
+            Ok(exp) => exp,
+            Err(err) => {
+                analysis.diagnostics.push(Diagnostic {
+                    loc: err.loc,
+                    message: err.reason,
+                    warning: false,
+                });
+                // The reader can not recover from an error.
+                break;
+            }
+        };
+        vm.heap_sticky(exp);
+        let def = definition(vm, exp);
+        let result = compile_form(vm, name, exp, doc_string);
+        add_warnings(vm, name, &mut analysis);
+        if let Some(doc_string) = doc_string {
+            vm.heap_unsticky(doc_string);
+        }
+        doc_string = None;
+        match result {
+            Ok((chunk, new_doc_string)) => {
+                doc_string = new_doc_string;
+                if let Some(doc_string) = doc_string {
+                    vm.heap_sticky(doc_string);
+                }
+                if run_all
+                    || matches!(
+                        def,
+                        Some(Definition {
+                            kind: DefKind::Macro,
+                            ..
+                        })
+                    )
+                {
+                    if let Err(err) = vm.execute(chunk) {
+                        let loc = SourceLoc::from_value(vm, exp).unwrap_or_default();
+                        analysis.diagnostics.push(diagnostic(loc, &err, vm));
+                    }
+                }
+            }
+            Err(err) => {
+                let loc = SourceLoc::compile_error(vm, name, exp);
+                analysis.diagnostics.push(diagnostic(loc, &err, vm));
+            }
+        }
+        vm.heap_unsticky(exp);
+        if let Some(def) = def {
+            analysis.definitions.push(def);
+        }
+    }
+    check_undefined_globals(reader.vm(), undefined_start);
+    add_warnings(reader.vm(), name, &mut analysis);
+    analysis
+}
+
+/// Analyze the source of a document in vm (see new_analysis_vm()), nothing is run except macro
+/// definitions.
+pub fn analyze(vm: &mut SloshVm, name: &'static str, src: &str) -> Analysis {
+    run_source(vm, name, src, false)
+}
+
+/// Doc string for the global named name.
+pub fn doc_for(vm: &mut SloshVm, name: &str) -> Option<String> {
+    let sym = vm.intern(name);
+    doc_string_for(vm, sym)
+}
+
+fn is_symbol_char(ch: char) -> bool {
+    !ch.is_whitespace()
+        && !matches!(
+            ch,
+            '(' | ')' | '[' | ']' | '{' | '}' | '"' | '\'' | '`' | '~' | ';' | ',' | '#' | '\\'
+        )
+}
+
+/// The symbol at (or just before) character column of line (both 0 based) in src and the
+/// column it starts at.
+pub fn symbol_at(src: &str, line: usize, column: usize) -> Option<(String, usize)> {
+    let chars: Vec<char> = src.lines().nth(line)?.chars().collect();
+    let column = column.min(chars.len());
+    let mut start = column;
+    while start > 0 && is_symbol_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = column;
+    while end < chars.len() && is_symbol_char(chars[end]) {
+        end += 1;
+    }
+    if start == end {
+        None
+    } else {
+        Some((chars[start..end].iter().collect(), start))
+    }
+}
+
+/// The start of the symbol before character column of line (for completion).
+pub fn prefix_at(src: &str, line: usize, column: usize) -> String {
+    let chars: Vec<char> = match src.lines().nth(line) {
+        Some(line) => line.chars().collect(),
+        None => return String::new(),
+    };
+    let column = column.min(chars.len());
+    let mut start = column;
+    while start > 0 && is_symbol_char(chars[start - 1]) {
+        start -= 1;
+    }
+    chars[start..column].iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let mut vm = new_analysis_vm(None);
+        let src = "#! Doc for x. !#\n(def x 1)\n(def f (fn (a) (+ a x)))\n(def g (fn () (undefined-fn 1)))\n(def m (macro (v) `(+ ~v 1)))\n(def y (m 2))\n";
+        let analysis = analyze(&mut vm, "test.slosh", src);
+        let names: Vec<(&str, DefKind, usize)> = analysis
+            .definitions
+            .iter()
+            .map(|d| (&d.name[..], d.kind, d.loc.line))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("x", DefKind::Variable, 2),
+                ("f", DefKind::Function, 3),
+                ("g", DefKind::Function, 4),
+                ("m", DefKind::Macro, 5),
+                ("y", DefKind::Variable, 6),
+            ]
+        );
+        assert_eq!(analysis.diagnostics.len(), 1, "{:?}", analysis.diagnostics);
+        let diag = &analysis.diagnostics[0];
+        assert!(diag.warning);
+        assert!(diag.message.contains("undefined-fn"));
+        assert_eq!((diag.loc.line, diag.loc.column), (4, 15));
+        assert_eq!(doc_for(&mut vm, "x").as_deref(), Some(" Doc for x. "));
+        // Nothing was run (except the macro).
+        let x = vm.intern("x");
+        let slot = vm.global_intern_slot(x).unwrap();
+        assert!(matches!(vm.get_global(slot), Value::Undefined));
+
+        let mut vm = new_analysis_vm(None);
+        let analysis = analyze(
+            &mut vm,
+            "test.slosh",
+            "(def x 1)\n(def y (car 1 2))\n(def z",
+        );
+        assert_eq!(analysis.diagnostics.len(), 2, "{:?}", analysis.diagnostics);
+        assert!(!analysis.diagnostics[0].warning);
+        assert_eq!(analysis.diagnostics[0].loc.line, 2);
+        assert_eq!(analysis.diagnostics[1].message, "Unclosed list");
+        assert_eq!(analysis.diagnostics[1].loc.line, 3);
+    }
+
+    #[test]
+    fn test_init_slosh() {
+        // init.slosh can be loaded more than once and analyzed after it was loaded.
+        let init_file = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../init.slosh"));
+        let mut vm = new_analysis_vm(Some(init_file));
+        let src = std::fs::read_to_string(init_file).unwrap();
+        let errors = |analysis: Analysis| -> Vec<Diagnostic> {
+            analysis
+                .diagnostics
+                .into_iter()
+                .filter(|d| !d.warning)
+                .collect()
+        };
+        let analysis = run_source(&mut vm, "init.slosh", &src, true);
+        assert!(errors(analysis.clone()).is_empty(), "{:?}", analysis);
+        let analysis = analyze(&mut vm, "init.slosh", &src);
+        assert!(errors(analysis.clone()).is_empty(), "{:?}", analysis);
+    }
+
+    #[test]
+    fn test_symbol_at() {
+        let src = "(def x 1)\n(str-trim (car xs))";
+        assert_eq!(symbol_at(src, 1, 3), Some(("str-trim".to_string(), 1)));
+        assert_eq!(symbol_at(src, 1, 9), Some(("str-trim".to_string(), 1)));
+        assert_eq!(symbol_at(src, 1, 16), Some(("xs".to_string(), 15)));
+        assert_eq!(symbol_at(src, 0, 0), None);
+        assert_eq!(symbol_at(src, 5, 0), None);
+        assert_eq!(prefix_at(src, 1, 5), "str-");
+        assert_eq!(prefix_at(src, 1, 10), "");
+    }
+}
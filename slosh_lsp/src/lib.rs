@@ -0,0 +1,9 @@
+//! Language server for slosh.  Diagnostics come from reading and compiling (not running) each
+//! open document, completion and hover use the globals (and their doc strings) of the VM the
+//! document was compiled in and definitions/document symbols are the top level def forms.
+//!
+//! Positions are converted with one character per LSP character (not UTF-16 code units).
+
+pub mod analysis;
+pub mod protocol;
+pub mod server;
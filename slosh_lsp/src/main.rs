@@ -0,0 +1,36 @@
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+
+use slosh_lsp::protocol::{read_message, write_message};
+use slosh_lsp::server::Server;
+
+/// The file the slosh shell loads at startup, its macros are available to documents.
+fn init_file() -> Option<PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    Some(PathBuf::from(home).join(".config/slosh/init.slosh"))
+}
+
+fn main() {
+    let mut input = BufReader::new(io::stdin());
+    let mut output = io::stdout();
+    let mut server = Server::new(init_file());
+    loop {
+        let msg = match read_message(&mut input) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("slosh-lsp: {err}");
+                continue;
+            }
+        };
+        if msg["method"] == "exit" {
+            std::process::exit(if server.is_shutdown() { 0 } else { 1 });
+        }
+        for out in server.handle(&msg) {
+            if let Err(err) = write_message(&mut output, &out) {
+                eprintln!("slosh-lsp: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Base protocol (shared by LSP and DAP): JSON messages with a Content-Length header.
+
+use serde_json::{json, Value as Json};
+use std::io::{self, BufRead, Write};
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Read one message, returns None at the end of input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        // Other headers (Content-Type) are ignored.
+        if let Some(len) = line.strip_prefix("Content-Length:") {
+            let len: usize = len
+                .trim()
+                .parse()
+                .map_err(|e| invalid_data(format!("Invalid Content-Length: {e}")))?;
+            length = Some(len);
+        }
+    }
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid_data(format!("Invalid message: {e}")))
+}
+
+/// Write one message and flush.
+pub fn write_message(out: &mut impl Write, msg: &Json) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+/// JSON-RPC response to the request with id.
+pub fn response(id: &Json, result: Json) -> Json {
+    json!({"jsonrpc": "2.0", "id": id, "result": result})
+}
+
+/// JSON-RPC error response to the request with id.
+pub fn error_response(id: &Json, code: i64, message: &str) -> Json {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+/// JSON-RPC notification.
+pub fn notification(method: &str, params: Json) -> Json {
+    json!({"jsonrpc": "2.0", "method": method, "params": params})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_framing() {
+        let mut out = Vec::new();
+        write_message(&mut out, &notification("a", json!({"x": 1}))).unwrap();
+        write_message(&mut out, &response(&json!(2), json!(null))).unwrap();
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert!(text.starts_with("Content-Length: 47\r\n\r\n{"));
+        let mut input = &out[..];
+        let msg = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(msg["method"], "a");
+        assert_eq!(msg["params"]["x"], 1);
+        let msg = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(msg["id"], 2);
+        assert!(read_message(&mut input).unwrap().is_none());
+        let mut input = &b"Content-Length: x\r\n\r\n{}"[..];
+        assert!(read_message(&mut input).is_err());
+    }
+}
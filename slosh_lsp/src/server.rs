@@ -0,0 +1,330 @@
+//! LSP request and notification handling.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use builtins::docs::DocEntry;
+use compile_state::state::{SloshVm, SloshVmTrait};
+use serde_json::{json, Value as Json};
+use sl_compiler::globals::{find_globals, is_callable, GlobalKind};
+use sl_compiler::SourceLoc;
+use slvm::Value;
+
+use crate::analysis::{analyze, doc_for, new_analysis_vm, prefix_at, symbol_at, Analysis, DefKind};
+use crate::protocol::{error_response, notification, response};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+struct Document {
+    text: String,
+    vm: SloshVm,
+    analysis: Analysis,
+}
+
+pub struct Server {
+    init_file: Option<PathBuf>,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+/// File name for a document uri (used in locations the reader records).
+fn uri_path(uri: &str) -> &str {
+    uri.strip_prefix("file://").unwrap_or(uri)
+}
+
+/// LSP position (0 based line and character) of loc.
+fn position(loc: &SourceLoc) -> Json {
+    json!({"line": loc.line.saturating_sub(1), "character": loc.column.saturating_sub(1)})
+}
+
+fn range(loc: &SourceLoc, span: usize) -> Json {
+    let mut end = *loc;
+    end.column = loc.column.max(1) + span;
+    json!({"start": position(loc), "end": position(&end)})
+}
+
+fn symbol_kind(kind: DefKind) -> u32 {
+    match kind {
+        DefKind::Function | DefKind::Macro => 12,
+        DefKind::Variable => 13,
+        DefKind::Constant => 14,
+        DefKind::Struct => 23,
+    }
+}
+
+impl Server {
+    /// New server, init_file is loaded into each document's VM (normally the slosh init.slosh).
+    pub fn new(init_file: Option<PathBuf>) -> Self {
+        Self {
+            init_file,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Has the client asked the server to shutdown?
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown
+    }
+
+    /// Handle one message from the client, returns the messages to send back.
+    pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        let id = msg.get("id");
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": {},
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": {"name": "slosh-lsp", "version": env!("CARGO_PKG_VERSION")},
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Some(Json::Null)
+            }
+            "textDocument/didOpen" => {
+                let doc = &params["textDocument"];
+                return self.update(doc["uri"].as_str(), doc["text"].as_str());
+            }
+            "textDocument/didChange" => {
+                // Full sync so the last change is the whole document.
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                return self.update(params["textDocument"]["uri"].as_str(), text);
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                self.documents.remove(uri);
+                return vec![notification(
+                    "textDocument/publishDiagnostics",
+                    json!({"uri": uri, "diagnostics": []}),
+                )];
+            }
+            "textDocument/completion" => self.completion(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            _ => {
+                // Unknown notifications are ignored.
+                return match id {
+                    Some(id) => vec![error_response(id, METHOD_NOT_FOUND, method)],
+                    None => Vec::new(),
+                };
+            }
+        };
+        match (id, result) {
+            (Some(id), Some(result)) => vec![response(id, result)],
+            (Some(id), None) => vec![error_response(id, INVALID_PARAMS, "Unknown document")],
+            (None, _) => Vec::new(),
+        }
+    }
+
+    /// Re-analyze a document that was opened or changed and publish its diagnostics.
+    fn update(&mut self, uri: Option<&str>, text: Option<&str>) -> Vec<Json> {
+        let (uri, text) = match (uri, text) {
+            (Some(uri), Some(text)) => (uri, text),
+            _ => return Vec::new(),
+        };
+        let mut vm = new_analysis_vm(self.init_file.as_deref());
+        let name = vm.intern(uri_path(uri));
+        let name = vm.get_interned(name);
+        let analysis = analyze(&mut vm, name, text);
+        let diagnostics: Vec<Json> = analysis
+            .diagnostics
+            .iter()
+            .map(|d| {
+                json!({
+                    "range": range(&d.loc, d.loc.span_in(text)),
+                    "severity": if d.warning { 2 } else { 1 },
+                    "source": "slosh",
+                    "message": d.message,
+                })
+            })
+            .collect();
+        self.documents.insert(
+            uri.to_string(),
+            Document {
+                text: text.to_string(),
+                vm,
+                analysis,
+            },
+        );
+        vec![notification(
+            "textDocument/publishDiagnostics",
+            json!({"uri": uri, "diagnostics": diagnostics}),
+        )]
+    }
+
+    /// The document and 0 based line and character for a text document position request.
+    fn doc_position(&mut self, params: &Json) -> Option<(&mut Document, usize, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        self.documents
+            .get_mut(uri)
+            .map(|doc| (doc, line, character))
+    }
+
+    fn completion(&mut self, params: &Json) -> Option<Json> {
+        let (doc, line, character) = self.doc_position(params)?;
+        let prefix = prefix_at(&doc.text, line, character);
+        let mut names = find_globals(&doc.vm, &prefix, GlobalKind::Any);
+        names.sort_unstable();
+        let items: Vec<Json> = names
+            .into_iter()
+            .map(|name| {
+                let sym = doc.vm.intern(name);
+                let slot = doc.vm.global_intern_slot(sym);
+                let val = slot.map(|slot| doc.vm.get_global(slot));
+                let kind = match val {
+                    Some(Value::Special(_)) => 14,
+                    Some(val) if is_callable(val) => 3,
+                    _ => 6,
+                };
+                let mut item = json!({"label": name, "kind": kind});
+                if let Some(doc) = doc_for(&mut doc.vm, name) {
+                    item["detail"] = json!(DocEntry::parse(name, &doc).summary());
+                }
+                item
+            })
+            .collect();
+        Some(json!(items))
+    }
+
+    fn hover(&mut self, params: &Json) -> Option<Json> {
+        let (doc, line, character) = self.doc_position(params)?;
+        let (name, _) = match symbol_at(&doc.text, line, character) {
+            Some(sym) => sym,
+            None => return Some(Json::Null),
+        };
+        let text = match doc_for(&mut doc.vm, &name) {
+            Some(doc) => DocEntry::parse(&name, &doc).format_help(),
+            None => {
+                let sym = doc.vm.intern(&name);
+                match doc.vm.global_intern_slot(sym) {
+                    Some(slot) => {
+                        let val = doc.vm.get_global(slot);
+                        format!("{name}\n\n{}", val.display_type(&doc.vm))
+                    }
+                    None => return Some(Json::Null),
+                }
+            }
+        };
+        Some(json!({"contents": {"kind": "plaintext", "value": text}}))
+    }
+
+    fn definition(&mut self, params: &Json) -> Option<Json> {
+        let (doc, line, character) = self.doc_position(params)?;
+        let (name, _) = match symbol_at(&doc.text, line, character) {
+            Some(sym) => sym,
+            None => return Some(Json::Null),
+        };
+        // Look in this document first then the other open documents.
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let mut docs: Vec<(&String, &Document)> = self.documents.iter().collect();
+        docs.sort_by_key(|(doc_uri, _)| *doc_uri != uri);
+        for (doc_uri, doc) in docs {
+            if let Some(def) = doc.analysis.definitions.iter().find(|d| d.name == name) {
+                return Some(json!({"uri": doc_uri, "range": range(&def.loc, 1)}));
+            }
+        }
+        Some(Json::Null)
+    }
+
+    fn document_symbols(&mut self, params: &Json) -> Option<Json> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let doc = self.documents.get(uri)?;
+        let symbols: Vec<Json> = doc
+            .analysis
+            .definitions
+            .iter()
+            .map(|def| {
+                let range = range(&def.loc, def.loc.span_in(&doc.text));
+                json!({
+                    "name": def.name,
+                    "kind": symbol_kind(def.kind),
+                    "range": range,
+                    "selectionRange": range,
+                })
+            })
+            .collect();
+        Some(json!(symbols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u32, method: &str, params: Json) -> Json {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    fn pos(line: u32, character: u32) -> Json {
+        json!({"textDocument": {"uri": "file:///t.slosh"}, "position": {"line": line, "character": character}})
+    }
+
+    #[test]
+    fn test_server() {
+        let mut server = Server::new(None);
+        let out = server.handle(&request(1, "initialize", json!({})));
+        assert_eq!(out[0]["result"]["capabilities"]["hoverProvider"], true);
+
+        let text = "#! Usage: (add1 x)\n\nAdd one.\n!#\n(def add1 (fn (x) (+ x 1)))\n(add1 (car 1 2))\n(str-tr";
+        let out = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": "file:///t.slosh", "languageId": "slosh", "version": 1, "text": text}},
+        }));
+        assert_eq!(out[0]["method"], "textDocument/publishDiagnostics");
+        let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diags.len(), 2, "{diags:?}");
+        assert_eq!(
+            diags[0]["range"]["start"],
+            json!({"line": 5, "character": 6})
+        );
+        assert_eq!(
+            diags[0]["range"]["end"],
+            json!({"line": 5, "character": 15})
+        );
+        assert_eq!(diags[1]["message"], "Unclosed list");
+
+        let out = server.handle(&request(2, "textDocument/hover", pos(5, 2)));
+        let hover = out[0]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(
+            hover.starts_with("add1\nUsage: (add1 x)\n\nAdd one."),
+            "{hover}"
+        );
+
+        let out = server.handle(&request(3, "textDocument/definition", pos(5, 1)));
+        assert_eq!(
+            out[0]["result"]["range"]["start"],
+            json!({"line": 4, "character": 0})
+        );
+
+        let out = server.handle(&request(4, "textDocument/completion", pos(6, 7)));
+        let items = out[0]["result"].as_array().unwrap();
+        assert!(items.iter().any(|i| i["label"] == "str-trim"));
+        assert!(items
+            .iter()
+            .all(|i| i["label"].as_str().unwrap().starts_with("str-tr")));
+
+        let out = server.handle(&request(5, "textDocument/documentSymbol", pos(0, 0)));
+        assert_eq!(out[0]["result"][0]["name"], "add1");
+        assert_eq!(out[0]["result"][0]["kind"], 12);
+
+        let out = server.handle(&request(6, "unknown/method", json!({})));
+        assert_eq!(out[0]["error"]["code"], METHOD_NOT_FOUND);
+        let out = server.handle(&request(7, "shutdown", Json::Null));
+        assert_eq!(out[0]["result"], Json::Null);
+        assert!(server.is_shutdown());
+    }
+}
@@ -4,9 +4,107 @@ use crate::error::*;
 use crate::interner::Interned;
 use crate::opcodes::*;
 use crate::value::*;
+use crate::{FxHashMap, GVm};
 
 #[macro_use]
 pub mod disassemble;
+pub mod image;
+
+/// Source position (line, column and span in characters, 0 if not known) of a form.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourcePos {
+    pub line: u32,
+    pub column: u32,
+    pub span: u32,
+}
+
+/// A key in a CaseTable, any integer (or float with an integral value) finds the Int entry.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum CaseKey {
+    Int(i64),
+    Other(Value),
+}
+
+impl CaseKey {
+    /// Key for val or None if it can not be a case key (only numbers, chars, keywords and symbols).
+    pub fn from_value<ENV>(vm: &GVm<ENV>, val: Value) -> Option<CaseKey> {
+        match val {
+            Value::Byte(_)
+            | Value::Int32(_)
+            | Value::UInt32(_)
+            | Value::Int64(_)
+            | Value::UInt64(_) => val.get_int(vm).ok().map(CaseKey::Int),
+            Value::Float64(_) => {
+                let f = val.get_float(vm).ok()?;
+                if f.fract() == 0.0 && f >= i64::MIN as f64 && f <= i64::MAX as f64 {
+                    Some(CaseKey::Int(f as i64))
+                } else {
+                    None
+                }
+            }
+            Value::CodePoint(_)
+            | Value::CharCluster(_, _)
+            | Value::Keyword(_)
+            | Value::Symbol(_) => Some(CaseKey::Other(val)),
+            _ => None,
+        }
+    }
+}
+
+/// Jump targets (indexes into the jump_table) of a CASE instruction by key.
+#[derive(Clone, Debug)]
+pub enum CaseTable {
+    /// Integer keys from min, targets[i] is for min + i (None if there is no clause for it).
+    Indexed {
+        min: i64,
+        targets: Vec<Option<u32>>,
+    },
+    Hashed(FxHashMap<CaseKey, u32>),
+}
+
+impl CaseTable {
+    /// Build a table from (key, jump index) pairs, dense integer keys are indexed.
+    pub fn new(entries: &[(CaseKey, u32)]) -> Self {
+        let mut ints = Vec::new();
+        for (key, _) in entries {
+            if let CaseKey::Int(i) = key {
+                ints.push(*i);
+            }
+        }
+        if ints.len() == entries.len() && !ints.is_empty() {
+            let min = ints.iter().copied().min().unwrap_or(0);
+            let max = ints.iter().copied().max().unwrap_or(0);
+            let span = (max as i128 - min as i128 + 1) as u128;
+            // Use an index if at least half the slots would be used.
+            if span <= (entries.len() * 2) as u128 {
+                let mut targets = vec![None; span as usize];
+                for (key, target) in entries {
+                    if let CaseKey::Int(i) = key {
+                        targets[(*i - min) as usize] = Some(*target);
+                    }
+                }
+                return CaseTable::Indexed { min, targets };
+            }
+        }
+        CaseTable::Hashed(entries.iter().copied().collect())
+    }
+
+    /// The jump index for key if it has one.
+    pub fn target(&self, key: CaseKey) -> Option<u32> {
+        match (self, key) {
+            (CaseTable::Indexed { min, targets }, CaseKey::Int(i)) => {
+                let idx = i.checked_sub(*min)?;
+                if idx >= 0 {
+                    targets.get(idx as usize).copied().flatten()
+                } else {
+                    None
+                }
+            }
+            (CaseTable::Indexed { .. }, _) => None,
+            (CaseTable::Hashed(map), key) => map.get(&key).copied(),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Chunk {
@@ -15,9 +113,17 @@ pub struct Chunk {
     start_line: u32,
     last_line: u32,
     line_numbers: Vec<u8>,
+    // Run list of (code offset, position), the position applies to instructions from offset on.
+    columns: Vec<(u32, SourcePos)>,
+    // Position for the next instruction encoded.
+    dbg_pos: Option<SourcePos>,
     pub constants: Vec<Value>,
     pub jump_table: Vec<u32>,
+    // Tables for CASE instructions.
+    pub case_tables: Vec<CaseTable>,
     pub captures: Option<Vec<u32>>,
+    // Registers that a closure must box (vs copy) when it captures them (they are mutated).
+    pub boxed_regs: Vec<u32>,
     // Registers holding input (arguments and closed over values) plus 1 for the result.
     pub input_regs: usize,
     // Number of registers needed beyond input_regs for computations.
@@ -25,6 +131,12 @@ pub struct Chunk {
     pub args: u16,
     pub opt_args: u16,
     pub rest: bool,
+    // Keyword (&key) params, passed as :key value pairs after the other args (in the rest arg).
+    pub keys: Vec<Interned>,
+    // Type annotations (type keywords) of the params by position (empty if none are annotated).
+    pub arg_types: Vec<Option<Interned>>,
+    // Type annotation of the return value.
+    pub ret_type: Option<Interned>,
 
     pub dbg_args: Option<Vec<Interned>>,
 }
@@ -37,14 +149,21 @@ impl Chunk {
             start_line,
             last_line: start_line,
             line_numbers: Vec::new(),
+            columns: Vec::new(),
+            dbg_pos: None,
             constants: Vec::new(),
             jump_table: Vec::new(),
+            case_tables: Vec::new(),
             captures: None,
+            boxed_regs: Vec::new(),
             input_regs: 0,
             extra_regs: 0,
             args: 0,
             opt_args: 0,
             rest: false,
+            keys: Vec::new(),
+            arg_types: Vec::new(),
+            ret_type: None,
             dbg_args: None,
         }
     }
@@ -56,7 +175,27 @@ impl Chunk {
         self.code.push((op & 0x00FF) as u8);
     }
 
+    /// Set the source position of the form being compiled, instructions encoded after this will
+    /// be tagged with it.
+    pub fn set_dbg_pos(&mut self, pos: Option<SourcePos>) {
+        self.dbg_pos = pos;
+    }
+
+    /// The source position that will be given to the next instruction encoded.
+    pub fn dbg_pos(&self) -> Option<SourcePos> {
+        self.dbg_pos
+    }
+
+    fn encode_column(&mut self) {
+        if let Some(pos) = self.dbg_pos {
+            if self.columns.last().map(|(_, last)| *last) != Some(pos) {
+                self.columns.push((self.code.len() as u32, pos));
+            }
+        }
+    }
+
     fn encode_line_number(&mut self, offsets: u8, line_number: Option<u32>) -> VMResult<()> {
+        self.encode_column();
         let line_number = if let Some(ln) = line_number {
             ln
         } else {
@@ -122,6 +261,23 @@ impl Chunk {
         None
     }
 
+    /// Source position of the instruction at offset.  Line numbers are tracked separately (see
+    /// offset_to_line()) so this is only returned if it is on the same line as the instruction.
+    pub fn offset_to_pos(&self, offset: usize) -> Option<SourcePos> {
+        let idx = self
+            .columns
+            .partition_point(|(start, _)| *start as usize <= offset);
+        if idx == 0 {
+            return None;
+        }
+        let pos = self.columns[idx - 1].1;
+        if pos.column > 0 && Some(pos.line) == self.offset_to_line(offset) {
+            Some(pos)
+        } else {
+            None
+        }
+    }
+
     pub fn line_to_offset(&self, line: u32) -> Option<usize> {
         if line > self.last_line {
             return None;
@@ -168,6 +324,11 @@ impl Chunk {
         self.jump_table[jmp] = offset;
     }
 
+    pub fn add_case_table(&mut self, table: CaseTable) -> usize {
+        self.case_tables.push(table);
+        self.case_tables.len() - 1
+    }
+
     pub fn encode0(&mut self, op_code: OpCode, line_number: Option<u32>) -> VMResult<()> {
         self.encode_line_number(1, line_number)?;
         self.code.push(op_code);
@@ -248,6 +409,29 @@ impl Chunk {
         Ok(())
     }
 
+    pub fn encode_defc(&mut self, reg: u16, global: u32, line_number: Option<u32>) -> VMResult<()> {
+        let mut bytes: u8 = 4;
+        let mut wide = false;
+        if reg > u8::MAX as u16 || global > u16::MAX as u32 {
+            wide = true;
+            bytes = 7;
+            self.encode_line_number(1, line_number)?;
+            self.code.push(WIDE);
+        }
+
+        self.encode_line_number(bytes, line_number)?;
+        self.code.push(DEFC);
+        self.encode_operand(reg, wide);
+        if wide {
+            self.code.push(((global & 0xFF00_0000) >> 24) as u8);
+            self.code.push(((global & 0x00FF_0000) >> 16) as u8);
+        }
+        self.code.push(((global & 0x0000_FF00) >> 8) as u8);
+        self.code.push((global & 0x0000_00FF) as u8);
+
+        Ok(())
+    }
+
     pub fn encode_refi(&mut self, reg: u16, global: u32, line_number: Option<u32>) -> VMResult<()> {
         let mut bytes: u8 = 4;
         let mut wide = false;
@@ -569,4 +753,43 @@ mod tests {
         assert!(chunk.line_to_offset(101).is_none());
         assert!(chunk.encode0(RET, Some(1)).is_err());
     }
+
+    #[test]
+    fn test_source_pos() {
+        let mut chunk = Chunk::new("no_file", 1);
+        let outer = SourcePos {
+            line: 1,
+            column: 1,
+            span: 20,
+        };
+        let inner = SourcePos {
+            line: 1,
+            column: 8,
+            span: 7,
+        };
+        chunk.encode2(MOV, 1, 2, Some(1)).unwrap();
+        chunk.set_dbg_pos(Some(outer));
+        chunk.encode2(MOV, 1, 2, Some(1)).unwrap();
+        chunk.set_dbg_pos(Some(inner));
+        chunk.encode2(MOV, 1, 2, Some(1)).unwrap();
+        chunk.encode2(MOV, 1, 2, Some(1)).unwrap();
+        chunk.set_dbg_pos(Some(outer));
+        chunk.encode2(MOV, 1, 2, Some(1)).unwrap();
+        // Line moved on without a new position, the old column does not apply.
+        chunk.encode2(MOV, 1, 2, Some(2)).unwrap();
+        chunk.set_dbg_pos(Some(SourcePos {
+            line: 2,
+            column: 3,
+            span: 0,
+        }));
+        chunk.encode1(WIDE, 1, Some(2)).unwrap();
+        assert!(chunk.offset_to_pos(0).is_none());
+        assert_eq!(chunk.offset_to_pos(3), Some(outer));
+        assert_eq!(chunk.offset_to_pos(5), Some(outer));
+        assert_eq!(chunk.offset_to_pos(6), Some(inner));
+        assert_eq!(chunk.offset_to_pos(11), Some(inner));
+        assert_eq!(chunk.offset_to_pos(12), Some(outer));
+        assert!(chunk.offset_to_pos(15).is_none());
+        assert_eq!(chunk.offset_to_pos(18).unwrap().column, 3);
+    }
 }
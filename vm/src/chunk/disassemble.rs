@@ -1,5 +1,5 @@
 use crate::opcodes::*;
-use crate::{Chunk, GVm, VMError, VMResult, Value};
+use crate::{CaseKey, CaseTable, Chunk, GVm, VMError, VMResult, Value};
 
 #[macro_export]
 macro_rules! decode_u8_enum {
@@ -224,6 +224,15 @@ impl Chunk {
                 println!("]");
                 Ok(false)
             }
+            DEFC => {
+                print!("DEFC({DEFC:#04x})   \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                print!("G[");
+                disassemble_immediate_global!(code, wide, _vm);
+                println!("]");
+                Ok(false)
+            }
             DEFV => {
                 print!("DEFV({DEFV:#04x})   \t");
                 disassemble_operand!(code, true, wide);
@@ -586,6 +595,54 @@ impl Chunk {
                 println!();
                 Ok(false)
             }
+            JMPNLS => {
+                print!("JMPNLS({JMPNLS:#04x}) \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                print!("\t");
+                disassemble_jump_operand!(self, code, wide);
+                println!();
+                Ok(false)
+            }
+            JMPNLSR => {
+                print!("JMPNLSR({JMPNLSR:#04x})\t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                print!("\t");
+                disassemble_jump_operand!(self, code, wide);
+                println!();
+                Ok(false)
+            }
+            JMPNVC => {
+                print!("JMPNVC({JMPNVC:#04x}) \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                print!("\t");
+                disassemble_jump_operand!(self, code, wide);
+                println!();
+                Ok(false)
+            }
+            JMPNVCR => {
+                print!("JMPNVCR({JMPNVCR:#04x})\t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                print!("\t");
+                disassemble_jump_operand!(self, code, wide);
+                println!();
+                Ok(false)
+            }
+            JMPNMP => {
+                print!("JMPNMP({JMPNMP:#04x}) \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_jump_operand!(self, code, wide);
+                println!();
+                Ok(false)
+            }
             ADD => {
                 print!("ADD    \t");
                 disassemble_operand!(code, true, wide);
@@ -866,6 +923,80 @@ impl Chunk {
                 println!();
                 Ok(false)
             }
+            STRUCT => {
+                print!("STRUCT  \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                println!();
+                Ok(false)
+            }
+            STRUCTP => {
+                print!("STRUCTP \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_operand!(code, true, wide);
+                println!();
+                Ok(false)
+            }
+            SGET => {
+                print!("SGET    \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                println!();
+                Ok(false)
+            }
+            SSET => {
+                print!("SSET    \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                println!();
+                Ok(false)
+            }
+            VALUES => {
+                print!("VALUES  \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                println!();
+                Ok(false)
+            }
+            SRETV => {
+                print!("SRETV   \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                println!();
+                Ok(false)
+            }
+            CASE => {
+                print!("CASE    \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                print!("\t");
+                disassemble_jump_operand!(self, code, wide);
+                println!();
+                Ok(false)
+            }
+            MVBIND => {
+                print!("MVBIND  \t");
+                disassemble_operand!(code, true, wide);
+                print!("\t");
+                disassemble_immediate!(code, wide);
+                println!();
+                Ok(false)
+            }
             _ => Err(VMError::new_chunk(format!("ERROR: unknown opcode {op}"))),
         }
     }
@@ -881,6 +1012,15 @@ impl Chunk {
             "INPUTS: {} args/optional/rest {}/{}/{}",
             self.input_regs, self.args, self.opt_args, self.rest
         );
+        if !self.keys.is_empty() {
+            indent(indent_level);
+            let keys: Vec<String> = self
+                .keys
+                .iter()
+                .map(|k| format!(":{}", vm.get_interned(*k)))
+                .collect();
+            println!("KEYS: {}", keys.join(" "));
+        }
         indent(indent_level);
         println!("EXTRA REGS: {}", self.extra_regs);
         indent(indent_level);
@@ -899,22 +1039,60 @@ impl Chunk {
             indent(indent_level);
             println!("Captures: {caps:?}");
         }
+        if !self.boxed_regs.is_empty() {
+            indent(indent_level);
+            println!("Boxed registers: {:?}", self.boxed_regs);
+        }
+        for (i, table) in self.case_tables.iter().enumerate() {
+            indent(indent_level);
+            print!("CASE TABLE {i}:");
+            let mut entries: Vec<(String, u32)> = match table {
+                CaseTable::Indexed { min, targets } => targets
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(j, t)| t.map(|t| ((min + j as i64).to_string(), t)))
+                    .collect(),
+                CaseTable::Hashed(map) => map
+                    .iter()
+                    .map(|(key, t)| match key {
+                        CaseKey::Int(k) => (k.to_string(), *t),
+                        CaseKey::Other(v) => (v.display_value(vm), *t),
+                    })
+                    .collect(),
+            };
+            entries.sort_by_key(|(key, t)| (self.jump_table[*t as usize], key.clone()));
+            for (key, t) in entries {
+                print!(" {key}->{:#010x}", self.jump_table[t as usize]);
+            }
+            println!();
+        }
         let mut code = self.code.iter().cloned().enumerate();
         let mut op = code.next();
         let mut last_line = 0;
+        let mut last_column = 0;
         let mut wide = false;
         while let Some((idx, curr_op)) = op {
             indent(indent_level);
             print!("{idx:#010x} ");
-            if let Some(line_number) = self.offset_to_line(idx) {
+            let new_line = if let Some(line_number) = self.offset_to_line(idx) {
                 if last_line != line_number {
-                    print!("{line_number:>6} ");
+                    print!("{line_number:>6}");
                     last_line = line_number;
+                    true
                 } else {
-                    print!("     | ");
+                    print!("     |");
+                    false
                 }
             } else {
-                print!("     | ");
+                print!("     |");
+                false
+            };
+            match self.offset_to_pos(idx) {
+                Some(pos) if new_line || pos.column != last_column => {
+                    print!(":{:<4} ", pos.column);
+                    last_column = pos.column;
+                }
+                _ => print!("      "),
             }
             wide = self.disassemble_instruction(&mut code, curr_op, wide, vm)?;
             op = code.next();
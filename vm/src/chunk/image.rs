@@ -0,0 +1,750 @@
+//! Save compiled chunks (and the constants they use) as bytes and load them back into a VM.
+//!
+//! Interned values (symbols, keywords, string constants, file names) are saved by name and heap
+//! objects are rebuilt (with their mutability and properties) when read.  Builtins are saved by
+//! index so an image can only be loaded into a VM with the same builtins (in the same order).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{
+    CaseKey, CaseTable, Chunk, FxHashMap, GVm, Interned, SourcePos, VMError, VMResult, Value,
+};
+
+const BYTE: u8 = 0;
+const INT32: u8 = 1;
+const UINT32: u8 = 2;
+const INT64: u8 = 3;
+const UINT64: u8 = 4;
+const FLOAT64: u8 = 5;
+const CODE_POINT: u8 = 6;
+const CHAR_CLUSTER: u8 = 7;
+const CHAR_CLUSTER_LONG: u8 = 8;
+const SYMBOL: u8 = 9;
+const KEYWORD: u8 = 10;
+const STRING_CONST: u8 = 11;
+const SPECIAL: u8 = 12;
+const BUILTIN: u8 = 13;
+const TRUE: u8 = 14;
+const FALSE: u8 = 15;
+const NIL: u8 = 16;
+const UNDEFINED: u8 = 17;
+const STRING: u8 = 20;
+const VECTOR: u8 = 21;
+const MAP: u8 = 22;
+const BYTES: u8 = 23;
+const PAIR: u8 = 24;
+const LIST: u8 = 25;
+const LAMBDA: u8 = 26;
+const STRUCT: u8 = 27;
+// A heap object already written, followed by its index in the order objects were finished.
+const REF: u8 = 30;
+
+/// Append the chunks to out in a form read_chunks() can load.
+pub fn write_chunks<ENV>(vm: &GVm<ENV>, chunks: &[Arc<Chunk>], out: &mut Vec<u8>) -> VMResult<()> {
+    let mut writer = ImageWriter {
+        vm,
+        out,
+        written: HashMap::new(),
+        in_progress: Vec::new(),
+    };
+    writer.u32(chunks.len() as u32);
+    for chunk in chunks {
+        writer.chunk(chunk)?;
+    }
+    Ok(())
+}
+
+/// Load chunks saved with write_chunks() from the start of bytes, returns them and the number
+/// of bytes used.  Garbage collection should be paused until the chunks are rooted (for instance
+/// in a sticky lambda) since nothing else refers to their constants.
+pub fn read_chunks<ENV>(vm: &mut GVm<ENV>, bytes: &[u8]) -> VMResult<(Vec<Arc<Chunk>>, usize)> {
+    let mut reader = ImageReader {
+        vm,
+        bytes,
+        pos: 0,
+        objects: Vec::new(),
+    };
+    let len = reader.u32()? as usize;
+    let mut chunks = Vec::with_capacity(len);
+    for _ in 0..len {
+        chunks.push(reader.chunk()?);
+    }
+    Ok((chunks, reader.pos))
+}
+
+struct ImageWriter<'vm, 'out, ENV> {
+    vm: &'vm GVm<ENV>,
+    out: &'out mut Vec<u8>,
+    written: HashMap<Value, u32>,
+    in_progress: Vec<Value>,
+}
+
+impl<'vm, 'out, ENV> ImageWriter<'vm, 'out, ENV> {
+    fn u8(&mut self, b: u8) {
+        self.out.push(b);
+    }
+
+    fn u16(&mut self, i: u16) {
+        self.out.extend_from_slice(&i.to_le_bytes());
+    }
+
+    fn u32(&mut self, i: u32) {
+        self.out.extend_from_slice(&i.to_le_bytes());
+    }
+
+    fn u64(&mut self, i: u64) {
+        self.out.extend_from_slice(&i.to_le_bytes());
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        self.u32(b.len() as u32);
+        self.out.extend_from_slice(b);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn interned(&mut self, i: Interned) {
+        self.str(self.vm.get_interned(i));
+    }
+
+    fn opt_interned(&mut self, i: Option<Interned>) {
+        match i {
+            Some(i) => {
+                self.u8(1);
+                self.interned(i);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn u32s(&mut self, v: &[u32]) {
+        self.u32(v.len() as u32);
+        for i in v {
+            self.u32(*i);
+        }
+    }
+
+    fn pos(&mut self, pos: SourcePos) {
+        self.u32(pos.line);
+        self.u32(pos.column);
+        self.u32(pos.span);
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) -> VMResult<()> {
+        self.bytes(&chunk.code);
+        self.str(chunk.file_name);
+        self.u32(chunk.start_line);
+        self.u32(chunk.last_line);
+        self.bytes(&chunk.line_numbers);
+        self.u32(chunk.columns.len() as u32);
+        for (offset, pos) in &chunk.columns {
+            self.u32(*offset);
+            self.pos(*pos);
+        }
+        self.u32(chunk.constants.len() as u32);
+        for constant in &chunk.constants {
+            self.value(*constant)?;
+        }
+        self.u32s(&chunk.jump_table);
+        self.u32(chunk.case_tables.len() as u32);
+        for table in &chunk.case_tables {
+            self.case_table(table)?;
+        }
+        match &chunk.captures {
+            Some(captures) => {
+                self.u8(1);
+                self.u32s(captures);
+            }
+            None => self.u8(0),
+        }
+        self.u32s(&chunk.boxed_regs);
+        self.u64(chunk.input_regs as u64);
+        self.u64(chunk.extra_regs as u64);
+        self.u16(chunk.args);
+        self.u16(chunk.opt_args);
+        self.u8(chunk.rest as u8);
+        self.u32(chunk.keys.len() as u32);
+        for key in &chunk.keys {
+            self.interned(*key);
+        }
+        self.u32(chunk.arg_types.len() as u32);
+        for arg_type in &chunk.arg_types {
+            self.opt_interned(*arg_type);
+        }
+        self.opt_interned(chunk.ret_type);
+        match &chunk.dbg_args {
+            Some(args) => {
+                self.u8(1);
+                self.u32(args.len() as u32);
+                for arg in args {
+                    self.interned(*arg);
+                }
+            }
+            None => self.u8(0),
+        }
+        Ok(())
+    }
+
+    fn case_key(&mut self, key: CaseKey) -> VMResult<()> {
+        match key {
+            CaseKey::Int(i) => {
+                self.u8(0);
+                self.u64(i as u64);
+                Ok(())
+            }
+            CaseKey::Other(val) => {
+                self.u8(1);
+                self.value(val)
+            }
+        }
+    }
+
+    fn case_table(&mut self, table: &CaseTable) -> VMResult<()> {
+        match table {
+            CaseTable::Indexed { min, targets } => {
+                self.u8(0);
+                self.u64(*min as u64);
+                self.u32(targets.len() as u32);
+                for target in targets {
+                    // Jump indexes are u32 but never this big.
+                    self.u32(target.unwrap_or(u32::MAX));
+                }
+            }
+            CaseTable::Hashed(map) => {
+                self.u8(1);
+                self.u32(map.len() as u32);
+                for (key, target) in map {
+                    self.case_key(*key)?;
+                    self.u32(*target);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn values(&mut self, vals: &[Value]) -> VMResult<()> {
+        self.u32(vals.len() as u32);
+        for val in vals {
+            self.value(*val)?;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, val: Value) -> VMResult<()> {
+        let vm = self.vm;
+        match val {
+            Value::Byte(b) => {
+                self.u8(BYTE);
+                self.u8(b);
+            }
+            Value::Int32(i) => {
+                self.u8(INT32);
+                self.u32(i as u32);
+            }
+            Value::UInt32(i) => {
+                self.u8(UINT32);
+                self.u32(i);
+            }
+            Value::Int64(n) => {
+                self.u8(INT64);
+                self.u64(vm.get_int(n) as u64);
+            }
+            Value::UInt64(n) => {
+                self.u8(UINT64);
+                self.u64(vm.get_uint(n));
+            }
+            Value::Float64(n) => {
+                self.u8(FLOAT64);
+                self.u64(vm.get_float(n).to_bits());
+            }
+            Value::CodePoint(ch) => {
+                self.u8(CODE_POINT);
+                self.u32(ch as u32);
+            }
+            Value::CharCluster(len, chars) => {
+                self.u8(CHAR_CLUSTER);
+                self.u8(len);
+                self.out.extend_from_slice(&chars);
+            }
+            Value::Symbol(i) => {
+                self.u8(SYMBOL);
+                self.interned(i);
+            }
+            Value::Keyword(i) => {
+                self.u8(KEYWORD);
+                self.interned(i);
+            }
+            Value::StringConst(i) => {
+                self.u8(STRING_CONST);
+                self.interned(i);
+            }
+            Value::Special(i) => {
+                self.u8(SPECIAL);
+                self.interned(i);
+            }
+            Value::Builtin(i) => {
+                self.u8(BUILTIN);
+                self.u32(i);
+            }
+            Value::True => self.u8(TRUE),
+            Value::False => self.u8(FALSE),
+            Value::Nil => self.u8(NIL),
+            Value::Undefined => self.u8(UNDEFINED),
+            _ => return self.heap_value(val),
+        }
+        Ok(())
+    }
+
+    fn heap_value(&mut self, val: Value) -> VMResult<()> {
+        if let Some(idx) = self.written.get(&val).copied() {
+            self.u8(REF);
+            self.u32(idx);
+            return Ok(());
+        }
+        if self.in_progress.contains(&val) {
+            return Err(VMError::new_image(format!(
+                "can not save a {} that contains itself",
+                val.display_type(self.vm)
+            )));
+        }
+        self.in_progress.push(val);
+        let vm = self.vm;
+        match val {
+            Value::CharClusterLong(h) => {
+                self.u8(CHAR_CLUSTER_LONG);
+                self.str(vm.get_string(h));
+            }
+            Value::String(h) => {
+                self.u8(STRING);
+                self.str(vm.get_string(h));
+            }
+            Value::Vector(h) => {
+                self.u8(VECTOR);
+                self.values(vm.get_vector(h))?;
+            }
+            Value::List(h, start) => {
+                self.u8(LIST);
+                self.u16(start);
+                self.values(vm.get_vector(h))?;
+            }
+            Value::Map(h) => {
+                self.u8(MAP);
+                let map = vm.get_map(h);
+                self.u32(map.len() as u32);
+                for (key, val) in map {
+                    self.value(*key)?;
+                    self.value(*val)?;
+                }
+            }
+            Value::Bytes(h) => {
+                self.u8(BYTES);
+                self.bytes(vm.get_bytes(h));
+            }
+            Value::Pair(h) => {
+                self.u8(PAIR);
+                let (car, cdr) = vm.get_pair(h);
+                self.value(car)?;
+                self.value(cdr)?;
+            }
+            Value::Lambda(h) => {
+                self.u8(LAMBDA);
+                self.chunk(&vm.get_lambda(h))?;
+            }
+            Value::Struct(h) => {
+                self.u8(STRUCT);
+                let (desc, fields) = vm.get_struct(h);
+                self.value(desc)?;
+                self.values(fields)?;
+            }
+            _ => {
+                return Err(VMError::new_image(format!(
+                    "can not save a {}",
+                    val.display_type(vm)
+                )))
+            }
+        }
+        self.u8(vm.heap_is_mutable(val) as u8);
+        let props = vm.get_heap_properties(val).unwrap_or_default();
+        self.u32(props.len() as u32);
+        for (key, prop) in props.iter() {
+            self.interned(*key);
+            self.value(*prop)?;
+        }
+        self.in_progress.pop();
+        let idx = self.written.len() as u32;
+        self.written.insert(val, idx);
+        Ok(())
+    }
+}
+
+struct ImageReader<'vm, 'b, ENV> {
+    vm: &'vm mut GVm<ENV>,
+    bytes: &'b [u8],
+    pos: usize,
+    objects: Vec<Value>,
+}
+
+impl<'vm, 'b, ENV> ImageReader<'vm, 'b, ENV> {
+    fn take(&mut self, len: usize) -> VMResult<&'b [u8]> {
+        let bytes = self.bytes;
+        if let Some(b) = bytes.get(self.pos..self.pos + len) {
+            self.pos += len;
+            Ok(b)
+        } else {
+            Err(VMError::new_image("image is truncated"))
+        }
+    }
+
+    fn u8(&mut self) -> VMResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> VMResult<u16> {
+        let mut b = [0_u8; 2];
+        b.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(b))
+    }
+
+    fn u32(&mut self) -> VMResult<u32> {
+        let mut b = [0_u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn u64(&mut self) -> VMResult<u64> {
+        let mut b = [0_u8; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn bytes(&mut self) -> VMResult<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn str(&mut self) -> VMResult<String> {
+        String::from_utf8(self.bytes()?)
+            .map_err(|_| VMError::new_image("image has an invalid string"))
+    }
+
+    fn interned(&mut self) -> VMResult<Interned> {
+        let s = self.str()?;
+        Ok(self.vm.intern(&s))
+    }
+
+    fn opt_interned(&mut self) -> VMResult<Option<Interned>> {
+        if self.u8()? == 1 {
+            Ok(Some(self.interned()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn u32s(&mut self) -> VMResult<Vec<u32>> {
+        let len = self.u32()? as usize;
+        let mut v = Vec::with_capacity(len);
+        for _ in 0..len {
+            v.push(self.u32()?);
+        }
+        Ok(v)
+    }
+
+    fn pos(&mut self) -> VMResult<SourcePos> {
+        Ok(SourcePos {
+            line: self.u32()?,
+            column: self.u32()?,
+            span: self.u32()?,
+        })
+    }
+
+    fn chunk(&mut self) -> VMResult<Arc<Chunk>> {
+        let code = self.bytes()?;
+        let file_name = self.interned()?;
+        let mut chunk = Chunk::new(self.vm.get_interned(file_name), self.u32()?);
+        chunk.code = code;
+        chunk.last_line = self.u32()?;
+        chunk.line_numbers = self.bytes()?;
+        for _ in 0..self.u32()? {
+            let offset = self.u32()?;
+            let pos = self.pos()?;
+            chunk.columns.push((offset, pos));
+        }
+        for _ in 0..self.u32()? {
+            let constant = self.value()?;
+            chunk.constants.push(constant);
+        }
+        chunk.jump_table = self.u32s()?;
+        for _ in 0..self.u32()? {
+            let table = self.case_table()?;
+            chunk.case_tables.push(table);
+        }
+        if self.u8()? == 1 {
+            chunk.captures = Some(self.u32s()?);
+        }
+        chunk.boxed_regs = self.u32s()?;
+        chunk.input_regs = self.u64()? as usize;
+        chunk.extra_regs = self.u64()? as usize;
+        chunk.args = self.u16()?;
+        chunk.opt_args = self.u16()?;
+        chunk.rest = self.u8()? == 1;
+        for _ in 0..self.u32()? {
+            let key = self.interned()?;
+            chunk.keys.push(key);
+        }
+        for _ in 0..self.u32()? {
+            let arg_type = self.opt_interned()?;
+            chunk.arg_types.push(arg_type);
+        }
+        chunk.ret_type = self.opt_interned()?;
+        if self.u8()? == 1 {
+            let mut args = Vec::new();
+            for _ in 0..self.u32()? {
+                args.push(self.interned()?);
+            }
+            chunk.dbg_args = Some(args);
+        }
+        Ok(Arc::new(chunk))
+    }
+
+    fn case_key(&mut self) -> VMResult<CaseKey> {
+        if self.u8()? == 0 {
+            Ok(CaseKey::Int(self.u64()? as i64))
+        } else {
+            Ok(CaseKey::Other(self.value()?))
+        }
+    }
+
+    fn case_table(&mut self) -> VMResult<CaseTable> {
+        if self.u8()? == 0 {
+            let min = self.u64()? as i64;
+            let targets = self
+                .u32s()?
+                .into_iter()
+                .map(|t| if t == u32::MAX { None } else { Some(t) })
+                .collect();
+            Ok(CaseTable::Indexed { min, targets })
+        } else {
+            let mut map = FxHashMap::default();
+            for _ in 0..self.u32()? {
+                let key = self.case_key()?;
+                map.insert(key, self.u32()?);
+            }
+            Ok(CaseTable::Hashed(map))
+        }
+    }
+
+    fn values(&mut self) -> VMResult<Vec<Value>> {
+        let len = self.u32()? as usize;
+        let mut v = Vec::with_capacity(len);
+        for _ in 0..len {
+            v.push(self.value()?);
+        }
+        Ok(v)
+    }
+
+    fn value(&mut self) -> VMResult<Value> {
+        let tag = self.u8()?;
+        let val =
+            match tag {
+                BYTE => Value::Byte(self.u8()?),
+                INT32 => Value::Int32(self.u32()? as i32),
+                UINT32 => Value::UInt32(self.u32()?),
+                INT64 => {
+                    let i = self.u64()? as i64;
+                    self.vm.alloc_i64(i)
+                }
+                UINT64 => {
+                    let i = self.u64()?;
+                    self.vm.alloc_u64(i)
+                }
+                FLOAT64 => {
+                    let f = f64::from_bits(self.u64()?);
+                    self.vm.alloc_f64(f)
+                }
+                CODE_POINT => Value::CodePoint(
+                    char::from_u32(self.u32()?)
+                        .ok_or_else(|| VMError::new_image("image has an invalid char"))?,
+                ),
+                CHAR_CLUSTER => {
+                    let len = self.u8()?;
+                    let mut chars = [0_u8; 6];
+                    chars.copy_from_slice(self.take(6)?);
+                    Value::CharCluster(len, chars)
+                }
+                SYMBOL => Value::Symbol(self.interned()?),
+                KEYWORD => Value::Keyword(self.interned()?),
+                STRING_CONST => Value::StringConst(self.interned()?),
+                SPECIAL => Value::Special(self.interned()?),
+                BUILTIN => Value::Builtin(self.u32()?),
+                TRUE => Value::True,
+                FALSE => Value::False,
+                NIL => Value::Nil,
+                UNDEFINED => Value::Undefined,
+                REF => {
+                    let idx = self.u32()? as usize;
+                    return self.objects.get(idx).copied().ok_or_else(|| {
+                        VMError::new_image("image has an invalid object reference")
+                    });
+                }
+                _ => return self.heap_value(tag),
+            };
+        Ok(val)
+    }
+
+    fn heap_value(&mut self, tag: u8) -> VMResult<Value> {
+        let val = match tag {
+            CHAR_CLUSTER_LONG => {
+                let s = self.str()?;
+                match self.vm.alloc_string(s) {
+                    Value::String(h) => Value::CharClusterLong(h),
+                    _ => return Err(VMError::new_image("allocated string not a string")),
+                }
+            }
+            STRING => {
+                let s = self.str()?;
+                self.vm.alloc_string(s)
+            }
+            VECTOR => {
+                let v = self.values()?;
+                self.vm.alloc_vector(v)
+            }
+            LIST => {
+                let start = self.u16()?;
+                let v = self.values()?;
+                match self.vm.alloc_vector(v) {
+                    Value::Vector(h) => Value::List(h, start),
+                    _ => return Err(VMError::new_image("allocated vector not a vector")),
+                }
+            }
+            MAP => {
+                let mut map = HashMap::new();
+                for _ in 0..self.u32()? {
+                    let key = self.value()?;
+                    let val = self.value()?;
+                    map.insert(key, val);
+                }
+                self.vm.alloc_map(map)
+            }
+            BYTES => {
+                let b = self.bytes()?;
+                self.vm.alloc_bytes(b)
+            }
+            PAIR => {
+                let car = self.value()?;
+                let cdr = self.value()?;
+                self.vm.alloc_pair(car, cdr)
+            }
+            LAMBDA => {
+                let chunk = self.chunk()?;
+                self.vm.alloc_lambda(chunk)
+            }
+            STRUCT => {
+                let desc = self.value()?;
+                let fields = self.values()?;
+                self.vm.alloc_struct(desc, fields)
+            }
+            _ => {
+                return Err(VMError::new_image(format!(
+                    "image has an unknown value tag {tag}"
+                )))
+            }
+        };
+        if self.u8()? == 0 {
+            self.vm.heap_immutable(val);
+        }
+        for _ in 0..self.u32()? {
+            let key = self.interned()?;
+            let prop = self.value()?;
+            self.vm.set_heap_property_interned(val, key, prop);
+        }
+        self.objects.push(val);
+        Ok(val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Vm, CONST, RET};
+
+    #[test]
+    fn test_image_round_trip() {
+        let mut vm = Vm::new();
+        let sym = vm.intern("a-symbol");
+        let macro_prop = vm.intern(":macro");
+        let mut inner = Chunk::new("test.slosh", 3);
+        inner.encode2(CONST, 0, 0, Some(4)).unwrap();
+        inner.encode1(RET, 0, Some(4)).unwrap();
+        inner.add_constant(Value::Symbol(sym));
+        inner.args = 2;
+        inner.rest = true;
+        inner.arg_types = vec![None, Some(sym)];
+        let lambda = vm.alloc_lambda(Arc::new(inner));
+        vm.set_heap_property_interned(lambda, macro_prop, Value::True);
+        let string = vm.alloc_string_ro("a string".to_string());
+        let list = vm.alloc_pair(Value::Int32(1), Value::Nil);
+        let big = vm.alloc_i64(i64::MAX);
+
+        let mut chunk = Chunk::new("test.slosh", 1);
+        chunk.encode1(RET, 0, Some(1)).unwrap();
+        for val in [lambda, string, list, big, string, Value::Keyword(sym)] {
+            chunk.constants.push(val);
+        }
+        chunk.case_tables.push(CaseTable::new(&[
+            (CaseKey::Int(1), 0),
+            (CaseKey::Other(Value::Symbol(sym)), 1),
+        ]));
+        let mut bytes = Vec::new();
+        write_chunks(&vm, &[Arc::new(chunk)], &mut bytes).unwrap();
+
+        let mut vm2 = Vm::new();
+        vm2.pause_gc();
+        let (chunks, used) = read_chunks(&mut vm2, &bytes).unwrap();
+        assert_eq!(used, bytes.len());
+        assert_eq!(chunks.len(), 1);
+        let chunk = &chunks[0];
+        assert_eq!(chunk.file_name, "test.slosh");
+        assert_eq!(chunk.offset_to_line(0), Some(1));
+        let consts = &chunk.constants;
+        let Value::Lambda(h) = consts[0] else {
+            panic!("expected a lambda");
+        };
+        let inner = vm2.get_lambda(h);
+        assert_eq!(inner.code, vec![CONST, 0, 0, RET, 0]);
+        assert_eq!((inner.args, inner.rest), (2, true));
+        assert_eq!(inner.arg_types, vec![None, Some(vm2.intern("a-symbol"))]);
+        assert_eq!(inner.offset_to_line(0), Some(4));
+        let sym2 = vm2.intern("a-symbol");
+        assert_eq!(inner.constants, vec![Value::Symbol(sym2)]);
+        let macro_prop2 = vm2.intern(":macro");
+        assert_eq!(
+            vm2.get_heap_property_interned(consts[0], macro_prop2),
+            Some(Value::True)
+        );
+        assert_eq!(consts[1].display_value(&vm2), "\"a string\"");
+        assert!(!vm2.heap_is_mutable(consts[1]));
+        // The same object is shared, not copied.
+        assert_eq!(consts[1], consts[4]);
+        assert_eq!(consts[2].display_value(&vm2), "(1)");
+        assert!(vm2.heap_is_mutable(consts[2]));
+        assert_eq!(consts[3].get_int(&vm2).unwrap(), i64::MAX);
+        assert_eq!(consts[5], Value::Keyword(sym2));
+        assert_eq!(
+            chunk.case_tables[0].target(CaseKey::Other(Value::Symbol(sym2))),
+            Some(1)
+        );
+        vm2.unpause_gc();
+
+        let Value::Lambda(h) = lambda else {
+            panic!("expected a lambda");
+        };
+        let closure = vm.alloc_closure(vm.get_lambda(h), Vec::new());
+        let mut chunk = Chunk::new("test.slosh", 1);
+        chunk.constants.push(closure);
+        let mut bytes = Vec::new();
+        assert!(write_chunks(&vm, &[Arc::new(chunk)], &mut bytes).is_err());
+    }
+}
@@ -70,6 +70,10 @@ impl VMError {
         VMError::new("compile", reason)
     }
 
+    pub fn new_image<S: Into<String>>(reason: S) -> Self {
+        VMError::new("image", reason)
+    }
+
     pub fn new_other<S: Into<String>>(reason: S) -> Self {
         VMError::new("error", reason)
     }
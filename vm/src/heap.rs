@@ -39,10 +39,26 @@ impl CallFrame {
         self.chunk.offset_to_line(offset)
     }
 
+    /// Return the source position (column and span on the current line) that corresponds to the
+    /// current_ip if available.
+    pub fn current_pos(&self) -> Option<SourcePos> {
+        self.chunk.offset_to_pos(self.current_offset())
+    }
+
     /// Return the current offset (IP) for the frame using current_ip.
     pub fn current_offset(&self) -> usize {
         unsafe { self.current_ip.offset_from(get_code!(self.chunk)) as usize }
     }
+
+    /// Return file, line and column (if known) of the current_ip, for example "file.slosh:3:7".
+    pub fn current_location(&self) -> String {
+        let line = self.current_line().unwrap_or(0);
+        if let Some(pos) = self.current_pos() {
+            format!("{}:{}:{}", self.chunk.file_name, line, pos.column)
+        } else {
+            format!("{}:{}", self.chunk.file_name, line)
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -63,6 +79,9 @@ enum Object {
     Bytes(Arc<Vec<u8>>),
     Pair(Arc<(Value, Value)>),
     Value(Value),
+    // A record (defstruct) instance, the type descriptor (vector of the type name then field names)
+    // and the field values in the same order.
+    Struct(Value, Arc<Vec<Value>>),
 
     PersistentVec(Arc<PersistentVec>),
     VecNode(Arc<VecNode>),
@@ -72,7 +91,7 @@ enum Object {
     CallFrame(CallFrame),
     // Everything below here is always read only.
     Lambda(Arc<Chunk>),
-    Closure(Arc<Chunk>, Arc<Vec<Handle>>),
+    Closure(Arc<Chunk>, Arc<Vec<Value>>),
     Continuation(Continuation),
     // Place holder for an empty object slot.
     Empty,
@@ -140,6 +159,7 @@ macro_rules! value_op {
             Value::Continuation(handle) => $heap.objects.$op(handle.idx()),
             Value::CallFrame(handle) => $heap.objects.$op(handle.idx()),
             Value::Value(handle) => $heap.objects.$op(handle.idx()),
+            Value::Struct(handle) => $heap.objects.$op(handle.idx()),
 
             Value::Int64(handle) => match handle {
                 Numeric::Local(_) => $default,
@@ -242,7 +262,7 @@ impl Heap {
             self.collect(mark_roots);
         }
         let num = Numeric64 { uint: num };
-        Value::Int64(Numeric::Heap(
+        Value::UInt64(Numeric::Heap(
             self.numerics.alloc(num, mutable.flag()).into(),
         ))
     }
@@ -260,7 +280,7 @@ impl Heap {
             self.collect(mark_roots);
         }
         let num = Numeric64 { int: num };
-        Value::UInt64(Numeric::Heap(
+        Value::Int64(Numeric::Heap(
             self.numerics.alloc(num, mutable.flag()).into(),
         ))
     }
@@ -383,6 +403,23 @@ impl Heap {
         Value::Map(self.alloc(Object::Map(Arc::new(map)), mutable.flag(), mark_roots))
     }
 
+    pub fn alloc_struct<MarkFunc>(
+        &mut self,
+        desc: Value,
+        fields: Vec<Value>,
+        mutable: MutState,
+        mark_roots: MarkFunc,
+    ) -> Value
+    where
+        MarkFunc: FnMut(&mut Heap) -> VMResult<()>,
+    {
+        Value::Struct(self.alloc(
+            Object::Struct(desc, Arc::new(fields)),
+            mutable.flag(),
+            mark_roots,
+        ))
+    }
+
     pub fn alloc_bytes<MarkFunc>(
         &mut self,
         v: Vec<u8>,
@@ -405,7 +442,7 @@ impl Heap {
     pub fn alloc_closure<MarkFunc>(
         &mut self,
         l: Arc<Chunk>,
-        v: Vec<Handle>,
+        v: Vec<Value>,
         mark_roots: MarkFunc,
     ) -> Value
     where
@@ -612,6 +649,25 @@ impl Heap {
         }
     }
 
+    pub fn get_struct(&self, handle: Handle) -> (Value, &[Value]) {
+        if let Some(Object::Struct(desc, fields)) = self.objects.get(handle.idx()) {
+            (*desc, fields)
+        } else {
+            panic!("Handle {} is not a struct!", handle.idx());
+        }
+    }
+
+    pub fn get_struct_mut(&mut self, handle: Handle) -> VMResult<&mut Vec<Value>> {
+        if !self.objects.is_mutable(handle.idx()) {
+            return Err(VMError::new_heap("Struct is not mutable!"));
+        }
+        if let Some(Object::Struct(_, fields)) = self.objects.get_mut(handle.idx()) {
+            Ok(Arc::make_mut(fields))
+        } else {
+            panic!("Handle {} is not a struct!", handle.idx());
+        }
+    }
+
     pub fn get_bytes(&self, handle: Handle) -> &[u8] {
         if let Some(Object::Bytes(v)) = self.objects.get(handle.idx()) {
             v
@@ -657,7 +713,7 @@ impl Heap {
         }
     }
 
-    pub fn get_closure(&self, handle: Handle) -> (Arc<Chunk>, &[Handle]) {
+    pub fn get_closure(&self, handle: Handle) -> (Arc<Chunk>, &[Value]) {
         if let Some(Object::Closure(lambda, captures)) = self.objects.get(handle.idx()) {
             (lambda.clone(), captures)
         } else {
@@ -665,7 +721,7 @@ impl Heap {
         }
     }
 
-    pub fn get_closure_captures(&self, handle: Handle) -> &[Handle] {
+    pub fn get_closure_captures(&self, handle: Handle) -> &[Value] {
         if let Some(Object::Closure(_, captures)) = self.objects.get(handle.idx()) {
             captures
         } else {
@@ -724,6 +780,10 @@ impl Heap {
         value_op!(self, val, immutable, ());
     }
 
+    pub fn is_mutable(&self, val: Value) -> bool {
+        value_op!(self, val, is_mutable, false)
+    }
+
     pub fn sticky(&mut self, val: Value) {
         value_op!(self, val, sticky, ());
     }
@@ -788,7 +848,7 @@ impl Heap {
             Object::Closure(chunk, closures) => {
                 self.mark_chunk(chunk);
                 for close in closures.iter() {
-                    self.mark_trace(Value::Value(*close));
+                    self.mark_trace(*close);
                 }
             }
             Object::Continuation(continuation) => {
@@ -801,6 +861,12 @@ impl Heap {
             Object::Value(val) => {
                 self.mark_trace(*val);
             }
+            Object::Struct(desc, fields) => {
+                self.mark_trace(*desc);
+                for v in fields.iter() {
+                    self.mark_trace(*v);
+                }
+            }
             Object::PersistentVec(pvec) => {
                 if let Some(root) = pvec.root() {
                     if let Some(nodes) = root.nodes() {
@@ -858,7 +924,8 @@ impl Heap {
             | Value::Closure(handle)
             | Value::Continuation(handle)
             | Value::CallFrame(handle)
-            | Value::Value(handle) => {
+            | Value::Value(handle)
+            | Value::Struct(handle) => {
                 let obj = self
                     .objects
                     .get(handle.idx())
@@ -940,6 +1007,11 @@ impl Heap {
         None
     }
 
+    /// All the properties set on value (if any).
+    pub fn get_properties(&self, value: Value) -> Option<Arc<FxHashMap<Interned, Value>>> {
+        self.props().get(&value).cloned()
+    }
+
     pub fn set_property(&mut self, key_value: Value, prop: Interned, value: Value) {
         if let Some(map) = self.props_mut().get_mut(&key_value) {
             let map = Arc::make_mut(map);
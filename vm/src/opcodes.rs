@@ -99,9 +99,19 @@ pub const MKERR: OpCode = FLOW_BASE + 24;
 pub const ISERR: OpCode = FLOW_BASE + 25;
 // ISOK A B - R(A) is #f if R(B) is an error type, #t otherwise
 pub const ISOK: OpCode = FLOW_BASE + 26;
+// JMPNLS A B OFFSET - Jump to current IP + OFFSET if R(A) is NOT a list (nil or pairs) with exactly B items
+pub const JMPNLS: OpCode = FLOW_BASE + 27;
+// JMPNLSR A B OFFSET - Jump to current IP + OFFSET if R(A) is NOT a list (nil or pairs) with at least B items
+pub const JMPNLSR: OpCode = FLOW_BASE + 28;
+// JMPNVC A B OFFSET - Jump to current IP + OFFSET if R(A) is NOT a vector with exactly B items
+pub const JMPNVC: OpCode = FLOW_BASE + 29;
+// JMPNVCR A B OFFSET - Jump to current IP + OFFSET if R(A) is NOT a vector with at least B items
+pub const JMPNVCR: OpCode = FLOW_BASE + 30;
+// JMPNMP A OFFSET - Jump to current IP + OFFSET if R(A) is NOT a map
+pub const JMPNMP: OpCode = FLOW_BASE + 31;
 
 // Basic math
-const MATH_BASE: OpCode = FLOW_BASE + 27;
+const MATH_BASE: OpCode = FLOW_BASE + 32;
 // ADD A B - set R(A) = R(A) + R(B)
 pub const ADD: OpCode = MATH_BASE;
 // SUB A B - set R(A) = R(A) - R(B)
@@ -176,4 +186,34 @@ const TYPE_BASE: OpCode = STRING_BASE + 1;
 // TYPE A B - R(A) = type(R(B)) as a StringConst
 pub const TYPE: OpCode = TYPE_BASE;
 
-pub const MAX_OP_CODE: OpCode = TYPE_BASE;
+// Structs (records)
+const STRUCT_BASE: OpCode = TYPE_BASE + 1;
+// STRUCT A B C - R(A) = new struct with type descriptor R(B) and fields R(B+1)..R(B+C)
+pub const STRUCT: OpCode = STRUCT_BASE;
+// STRUCTP A B - R(A) is #t if R(B+1) is a struct with type descriptor R(B), #f otherwise
+pub const STRUCTP: OpCode = STRUCT_BASE + 1;
+// SGET A B C - R(A) = field C of the struct in R(B+1), error if not a struct with type descriptor R(B)
+pub const SGET: OpCode = STRUCT_BASE + 2;
+// SSET A B C - set field C of the struct in R(A+1) to R(B), error if not a struct with type descriptor R(A)
+pub const SSET: OpCode = STRUCT_BASE + 3;
+
+// Multiple values
+const VALUES_BASE: OpCode = STRUCT_BASE + 4;
+// VALUES A B C - R(A) = R(B) (nil if C is 0), R(B)..R(B+C) (exclusive) are the values for an MVBIND that directly follows
+pub const VALUES: OpCode = VALUES_BASE;
+// SRETV A B - R(0) = R(A) (nil if B is 0) and then RET, R(A)..R(A+B) (exclusive) are the values for an MVBIND that directly follows the call
+pub const SRETV: OpCode = VALUES_BASE + 1;
+// MVBIND A B - R(A)..R(A+B) (exclusive) = the values left by the previous instruction, if there are none R(A) is kept and the rest are nil
+pub const MVBIND: OpCode = VALUES_BASE + 2;
+
+// Jump tables
+const CASE_BASE: OpCode = VALUES_BASE + 3;
+// CASE A B OFFSET - Jump to the target for R(A) in case table B, if it has none jump to current IP + OFFSET
+pub const CASE: OpCode = CASE_BASE;
+
+// Constant globals
+const DEFC_BASE: OpCode = CASE_BASE + 1;
+// DEFC A B - G(B) = R(A) and G(B) becomes a constant, error if G(B) is already a constant
+pub const DEFC: OpCode = DEFC_BASE;
+
+pub const MAX_OP_CODE: OpCode = DEFC_BASE;
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::iter;
@@ -146,6 +146,7 @@ pub enum Value {
     Continuation(Handle),
     CallFrame(Handle),
     Value(Handle),
+    Struct(Handle),
     Error(Handle),
 }
 
@@ -285,6 +286,7 @@ impl Value {
             Value::Continuation(handle) => Some(*handle),
             Value::CallFrame(handle) => Some(*handle),
             Value::Value(handle) => Some(*handle),
+            Value::Struct(handle) => Some(*handle),
             Value::Error(handle) => Some(*handle),
 
             Value::Byte(_) => None,
@@ -472,6 +474,27 @@ impl Value {
             Value::String(handle) => format!("\"{}\"", vm.get_string(*handle)),
             Value::Bytes(_) => "Bytes".to_string(), // XXX TODO
             Value::Value(handle) => vm.get_value(*handle).display_value(vm),
+            Value::Struct(handle) => {
+                let (desc, fields) = vm.get_struct(*handle);
+                let desc = if let Value::Vector(h) = desc {
+                    vm.get_vector(h)
+                } else {
+                    &[]
+                };
+                let mut res = String::from("#S(");
+                if let Some(name) = desc.first() {
+                    res.push_str(&name.display_value(vm));
+                }
+                for (name, val) in desc.iter().skip(1).zip(fields.iter()) {
+                    res.push_str(&format!(
+                        " :{} {}",
+                        name.display_value(vm),
+                        val.display_value(vm)
+                    ));
+                }
+                res.push(')');
+                res
+            }
             Value::Error(handle) => {
                 let err = vm.get_error(*handle);
                 let key = vm.get_interned(err.keyword);
@@ -528,6 +551,7 @@ impl Value {
             Value::String(_) => "String",
             Value::Bytes(_) => "Bytes",
             Value::Value(handle) => vm.get_value(*handle).display_type(vm),
+            Value::Struct(_) => "Struct",
             Value::Error(_) => "Error",
         }
     }
@@ -551,6 +575,7 @@ impl Value {
 pub struct Globals {
     objects: Vec<Value>,
     props: HashMap<u32, Arc<HashMap<Interned, Value>>>,
+    constants: HashSet<u32>,
 }
 
 impl Default for Globals {
@@ -564,6 +589,7 @@ impl Globals {
         Globals {
             objects: Vec::new(),
             props: HashMap::new(),
+            constants: HashSet::new(),
         }
     }
 
@@ -579,6 +605,15 @@ impl Globals {
         self.objects[idx as usize] = val;
     }
 
+    /// Make the global at idx a constant, DEF and DEFC will no longer change it.
+    pub fn set_constant(&mut self, idx: u32) {
+        self.constants.insert(idx);
+    }
+
+    pub fn is_constant(&self, idx: u32) -> bool {
+        self.constants.contains(&idx)
+    }
+
     pub fn get(&self, idx: u32) -> Value {
         self.objects
             .get(idx as usize)
@@ -22,6 +22,10 @@ pub const STACK_CAP: usize = 1024;
 
 const DEAD_CODE: [u8; 3] = [HALT, HALT, HALT];
 
+/// Called before each instruction is executed (while set) with the chunk that is running.  This
+/// is for debuggers (breakpoints, stepping), an error returned aborts the execution with it.
+pub type DebugHook<ENV> = fn(vm: &mut GVm<ENV>, chunk: &Arc<Chunk>) -> VMResult<()>;
+
 pub struct GVm<ENV> {
     interner: Interner,
     heap: Option<Heap>,
@@ -43,6 +47,10 @@ pub struct GVm<ENV> {
     current_ip_ptr: *const u8,
     callframe_id: usize,
     defers: Vec<Value>,
+    // Extra values from VALUES or SRETV and the ip of the instruction they are for (an MVBIND).
+    values: Vec<Value>,
+    values_ip: Option<*const u8>,
+    debug_hook: Option<DebugHook<ENV>>,
     env: ENV,
 }
 
@@ -93,6 +101,9 @@ impl<ENV> GVm<ENV> {
             current_ip_ptr: DEAD_CODE.as_ptr(),
             callframe_id: 0,
             defers: Vec::new(),
+            values: Vec::new(),
+            values_ip: None,
+            debug_hook: None,
             env,
         }
     }
@@ -177,6 +188,17 @@ impl<ENV> GVm<ENV> {
         }
     }
 
+    /// Replace the values (for an MVBIND) with registers first..first+count.  Local numbers are
+    /// moved to the heap since their registers are about to be reused.
+    fn set_values(&mut self, first: usize, count: usize) {
+        self.values.clear();
+        for reg in first..first + count {
+            let val = self.register_unref(reg);
+            let val = self.promote_number(val);
+            self.values.push(val);
+        }
+    }
+
     fn mk_str(&mut self, reg1: u16, reg2: u16) -> VMResult<Value> {
         let mut val = String::new();
         for reg in reg1..=reg2 {
@@ -207,6 +229,11 @@ impl<ENV> GVm<ENV> {
         Ok(val)
     }
 
+    /// Set (or clear with None) the debug hook, see DebugHook.
+    pub fn set_debug_hook(&mut self, hook: Option<DebugHook<ENV>>) {
+        self.debug_hook = hook;
+    }
+
     pub fn add_builtin(&mut self, func: CallFuncSig<ENV>) -> Value {
         let result = self.buitins.len();
         self.buitins.push(CallFunc { func });
@@ -287,6 +314,21 @@ impl<ENV> GVm<ENV> {
                         }
                     }
                 }
+                Value::Struct(h1) => {
+                    if let Value::Struct(h2) = val2 {
+                        let (desc1, f1) = self.heap().get_struct(h1);
+                        let (desc2, f2) = self.heap().get_struct(h2);
+                        if desc1 == desc2 {
+                            val = Value::True;
+                            for (v1, v2) in f1.iter().zip(f2.iter()) {
+                                val = self.is_equal_pair(*v1, *v2)?;
+                                if val == Value::False {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
                 Value::Pair(_) | Value::List(_, _) => {
                     // XXX use iterators to reduce recursion?
                     // Make sure pair iter will work for non-lists...
@@ -331,8 +373,14 @@ impl<ENV> GVm<ENV> {
         &mut self,
         chunk: Arc<Chunk>,
         params: &[Value],
-        caps: Option<&[Handle]>,
+        caps: Option<&[Value]>,
     ) -> VMResult<Value> {
+        if !chunk.keys.is_empty() {
+            let start = ((chunk.args + chunk.opt_args) as usize)
+                .saturating_sub(1)
+                .min(params.len());
+            call::check_keys(self, &chunk, &params[start..])?;
+        }
         let stack_top = self.stack_top;
         let stack_max = self.stack_max;
         let ip = self.ip_ptr;
@@ -356,14 +404,14 @@ impl<ENV> GVm<ENV> {
             if let Some(caps) = caps {
                 let cap_first = (chunk.args + chunk.opt_args + 1) as usize;
                 for (i, c) in caps.iter().enumerate() {
-                    mov_register!(self, cap_first + i, Value::Value(*c));
+                    mov_register!(self, cap_first + i, *c);
                 }
             }
             mov_register!(self, rest_reg, h);
         } else if let Some(caps) = caps {
             let cap_first = (chunk.args + chunk.opt_args + 1) as usize;
             for (i, c) in caps.iter().enumerate() {
-                mov_register!(self, cap_first + i, Value::Value(*c));
+                mov_register!(self, cap_first + i, *c);
             }
         }
         let res = self.execute2(chunk).map(|_| self.stack(self.stack_top));
@@ -1571,4 +1619,42 @@ mod tests {
         assert!(res.unwrap_err().to_string() == "[rt]: Divide by zero error.");
         Ok(())
     }
+
+    #[test]
+    fn test_debug_hook() -> VMResult<()> {
+        fn record_line(vm: &mut GVm<Vec<u32>>, chunk: &Arc<Chunk>) -> VMResult<()> {
+            let line = vm.current_frame(chunk.clone()).current_line().unwrap_or(0);
+            vm.env_mut().push(line);
+            Ok(())
+        }
+        fn stop_line_2(vm: &mut GVm<Vec<u32>>, chunk: &Arc<Chunk>) -> VMResult<()> {
+            if vm.current_frame(chunk.clone()).current_line() == Some(2) {
+                Err(VMError::new_vm("stopped"))
+            } else {
+                Ok(())
+            }
+        }
+        let mut vm = GVm::new_with_env(Vec::new());
+        let mut chunk = Chunk::new("no_file", 1);
+        let const0 = chunk.add_constant(Value::Int32(1)) as u16;
+        chunk.encode2(CONST, 0, const0, Some(1))?;
+        chunk.encode2(CONST, 1, const0, Some(2))?;
+        chunk.encode2(ADD, 0, 1, Some(2))?;
+        chunk.encode0(RET, Some(3))?;
+        let chunk = Arc::new(chunk);
+        vm.set_debug_hook(Some(record_line));
+        vm.execute(chunk.clone())?;
+        assert_eq!(vm.env(), &vec![1, 2, 2, 3]);
+        assert_eq!(vm.stack(0).get_int(&vm)?, 2);
+
+        vm.set_debug_hook(Some(stop_line_2));
+        let res = vm.execute(chunk.clone());
+        assert_eq!(res.unwrap_err().to_string(), "[rt]: stopped");
+        assert_eq!(vm.err_frame().as_ref().unwrap().current_line(), Some(2));
+        vm.reset();
+        vm.set_debug_hook(None);
+        vm.execute(chunk)?;
+        assert_eq!(vm.env().len(), 4);
+        Ok(())
+    }
 }
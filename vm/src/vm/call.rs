@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::chunk::*;
@@ -23,7 +24,19 @@ impl<ENV> GVm<ENV> {
             r.copy_from_slice(
                 &self.register_slice()[rest_reg as usize..(rest_reg as usize + rest_len)],
             );
-            self.alloc_list_ro(r)
+            if chunk.keys.is_empty() {
+                self.alloc_list_ro(r)
+            } else {
+                // &key args (checked to be :key value pairs) are destructured from a map so a
+                // value is never mistaken for a key, the first value given for a key is used.
+                let mut keys = HashMap::new();
+                for pair in r.chunks(2) {
+                    if let [key, value] = pair {
+                        keys.entry(*key).or_insert(*value);
+                    }
+                }
+                self.alloc_map_ro(keys)
+            }
         };
         (rest_reg.into(), v)
     }
@@ -139,6 +152,21 @@ impl<ENV> GVm<ENV> {
             chunk
         }
     }
+    /// True if a tail call to lambda is a call (builtin, map, vector or list) that puts its result
+    /// in register 0 and there is no call frame to go back to (the lambda was started by
+    /// do_call()).  The result is then the return value so execution has to stop after the call.
+    pub(crate) fn special_call_returns(&self, lambda: Value) -> bool {
+        self.call_frame().is_none()
+            && matches!(
+                lambda.unref(self),
+                Value::Builtin(_)
+                    | Value::Map(_)
+                    | Value::Vector(_)
+                    | Value::Pair(_)
+                    | Value::List(_, _)
+            )
+    }
+
     /// Main function to match and execute anything that is callable.
     pub fn make_call(
         &mut self,
@@ -172,6 +200,8 @@ impl<ENV> GVm<ENV> {
                 let stack_top = self.stack_top;
                 let l = self.heap().get_lambda(handle);
                 check_num_args(&l, num_args).map_err(|e| (e, chunk.clone()))?;
+                self.check_key_args(&l, first_reg, num_args)
+                    .map_err(|e| (e, chunk.clone()))?;
                 if !tail_call {
                     let frame = self.make_call_frame(chunk, lambda, true);
                     let aframe = self.alloc_callframe(frame);
@@ -193,6 +223,8 @@ impl<ENV> GVm<ENV> {
                 let stack_top = self.stack_top;
                 let (l, _) = self.heap().get_closure(handle);
                 check_num_args(&l, num_args).map_err(|e| (e, chunk.clone()))?;
+                self.check_key_args(&l, first_reg, num_args)
+                    .map_err(|e| (e, chunk.clone()))?;
                 let frame = if !tail_call {
                     let frame = self.make_call_frame(chunk, lambda, true);
                     self.stack_top += first_reg as usize;
@@ -211,13 +243,13 @@ impl<ENV> GVm<ENV> {
                     let (rest_reg, h) = self.setup_rest(&l, first_reg, num_args);
                     let cap_first = rest_reg + 1;
                     for (i, c) in caps.iter().enumerate() {
-                        *self.stack_mut(stack_top + cap_first + i) = Value::Value(*c);
+                        *self.stack_mut(stack_top + cap_first + i) = *c;
                     }
                     *self.stack_mut(stack_top + rest_reg) = h;
                 } else {
                     let cap_first = (first_reg + l.args + l.opt_args + 1) as usize;
                     for (i, c) in caps.iter().enumerate() {
-                        *self.stack_mut(stack_top + cap_first + i) = Value::Value(*c);
+                        *self.stack_mut(stack_top + cap_first + i) = *c;
                     }
                 }
                 // Put the heap back, if this doesn't happen will panic on next access attempt.
@@ -322,6 +354,18 @@ impl<ENV> GVm<ENV> {
         }
     }
 
+    /// Verify the key value pairs passed to a chunk with &key params (the args after the
+    /// positional args in the registers after first_reg).
+    fn check_key_args(&self, l: &Chunk, first_reg: u16, num_args: u16) -> VMResult<()> {
+        if l.keys.is_empty() {
+            return Ok(());
+        }
+        let first_reg = first_reg as usize;
+        let end = first_reg + num_args as usize + 1;
+        let start = (first_reg + (l.args + l.opt_args) as usize).min(end);
+        check_keys(self, l, &self.register_slice()[start..end])
+    }
+
     /// Clear out the unused optional regs.
     /// Will clear working set to avoid writing to globals or closures by accident.
     fn clear_opts(&mut self, l: &Chunk, first_reg: u16, num_args: u16) {
@@ -383,3 +427,31 @@ fn check_num_args(l: &Chunk, num_args: u16) -> VMResult<()> {
     }
     Ok(())
 }
+
+/// Verify that key_args are :key value pairs for the &key params of a chunk.
+pub(crate) fn check_keys<ENV>(vm: &GVm<ENV>, l: &Chunk, key_args: &[Value]) -> VMResult<()> {
+    if !key_args.len().is_multiple_of(2) {
+        return Err(VMError::new_vm(format!(
+            "Key arguments must be :key value pairs, got {} values.",
+            key_args.len()
+        )));
+    }
+    for key in key_args.iter().step_by(2) {
+        match key {
+            Value::Keyword(i) if l.keys.contains(i) => {}
+            _ => {
+                let keys: Vec<String> = l
+                    .keys
+                    .iter()
+                    .map(|k| format!(":{}", vm.get_interned(*k)))
+                    .collect();
+                return Err(VMError::new_vm(format!(
+                    "Unknown key {}, expected one of {}.",
+                    key.display_value(vm),
+                    keys.join(" ")
+                )));
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,58 @@
 use crate::opcodes::*;
 use crate::{
-    CallFrame, Chunk, Continuation, Error, GVm, VMError, VMErrorObj, VMResult, Value, STACK_CAP,
+    CallFrame, CaseKey, Chunk, Continuation, Error, GVm, Handle, Interned, VMError, VMErrorObj,
+    VMResult, Value, STACK_CAP,
 };
 use std::marker::PhantomData;
 use std::sync::Arc;
 
 impl<ENV> GVm<ENV> {
+    /// True if the sequence val has exactly len items (or at least len items if not exact).
+    /// Only walks as much of the sequence as needed to decide.
+    fn seq_has_len(&self, val: Value, len: usize, exact: bool) -> bool {
+        let count = val.iter(self).take(len + 1).count();
+        if exact {
+            count == len
+        } else {
+            count >= len
+        }
+    }
+
+    /// Return the type name of val if it is a struct.
+    fn struct_name(&self, val: Value) -> Option<Interned> {
+        if let Value::Struct(h) = val {
+            if let Value::Vector(desc) = self.get_struct(h).0 {
+                if let Some(Value::Symbol(name)) = self.get_vector(desc).first() {
+                    return Some(*name);
+                }
+            }
+        }
+        None
+    }
+
+    /// Return the handle of the struct in R(desc_reg + 1) if it has the type descriptor in
+    /// R(desc_reg), otherwise an error.
+    fn struct_handle(&self, desc_reg: usize) -> VMResult<Handle> {
+        let desc = self.register(desc_reg);
+        match self.register_unref(desc_reg + 1) {
+            Value::Struct(h) if self.get_struct(h).0 == desc => Ok(h),
+            val => {
+                let name = match desc {
+                    Value::Vector(h) => self
+                        .get_vector(h)
+                        .first()
+                        .map(|n| n.display_value(self))
+                        .unwrap_or_default(),
+                    _ => String::new(),
+                };
+                Err(VMError::new_vm(format!(
+                    "not a {name}: {}",
+                    val.display_value(self)
+                )))
+            }
+        }
+    }
+
     #[inline]
     fn map_destructure(
         &mut self,
@@ -120,11 +167,15 @@ impl<ENV> GVm<ENV> {
         Ok(())
     }
 
-    fn get_line(&self, wide: bool, chunk: &Chunk) -> Option<u32> {
-        if wide {
-            unsafe { chunk.offset_to_line(self.ip_ptr.offset_from(get_code!(chunk)) as usize - 4) }
+    /// Line and column (if known) of the instruction that was just decoded.
+    fn get_location(&self, wide: bool, chunk: &Chunk) -> String {
+        let offset = unsafe { self.ip_ptr.offset_from(get_code!(chunk)) as usize };
+        let offset = if wide { offset - 4 } else { offset - 2 };
+        let line = chunk.offset_to_line(offset).unwrap_or(0);
+        if let Some(pos) = chunk.offset_to_pos(offset) {
+            format!("line {line}, col {}", pos.column)
         } else {
-            unsafe { chunk.offset_to_line(self.ip_ptr.offset_from(get_code!(chunk)) as usize - 2) }
+            format!("line {line}")
         }
     }
 
@@ -324,6 +375,11 @@ impl<ENV> GVm<ENV> {
                 wide = false;
             }
             self.current_ip_ptr = self.ip_ptr;
+            if let Some(hook) = self.debug_hook {
+                if let Err(e) = hook(self, &chunk) {
+                    return Err((e, chunk));
+                }
+            }
             opcode = decode_u8!(self.ip_ptr);
             match opcode {
                 NOP => {}
@@ -361,15 +417,22 @@ impl<ENV> GVm<ENV> {
                         }
                     }
                 }
-                SRET => {
+                SRET | SRETV => {
                     if let Some(defer) = self.defers.pop() {
                         let first_reg = (chunk.input_regs + chunk.extra_regs + 1) as u16;
                         self.ip_ptr = self.current_ip_ptr;
                         chunk = self.make_call(defer, chunk, first_reg, 0, false)?;
                         self.make_registers();
                     } else {
-                        let src = decode1!(self.ip_ptr, wide);
-                        let val = self.register(src as usize);
+                        let val = if opcode == SRETV {
+                            let (src, count) = decode2!(self.ip_ptr, wide);
+                            self.set_values(src as usize, count as usize);
+                            self.values.first().copied().unwrap_or(Value::Nil)
+                        } else {
+                            let src = decode1!(self.ip_ptr, wide);
+                            self.values.clear();
+                            self.register(src as usize)
+                        };
                         let old_top = self.stack_top;
                         // Clear used regs to make sure no closures or globals get overwritten later.
                         for r in self.stack_top + 1..=self.stack_max {
@@ -391,9 +454,17 @@ impl<ENV> GVm<ENV> {
                             self.this_fn = this_fn;
                             self.on_error = on_error;
                         } else {
+                            self.values.clear();
+                            self.values_ip = None;
                             *self.stack_mut(old_top) = val;
                             return Ok(());
                         }
+                        // The values are for the instruction after the call (if it is an MVBIND).
+                        self.values_ip = if opcode == SRETV {
+                            Some(self.ip_ptr)
+                        } else {
+                            None
+                        };
                         *self.stack_mut(old_top) = val;
                     }
                 }
@@ -508,9 +579,32 @@ impl<ENV> GVm<ENV> {
                     } else {
                         decode_u16!(self.ip_ptr) as u32
                     };
+                    if self.globals.is_constant(idx) {
+                        return Err((
+                            VMError::new_vm("DEF: can not redefine a constant global"),
+                            chunk,
+                        ));
+                    }
                     let val = self.register(src as usize);
                     self.set_global(idx, val);
                 }
+                DEFC => {
+                    let src = decode1!(self.ip_ptr, wide);
+                    let idx = if wide {
+                        decode_u32!(self.ip_ptr)
+                    } else {
+                        decode_u16!(self.ip_ptr) as u32
+                    };
+                    if self.globals.is_constant(idx) {
+                        return Err((
+                            VMError::new_vm("DEFC: can not redefine a constant global"),
+                            chunk,
+                        ));
+                    }
+                    let val = self.register(src as usize);
+                    self.set_global(idx, val);
+                    self.globals.set_constant(idx);
+                }
                 DEFV => {
                     let src = decode1!(self.ip_ptr, wide);
                     let idx = if wide {
@@ -574,12 +668,17 @@ impl<ENV> GVm<ENV> {
                         if let Some(captures) = &l.captures {
                             for c in captures {
                                 let r = self.register(*c as usize);
-                                if let Value::Value(b) = r {
-                                    caps.push(b);
-                                } else {
+                                if let Value::Value(_) = r {
+                                    // Already boxed, share it.
+                                    caps.push(r);
+                                } else if chunk.boxed_regs.contains(c) {
+                                    // Mutated after capture so box it and share the box.
                                     let val = self.new_upval(r);
                                     mov_register!(self, *c as usize, val);
-                                    caps.push(val.get_handle().unwrap());
+                                    caps.push(val);
+                                } else {
+                                    // Never changes once captured so the closure gets a copy.
+                                    caps.push(self.promote_number(r));
                                 }
                             }
                         }
@@ -613,7 +712,11 @@ impl<ENV> GVm<ENV> {
                 TCALL => {
                     let (lambda, num_args) = decode2!(self.ip_ptr, wide);
                     let lambda = self.register(lambda as usize);
+                    let returns = self.special_call_returns(lambda);
                     chunk = self.make_call(lambda, chunk, 0, num_args, true)?;
+                    if returns {
+                        return Ok(());
+                    }
                     self.make_registers(); // In case of a builtin call
                 }
                 TCALLG => {
@@ -624,7 +727,11 @@ impl<ENV> GVm<ENV> {
                     };
                     let num_args = decode1!(self.ip_ptr, wide);
                     let lambda = self.get_global(idx);
+                    let returns = self.special_call_returns(lambda);
                     chunk = self.make_call(lambda, chunk, 0, num_args, true)?;
+                    if returns {
+                        return Ok(());
+                    }
                     self.make_registers(); // In case of a builtin call
                 }
                 CALLM => {
@@ -633,11 +740,10 @@ impl<ENV> GVm<ENV> {
                         chunk = self.make_call(this_fn, chunk, first_reg, num_args, false)?;
                         self.make_registers();
                     } else {
-                        let line = self.get_line(wide, &chunk);
+                        let location = self.get_location(wide, &chunk);
                         return Err((
                             VMError::new_vm(format!(
-                                "CALLM: Not in an existing lambda call, line {}.",
-                                line.unwrap_or(0)
+                                "CALLM: Not in an existing lambda call, {location}."
                             )),
                             chunk,
                         ));
@@ -646,14 +752,17 @@ impl<ENV> GVm<ENV> {
                 TCALLM => {
                     let num_args = decode1!(self.ip_ptr, wide);
                     if let Some(this_fn) = self.this_fn {
+                        let returns = self.special_call_returns(this_fn);
                         chunk = self.make_call(this_fn, chunk, 0, num_args, true)?;
+                        if returns {
+                            return Ok(());
+                        }
                         self.make_registers(); // In case of a builtin call
                     } else {
-                        let line = self.get_line(wide, &chunk);
+                        let location = self.get_location(wide, &chunk);
                         return Err((
                             VMError::new_vm(format!(
-                                "TCALLM: Not in an existing lambda call, line {}.",
-                                line.unwrap_or(0)
+                                "TCALLM: Not in an existing lambda call, {location}."
                             )),
                             chunk,
                         ));
@@ -777,6 +886,28 @@ impl<ENV> GVm<ENV> {
                     };
                     set_register!(self, dest as usize, val);
                 }
+                JMPNLS | JMPNLSR => {
+                    let (test, len, jmp) = decode3!(self.ip_ptr, wide);
+                    let val = self.register_unref(test as usize);
+                    let is_list = matches!(val, Value::Nil | Value::Pair(_) | Value::List(_, _));
+                    if !is_list || !self.seq_has_len(val, len as usize, opcode == JMPNLS) {
+                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                    }
+                }
+                JMPNVC | JMPNVCR => {
+                    let (test, len, jmp) = decode3!(self.ip_ptr, wide);
+                    let val = self.register_unref(test as usize);
+                    let is_vec = matches!(val, Value::Vector(_));
+                    if !is_vec || !self.seq_has_len(val, len as usize, opcode == JMPNVC) {
+                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                    }
+                }
+                JMPNMP => {
+                    let (test, jmp) = decode2!(self.ip_ptr, wide);
+                    if !matches!(self.register_unref(test as usize), Value::Map(_)) {
+                        self.ip_ptr = get_code_at!(chunk, chunk.jump_table[jmp as usize] as isize);
+                    }
+                }
                 ISOK => {
                     let (dest, testreg) = decode2!(self.ip_ptr, wide);
                     let test = self.register_unref(testreg as usize);
@@ -1228,9 +1359,89 @@ impl<ENV> GVm<ENV> {
                 TYPE => {
                     let (dest, val) = decode2!(self.ip_ptr, wide);
                     let val = self.register(val as usize);
-                    let t = Value::Keyword(self.intern_static(val.display_type(self)));
+                    let t = if let Some(name) = self.struct_name(val.unref(self)) {
+                        Value::Keyword(name)
+                    } else {
+                        Value::Keyword(self.intern_static(val.display_type(self)))
+                    };
                     set_register!(self, dest as usize, t);
                 }
+                STRUCT => {
+                    let (dest, desc, len) = decode3!(self.ip_ptr, wide);
+                    let desc_reg = desc as usize;
+                    let fields: Vec<Value> = (0..len as usize)
+                        .map(|i| self.register_unref(desc_reg + i + 1))
+                        .collect();
+                    let desc = self.register(desc_reg);
+                    let val = self.alloc_struct(desc, fields);
+                    set_register!(self, dest as usize, val);
+                }
+                STRUCTP => {
+                    let (dest, desc) = decode2!(self.ip_ptr, wide);
+                    let desc_reg = desc as usize;
+                    let val = match self.register_unref(desc_reg + 1) {
+                        Value::Struct(h) if self.get_struct(h).0 == self.register(desc_reg) => {
+                            Value::True
+                        }
+                        _ => Value::False,
+                    };
+                    set_register!(self, dest as usize, val);
+                }
+                SGET => {
+                    let (dest, desc, field) = decode3!(self.ip_ptr, wide);
+                    let handle = self
+                        .struct_handle(desc as usize)
+                        .map_err(|e| (e, chunk.clone()))?;
+                    let val = self.get_struct(handle).1[field as usize];
+                    set_register!(self, dest as usize, val);
+                }
+                SSET => {
+                    let (desc, src, field) = decode3!(self.ip_ptr, wide);
+                    let handle = self
+                        .struct_handle(desc as usize)
+                        .map_err(|e| (e, chunk.clone()))?;
+                    let val = self.register_unref(src as usize);
+                    self.get_struct_mut(handle)
+                        .map_err(|e| (e, chunk.clone()))?[field as usize] = val;
+                }
+                VALUES => {
+                    let (dest, first, count) = decode3!(self.ip_ptr, wide);
+                    self.set_values(first as usize, count as usize);
+                    let val = self.values.first().copied().unwrap_or(Value::Nil);
+                    set_register!(self, dest as usize, val);
+                    self.values_ip = Some(self.ip_ptr);
+                }
+                CASE => {
+                    let (reg, table, jmp) = decode3!(self.ip_ptr, wide);
+                    let val = self.register_unref(reg as usize);
+                    let target = CaseKey::from_value(self, val)
+                        .and_then(|key| chunk.case_tables[table as usize].target(key))
+                        .unwrap_or(jmp as u32);
+                    self.ip_ptr = get_code_at!(chunk, chunk.jump_table[target as usize] as isize);
+                }
+                MVBIND => {
+                    let (dest, count) = decode2!(self.ip_ptr, wide);
+                    let dest = dest as usize;
+                    let ip = if wide {
+                        // Values are for the WIDE prefix of this instruction.
+                        unsafe { self.current_ip_ptr.sub(1) }
+                    } else {
+                        self.current_ip_ptr
+                    };
+                    if self.values_ip == Some(ip) {
+                        for i in 0..count as usize {
+                            let val = self.values.get(i).copied().unwrap_or(Value::Nil);
+                            mov_register!(self, dest + i, val);
+                        }
+                    } else {
+                        // A single value was produced, it is already in R(dest).
+                        for i in 1..count as usize {
+                            mov_register!(self, dest + i, Value::Nil);
+                        }
+                    }
+                    self.values.clear();
+                    self.values_ip = None;
+                }
                 _ => {
                     return Err((VMError::new_vm(format!("Invalid opcode {opcode}")), chunk));
                 }
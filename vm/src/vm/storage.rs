@@ -8,7 +8,7 @@ use crate::interner::*;
 use crate::persistent_map::{MapNode, PersistentMap};
 use crate::persistent_vec::{PersistentVec, VecNode};
 use crate::value::*;
-use crate::GVm;
+use crate::{FxHashMap, GVm};
 
 /// Vm code to access storage, heap, stack, globals, etc.
 
@@ -55,6 +55,22 @@ impl<ENV> GVm<ENV> {
         &self.err_frame
     }
 
+    /// Frame for the code that is executing now (the VM only has frames for the callers on the
+    /// stack), chunk is the chunk that is running.  Used by a DebugHook.
+    pub fn current_frame(&self, chunk: Arc<Chunk>) -> CallFrame {
+        CallFrame {
+            id: self.callframe_id,
+            chunk,
+            ip: self.ip_ptr,
+            current_ip: self.current_ip_ptr,
+            stack_top: self.stack_top,
+            this_fn: self.this_fn,
+            defers: Vec::new(),
+            on_error: self.on_error,
+            called: Value::Undefined,
+        }
+    }
+
     pub fn get_registers(&self, start: usize, end: usize) -> &[Value] {
         &self.stack_slice()[start..end]
     }
@@ -94,6 +110,15 @@ impl<ENV> GVm<ENV> {
         self.globals.set(slot, value);
     }
 
+    /// Make the global in slot a constant (it can not be redefined).
+    pub fn set_global_constant(&mut self, slot: u32) {
+        self.globals.set_constant(slot);
+    }
+
+    pub fn is_global_constant(&self, slot: u32) -> bool {
+        self.globals.is_constant(slot)
+    }
+
     pub fn reserve_global(&mut self) -> u32 {
         self.globals.reserve()
     }
@@ -211,6 +236,15 @@ impl<ENV> GVm<ENV> {
         res
     }
 
+    pub fn alloc_struct(&mut self, desc: Value, fields: Vec<Value>) -> Value {
+        let mut heap = self.heap.take().expect("VM must have a Heap!");
+        let res = heap.alloc_struct(desc, fields, MutState::Mutable, |heap| {
+            self.mark_roots(heap)
+        });
+        self.heap = Some(heap);
+        res
+    }
+
     pub fn alloc_persistent_vector(&mut self, vec: PersistentVec) -> Value {
         let mut heap = self.heap.take().expect("VM must have a Heap!");
         let res =
@@ -286,7 +320,7 @@ impl<ENV> GVm<ENV> {
         res
     }
 
-    pub fn alloc_closure(&mut self, l: Arc<Chunk>, v: Vec<Handle>) -> Value {
+    pub fn alloc_closure(&mut self, l: Arc<Chunk>, v: Vec<Value>) -> Value {
         let mut heap = self.heap.take().expect("VM must have a Heap!");
         // alloc must not save mark_roots (it does not) since we broke heap away from self.
         let res = heap.alloc_closure(l, v, |heap| self.mark_roots(heap));
@@ -312,7 +346,7 @@ impl<ENV> GVm<ENV> {
 
     /// If val is a 64 bit number stored on the number stack then promote to the heap.  Otherwise
     /// just return val.
-    fn promote_number(&mut self, mut val: Value) -> Value {
+    pub(crate) fn promote_number(&mut self, mut val: Value) -> Value {
         // If we have a number stored locally then put it on the heap as well as the value that references it.
         if let Value::Int64(Numeric::Local(idx)) = val {
             val = self.alloc_i64(unsafe { self.numbers[idx as usize].int });
@@ -348,6 +382,11 @@ impl<ENV> GVm<ENV> {
         self.heap_mut().immutable(val);
     }
 
+    /// True if val is a heap object that can be modified (false for any non-heap value).
+    pub fn heap_is_mutable(&self, val: Value) -> bool {
+        self.heap().is_mutable(val)
+    }
+
     pub fn heap_sticky(&mut self, val: Value) {
         self.heap_mut().sticky(val);
     }
@@ -385,6 +424,11 @@ impl<ENV> GVm<ENV> {
         self.heap().get_property(key_val, prop)
     }
 
+    /// All the properties set on the heap object key_val (if any).
+    pub fn get_heap_properties(&self, key_val: Value) -> Option<Arc<FxHashMap<Interned, Value>>> {
+        self.heap().get_properties(key_val)
+    }
+
     pub fn set_heap_property_interned(&mut self, key_val: Value, prop: Interned, value: Value) {
         self.heap_mut().set_property(key_val, prop, value)
     }
@@ -459,6 +503,14 @@ impl<ENV> GVm<ENV> {
         self.heap_mut().get_vector_mut(handle)
     }
 
+    pub fn get_struct(&self, handle: Handle) -> (Value, &[Value]) {
+        self.heap().get_struct(handle)
+    }
+
+    pub fn get_struct_mut(&mut self, handle: Handle) -> VMResult<&mut Vec<Value>> {
+        self.heap_mut().get_struct_mut(handle)
+    }
+
     pub(crate) fn get_persistent_vector(&self, handle: Handle) -> &PersistentVec {
         self.heap().get_persistent_vector(handle)
     }
@@ -503,7 +555,7 @@ impl<ENV> GVm<ENV> {
         self.heap().get_lambda(handle)
     }
 
-    pub fn get_closure(&self, handle: Handle) -> (Arc<Chunk>, &[Handle]) {
+    pub fn get_closure(&self, handle: Handle) -> (Arc<Chunk>, &[Value]) {
         self.heap().get_closure(handle)
     }
 
@@ -577,6 +629,9 @@ impl<ENV> GVm<ENV> {
         for defer in &self.defers {
             heap.mark(*defer);
         }
+        for val in &self.values {
+            heap.mark(*val);
+        }
         Ok(())
     }
 }